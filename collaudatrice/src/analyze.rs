@@ -7,6 +7,7 @@ use std::{
 };
 
 use anyhow::{bail, anyhow};
+use servente_common::net::{Resolver, SystemResolver};
 use tokio::{net::TcpStream, io::{AsyncWriteExt, AsyncReadExt, BufReader}};
 
 use crate::{Configuration, io::read_to_crlf};
@@ -21,7 +22,7 @@ pub struct ServerAnalysis {
 /// Find out some details about the server.
 pub async fn analyze_server(config: &Configuration) -> anyhow::Result<ServerAnalysis> {
     let host = format!("{}:{}", config.args.host, config.args.port);
-    let addresses = tokio::net::lookup_host(&host).await
+    let addresses = SystemResolver.resolve(&config.args.host, config.args.port).await
         .map_err(|e| anyhow::Error::from(e).context(format!("Failed to lookup host: {host}")))?;
 
     let mut analysis = ServerAnalysis {
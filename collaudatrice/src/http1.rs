@@ -2,7 +2,6 @@
 // All Rights Reserved.
 
 use std::{
-    net::SocketAddr,
     sync::Arc,
     time::{
         Duration,
@@ -21,11 +20,12 @@ use tokio::{
         AsyncWriteExt,
         split,
     },
-    net::TcpStream,
 };
 
 use crate::Configuration;
 
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct TestName(&'static str);
 
@@ -33,7 +33,7 @@ struct TestName(&'static str);
 struct TestParameters {
     rustls_config: Arc<rustls::ClientConfig>,
     domain: Arc<str>,
-    address: SocketAddr
+    port: u16,
 }
 
 #[derive(Debug)]
@@ -59,11 +59,11 @@ async fn invoke_task<F>(name: TestName, f: F) -> (TestName, TestResult)
     }
 }
 
-pub async fn run(config: &Configuration, address: SocketAddr) {
+pub async fn run(config: &Configuration) {
     let parameters = TestParameters {
         rustls_config: Arc::clone(&config.rustls_client_config),
         domain: Arc::from(config.args.host.as_str()),
-        address,
+        port: config.args.port,
     };
 
     let mut tasks = FuturesUnordered::new();
@@ -78,7 +78,7 @@ pub async fn run(config: &Configuration, address: SocketAddr) {
 }
 
 async fn simple_exchange(parameters: TestParameters, input: &[u8]) -> anyhow::Result<Vec<u8>> {
-    let stream = TcpStream::connect(parameters.address).await?;
+    let stream = servente_common::connect(&parameters.domain, parameters.port, CONNECT_TIMEOUT).await?;
     let connection = tokio_rustls::TlsConnector::from(parameters.rustls_config)
             .connect(rustls::ServerName::try_from(parameters.domain.as_ref())?, stream).await?;
 
@@ -66,13 +66,13 @@ async fn main() -> anyhow::Result<()> {
 
     let analysis = run_analysis(&mut config).await?;
 
-    if let Some(address) = analysis.ipv6_address.or(analysis.ipv4_address) {
+    if analysis.ipv6_address.is_some() || analysis.ipv4_address.is_some() {
         println!("{} Indexing server", "II:".blue());
         // TODO
         _ = analysis;
 
         println!("{} Running HTTP/1.1 tests", "III".blue());
-        http1::run(&config, address).await;
+        http1::run(&config).await;
     }
 
     println!();
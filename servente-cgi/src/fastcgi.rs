@@ -0,0 +1,717 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The FastCGI backend, which passes the requests for scripts to a long-living
+//! application, e.g. PHP-FPM, instead of starting a process per request like
+//! the [`CgiMiddleware`](crate::CgiMiddleware) does.
+//!
+//! # References
+//! * [FastCGI Specification](https://fastcgi-archives.github.io/FastCGI_Specification.html)
+
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use servente_http::{
+    BodyKind,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+use servente_http_handling::{
+    middleware::{
+        ExchangeState,
+        MiddlewareError,
+    },
+    ConnectionInfo,
+    Middleware,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{meta_variables, produce_response_for_cgi_output, CgiScripts, ScriptPath};
+
+/// The version of the protocol, i.e. `FCGI_VERSION_1`.
+const VERSION: u8 = 1;
+
+/// The identifier of the requests. The exchanges aren't multiplexed, so a
+/// connection carries a single request at a time.
+const REQUEST_ID: u16 = 1;
+
+/// The maximum length of the content of a single record.
+const MAX_CONTENT_LENGTH: usize = u16::MAX as usize;
+
+/// The maximum number of idle connections kept for later requests.
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+/// The default of [`FastCgiMiddleware::with_max_response_size`].
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The time the application has to respond, like the timeout of CGI scripts.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `FCGI_RESPONDER` role, in which the application generates the response.
+const ROLE_RESPONDER: u16 = 1;
+
+/// The `FCGI_KEEP_CONN` flag, which asks the application to keep the
+/// connection open after the request.
+const FLAG_KEEP_CONN: u8 = 1;
+
+/// The `FCGI_REQUEST_COMPLETE` protocol status of the `FCGI_END_REQUEST`.
+const REQUEST_COMPLETE: u8 = 0;
+
+/// The types of the records.
+mod record_type {
+    pub const BEGIN_REQUEST: u8 = 1;
+    pub const END_REQUEST: u8 = 3;
+    pub const PARAMS: u8 = 4;
+    pub const STDIN: u8 = 5;
+    pub const STDOUT: u8 = 6;
+    pub const STDERR: u8 = 7;
+}
+
+/// The address the FastCGI application listens on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FastCgiAddress {
+    Tcp(SocketAddr),
+
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FastCgiAddress {
+    async fn connect(&self) -> std::io::Result<Connection> {
+        Ok(match self {
+            Self::Tcp(address) => {
+                let stream = tokio::net::TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+
+            #[cfg(unix)]
+            Self::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+        })
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type Connection = Box<dyn Stream>;
+
+/// The idle connections to the application, which are shared by the clones
+/// of the middleware.
+#[derive(Clone, Default)]
+struct ConnectionPool(Arc<Mutex<Vec<Connection>>>);
+
+impl ConnectionPool {
+    fn take(&self) -> Option<Connection> {
+        self.0.lock().unwrap().pop()
+    }
+
+    fn put(&self, connection: Connection) {
+        let mut idle_connections = self.0.lock().unwrap();
+        if idle_connections.len() < MAX_IDLE_CONNECTIONS {
+            idle_connections.push(connection);
+        }
+    }
+}
+
+impl Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("idle", &self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// The exchange with the application failed.
+#[derive(Debug)]
+enum ExchangeError {
+    /// The request couldn't be written, e.g. because the application closed
+    /// the idle connection in the meantime.
+    NotSent(std::io::Error),
+
+    /// The application didn't respond at all, even though the request was
+    /// written, so it might have handled the request nonetheless.
+    NoResponse(std::io::Error),
+
+    /// The application failed while responding.
+    Response(std::io::Error),
+}
+
+impl From<std::io::Error> for ExchangeError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Response(value)
+    }
+}
+
+impl std::fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotSent(e) => write!(f, "request not sent: {e}"),
+            Self::NoResponse(e) => write!(f, "no response: {e}"),
+            Self::Response(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// The output the application wrote for the request.
+struct Output {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+/// The middleware that passes the requests for scripts to a FastCGI
+/// application, and responds with the output of the application. Which files
+/// are scripts is configured the same way as for the
+/// [`CgiMiddleware`](crate::CgiMiddleware), but the interpreters are ignored,
+/// since the application runs the scripts.
+///
+/// The connections to the application are kept open and reused for later
+/// requests, which avoids the costs of starting a process per request.
+#[derive(Clone, Debug)]
+pub struct FastCgiMiddleware {
+    /// The address of the application.
+    address: FastCgiAddress,
+
+    /// The resolved document root, see
+    /// [`servente_http_handling::config::resolve_document_root`].
+    document_root: PathBuf,
+
+    /// Which files are scripts, the others are left to the static handler.
+    scripts: CgiScripts,
+
+    /// The maximum size of the output of the application.
+    max_response_size: usize,
+
+    pool: ConnectionPool,
+}
+
+impl FastCgiMiddleware {
+    /// Creates a new instance of FastCgiMiddleware, which passes the requests
+    /// for the `scripts` in the `document_root` to the application at the
+    /// `address`.
+    pub fn new(address: FastCgiAddress, document_root: PathBuf, scripts: CgiScripts) -> Self {
+        Self {
+            address,
+            document_root,
+            scripts,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            pool: ConnectionPool::default(),
+        }
+    }
+
+    /// The maximum size of the output the application writes for a request,
+    /// i.e. of the headers and the body together. The output is kept in
+    /// memory, so larger responses are answered with `502 Bad Gateway`
+    /// instead.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// The entrypoint for the FastCGI middleware.
+    async fn invoke_fastcgi(&self, state: &mut ExchangeState<'_>) -> Result<(), anyhow::Error> {
+        let RequestTarget::Origin { path, .. } = &state.request.target else {
+            return Ok(());
+        };
+
+        let Some(script) = ScriptPath::find(&self.document_root, path).filter(|script| self.scripts.matches(script)) else {
+            return Ok(());
+        };
+
+        let params = encode_params(state.request, state.connection, &script);
+        let result = match tokio::time::timeout(TIMEOUT, self.exchange(&params, state.request)).await {
+            Ok(result) => result,
+            Err(_) => {
+                state.response = Response::with_status_and_string_body(StatusCode::GatewayTimeout, "Gateway Timeout");
+                return Ok(());
+            }
+        };
+
+        match result {
+            Ok(output) => {
+                if cfg!(debug_assertions) && !output.stderr.is_empty() {
+                    println!("[FastCGI] {}", String::from_utf8_lossy(&output.stderr).trim_end());
+                }
+
+                produce_response_for_cgi_output(state, &output.stdout).await
+            }
+            Err(e) => {
+                state.response = if cfg!(debug_assertions) {
+                    Response::with_status_and_string_body(StatusCode::BadGateway, format!("Error: {e}"))
+                } else {
+                    Response::with_status_and_string_body(StatusCode::BadGateway, "Bad Gateway")
+                };
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends the request over an idle connection, or over a new connection
+    /// when there is none, or when the idle connection turns out to be
+    /// closed.
+    ///
+    /// The request is only sent again when it couldn't be written, or when
+    /// its method is idempotent, since the application might have handled
+    /// it when it closed the connection without responding.
+    async fn exchange(&self, params: &[u8], request: &Request) -> Result<Output, ExchangeError> {
+        if let Some(mut connection) = self.pool.take() {
+            match exchange_on_connection(&mut connection, params, request, self.max_response_size).await {
+                Ok(output) => {
+                    self.pool.put(connection);
+                    return Ok(output);
+                }
+                Err(ExchangeError::NotSent(..)) => (),
+                Err(ExchangeError::NoResponse(..)) if request.method.is_idempotent() => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut connection = self.address.connect().await.map_err(ExchangeError::NotSent)?;
+        let output = exchange_on_connection(&mut connection, params, request, self.max_response_size).await?;
+        self.pool.put(connection);
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl Middleware for FastCgiMiddleware {
+    fn debug_identifier(&self) -> &str {
+        "servente_cgi::FastCgiMiddleware"
+    }
+
+    async fn invoke(&mut self, state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+        self.invoke_fastcgi(state).await?;
+        Ok(())
+    }
+}
+
+async fn exchange_on_connection(connection: &mut Connection, params: &[u8], request: &Request, max_response_size: usize) -> Result<Output, ExchangeError> {
+    write_request(connection, params, request).await.map_err(ExchangeError::NotSent)?;
+    read_response(connection, max_response_size).await
+}
+
+/// Writes the `FCGI_BEGIN_REQUEST`, the `FCGI_PARAMS` stream and the
+/// `FCGI_STDIN` stream containing the body.
+async fn write_request(connection: &mut Connection, params: &[u8], request: &Request) -> std::io::Result<()> {
+    let mut buffer = Vec::with_capacity(params.len() + 64);
+
+    let mut begin_request = [0u8; 8];
+    begin_request[0..2].copy_from_slice(&ROLE_RESPONDER.to_be_bytes());
+    begin_request[2] = FLAG_KEEP_CONN;
+    write_record(&mut buffer, record_type::BEGIN_REQUEST, &begin_request);
+
+    write_stream(&mut buffer, record_type::PARAMS, params);
+
+    match &request.body {
+        Some(BodyKind::Bytes(data)) => write_stream(&mut buffer, record_type::STDIN, data),
+        Some(BodyKind::String(data)) => write_stream(&mut buffer, record_type::STDIN, data.as_bytes()),
+
+        // The body was spilled to a temporary file, which is streamed instead
+        // of read in memory.
+        Some(BodyKind::File { handle, .. }) => {
+            connection.write_all(&buffer).await?;
+            buffer.clear();
+
+            let mut file = handle.try_clone().await?;
+            file.rewind().await?;
+
+            let mut chunk = vec![0; MAX_CONTENT_LENGTH];
+            loop {
+                let length = file.read(&mut chunk).await?;
+                if length == 0 {
+                    break;
+                }
+
+                write_record(&mut buffer, record_type::STDIN, &chunk[..length]);
+                connection.write_all(&buffer).await?;
+                buffer.clear();
+            }
+
+            write_record(&mut buffer, record_type::STDIN, &[]);
+        }
+
+        _ => write_record(&mut buffer, record_type::STDIN, &[]),
+    }
+
+    connection.write_all(&buffer).await?;
+    connection.flush().await
+}
+
+/// Reads the `FCGI_STDOUT` and `FCGI_STDERR` streams, until the
+/// `FCGI_END_REQUEST` record. Fails when these streams are larger than the
+/// `max_response_size` together.
+async fn read_response(connection: &mut Connection, max_response_size: usize) -> Result<Output, ExchangeError> {
+    let mut output = Output {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    };
+
+    let mut responded = false;
+    loop {
+        let mut header = [0u8; 8];
+        if let Err(e) = connection.read_exact(&mut header).await {
+            return Err(if responded { ExchangeError::Response(e) } else { ExchangeError::NoResponse(e) });
+        }
+        responded = true;
+
+        let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let padding_length = header[6] as usize;
+        let mut content = vec![0; content_length + padding_length];
+        connection.read_exact(&mut content).await?;
+        content.truncate(content_length);
+
+        // Management records, which aren't sent by this implementation.
+        if u16::from_be_bytes([header[2], header[3]]) != REQUEST_ID {
+            continue;
+        }
+
+        let is_output = matches!(header[1], record_type::STDOUT | record_type::STDERR);
+        if is_output && output.stdout.len() + output.stderr.len() + content.len() > max_response_size {
+            return Err(ExchangeError::Response(std::io::Error::other(
+                format!("response exceeds the maximum size of {max_response_size} bytes"))));
+        }
+
+        match header[1] {
+            record_type::STDOUT => output.stdout.extend_from_slice(&content),
+            record_type::STDERR => output.stderr.extend_from_slice(&content),
+            record_type::END_REQUEST => {
+                let protocol_status = content.get(4).copied();
+                if protocol_status != Some(REQUEST_COMPLETE) {
+                    return Err(ExchangeError::Response(std::io::Error::other(
+                        format!("request rejected with protocol status {protocol_status:?}"))));
+                }
+                return Ok(output);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Encodes the CGI meta-variables, and the `SCRIPT_FILENAME` applications
+/// like PHP-FPM use to find the script, as name-value pairs.
+fn encode_params(request: &Request, connection: &ConnectionInfo, script: &ScriptPath) -> Vec<u8> {
+    let mut params = Vec::new();
+    encode_name_value_pair(&mut params, b"SCRIPT_FILENAME", script.script_path.as_os_str().as_encoded_bytes());
+    for (name, value) in meta_variables(request, connection, script) {
+        encode_name_value_pair(&mut params, name.as_bytes(), value.as_encoded_bytes());
+    }
+    params
+}
+
+/// # References
+/// * [FastCGI Specification Section 3.4](https://fastcgi-archives.github.io/FastCGI_Specification.html#S3.4)
+fn encode_name_value_pair(buffer: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    encode_length(buffer, name.len());
+    encode_length(buffer, value.len());
+    buffer.extend_from_slice(name);
+    buffer.extend_from_slice(value);
+}
+
+/// Lengths below 128 are encoded in a single byte, others in four bytes of
+/// which the highest bit is set.
+fn encode_length(buffer: &mut Vec<u8>, length: usize) {
+    if length < 0x80 {
+        buffer.push(length as u8);
+    } else {
+        buffer.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// # References
+/// * [FastCGI Specification Section 3.3](https://fastcgi-archives.github.io/FastCGI_Specification.html#S3.3)
+fn write_record(buffer: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+    debug_assert!(content.len() <= MAX_CONTENT_LENGTH);
+    buffer.extend_from_slice(&[VERSION, record_type]);
+    buffer.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    buffer.extend_from_slice(&(content.len() as u16).to_be_bytes());
+
+    // No padding, and the reserved byte.
+    buffer.extend_from_slice(&[0, 0]);
+    buffer.extend_from_slice(content);
+}
+
+/// Writes the content as a stream of records, which is terminated by an
+/// empty record.
+fn write_stream(buffer: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+    for chunk in content.chunks(MAX_CONTENT_LENGTH) {
+        write_record(buffer, record_type, chunk);
+    }
+    write_record(buffer, record_type, &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use servente_http::{HeaderMap, HeaderName, HttpVersion, Method};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn read_record(stream: &mut tokio::net::TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut content = vec![0; u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize];
+        stream.read_exact(&mut content).await.unwrap();
+        content.truncate(u16::from_be_bytes([header[4], header[5]]) as usize);
+        (header[1], content)
+    }
+
+    fn decode_length(params: &mut &[u8]) -> usize {
+        if params[0] < 0x80 {
+            let length = params[0] as usize;
+            *params = &params[1..];
+            return length;
+        }
+        let length = u32::from_be_bytes([params[0] & 0x7F, params[1], params[2], params[3]]) as usize;
+        *params = &params[4..];
+        length
+    }
+
+    fn decode_params(mut params: &[u8]) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        while !params.is_empty() {
+            let name_length = decode_length(&mut params);
+            let value_length = decode_length(&mut params);
+            let name = String::from_utf8(params[..name_length].to_vec()).unwrap();
+            let value = String::from_utf8(params[name_length..name_length + value_length].to_vec()).unwrap();
+            params = &params[name_length + value_length..];
+            pairs.push((name, value));
+        }
+        pairs
+    }
+
+    /// Reads a request, and returns its params and its body.
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> (Vec<u8>, Vec<u8>) {
+        let (record_type, begin_request) = read_record(stream).await;
+        assert_eq!(record_type, record_type::BEGIN_REQUEST);
+        assert_eq!(begin_request, [0, 1, FLAG_KEEP_CONN, 0, 0, 0, 0, 0]);
+
+        let mut params = Vec::new();
+        loop {
+            let (record_type, content) = read_record(stream).await;
+            assert_eq!(record_type, record_type::PARAMS);
+            if content.is_empty() {
+                break;
+            }
+            params.extend(content);
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let (record_type, content) = read_record(stream).await;
+            assert_eq!(record_type, record_type::STDIN);
+            if content.is_empty() {
+                break;
+            }
+            body.extend(content);
+        }
+
+        (params, body)
+    }
+
+    /// Responds to a request, echoing the body.
+    async fn write_response(stream: &mut tokio::net::TcpStream, body: Vec<u8>) {
+        let mut response = Vec::new();
+        write_record(&mut response, record_type::STDERR, b"warning");
+        let output = format!("Status: 201 Created\r\nContent-Type: text/plain\r\n\r\nreceived {}", String::from_utf8(body).unwrap());
+        write_stream(&mut response, record_type::STDOUT, output.as_bytes());
+        write_record(&mut response, record_type::END_REQUEST, &[0, 0, 0, 0, REQUEST_COMPLETE, 0, 0, 0]);
+        stream.write_all(&response).await.unwrap();
+    }
+
+    /// Serves the requests on a single connection, echoing the body.
+    async fn serve_application(listener: TcpListener, requests: usize) -> Vec<Vec<(String, String)>> {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        for _ in 0..requests {
+            let (params, body) = read_request(&mut stream).await;
+            write_response(&mut stream, body).await;
+            received.push(decode_params(&params));
+        }
+        received
+    }
+
+    fn create_request(method: Method, body: &str) -> Request {
+        Request {
+            method,
+            target: RequestTarget::parse("/index.php").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: Some(BodyKind::Bytes(body.as_bytes().to_vec())),
+            raw_body: None,
+        }
+    }
+
+    async fn invoke(middleware: &FastCgiMiddleware, request: &Request) -> Response {
+        let connection = ConnectionInfo::new(servente_http_handling::Scheme::Http);
+        let mut state = ExchangeState {
+            id: Default::default(),
+            request,
+            connection: &connection,
+            response: Response::with_status(StatusCode::Ok),
+            started: std::time::Instant::now(),
+            internal_redirect: None,
+        };
+        middleware.invoke_fastcgi(&mut state).await.unwrap();
+        state.response
+    }
+
+    #[test]
+    fn test_encode_name_value_pair() {
+        let mut buffer = Vec::new();
+        encode_name_value_pair(&mut buffer, b"REQUEST_METHOD", b"GET");
+        assert_eq!(buffer, b"\x0E\x03REQUEST_METHODGET");
+
+        let value = vec![b'a'; 200];
+        let mut buffer = Vec::new();
+        encode_name_value_pair(&mut buffer, b"QUERY_STRING", &value);
+        assert_eq!(&buffer[..5], b"\x0C\x80\x00\x00\xC8");
+        assert_eq!(buffer.len(), 5 + 12 + 200);
+    }
+
+    #[tokio::test]
+    async fn test_requests_reuse_the_connection() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.php"), "").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = FastCgiAddress::Tcp(listener.local_addr().unwrap());
+        let application = tokio::spawn(serve_application(listener, 2));
+
+        let middleware = FastCgiMiddleware::new(address, root.path().to_path_buf(), CgiScripts::new().with_extensions(&["php"]));
+        let connection = ConnectionInfo::new(servente_http_handling::Scheme::Http);
+        for body in ["first", "second"] {
+            let request = Request {
+                method: Method::Post,
+                target: RequestTarget::parse("/index.php/extra?page=2").unwrap(),
+                version: HttpVersion::Http11,
+                headers: HeaderMap::new(),
+                body: Some(BodyKind::Bytes(body.as_bytes().to_vec())),
+                raw_body: None,
+            };
+            let mut state = ExchangeState {
+                id: Default::default(),
+                request: &request,
+                connection: &connection,
+                response: Response::with_status(StatusCode::Ok),
+                started: std::time::Instant::now(),
+                internal_redirect: None,
+            };
+
+            middleware.invoke_fastcgi(&mut state).await.unwrap();
+            assert_eq!(state.response.status, StatusCode::Created);
+            assert!(state.response.headers.contains(&HeaderName::ContentType));
+            assert!(matches!(&state.response.body, Some(BodyKind::Bytes(data)) if *data == format!("received {body}").as_bytes()));
+        }
+
+        let received = application.await.unwrap();
+        let param = |name: &str| received[0].iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        assert_eq!(param("SCRIPT_FILENAME"), root.path().join("index.php").to_str());
+        assert_eq!(param("SCRIPT_NAME"), Some("/index.php"));
+        assert_eq!(param("PATH_INFO"), Some("/extra"));
+        assert_eq!(param("QUERY_STRING"), Some("page=2"));
+        assert_eq!(param("REQUEST_METHOD"), Some("POST"));
+        assert_eq!(param("CONTENT_LENGTH"), Some("5"));
+    }
+
+    #[tokio::test]
+    async fn test_other_files_are_left_alone() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("style.css"), "").unwrap();
+
+        // Nothing listens on this address, which isn't contacted anyway.
+        let address = FastCgiAddress::Tcp("127.0.0.1:9".parse().unwrap());
+        let middleware = FastCgiMiddleware::new(address, root.path().to_path_buf(), CgiScripts::new().with_extensions(&["php"]));
+        let request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/style.css").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        let connection = ConnectionInfo::new(servente_http_handling::Scheme::Http);
+        let mut state = ExchangeState {
+            id: Default::default(),
+            request: &request,
+            connection: &connection,
+            response: Response::with_status(StatusCode::NoContent),
+            started: std::time::Instant::now(),
+            internal_redirect: None,
+        };
+
+        middleware.invoke_fastcgi(&mut state).await.unwrap();
+        assert_eq!(state.response.status, StatusCode::NoContent);
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_response_are_only_retried_when_idempotent() {
+        for (method, retried) in [(Method::Get, true), (Method::Post, false)] {
+            let root = tempfile::tempdir().unwrap();
+            std::fs::write(root.path().join("index.php"), "").unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = FastCgiAddress::Tcp(listener.local_addr().unwrap());
+
+            // The application closes the connection after reading the second
+            // request, without responding.
+            let application = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (_, body) = read_request(&mut stream).await;
+                write_response(&mut stream, body).await;
+                read_request(&mut stream).await;
+                drop(stream);
+
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (_, body) = read_request(&mut stream).await;
+                write_response(&mut stream, body).await;
+            });
+
+            let middleware = FastCgiMiddleware::new(address, root.path().to_path_buf(), CgiScripts::new().with_extensions(&["php"]));
+            let response = invoke(&middleware, &create_request(method.clone(), "first")).await;
+            assert_eq!(response.status, StatusCode::Created);
+
+            let response = invoke(&middleware, &create_request(method.clone(), "second")).await;
+            if retried {
+                assert_eq!(response.status, StatusCode::Created, "{method:?}");
+                application.await.unwrap();
+            } else {
+                assert_eq!(response.status, StatusCode::BadGateway, "{method:?}");
+                application.abort();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_too_large_responses_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.php"), "").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = FastCgiAddress::Tcp(listener.local_addr().unwrap());
+        let application = tokio::spawn(serve_application(listener, 2));
+
+        // The echoed body makes the output larger than the maximum.
+        let middleware = FastCgiMiddleware::new(address, root.path().to_path_buf(), CgiScripts::new().with_extensions(&["php"]))
+            .with_max_response_size(100);
+        let response = invoke(&middleware, &create_request(Method::Post, "small")).await;
+        assert_eq!(response.status, StatusCode::Created);
+
+        let response = invoke(&middleware, &create_request(Method::Post, &"large".repeat(20))).await;
+        assert_eq!(response.status, StatusCode::BadGateway);
+
+        application.abort();
+    }
+}
@@ -8,7 +8,7 @@
 //! * Robinson, D. and K. Coar, "The Common Gateway Interface (CGI) Version 1.1",
 //!   RFC 3875, DOI 10.17487/RFC3875, October 2004, <https://www.rfc-editor.org/info/rfc3875>.
 
-use std::{time::Duration, env::current_dir};
+use std::{collections::HashMap, ffi::OsString, path::{Path, PathBuf}, time::Duration};
 
 use async_trait::async_trait;
 
@@ -21,78 +21,259 @@ use servente_http::{
 };
 
 use servente_http_handling::{
+    config::LineEndingHandling,
     middleware::{
         ExchangeState,
         MiddlewareError,
     },
+    ConnectionInfo,
     Middleware,
 };
 
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
-#[derive(Copy, Clone, Debug)]
+pub mod fastcgi;
+
+pub use fastcgi::{FastCgiAddress, FastCgiMiddleware};
+
+#[derive(Clone, Debug)]
 /// The middleware that supports interacting with CGI scripts by handling
 /// requests that depend on CGI behavior.
 ///
 /// It implements the [`Middleware`] trait, which detects CGI invocations and
 /// invokes those commands instead of returning the contents of the script.
 pub struct CgiMiddleware {
+    /// The resolved document root, see
+    /// [`servente_http_handling::config::resolve_document_root`].
+    document_root: PathBuf,
+
+    /// Which files are scripts, the others are left to the static handler.
+    scripts: CgiScripts,
+}
+
+/// Which files in the document root are CGI scripts, i.e. those with one of
+/// the extensions, or that are inside one of the directories, and how they
+/// are run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CgiScripts {
+    /// The lowercase extensions, without the dot, e.g. `cgi`.
+    extensions: Vec<String>,
+
+    /// The request path prefixes of the directories, e.g. `/cgi-bin/`.
+    directories: Vec<String>,
+
+    /// The interpreters by the lowercase extension of the scripts they run,
+    /// e.g. `py` to `python3`. Scripts with other extensions are executed
+    /// directly, which requires them to be executable.
+    interpreters: HashMap<String, PathBuf>,
+}
+
+impl CgiScripts {
+    /// No file is a script, until extensions or directories are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Files with these extensions are scripts, e.g. `&["cgi", "pl", "py"]`.
+    /// The leading dot is optional.
+    pub fn with_extensions(mut self, extensions: &[&str]) -> Self {
+        self.extensions.extend(extensions.iter().map(|extension| extension.trim_start_matches('.').to_ascii_lowercase()));
+        self
+    }
+
+    /// Every file inside the directory of this request path is a script, e.g.
+    /// `/cgi-bin/`.
+    pub fn with_directory(mut self, directory: &str) -> Self {
+        let mut directory = directory.trim_end_matches('/').to_string();
+        directory.push('/');
+        self.directories.push(directory);
+        self
+    }
+
+    /// Files with this extension are scripts, which are run by passing them
+    /// to the `interpreter`, e.g. `with_interpreter("py", "python3")`. This
+    /// also works for scripts that aren't executable or lack a shebang.
+    pub fn with_interpreter(mut self, extension: &str, interpreter: impl Into<PathBuf>) -> Self {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.interpreters.insert(extension.clone(), interpreter.into());
+        self.extensions.push(extension);
+        self
+    }
+
+    fn matches(&self, script: &ScriptPath) -> bool {
+        if self.directories.iter().any(|directory| script.script_name.starts_with(directory.as_str())) {
+            return true;
+        }
+
+        script_extension(script)
+            .is_some_and(|extension| self.extensions.iter().any(|candidate| candidate.eq_ignore_ascii_case(extension)))
+    }
+
+    /// The interpreter configured for the extension of the script, if any.
+    fn interpreter(&self, script: &ScriptPath) -> Option<&Path> {
+        let extension = script_extension(script)?.to_ascii_lowercase();
+        self.interpreters.get(&extension).map(PathBuf::as_path)
+    }
+}
+
+fn script_extension(script: &ScriptPath) -> Option<&str> {
+    script.script_path.extension()?.to_str()
+}
+
+/// The request path split at the script, e.g. `/cgi-bin/search.cgi/images`
+/// into the `/cgi-bin/search.cgi` script and the `/images` extra path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScriptPath {
+    /// The script on disk.
+    script_path: PathBuf,
+
+    /// The part of the request path identifying the script.
+    ///
+    /// # References
+    /// * [RFC 3875 Section 4.1.13](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1.13)
+    script_name: String,
+
+    /// The URL-decoded rest of the request path, which is empty when the
+    /// path ends at the script.
+    ///
+    /// # References
+    /// * [RFC 3875 Section 4.1.5](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1.5)
+    path_info: String,
+
+    /// The `path_info` mapped to the document root, if it isn't empty.
+    ///
+    /// # References
+    /// * [RFC 3875 Section 4.1.6](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1.6)
+    path_translated: Option<PathBuf>,
+}
+
+impl ScriptPath {
+    /// Finds the script the request path refers to, which is the first file
+    /// when walking the segments from the document root.
+    fn find(document_root: &Path, path: &str) -> Option<Self> {
+        let boundaries = path.match_indices('/')
+            .map(|(index, _)| index)
+            .skip(1)
+            .chain(std::iter::once(path.len()));
+
+        for boundary in boundaries {
+            let script_name = &path[..boundary];
+            let script_path = servente_http_handling::find_request_path_in_wwwroot(document_root, script_name).ok()?;
+            if script_path.is_dir() {
+                continue;
+            }
+
+            if !script_path.is_file() {
+                return None;
+            }
+
+            let extra_path = &path[boundary..];
+            let path_translated = if extra_path.is_empty() {
+                None
+            } else {
+                servente_http_handling::find_request_path_in_wwwroot(document_root, extra_path).ok()
+            };
+
+            return Some(Self {
+                script_path,
+                script_name: script_name.to_string(),
+                path_info: urlencoding::decode(extra_path).ok()?.into_owned(),
+                path_translated,
+            });
+        }
+
+        None
+    }
 }
 
-fn set_command_environment_variables(request: &Request, command: &mut tokio::process::Command) {
-    command.env("GATEWAY_INTERFACE", "CGI/1.1")
-        .env("REQUEST_METHOD", request.method.as_string())
-        .env("SERVER_NAME", "localhost")
-        .env("SERVER_PORT", "8080")
-        .env("SERVER_PROTOCOL", request.version.to_http_version())
-        .env("SERVER_SOFTWARE", "Servente");
+/// The meta-variables describing the request to the script, which are passed
+/// as environment variables to CGI scripts and as parameters to FastCGI
+/// applications.
+///
+/// # References
+/// * [RFC 3875 Section 4.1](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1)
+fn meta_variables(request: &Request, connection: &ConnectionInfo, script: &ScriptPath) -> Vec<(&'static str, OsString)> {
+    let mut variables: Vec<(&'static str, OsString)> = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".into()),
+        ("REQUEST_METHOD", request.method.as_string().into()),
+        ("SERVER_NAME", "localhost".into()),
+        ("SERVER_PORT", "8080".into()),
+        ("SERVER_PROTOCOL", request.version.to_http_version().into()),
+        ("SERVER_SOFTWARE", "Servente".into()),
+    ];
+
+    if let RequestTarget::Origin { query, .. } = &request.target {
+        variables.push(("QUERY_STRING", query.into()));
+    }
 
-    if let RequestTarget::Origin { path, query } = &request.target {
-        command.env("QUERY_STRING", query)
-            .env("PATH_INFO", path);
+    variables.push(("SCRIPT_NAME", script.script_name.clone().into()));
+    variables.push(("PATH_INFO", script.path_info.clone().into()));
+    if let Some(path_translated) = &script.path_translated {
+        variables.push(("PATH_TRANSLATED", path_translated.into()));
     }
 
-    // TODO set [REMOTE_ADDR](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1.8)
+    // [REMOTE_ADDR](https://www.rfc-editor.org/rfc/rfc3875.html#section-4.1.8)
+    // and the common REMOTE_PORT extension.
+    if let Some(peer_address) = connection.peer_address {
+        variables.push(("REMOTE_ADDR", peer_address.ip().to_string().into()));
+        variables.push(("REMOTE_PORT", peer_address.port().to_string().into()));
+    }
 
     if let Some(body) = &request.body {
         match body {
-            servente_http::BodyKind::Bytes(data) => _ = command.env("CONTENT_LENGTH", format!("{}", data.len())),
-            servente_http::BodyKind::String(data) => _ = command.env("CONTENT_LENGTH", format!("{}", data.len())),
+            servente_http::BodyKind::Bytes(data) => variables.push(("CONTENT_LENGTH", data.len().to_string().into())),
+            servente_http::BodyKind::String(data) => variables.push(("CONTENT_LENGTH", data.len().to_string().into())),
+            servente_http::BodyKind::File { metadata, .. } => variables.push(("CONTENT_LENGTH", metadata.len().to_string().into())),
             _ => ()
         }
     }
 
     for (header_name, header_value) in request.headers.iter() {
         match header_name {
-            HeaderName::ContentType => _ = command.env("CONTENT_TYPE", header_value.as_str_may_convert().as_ref()),
+            HeaderName::ContentType => variables.push(("CONTENT_TYPE", header_value.as_str_may_convert().as_ref().into())),
             _ => (),
         }
     }
+
+    variables
+}
+
+fn set_command_environment_variables(request: &Request, connection: &ConnectionInfo, script: &ScriptPath, command: &mut tokio::process::Command) {
+    command.envs(meta_variables(request, connection, script));
 }
 
 impl CgiMiddleware {
-    /// Creates a new instance of CgiMiddleware.
-    pub fn new() -> Self {
+    /// Creates a new instance of CgiMiddleware, which runs the `scripts` in
+    /// the `document_root`.
+    pub fn new(document_root: PathBuf, scripts: CgiScripts) -> Self {
         Self {
+            document_root,
+            scripts,
         }
     }
 
     /// Creates a [`tokio::process::Command`] which can be used to `spawn` the
     /// script, with the correct environment already defined.
-    fn create_cgi_script_command(&self, request: &Request) -> Option<tokio::process::Command> {
+    fn create_cgi_script_command(&self, request: &Request, connection: &ConnectionInfo) -> Option<tokio::process::Command> {
         let RequestTarget::Origin { path, .. } = &request.target else {
             return None;
         };
 
-        let wwwroot = current_dir().ok()?.join("wwwroot");
-        let script_path = match servente_http_handling::find_request_path_in_wwwroot(&wwwroot, &path) {
-            Ok(path) => path,
-            Err(_) => return None,
-        };
-
+        let script = ScriptPath::find(&self.document_root, path)
+            .filter(|script| self.scripts.matches(script))?;
+        let script_path = &script.script_path;
         let script_file_name = script_path.file_name()?;
 
-        let mut command = tokio::process::Command::new(format!("./{}", script_file_name.to_string_lossy()));
+        // The working directory is the directory of the script.
+        let relative_script_path = Path::new(".").join(script_file_name);
+        let mut command = match self.scripts.interpreter(&script) {
+            Some(interpreter) => {
+                let mut command = tokio::process::Command::new(interpreter);
+                command.arg(relative_script_path);
+                command
+            }
+            None => tokio::process::Command::new(relative_script_path),
+        };
 
         if let Some(parent_dir) = script_path.parent() {
             if let Some(parent_dir) = parent_dir.canonicalize().ok() {
@@ -104,7 +285,7 @@ impl CgiMiddleware {
             return None;
         }
 
-        set_command_environment_variables(request, &mut command);
+        set_command_environment_variables(request, connection, &script, &mut command);
 
         command
             .stderr(std::process::Stdio::piped())
@@ -116,7 +297,7 @@ impl CgiMiddleware {
 
     /// The entrypoint for the CGI middleware.
     async fn invoke_cgi<'a>(&self, state: &mut ExchangeState<'a>) -> Result<(), anyhow::Error> {
-        let Some(mut command) = self.create_cgi_script_command(state.request) else {
+        let Some(mut command) = self.create_cgi_script_command(state.request, state.connection) else {
             return Ok(())
         };
 
@@ -143,6 +324,14 @@ impl CgiMiddleware {
         match body {
             servente_http::BodyKind::Bytes(data) => stdin.write_all_buf(&mut std::io::Cursor::new(data)).await,
             servente_http::BodyKind::String(data) => stdin.write_all_buf(&mut std::io::Cursor::new(data)).await,
+
+            // The body was spilled to a temporary file, which is streamed
+            // instead of read in memory.
+            servente_http::BodyKind::File { handle, .. } => {
+                let mut file = handle.try_clone().await?;
+                file.rewind().await?;
+                tokio::io::copy(&mut file, stdin).await.map(|_| ())
+            }
             _ => Ok(())
         }
     }
@@ -160,7 +349,7 @@ impl CgiMiddleware {
         };
 
         match result {
-            Ok(e) => return self.produce_response_for_cgi_output(state, &e.stdout).await,
+            Ok(e) => return produce_response_for_cgi_output(state, &e.stdout).await,
             Err(e) => {
                 state.response = if cfg!(debug_assertions) {
                     Response::with_status_and_string_body(StatusCode::InternalServerError, format!("Error: {e}"))
@@ -172,39 +361,92 @@ impl CgiMiddleware {
             }
         }
     }
+}
 
-    /// Processes the CGI process output, parsing it's content and producing a
-    /// response accordingly.
-    async fn produce_response_for_cgi_output<'a>(&self, state: &mut ExchangeState<'a>, stdout: &Vec<u8>) -> Result<(), anyhow::Error> {
-        state.response = Response::with_status(StatusCode::Ok);
-        let mut stdout_cursor = std::io::Cursor::new(&stdout);
-
-        // TODO newlines in CGI are different from newlines in HTTP/1, since
-        //      they *may* be platform-dependent. As an example, Windows uses
-        //      CRLF, macOS CR and Linux commonly LF.
-        let headers = servente_http1::read::read_headers(&mut stdout_cursor).await
-            .map_err(|e| anyhow::anyhow!(format!("{e:?}")))?;
-
-        for (header_name, header_value) in headers.into_iter() {
-            match header_name.class() {
-                servente_http::HeaderNameClass::CgiExtension => {
-                    // Ignore CGI-specific header fields.
-                    println!("[CGI] Ignoring extension field: \"{}\" => {:?}", header_name.to_string_h1(), header_value);
-                }
-                servente_http::HeaderNameClass::ConnectionSpecific => (),
-                servente_http::HeaderNameClass::Other => {
-                    state.response.headers.append_or_override(header_name, header_value);
-                }
-            }
+/// Processes the output of the CGI script, parsing it's content and producing a
+/// response accordingly.
+async fn produce_response_for_cgi_output(state: &mut ExchangeState<'_>, stdout: &[u8]) -> Result<(), anyhow::Error> {
+    state.response = Response::with_status(StatusCode::Ok);
+    let mut stdout_cursor = std::io::Cursor::new(&stdout);
+
+    // TODO newlines in CGI are different from newlines in HTTP/1, since
+    //      they *may* be platform-dependent. As an example, Windows uses
+    //      CRLF, macOS CR and Linux commonly LF. The lenient line endings
+    //      cover all but the bare CR.
+    let mut headers = servente_http1::read::read_headers(&mut stdout_cursor, LineEndingHandling::Lenient).await
+        .map_err(|e| anyhow::anyhow!(format!("{e:?}")))?;
+
+    let location = headers.get(&HeaderName::Location).map(|location| location.as_str_may_convert().into_owned());
+
+    // A local redirect asks the server to serve the `Location` instead, as if
+    // the client requested it.
+    if !headers.contains(&HeaderName::Status) {
+        if let Some(target) = location.as_deref().filter(|location| is_local_redirect(location)).and_then(RequestTarget::parse) {
+            state.redirect_internally(target);
+            return Ok(());
         }
+    }
 
-        if !state.response.headers.contains(&HeaderName::CacheControl) {
-            state.response.headers.append_or_override(HeaderName::CacheControl, "no-store".into());
+    state.response.status = match headers.get(&HeaderName::Status) {
+        Some(status) => match parse_status_field(&status.as_str_may_convert()) {
+            Some(status) => status,
+            None => {
+                state.response = Response::with_status_and_string_body(StatusCode::BadGateway, "Bad Gateway");
+                return Ok(());
+            }
+        },
+
+        // A client redirect.
+        None if location.is_some() => StatusCode::Found,
+        None => StatusCode::Ok,
+    };
+    headers.remove(&HeaderName::Status);
+
+    for (header_name, header_value) in headers.into_iter() {
+        match header_name.class() {
+            servente_http::HeaderNameClass::CgiExtension => {
+                // Ignore CGI-specific header fields.
+                println!("[CGI] Ignoring extension field: \"{}\" => {:?}", header_name.to_string_h1(), header_value);
+            }
+            servente_http::HeaderNameClass::ConnectionSpecific => (),
+            servente_http::HeaderNameClass::Other => {
+                _ = state.response.headers.append(header_name, header_value);
+            }
         }
+    }
 
-        state.response.body = Some(servente_http::BodyKind::Bytes(stdout[(stdout_cursor.position() as usize)..].into()));
-        Ok(())
+    if !state.response.headers.contains(&HeaderName::CacheControl) {
+        state.response.headers.append_or_override(HeaderName::CacheControl, "no-store".into());
+    }
+
+    state.response.body = Some(servente_http::BodyKind::Bytes(stdout[(stdout_cursor.position() as usize)..].into()));
+    Ok(())
+}
+
+/// Parses the `Status` field of the CGI response, e.g. `404 Not Found`. The
+/// reason phrase is ignored, and an unknown status code is treated as the
+/// `x00` code of its class, as clients would.
+///
+/// # References
+/// * [RFC 3875 Section 6.3.3](https://www.rfc-editor.org/rfc/rfc3875.html#section-6.3.3)
+/// * [RFC 9110 Section 15](https://www.rfc-editor.org/rfc/rfc9110.html#section-15-6)
+fn parse_status_field(value: &str) -> Option<StatusCode> {
+    let code = value.trim_start().split(' ').next()?;
+    if code.len() != 3 || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
     }
+
+    let code: u16 = code.parse().ok()?;
+    StatusCode::from_u16(code).or_else(|| StatusCode::from_u16(code / 100 * 100))
+}
+
+/// Whether the `Location` of the CGI response is a local redirect, i.e. an
+/// absolute path instead of an absolute URI.
+///
+/// # References
+/// * [RFC 3875 Section 6.2.2](https://www.rfc-editor.org/rfc/rfc3875.html#section-6.2.2)
+fn is_local_redirect(location: &str) -> bool {
+    location.starts_with('/') && !location.starts_with("//")
 }
 
 #[async_trait]
@@ -218,3 +460,161 @@ impl Middleware for CgiMiddleware {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_script_path() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("cgi-bin")).unwrap();
+        std::fs::write(root.path().join("cgi-bin/search.cgi"), "").unwrap();
+        std::fs::write(root.path().join("hello.cgi"), "").unwrap();
+
+        let script = ScriptPath::find(root.path(), "/cgi-bin/search.cgi/images/a%20b.png").unwrap();
+        assert_eq!(script, ScriptPath {
+            script_path: root.path().join("cgi-bin/search.cgi"),
+            script_name: "/cgi-bin/search.cgi".into(),
+            path_info: "/images/a b.png".into(),
+            path_translated: Some(root.path().join("images/a b.png")),
+        });
+
+        let script = ScriptPath::find(root.path(), "/hello.cgi").unwrap();
+        assert_eq!(script, ScriptPath {
+            script_path: root.path().join("hello.cgi"),
+            script_name: "/hello.cgi".into(),
+            path_info: String::new(),
+            path_translated: None,
+        });
+
+        assert_eq!(ScriptPath::find(root.path(), "/hello.cgi/").unwrap().path_info, "/");
+        assert_eq!(ScriptPath::find(root.path(), "/cgi-bin/"), None);
+        assert_eq!(ScriptPath::find(root.path(), "/cgi-bin/missing.cgi/extra"), None);
+        assert_eq!(ScriptPath::find(root.path(), "/"), None);
+    }
+
+    #[test]
+    fn cgi_scripts_matches() {
+        let scripts = CgiScripts::new()
+            .with_extensions(&[".cgi", "PL"])
+            .with_directory("/cgi-bin");
+        let script = |script_name: &str| ScriptPath {
+            script_path: PathBuf::from(format!("/var/www{script_name}")),
+            script_name: script_name.into(),
+            path_info: String::new(),
+            path_translated: None,
+        };
+
+        assert!(scripts.matches(&script("/hello.cgi")));
+        assert!(scripts.matches(&script("/perl/hello.pl")));
+        assert!(scripts.matches(&script("/perl/hello.PL")));
+        assert!(scripts.matches(&script("/cgi-bin/search")));
+        assert!(!scripts.matches(&script("/index.html")));
+        assert!(!scripts.matches(&script("/cgi-bin-old/search")));
+        assert!(!scripts.matches(&script("/cgi")));
+        assert!(!CgiScripts::new().matches(&script("/hello.cgi")));
+    }
+
+    #[test]
+    fn cgi_scripts_interpreters() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("hello.py"), "").unwrap();
+        std::fs::write(root.path().join("hello.cgi"), "").unwrap();
+        std::fs::write(root.path().join("hello.txt"), "").unwrap();
+
+        let scripts = CgiScripts::new()
+            .with_extensions(&["cgi"])
+            .with_interpreter(".PY", "/usr/bin/python3");
+        let middleware = CgiMiddleware::new(root.path().to_path_buf(), scripts);
+        let connection = ConnectionInfo::new(servente_http_handling::Scheme::Http);
+        let command = |target: &str| {
+            let request = Request {
+                method: servente_http::Method::Get,
+                target: RequestTarget::parse(target).unwrap(),
+                version: servente_http::HttpVersion::Http11,
+                headers: servente_http::HeaderMap::new(),
+                body: None,
+                raw_body: None,
+            };
+            let command = middleware.create_cgi_script_command(&request, &connection)?;
+            let command = command.as_std();
+            Some((command.get_program().to_owned(), command.get_args().map(ToOwned::to_owned).collect::<Vec<_>>()))
+        };
+
+        assert_eq!(command("/hello.py"), Some(("/usr/bin/python3".into(), vec!["./hello.py".into()])));
+        assert_eq!(command("/hello.cgi"), Some(("./hello.cgi".into(), vec![])));
+        assert_eq!(command("/hello.txt"), None);
+    }
+
+    #[test]
+    fn test_parse_status_field() {
+        assert_eq!(parse_status_field("404 Not Found"), Some(StatusCode::NotFound));
+        assert_eq!(parse_status_field("201"), Some(StatusCode::Created));
+        assert_eq!(parse_status_field("299 Custom Success"), Some(StatusCode::Ok));
+        assert_eq!(parse_status_field("Not Found"), None);
+        assert_eq!(parse_status_field("4040 Not Found"), None);
+        assert_eq!(parse_status_field("999 Unknown"), None);
+    }
+
+    async fn produce_response(output: &str) -> Response {
+        produce_response_and_redirect(output).await.0
+    }
+
+    async fn produce_response_and_redirect(output: &str) -> (Response, Option<RequestTarget>) {
+        let request = Request {
+            method: servente_http::Method::Get,
+            target: RequestTarget::parse("/script.cgi").unwrap(),
+            version: servente_http::HttpVersion::Http11,
+            headers: servente_http::HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        let connection = ConnectionInfo::new(servente_http_handling::Scheme::Http);
+        let mut state = ExchangeState {
+            id: Default::default(),
+            request: &request,
+            connection: &connection,
+            response: Response::with_status(StatusCode::Ok),
+            started: std::time::Instant::now(),
+            internal_redirect: None,
+        };
+
+        produce_response_for_cgi_output(&mut state, output.as_bytes()).await.unwrap();
+        (state.response, state.internal_redirect)
+    }
+
+    #[tokio::test]
+    async fn test_status_field() {
+        let response = produce_response("Status: 404 Not Found\nContent-Type: text/plain\n\nmissing").await;
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert!(!response.headers.contains(&HeaderName::Status));
+        assert!(response.headers.contains(&HeaderName::ContentType));
+
+        let response = produce_response("Content-Type: text/plain\n\nhello").await;
+        assert_eq!(response.status, StatusCode::Ok);
+
+        let response = produce_response("Status: broken\n\n").await;
+        assert_eq!(response.status, StatusCode::BadGateway);
+    }
+
+    #[tokio::test]
+    async fn test_location_field() {
+        let response = produce_response("Location: https://example.com/\n\n").await;
+        assert_eq!(response.status, StatusCode::Found);
+
+        let (_, redirect) = produce_response_and_redirect("Location: /other.html?page=2\n\n").await;
+        assert_eq!(redirect, RequestTarget::parse("/other.html?page=2"));
+
+        let (response, redirect) = produce_response_and_redirect("Location: https://example.com/\n\n").await;
+        assert_eq!(redirect, None);
+        assert_eq!(response.headers.get(&HeaderName::Location).unwrap().as_str_may_convert(), "https://example.com/");
+
+        let response = produce_response("Status: 301 Moved Permanently\nLocation: /moved.html\n\n").await;
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+
+        assert!(is_local_redirect("/other.html"));
+        assert!(!is_local_redirect("//example.com/"));
+        assert!(!is_local_redirect("https://example.com/"));
+    }
+}
@@ -1,4 +1,9 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+pub mod net;
 pub mod platform;
+pub mod security;
+
+pub use net::connect;
+pub use security::constant_time_eq;
@@ -0,0 +1,179 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Outgoing connections, e.g. to upstream servers or the hosts under test.
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    net::TcpStream,
+    task::JoinSet,
+};
+
+/// The time to wait for a connection attempt before starting the next one in
+/// parallel.
+///
+/// # References
+/// * [RFC 8305 Section 5](https://www.rfc-editor.org/rfc/rfc8305.html#section-5)
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolves a host to the addresses (A/AAAA records) it can be reached on.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// The resolver of the operating system, using `getaddrinfo(3)` and friends.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(tokio::net::lookup_host((host, port)).await?.collect())
+    }
+}
+
+/// Connect to the given host using the [`SystemResolver`]. See
+/// [`connect_with_resolver`].
+pub async fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    connect_with_resolver(&SystemResolver, host, port, timeout).await
+}
+
+/// Resolve the host and connect to it using the Happy Eyeballs strategy, i.e.
+/// IPv6 and IPv4 are raced against each other, where every next attempt is
+/// started when the previous failed or didn't succeed within the
+/// [`CONNECTION_ATTEMPT_DELAY`]. The first connection established wins.
+///
+/// The `timeout` applies to the resolution and all attempts combined.
+///
+/// # References
+/// * [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305.html)
+pub async fn connect_with_resolver(resolver: &dyn Resolver, host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let future = async {
+        let addresses = resolver.resolve(host, port).await?;
+        if addresses.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no addresses found for host: {host}")));
+        }
+
+        race_connection_attempts(sort_addresses(addresses)).await
+    };
+
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, format!("connecting to {host}:{port} timed out"))),
+    }
+}
+
+/// Interleave the address families, starting with IPv6.
+///
+/// # References
+/// * [RFC 8305 Section 4](https://www.rfc-editor.org/rfc/rfc8305.html#section-4)
+fn sort_addresses(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+
+    let mut sorted = Vec::with_capacity(ipv6.len() + ipv4.len());
+    let mut ipv6 = ipv6.into_iter();
+    let mut ipv4 = ipv4.into_iter();
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    sorted
+}
+
+async fn race_connection_attempts(addresses: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut addresses = addresses.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+
+    loop {
+        if let Some(address) = addresses.next() {
+            attempts.spawn(TcpStream::connect(address));
+        } else if attempts.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            Some(result) = attempts.join_next() => match result {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(e) => last_error = Some(io::Error::other(e)),
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if addresses.len() != 0 => (),
+            else => break,
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use tokio::net::TcpListener;
+
+    struct StaticResolver(Vec<SocketAddr>);
+
+    #[async_trait]
+    impl Resolver for StaticResolver {
+        async fn resolve(&self, _host: &str, _port: u16) -> io::Result<Vec<SocketAddr>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_sort_addresses_interleaves_families() {
+        let v4 = |n| SocketAddr::from((Ipv4Addr::new(192, 0, 2, n), 80));
+        let v6 = |n| SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, n), 80));
+
+        assert_eq!(sort_addresses(vec![v4(1), v4(2), v4(3), v6(1), v6(2)]), [v6(1), v4(1), v6(2), v4(2), v4(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_dual_stack() {
+        let ipv4 = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = ipv4.local_addr().unwrap().port();
+
+        let mut addresses = vec![SocketAddr::from((Ipv4Addr::LOCALHOST, port))];
+        // IPv6 might not be available in this environment.
+        let ipv6 = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).await.ok();
+        if ipv6.is_some() {
+            addresses.push(SocketAddr::from((Ipv6Addr::LOCALHOST, port)));
+        }
+
+        let stream = connect_with_resolver(&StaticResolver(addresses.clone()), "localhost", port, Duration::from_secs(5)).await.unwrap();
+        assert!(addresses.contains(&stream.peer_addr().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_connect_falls_back_to_other_family() {
+        let closed_port = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).await
+            .or(TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await)
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let resolver = StaticResolver(vec![closed_port, address]);
+        let stream = connect_with_resolver(&resolver, "localhost", address.port(), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), address);
+    }
+
+    #[tokio::test]
+    async fn test_connect_no_addresses() {
+        let error = connect_with_resolver(&StaticResolver(Vec::new()), "localhost", 80, Duration::from_secs(5)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+/// Compare two byte strings in a time that only depends on their length, not
+/// on their contents, to avoid leaking secrets (passwords, bearer tokens)
+/// through timing side channels.
+///
+/// The length itself isn't considered secret, so inputs of different length
+/// are rejected immediately.
+#[must_use]
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let difference = a.iter()
+        .zip(b)
+        .fold(0u8, |difference, (a, b)| difference | (a ^ b));
+
+    // Prevent the compiler from short-circuiting the fold above.
+    std::hint::black_box(difference) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"hunter2", b"hunter2"));
+
+        assert!(!constant_time_eq(b"hunter2", b"hunter3"));
+        assert!(!constant_time_eq(b"hunter2", b"Hunter2"));
+        assert!(!constant_time_eq(b"hunter2", b"hunter"));
+        assert!(!constant_time_eq(b"", b"a"));
+    }
+}
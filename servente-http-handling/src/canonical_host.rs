@@ -0,0 +1,200 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Canonicalization of the host and scheme of requests, e.g. redirecting
+//! `www.example.com` to `example.com`, or `http` to `https`.
+
+use servente_http::{
+    HeaderName,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+use crate::connection::{ConnectionInfo, Scheme};
+
+/// The settings for the canonical-host redirects.
+#[derive(Clone, Debug)]
+pub struct CanonicalHostSettings {
+    /// The rules, which are applied in order. Every matching rule rewrites the
+    /// host and/or scheme, and the result of all rules combined is used for
+    /// the redirect, so the client only has to follow a single redirect.
+    pub rules: Vec<CanonicalHostRule>,
+
+    /// Keep the path and query of the request in the `Location`. If disabled,
+    /// clients are redirected to the root of the canonical host.
+    pub preserve_path_and_query: bool,
+}
+
+impl Default for CanonicalHostSettings {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            preserve_path_and_query: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CanonicalHostRule {
+    /// Redirect requests for the host `from` to the host `to`, e.g.
+    /// `www.example.com` → `example.com`. The host is compared
+    /// case-insensitively and the port (if any) is retained.
+    RedirectHost {
+        from: String,
+        to: String,
+    },
+
+    /// Redirect requests that were received over plaintext to `https`.
+    ForceHttps,
+}
+
+/// Check the request against the canonical-host rules, and produce a
+/// `301 Moved Permanently` response if the request isn't canonical.
+pub fn check_canonical_host(request: &Request, connection: &ConnectionInfo, settings: &CanonicalHostSettings) -> Option<Response> {
+    if settings.rules.is_empty() {
+        return None;
+    }
+
+    let authority = request.headers.get(&HeaderName::Host)?.as_str_no_convert()?;
+    let (host, port) = match authority.rsplit_once(':') {
+        // An IPv6 literal without a port, e.g. `[::1]`.
+        Some((host, _)) if host.contains(':') && !host.ends_with(']') => (authority, None),
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    };
+
+    let mut canonical_host = host;
+    let mut canonical_scheme = connection.scheme;
+    for rule in &settings.rules {
+        match rule {
+            CanonicalHostRule::RedirectHost { from, to } => {
+                if canonical_host.eq_ignore_ascii_case(from) {
+                    canonical_host = to;
+                }
+            }
+            CanonicalHostRule::ForceHttps => canonical_scheme = Scheme::Https,
+        }
+    }
+
+    if canonical_host == host && canonical_scheme == connection.scheme {
+        return None;
+    }
+
+    let mut location = format!("{}://{}", canonical_scheme.as_str(), canonical_host);
+    if let Some(port) = port {
+        location.push(':');
+        location.push_str(port);
+    }
+
+    match &request.target {
+        RequestTarget::Origin { path, query } if settings.preserve_path_and_query => {
+            location.push_str(path);
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(query);
+            }
+        }
+        _ => location.push('/'),
+    }
+
+    Some(Response::redirect(StatusCode::MovedPermanently, location))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use servente_http::{HeaderMap, HeaderValue, HttpVersion, Method};
+
+    fn create_request(host: &str, target: &str) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.append_or_override(HeaderName::Host, HeaderValue::from(host.to_string()));
+        Request {
+            method: Method::Get,
+            target: RequestTarget::parse(target).unwrap(),
+            version: HttpVersion::Http11,
+            headers,
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    fn location(response: &Response) -> &str {
+        response.headers.get(&HeaderName::Location).unwrap().as_str_no_convert().unwrap()
+    }
+
+    fn strip_www() -> CanonicalHostRule {
+        CanonicalHostRule::RedirectHost { from: "www.example.com".into(), to: "example.com".into() }
+    }
+
+    #[test]
+    fn test_no_rules() {
+        let request = create_request("www.example.com", "/");
+        let settings = CanonicalHostSettings::default();
+        assert!(check_canonical_host(&request, &ConnectionInfo::new(Scheme::Http), &settings).is_none());
+    }
+
+    #[test]
+    fn test_strip_www() {
+        let request = create_request("WWW.example.com", "/blog/post.html?page=2");
+        let settings = CanonicalHostSettings {
+            rules: vec![strip_www()],
+            ..Default::default()
+        };
+
+        let response = check_canonical_host(&request, &ConnectionInfo::new(Scheme::Https), &settings).unwrap();
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+        assert_eq!(location(&response), "https://example.com/blog/post.html?page=2");
+    }
+
+    #[test]
+    fn test_strip_www_already_canonical() {
+        let request = create_request("example.com", "/");
+        let settings = CanonicalHostSettings {
+            rules: vec![strip_www()],
+            ..Default::default()
+        };
+
+        assert!(check_canonical_host(&request, &ConnectionInfo::new(Scheme::Https), &settings).is_none());
+    }
+
+    #[test]
+    fn test_strip_www_retains_port() {
+        let request = create_request("www.example.com:8080", "/");
+        let settings = CanonicalHostSettings {
+            rules: vec![strip_www()],
+            ..Default::default()
+        };
+
+        let response = check_canonical_host(&request, &ConnectionInfo::new(Scheme::Http), &settings).unwrap();
+        assert_eq!(location(&response), "http://example.com:8080/");
+    }
+
+    #[test]
+    fn test_force_https() {
+        let request = create_request("example.com", "/index.html");
+        let settings = CanonicalHostSettings {
+            rules: vec![CanonicalHostRule::ForceHttps],
+            ..Default::default()
+        };
+
+        let response = check_canonical_host(&request, &ConnectionInfo::new(Scheme::Http), &settings).unwrap();
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+        assert_eq!(location(&response), "https://example.com/index.html");
+
+        assert!(check_canonical_host(&request, &ConnectionInfo::new(Scheme::Https), &settings).is_none());
+    }
+
+    #[test]
+    fn test_combined_rules_single_redirect() {
+        let request = create_request("www.example.com", "/index.html?q=1");
+        let settings = CanonicalHostSettings {
+            rules: vec![strip_www(), CanonicalHostRule::ForceHttps],
+            preserve_path_and_query: false,
+        };
+
+        let response = check_canonical_host(&request, &ConnectionInfo::new(Scheme::Http), &settings).unwrap();
+        assert_eq!(location(&response), "https://example.com/");
+    }
+}
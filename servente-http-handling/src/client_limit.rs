@@ -0,0 +1,156 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Limits the number of concurrent connections per client IP address, so a
+//! single client can't monopolize the server.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// What happens with a connection of a client that already has the maximum
+/// number of connections open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientLimitExceeded {
+    /// Close the connection immediately, without a response.
+    #[default]
+    Close,
+
+    /// Answer the first request on the connection with
+    /// `429 Too Many Requests`, and close it afterwards.
+    ///
+    /// # References
+    /// * [RFC 6585 Section 4](https://www.rfc-editor.org/rfc/rfc6585.html#section-4)
+    TooManyRequests,
+}
+
+/// The maximum number of concurrent connections per client IP address. This
+/// is shared across connections by wrapping it in an `Arc`.
+#[derive(Debug)]
+pub struct ClientConnectionLimit {
+    limit: usize,
+    exceeded: ClientLimitExceeded,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ClientConnectionLimit {
+    pub fn new(limit: usize, exceeded: ClientLimitExceeded) -> Self {
+        Self {
+            limit,
+            exceeded,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The maximum number of concurrent connections of a single client.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// What should happen with connections exceeding the limit.
+    pub fn exceeded(&self) -> ClientLimitExceeded {
+        self.exceeded
+    }
+
+    /// The number of connections the client currently has open.
+    pub fn connections_of(&self, address: IpAddr) -> usize {
+        self.connections.lock().unwrap().get(&address.to_canonical()).copied().unwrap_or(0)
+    }
+
+    /// Try to account a new connection of the client, which fails if the
+    /// client already has the maximum number of connections open. The
+    /// connection is accounted for until the guard is dropped.
+    pub fn try_acquire(self: &Arc<Self>, address: IpAddr) -> Option<ClientConnectionGuard> {
+        // IPv4 clients connecting to an IPv6 socket would otherwise be
+        // counted separately.
+        let address = address.to_canonical();
+
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(address).or_insert(0);
+        if *count >= self.limit {
+            if *count == 0 {
+                connections.remove(&address);
+            }
+            return None;
+        }
+
+        *count += 1;
+        Some(ClientConnectionGuard {
+            limit: Arc::clone(self),
+            address,
+        })
+    }
+
+    fn release(&self, address: IpAddr) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&address) {
+            *count -= 1;
+
+            // Remove idle clients, so the map doesn't grow with every client
+            // that ever connected.
+            if *count == 0 {
+                connections.remove(&address);
+            }
+        }
+    }
+}
+
+/// A connection that is accounted for in a [`ClientConnectionLimit`].
+#[derive(Debug)]
+pub struct ClientConnectionGuard {
+    limit: Arc<ClientConnectionLimit>,
+    address: IpAddr,
+}
+
+impl Drop for ClientConnectionGuard {
+    fn drop(&mut self) {
+        self.limit.release(self.address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const FIRST: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SECOND: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn test_limit_is_per_address() {
+        let limit = Arc::new(ClientConnectionLimit::new(2, ClientLimitExceeded::Close));
+
+        let first = [limit.try_acquire(FIRST).unwrap(), limit.try_acquire(FIRST).unwrap()];
+        assert!(limit.try_acquire(FIRST).is_none());
+
+        let second = limit.try_acquire(SECOND);
+        assert!(second.is_some());
+        assert_eq!(limit.connections_of(SECOND), 1);
+
+        drop(first);
+        assert!(limit.try_acquire(FIRST).is_some());
+    }
+
+    #[test]
+    fn test_idle_addresses_are_removed() {
+        let limit = Arc::new(ClientConnectionLimit::new(1, ClientLimitExceeded::Close));
+        drop(limit.try_acquire(FIRST));
+        assert!(limit.connections.lock().unwrap().is_empty());
+
+        let limit = Arc::new(ClientConnectionLimit::new(0, ClientLimitExceeded::Close));
+        assert!(limit.try_acquire(FIRST).is_none());
+        assert!(limit.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses_are_the_same_client() {
+        let limit = Arc::new(ClientConnectionLimit::new(1, ClientLimitExceeded::Close));
+        let _guard = limit.try_acquire(FIRST).unwrap();
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped());
+        assert!(limit.try_acquire(mapped).is_none());
+        assert!(limit.try_acquire(IpAddr::V6(Ipv6Addr::LOCALHOST)).is_some());
+    }
+}
@@ -1,9 +1,48 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::{time::Duration, sync::Arc};
+use std::{
+    fmt::Display,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use servente_http::{HeaderMap, HeaderName};
+use servente_resources::embedded::EmbeddedFiles;
+use servente_web_sockets::WebSocketRegistry;
+
+use crate::{
+    ClientConnectionLimit,
+    AssetFingerprints,
+    MaintenanceMode,
+    MemoryBudget,
+    Middleware,
+    RateLimiter,
+    ServerStats,
+    canonical_host::CanonicalHostSettings,
+    handler::HandlerController,
+    logging::{RedactedHeaders, RequestLogger},
+    rewrite::RewriteRule,
+    transform::BodyTransform,
+    validation::{RequestValidator, SensitivePathValidator},
+};
+
+/// The `Server` header of [`default_headers`].
+pub const DEFAULT_SERVER_HEADER: &str = "servente";
+
+/// The default [`ServenteSettings::default_headers`], which only contains
+/// `Server: servente`.
+pub fn default_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append_or_override(HeaderName::Server, DEFAULT_SERVER_HEADER.into());
+    headers
+}
 
-use crate::Middleware;
+/// The address the server listens on, unless configured otherwise.
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8080";
 
 #[derive(Clone)]
 pub struct ServenteConfig {
@@ -13,6 +52,9 @@ pub struct ServenteConfig {
     #[cfg(feature = "tls-boring")]
     pub tls_config: boring::ssl::SslAcceptor,
 
+    /// The socket address to listen on, e.g. `0.0.0.0:8080`.
+    pub bind_address: String,
+
     pub settings: ServenteSettings,
 }
 
@@ -22,8 +64,120 @@ impl ServenteConfig {
             alpn_list: determine_alpn_protocols()
         }
     }
+
+    /// Build the configuration from the environment, which is convenient for
+    /// containerized deployments. Variables that aren't set keep their
+    /// default value. The handlers should be registered afterwards on
+    /// `settings.handler_controller`.
+    ///
+    /// | Variable                        | Value                                       |
+    /// |---------------------------------|---------------------------------------------|
+    /// | `SERVENTE_BIND`                 | The socket address, e.g. `0.0.0.0:8080`     |
+    /// | `SERVENTE_ROOT`                 | The directory to serve the static files of  |
+    /// | `SERVENTE_READ_TIMEOUT`         | Seconds, for both the headers and the body  |
+    /// | `SERVENTE_READ_HEADERS_TIMEOUT` | Seconds, overrides `SERVENTE_READ_TIMEOUT`  |
+    /// | `SERVENTE_READ_BODY_TIMEOUT`    | Seconds, overrides `SERVENTE_READ_TIMEOUT`  |
+    /// | `SERVENTE_MAX_PARTS`            | See [`ServenteSettings::max_parts`]         |
+    /// | `SERVENTE_LOG_TLS_PARAMETERS`   | `true` or `false`                           |
+    /// | `SERVENTE_HTTP2_PING_INTERVAL`  | Seconds, or `0` to disable the HTTP/2 pings |
+    pub fn from_env() -> Result<ServenteConfig, EnvironmentError> {
+        Self::from_variables(|name| std::env::var(name).ok())
+    }
+
+    fn from_variables(lookup: impl Fn(&'static str) -> Option<String>) -> Result<ServenteConfig, EnvironmentError> {
+        let mut settings = ServenteSettings::new(HandlerController::new());
+
+        if let Some(root) = lookup("SERVENTE_ROOT") {
+            let path = PathBuf::from(&root);
+            if !path.is_dir() {
+                return Err(EnvironmentError::new("SERVENTE_ROOT", root, "not a directory"));
+            }
+            settings.document_root = Some(path);
+        }
+
+        if let Some(timeout) = parse_variable(&lookup, "SERVENTE_READ_TIMEOUT", "not a number of seconds")? {
+            settings.read_headers_timeout = Duration::from_secs(timeout);
+            settings.read_body_timeout = Duration::from_secs(timeout);
+        }
+
+        if let Some(timeout) = parse_variable(&lookup, "SERVENTE_READ_HEADERS_TIMEOUT", "not a number of seconds")? {
+            settings.read_headers_timeout = Duration::from_secs(timeout);
+        }
+
+        if let Some(timeout) = parse_variable(&lookup, "SERVENTE_READ_BODY_TIMEOUT", "not a number of seconds")? {
+            settings.read_body_timeout = Duration::from_secs(timeout);
+        }
+
+        if let Some(max_parts) = parse_variable(&lookup, "SERVENTE_MAX_PARTS", "not a number")? {
+            settings.max_parts = max_parts;
+        }
+
+        if let Some(log_tls_parameters) = parse_variable(&lookup, "SERVENTE_LOG_TLS_PARAMETERS", "expected `true` or `false`")? {
+            settings.log_tls_parameters = log_tls_parameters;
+        }
+
+        if let Some(interval) = parse_variable(&lookup, "SERVENTE_HTTP2_PING_INTERVAL", "not a number of seconds")? {
+            settings.http2_ping_interval = Some(Duration::from_secs(interval)).filter(|interval| !interval.is_zero());
+        }
+
+        let mut config = ServenteConfig::new().build(settings);
+        if let Some(bind_address) = lookup("SERVENTE_BIND") {
+            if bind_address.parse::<SocketAddr>().is_err() {
+                return Err(EnvironmentError::new("SERVENTE_BIND", bind_address, "expected an address with a port, e.g. `0.0.0.0:8080`"));
+            }
+            config.bind_address = bind_address;
+        }
+
+        Ok(config)
+    }
+}
+
+/// Resolves the directory the static files are served from: the given root,
+/// or the `wwwroot` directory in the current working directory. The path is
+/// canonicalized, so the traversal checks of the requested paths compare
+/// against the real location of the root. A root that doesn't exist is kept
+/// as is.
+pub fn resolve_document_root(document_root: Option<&Path>) -> io::Result<PathBuf> {
+    let root = match document_root {
+        Some(root) => root.to_path_buf(),
+        None => std::env::current_dir()?.join("wwwroot"),
+    };
+
+    Ok(root.canonicalize().unwrap_or(root))
+}
+
+fn parse_variable<T: std::str::FromStr>(lookup: &impl Fn(&'static str) -> Option<String>, variable: &'static str, reason: &'static str) -> Result<Option<T>, EnvironmentError> {
+    match lookup(variable) {
+        Some(value) => match value.trim().parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(EnvironmentError::new(variable, value, reason)),
+        },
+        None => Ok(None),
+    }
 }
 
+/// An environment variable that couldn't be used for the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvironmentError {
+    pub variable: &'static str,
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl EnvironmentError {
+    fn new(variable: &'static str, value: String, reason: &'static str) -> Self {
+        Self { variable, value, reason }
+    }
+}
+
+impl Display for EnvironmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid value for {}: \"{}\" ({})", self.variable, self.value, self.reason)
+    }
+}
+
+impl std::error::Error for EnvironmentError {}
+
 pub struct ServenteConfigBuilder<T> {
     alpn_list: T,
 }
@@ -34,13 +188,19 @@ impl<T> ServenteConfigBuilder<T>
         #[cfg(not(any(feature = "rustls", feature = "tls-boring")))]
         { _ = self.alpn_list }
 
+        let mut settings = settings;
+        if let Ok(document_root) = resolve_document_root(settings.document_root.as_deref()) {
+            settings.document_root = Some(document_root);
+        }
+
         ServenteConfig {
             #[cfg(feature = "rustls")]
-            tls_config: std::sync::Arc::new(create_tls_config_rustls(self.alpn_list.as_ref())),
+            tls_config: std::sync::Arc::new(create_tls_config_rustls(self.alpn_list.as_ref(), settings.tls_max_early_data_size)),
 
             #[cfg(feature = "tls-boring")]
             tls_config: create_tls_config_boring(self.alpn_list.as_ref()),
 
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
             settings,
         }
     }
@@ -55,6 +215,36 @@ impl<T> ServenteConfigBuilder<T>
 unsafe impl Send for ServenteConfig {}
 unsafe impl Sync for ServenteConfig {}
 
+/// The default value of [`ServenteSettings::max_parts`].
+pub const DEFAULT_MAX_PARTS: usize = 16;
+
+/// The default value of [`ServenteSettings::max_chunk_size`].
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The default value of [`ServenteSettings::max_request_body_size`].
+pub const DEFAULT_MAX_REQUEST_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The recommended value of [`ServenteSettings::request_body_spill_threshold`].
+pub const DEFAULT_REQUEST_BODY_SPILL_THRESHOLD: usize = 1024 * 1024;
+
+/// The default value of [`ServenteSettings::chunked_transfer_threshold`].
+pub const DEFAULT_CHUNKED_TRANSFER_THRESHOLD: u64 = 1024 * 1024;
+
+/// The default value of [`ServenteSettings::max_keep_alive_requests`].
+pub const DEFAULT_MAX_KEEP_ALIVE_REQUESTS: usize = 1000;
+
+/// The default value of [`ServenteSettings::keep_alive_idle_timeout`].
+pub const DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The default value of [`ServenteSettings::shutdown_grace_period`].
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// The default value of [`ServenteSettings::http2_ping_interval`].
+pub const DEFAULT_HTTP2_PING_INTERVAL: Option<Duration> = Some(Duration::from_secs(30));
+
+/// The default value of [`ServenteSettings::http2_max_unacknowledged_pings`].
+pub const DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS: usize = 3;
+
 #[derive(Clone)]
 pub struct ServenteSettings {
     pub handler_controller: crate::handler::HandlerController,
@@ -69,6 +259,216 @@ pub struct ServenteSettings {
 
     /// TODO: file serving should become middleware too.
     pub middleware: Vec<Arc<dyn Middleware>>,
+
+    /// The rules for redirecting requests to the canonical host and scheme,
+    /// which are checked before any handler runs.
+    pub canonical_host: CanonicalHostSettings,
+
+    /// What to do with the data the client sent directly after the body, as
+    /// delimited by `Content-Length`.
+    pub excess_body_data: ExcessBodyDataHandling,
+
+    /// What to do with requests of which the `Expect` header contains an
+    /// expectation other than `100-continue`.
+    pub unknown_expectations: UnknownExpectationHandling,
+
+    /// Whether a bare LF is accepted as the line terminator of the
+    /// request-line and the header fields, instead of only CRLF. The header
+    /// section always has to be terminated by an empty line.
+    pub line_endings: LineEndingHandling,
+
+    /// Log the TLS version, cipher suite and ALPN protocol that were
+    /// negotiated for every connection.
+    pub log_tls_parameters: bool,
+
+    /// The limit on the combined size of the header compression tables
+    /// (HPACK/QPACK) of all connections. When the budget is exhausted, a
+    /// connection that needs more is closed with a `COMPRESSION_ERROR`, since
+    /// the peer decides which entries the tables contain.
+    pub dynamic_table_budget: Option<Arc<MemoryBudget>>,
+
+    /// The order the response fields are serialized in by HTTP/1.x.
+    pub header_order: HeaderOrder,
+
+    /// The transforms applied to in-memory response bodies, in order.
+    pub body_transforms: Vec<Arc<dyn BodyTransform>>,
+
+    /// The maximum number of parts in a multipart message, e.g. the ranges of
+    /// a `multipart/byteranges` response. Requests for more ranges are served
+    /// with the full representation instead.
+    pub max_parts: usize,
+
+    /// The security headers that are added to every response, unless the
+    /// handler set them itself.
+    pub security_headers: SecurityHeaderSettings,
+
+    /// The headers that are added to every response, unless the handler set
+    /// them itself. By default, this is [`default_headers`]; remove the
+    /// `Server` header to not reveal the software, or replace its value.
+    pub default_headers: HeaderMap,
+
+    /// The `Strict-Transport-Security` of the responses sent over TLS. Not
+    /// sent by default, since browsers refuse plaintext connections to the
+    /// host for the whole `max_age` once they've seen it.
+    pub hsts: Option<HstsConfig>,
+
+    /// The client hints that HTML responses ask browsers for.
+    pub client_hints: ClientHintsSettings,
+
+    /// The directory the static files are served from. When `None`, the
+    /// `wwwroot` directory in the current working directory is used. This is
+    /// only used with [`DocumentSource::FileSystem`].
+    ///
+    /// [`ServenteConfigBuilder::build`] resolves the root once, using
+    /// [`resolve_document_root`], so it is always set afterwards.
+    pub document_root: Option<PathBuf>,
+
+    /// Where the static files are served from.
+    pub document_source: DocumentSource,
+
+    /// The files that are served for a request for a directory. When more
+    /// than one of them exists, the one whose media type is the best match
+    /// for the `Accept` header is served, e.g. `index.json` for an API
+    /// client. Without a preference, the first existing one is served.
+    pub index_files: Vec<String>,
+
+    /// The statistics of the exchanges. Keep a clone of the `Arc` to inspect
+    /// them using [`ServerStats::snapshot`].
+    pub stats: Arc<ServerStats>,
+
+    /// The loggers that are invoked for every handled request.
+    pub request_loggers: Vec<Arc<dyn RequestLogger>>,
+
+    /// The headers that are redacted for the `request_loggers`.
+    pub redacted_headers: RedactedHeaders,
+
+    /// The maximum size of a single chunk of a `chunked` request body.
+    /// Chunks announcing a larger size are rejected with `400 Bad Request`.
+    pub max_chunk_size: usize,
+
+    /// The maximum size of a request body. Larger bodies are rejected with
+    /// `413 Content Too Large`.
+    pub max_request_body_size: usize,
+
+    /// The size above which a request body is moved from memory to a
+    /// temporary file, which is given to the handlers as a
+    /// [`servente_http::BodyKind::File`], or `None` to keep every body in
+    /// memory. The file is removed when the request is dropped.
+    ///
+    /// Bodies with a `Content-Encoding`, and those of routes asking for the
+    /// raw body, are always kept in memory, since they are decoded or copied
+    /// as a whole.
+    pub request_body_spill_threshold: Option<usize>,
+
+    /// The maximum amount of TLS 1.3 early data (0-RTT) that is accepted from
+    /// a resuming client, or `0` to disable early data. Requests received as
+    /// early data can be replayed, so only those with a safe method (e.g.
+    /// `GET` and `HEAD`) are processed, and the others are answered with
+    /// `425 Too Early`.
+    ///
+    /// This is only supported with rustls, and applied when the TLS
+    /// configuration is built.
+    pub tls_max_early_data_size: u32,
+
+    /// How the static files that aren't cached in memory are read.
+    pub file_backend: FileBackend,
+
+    /// Whether the files cached in memory get a strong `ETag` computed from
+    /// the hash of their contents, instead of the weak one derived from
+    /// their modification time, which isn't reliable for files from build
+    /// pipelines or restored backups. Files that aren't cached keep the weak
+    /// `ETag`.
+    ///
+    /// This is applied when the cache is started, see
+    /// [`servente_resources::cache::CacheOptions::content_hash_etags`].
+    pub content_hash_etags: bool,
+
+    /// The UDP port the HTTP/3 listener is running on, which is advertised
+    /// using `Alt-Svc`. This should be `None` when HTTP/3 isn't running.
+    pub http3_port: Option<u16>,
+
+    /// How long an HTTP/2 connection may be idle before the server sends a
+    /// `PING` to check whether the client is still there, or `None` to never
+    /// send them.
+    pub http2_ping_interval: Option<Duration>,
+
+    /// The number of `PING`s that may be left unacknowledged before the HTTP/2
+    /// connection is considered dead and is closed.
+    pub http2_max_unacknowledged_pings: usize,
+
+    /// Reuse the HPACK header blocks of HTTP/2 responses serving the same
+    /// cached file with the same headers, instead of compressing the headers
+    /// of every response. The `Date` header is always compressed separately.
+    pub http2_precomputed_header_blocks: bool,
+
+    /// Accept HTTP/2 with prior knowledge on plaintext connections, i.e.
+    /// clients that start with the connection preface instead of an
+    /// HTTP/1.1 request, such as gRPC over `h2c`. This is off by default, and
+    /// those clients are told to use HTTP/1.1 instead. It doesn't affect TLS
+    /// connections.
+    ///
+    /// # References
+    /// * [RFC 9113 Section 3.3](https://www.rfc-editor.org/rfc/rfc9113.html#name-starting-http-2-with-prior-)
+    pub h2c_prior_knowledge: bool,
+
+    /// The maximum number of concurrent connections per client IP address,
+    /// and what happens with the connections exceeding it.
+    pub client_connection_limit: Option<Arc<ClientConnectionLimit>>,
+
+    /// The rules that rewrite the request paths before they are routed, see
+    /// [`crate::rewrite`].
+    pub rewrite_rules: Vec<RewriteRule>,
+
+    /// The maintenance mode, which can be toggled while the server is
+    /// running by keeping a clone of the `Arc`.
+    pub maintenance_mode: Arc<MaintenanceMode>,
+
+    /// The size above which files are transferred by HTTP/1.1 using the
+    /// `chunked` transfer coding, which allows them to be compressed while
+    /// streaming. Smaller files are sent with a `Content-Length`.
+    pub chunked_transfer_threshold: u64,
+
+    /// Whether symbolic links in the document root are followed. Paths that
+    /// are rejected by the policy are answered with `403 Forbidden`.
+    pub symlink_policy: SymlinkPolicy,
+
+    /// Redirect requests for a static directory without a trailing slash to
+    /// the path with one using `301 Moved Permanently`, e.g. `/docs` to
+    /// `/docs/`, so the relative links in its index file resolve correctly.
+    pub redirect_directories: bool,
+
+    /// The directories of which the assets are fingerprinted by the build,
+    /// e.g. `app.a1b2c3.js`, so they can be requested by their pretty name,
+    /// e.g. `/assets/app.js`, and cached forever. Empty by default.
+    pub asset_fingerprints: Vec<AssetFingerprints>,
+
+    /// How long the connections may take to finish when the server shuts
+    /// down gracefully, before they are dropped.
+    pub shutdown_grace_period: Duration,
+
+    /// The maximum rate of requests of the server as a whole, regardless of
+    /// the clients sending them. Excess requests are answered with
+    /// `429 Too Many Requests`.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// The checks every request has to pass before it is handled, see
+    /// [`crate::validation`]. By default, this contains the
+    /// [`SensitivePathValidator`].
+    pub request_validators: Vec<Arc<dyn RequestValidator>>,
+
+    /// The maximum number of requests on a single persistent HTTP/1.1
+    /// connection. The response to the last one is sent with
+    /// `Connection: close`. Use `usize::MAX` to not limit this.
+    pub max_keep_alive_requests: usize,
+
+    /// How long a connection may be idle between requests before it is
+    /// closed. This is separate from the `read_headers_timeout`, which only
+    /// starts when the client starts sending a request.
+    pub keep_alive_idle_timeout: Duration,
+
+    /// The paths that accept WebSocket connections, which are upgraded from
+    /// HTTP/1.1 requests. Empty by default.
+    pub web_sockets: WebSocketRegistry,
 }
 
 impl ServenteSettings {
@@ -77,11 +477,275 @@ impl ServenteSettings {
             handler_controller,
             read_headers_timeout: Duration::from_secs(10),
             read_body_timeout: Duration::from_secs(60),
-            middleware: Vec::new()
+            middleware: Vec::new(),
+            canonical_host: CanonicalHostSettings::default(),
+            excess_body_data: ExcessBodyDataHandling::default(),
+            unknown_expectations: UnknownExpectationHandling::default(),
+            line_endings: LineEndingHandling::default(),
+            log_tls_parameters: false,
+            dynamic_table_budget: None,
+            header_order: HeaderOrder::default(),
+            body_transforms: Vec::new(),
+            max_parts: DEFAULT_MAX_PARTS,
+            security_headers: SecurityHeaderSettings::default(),
+            default_headers: default_headers(),
+            hsts: None,
+            client_hints: ClientHintsSettings::default(),
+            document_root: None,
+            document_source: DocumentSource::default(),
+            request_loggers: Vec::new(),
+            redacted_headers: RedactedHeaders::default(),
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            max_request_body_size: DEFAULT_MAX_REQUEST_BODY_SIZE,
+            request_body_spill_threshold: None,
+            tls_max_early_data_size: 0,
+            file_backend: FileBackend::default(),
+            content_hash_etags: false,
+            http3_port: None,
+            http2_ping_interval: DEFAULT_HTTP2_PING_INTERVAL,
+            http2_max_unacknowledged_pings: DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS,
+            http2_precomputed_header_blocks: false,
+            h2c_prior_knowledge: false,
+            client_connection_limit: None,
+            rewrite_rules: Vec::new(),
+            maintenance_mode: Default::default(),
+            chunked_transfer_threshold: DEFAULT_CHUNKED_TRANSFER_THRESHOLD,
+            symlink_policy: SymlinkPolicy::default(),
+            redirect_directories: false,
+            asset_fingerprints: Vec::new(),
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            rate_limiter: None,
+            request_validators: vec![Arc::new(SensitivePathValidator)],
+            index_files: vec![String::from("index.html")],
+            stats: Default::default(),
+            max_keep_alive_requests: DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+            keep_alive_idle_timeout: DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT,
+            web_sockets: WebSocketRegistry::new(),
         }
     }
 }
 
+/// Whether symbolic links in the document root are followed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Follow all symbolic links, even those pointing outside of the
+    /// document root.
+    Follow,
+
+    /// Don't serve any path that traverses a symbolic link below the
+    /// document root.
+    RejectAll,
+
+    /// Follow symbolic links only if their target is inside the document
+    /// root.
+    #[default]
+    RejectEscaping,
+}
+
+#[allow(non_upper_case_globals)]
+impl SymlinkPolicy {
+    /// The same as [`SymlinkPolicy::RejectAll`].
+    pub const Deny: Self = Self::RejectAll;
+
+    /// The same as [`SymlinkPolicy::RejectEscaping`]: symbolic links are
+    /// followed as long as their canonical target stays within the canonical
+    /// document root.
+    pub const FollowWithinRoot: Self = Self::RejectEscaping;
+}
+
+/// Where the static files are served from.
+#[derive(Clone, Debug, Default)]
+pub enum DocumentSource {
+    /// Serve the files from the `document_root` on the filesystem.
+    #[default]
+    FileSystem,
+
+    /// Serve the files embedded into the binary, see
+    /// [`servente_resources::embed_files`]. Requests for other paths are
+    /// passed to the fallback handler, or answered with `404 Not Found`.
+    Embedded(Arc<EmbeddedFiles>),
+}
+
+/// How static files that aren't cached in memory are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileBackend {
+    /// Read the file using buffered reads.
+    #[default]
+    Read,
+
+    /// Map the file into memory, which saves the copies and system calls of
+    /// reading. Only files that are stable are mapped, since accessing a
+    /// mapping of a file that was truncated crashes the process (`SIGBUS`).
+    /// The other files are read instead.
+    #[cfg(all(unix, feature = "mmap"))]
+    Mmap,
+}
+
+/// The order in which the fields of a response are serialized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderOrder {
+    /// The order the fields are stored in the `HeaderMap`, which is the
+    /// cheapest, but isn't stable across responses.
+    #[default]
+    Stored,
+
+    /// The canonical order, as defined by `HeaderMap::iter_canonical`: the
+    /// general fields first, then the response fields, then the entity
+    /// fields and lastly the others.
+    Canonical,
+}
+
+/// A starting point for [`SecurityHeaderSettings::content_security_policy`],
+/// which only allows resources from the same origin, and disallows plugins
+/// and embedding the pages in frames.
+pub const STARTER_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'none'";
+
+/// The security headers that are added to every response, unless the handler
+/// set them itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityHeaderSettings {
+    /// `X-Frame-Options`, `DENY` by default. Use `SAMEORIGIN` or omit it to
+    /// allow the pages to be embedded in frames.
+    pub x_frame_options: SecurityHeaderSetting,
+
+    /// `X-Content-Type-Options`, `nosniff` by default.
+    pub x_content_type_options: SecurityHeaderSetting,
+
+    /// `X-XSS-Protection`, `1; mode=block` by default.
+    pub x_xss_protection: SecurityHeaderSetting,
+
+    /// The `Content-Security-Policy` of HTML responses, unless the handler
+    /// set one itself. Not sent by default, see
+    /// [`STARTER_CONTENT_SECURITY_POLICY`].
+    ///
+    /// # References
+    /// * [Content Security Policy Level 3](https://www.w3.org/TR/CSP3/)
+    pub content_security_policy: Option<String>,
+
+    /// The `Content-Security-Policy-Report-Only` of HTML responses, unless
+    /// the handler set one itself, e.g. to try out a stricter policy before
+    /// enforcing it. Not sent by default.
+    pub content_security_policy_report_only: Option<String>,
+}
+
+/// The client hints that HTML responses ask browsers for, e.g. to select an
+/// image of the right size for `Sec-CH-Viewport-Width`. Handlers read the
+/// received hints using e.g. `Request::sec_ch_ua`, and should list the ones
+/// the response depends on in `Vary`.
+///
+/// # References
+/// * [RFC 8942](https://www.rfc-editor.org/rfc/rfc8942.html)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientHintsSettings {
+    /// The hints sent as `Accept-CH`, which browsers include in the
+    /// subsequent requests to the origin.
+    pub accept: Vec<HeaderName>,
+
+    /// The hints sent as `Critical-CH`, without which the response would be
+    /// different, so browsers retry the request with them. These should be
+    /// in `accept` too.
+    ///
+    /// # References
+    /// * [Client Hints Reliability](https://www.ietf.org/archive/id/draft-davidben-http-client-hint-reliability-03.html)
+    pub critical: Vec<HeaderName>,
+}
+
+/// The directives of the `Strict-Transport-Security` header.
+///
+/// # References
+/// * [RFC 6797](https://www.rfc-editor.org/rfc/rfc6797.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HstsConfig {
+    /// How long browsers should only connect over TLS, which is rounded
+    /// down to whole seconds.
+    pub max_age: Duration,
+
+    /// Whether the policy applies to the subdomains of the host too.
+    pub include_subdomains: bool,
+
+    /// Whether the host consents to be included in the HSTS preload lists
+    /// of browsers. This isn't part of RFC 6797.
+    pub preload: bool,
+}
+
+impl HstsConfig {
+    /// Returns the value of the `Strict-Transport-Security` header.
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// How a built-in security header is sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SecurityHeaderSetting {
+    /// Send the header with the built-in value.
+    #[default]
+    Default,
+
+    /// Send the header with this value instead.
+    Custom(String),
+
+    /// Don't send the header, e.g. because the handlers manage it themselves.
+    Omit,
+}
+
+/// Specifies what to do with data that immediately follows a request body
+/// delimited by `Content-Length`. This only applies to persistent
+/// connections, since the data following a request with `Connection: close`
+/// is always ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExcessBodyDataHandling {
+    /// Treat the data as the start of the next (pipelined) request.
+    #[default]
+    NextRequest,
+
+    /// Reject the request with a `400 Bad Request` and close the connection.
+    ///
+    /// Only the data that was already received together with the body is
+    /// considered, i.e. the bytes buffered when the body is complete. The
+    /// server doesn't wait for or read more data to decide, so data that
+    /// arrives in a later packet is treated as the next request.
+    Reject,
+}
+
+/// Specifies what to do with a request that has an expectation the server
+/// doesn't understand, i.e. anything other than `100-continue`.
+///
+/// # References
+/// * [RFC 9110 Section 10.1.1](https://www.rfc-editor.org/rfc/rfc9110.html#section-10.1.1)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownExpectationHandling {
+    /// Reject the request with a `417 Expectation Failed`, since the client
+    /// relies on an expectation that can't be met.
+    #[default]
+    Reject,
+
+    /// Handle the request as if the expectation wasn't there.
+    Ignore,
+}
+
+/// Specifies which line terminators are accepted in the request-line and the
+/// header section of an HTTP/1.x request. A bare CR is always rejected.
+///
+/// # References
+/// * [RFC 9112 Section 2.2](https://www.rfc-editor.org/rfc/rfc9112.html#name-message-parsing)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEndingHandling {
+    /// Only accept CRLF, and reject a bare LF with a `400 Bad Request`.
+    #[default]
+    Strict,
+
+    /// Also accept a bare LF, which naive clients and scripts often send.
+    Lenient,
+}
+
 #[cfg(feature = "tls-boring")]
 fn create_tls_config_boring(alpn_list: &[&'static str]) -> boring::ssl::SslAcceptor {
     use boring::ssl::{
@@ -125,7 +789,7 @@ fn create_tls_config_boring(alpn_list: &[&'static str]) -> boring::ssl::SslAccep
 }
 
 #[cfg(feature = "rustls")]
-fn create_tls_config_rustls(alpn_list: &[&'static str]) -> rustls::ServerConfig {
+fn create_tls_config_rustls(alpn_list: &[&'static str], max_early_data_size: u32) -> rustls::ServerConfig {
     let cert_data = servente_self_signed_cert::load_certificate_locations();
 
     let mut tls_config = rustls::ServerConfig::builder()
@@ -137,6 +801,7 @@ fn create_tls_config_rustls(alpn_list: &[&'static str]) -> rustls::ServerConfig
     // https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids
     tls_config.alpn_protocols = alpn_list.iter().map(|str| str.as_bytes().to_owned()).collect();
     tls_config.send_half_rtt_data = true;
+    tls_config.max_early_data_size = max_early_data_size;
 
     #[cfg(feature = "ktls")]
     {
@@ -173,3 +838,96 @@ fn determine_alpn_protocols_boring(protocols: &[&'static str]) -> Vec<u8> {
     debug_assert_eq!(result.len(), length);
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_variables(variables: &[(&'static str, &str)]) -> Result<ServenteConfig, EnvironmentError> {
+        let variables: HashMap<&str, String> = variables.iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        ServenteConfig::from_variables(|name| variables.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_variables_defaults() {
+        let config = from_variables(&[]).unwrap();
+        assert_eq!(config.bind_address, DEFAULT_BIND_ADDRESS);
+        let wwwroot = std::env::current_dir().unwrap().join("wwwroot");
+        assert_eq!(config.settings.document_root, Some(wwwroot.canonicalize().unwrap_or(wwwroot)));
+        assert_eq!(config.settings.max_parts, DEFAULT_MAX_PARTS);
+    }
+
+    #[test]
+    fn test_from_variables() {
+        let root = tempfile::tempdir().unwrap();
+        let config = from_variables(&[
+            ("SERVENTE_BIND", "0.0.0.0:80"),
+            ("SERVENTE_ROOT", root.path().to_str().unwrap()),
+            ("SERVENTE_READ_TIMEOUT", "30"),
+            ("SERVENTE_READ_BODY_TIMEOUT", "90"),
+            ("SERVENTE_MAX_PARTS", "4"),
+            ("SERVENTE_LOG_TLS_PARAMETERS", "true"),
+            ("SERVENTE_HTTP2_PING_INTERVAL", "10"),
+        ]).unwrap();
+
+        assert_eq!(config.bind_address, "0.0.0.0:80");
+        assert_eq!(config.settings.document_root, Some(root.path().canonicalize().unwrap()));
+        assert_eq!(config.settings.read_headers_timeout, Duration::from_secs(30));
+        assert_eq!(config.settings.read_body_timeout, Duration::from_secs(90));
+        assert_eq!(config.settings.max_parts, 4);
+        assert!(config.settings.log_tls_parameters);
+        assert_eq!(config.settings.http2_ping_interval, Some(Duration::from_secs(10)));
+
+        let config = from_variables(&[("SERVENTE_HTTP2_PING_INTERVAL", "0")]).unwrap();
+        assert_eq!(config.settings.http2_ping_interval, None);
+    }
+
+    #[test]
+    fn test_from_variables_invalid() {
+        let error = from_variables(&[("SERVENTE_BIND", "localhost")]).err().unwrap();
+        assert_eq!(error.variable, "SERVENTE_BIND");
+
+        let error = from_variables(&[("SERVENTE_READ_TIMEOUT", "5s")]).err().unwrap();
+        assert_eq!(error.variable, "SERVENTE_READ_TIMEOUT");
+        assert_eq!(error.value, "5s");
+
+        let error = from_variables(&[("SERVENTE_ROOT", "/nonexistent/servente/root")]).err().unwrap();
+        assert_eq!(error.variable, "SERVENTE_ROOT");
+    }
+
+    #[test]
+    fn test_from_variables_specific_timeouts() {
+        let config = from_variables(&[
+            ("SERVENTE_BIND", "[::]:8443"),
+            ("SERVENTE_READ_TIMEOUT", "30"),
+            ("SERVENTE_READ_HEADERS_TIMEOUT", "7"),
+        ]).unwrap();
+
+        assert_eq!(config.bind_address, "[::]:8443");
+        assert_eq!(config.settings.read_headers_timeout, Duration::from_secs(7));
+        assert_eq!(config.settings.read_body_timeout, Duration::from_secs(30));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_document_root() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        let link = directory.path().join("link");
+        std::os::unix::fs::symlink(&root, &link).unwrap();
+
+        let resolved = resolve_document_root(Some(&link)).unwrap();
+        assert_eq!(resolved, root.canonicalize().unwrap());
+
+        // The traversal check compares against the real location.
+        let path = crate::find_request_path_in_wwwroot(&resolved, "/index.html").unwrap();
+        assert!(path.starts_with(root.canonicalize().unwrap()));
+
+        let missing = directory.path().join("missing");
+        assert_eq!(resolve_document_root(Some(&missing)).unwrap(), missing);
+    }
+}
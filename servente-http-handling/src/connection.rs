@@ -0,0 +1,165 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// The identifier of the last connection, see [`next_connection_id`].
+static LAST_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Generate a new identifier for an accepted connection, which is unique for
+/// the lifetime of the process. The first identifier is `1`, since `0` is
+/// reserved for connections that weren't given one, e.g. in tests.
+pub fn next_connection_id() -> u64 {
+    LAST_CONNECTION_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Information about the connection an exchange was received on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The scheme the client used to reach us.
+    pub scheme: Scheme,
+
+    /// The parameters negotiated during the TLS handshake, if any.
+    pub tls: Option<TlsParameters>,
+
+    /// The exchange was (partially) received as TLS 1.3 early data (0-RTT),
+    /// which an attacker might have replayed.
+    ///
+    /// # References
+    /// * [RFC 8470](https://www.rfc-editor.org/rfc/rfc8470.html)
+    pub early_data: bool,
+
+    /// The address of the client, i.e. the peer of the socket. This isn't
+    /// known for connections that weren't accepted from a socket, e.g. in
+    /// tests.
+    pub peer_address: Option<SocketAddr>,
+
+    /// The identifier of the connection, see [`next_connection_id`].
+    pub id: u64,
+
+    /// Numbers the requests received on this connection.
+    pub requests: RequestSequence,
+}
+
+impl ConnectionInfo {
+    pub fn new(scheme: Scheme) -> Self {
+        Self { scheme, tls: None, early_data: false, peer_address: None, id: 0, requests: RequestSequence::default() }
+    }
+
+    /// Create the information for a connection secured with TLS.
+    pub fn with_tls(tls: TlsParameters) -> Self {
+        Self { tls: Some(tls), ..Self::new(Scheme::Https) }
+    }
+
+    /// Sets the identifier of the connection, see [`next_connection_id`].
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Assign the identifier to the next request received on this connection.
+    pub fn next_exchange_id(&self) -> ExchangeId {
+        ExchangeId {
+            connection: self.id,
+            request: self.requests.next(),
+        }
+    }
+
+    /// Sets the address of the client.
+    pub fn with_peer_address(mut self, peer_address: SocketAddr) -> Self {
+        self.peer_address = Some(peer_address);
+        self
+    }
+}
+
+/// Numbers the requests received on a connection, starting at `1`. The clones
+/// share the counter, so the streams of an HTTP/2 connection are numbered
+/// together.
+#[derive(Clone, Debug, Default)]
+pub struct RequestSequence(Arc<AtomicU64>);
+
+impl RequestSequence {
+    fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl PartialEq for RequestSequence {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RequestSequence {}
+
+/// Identifies an exchange by the connection and the sequence number of the
+/// request on that connection, e.g. `conn=42 req=3`. This is used to
+/// correlate the exchanges of keep-alive and multiplexed connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ExchangeId {
+    /// The identifier of the connection, see [`ConnectionInfo::id`].
+    pub connection: u64,
+
+    /// The sequence number of the request on the connection.
+    pub request: u64,
+}
+
+impl Display for ExchangeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "conn={} req={}", self.connection, self.request)
+    }
+}
+
+/// The URI scheme of a connection.
+///
+/// # References
+/// * [RFC 9110 Section 4.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-http-related-uri-schemes)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    /// Get the scheme as it appears in a URI, e.g. `https`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+
+/// The parameters that were negotiated in the TLS handshake. These are
+/// stored as the names the TLS library uses, since those differ between
+/// rustls and BoringSSL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsParameters {
+    /// The protocol version, e.g. `TLSv1_3`.
+    pub version: Option<String>,
+
+    /// The cipher suite, e.g. `TLS13_AES_256_GCM_SHA384`.
+    pub cipher_suite: Option<String>,
+
+    /// The application protocol selected using ALPN, e.g. `h2`.
+    ///
+    /// # References
+    /// * [RFC 7301](https://www.rfc-editor.org/rfc/rfc7301.html)
+    pub alpn: Option<String>,
+}
+
+impl Display for TlsParameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "version={} cipher_suite={} alpn={}",
+            self.version.as_deref().unwrap_or("-"),
+            self.cipher_suite.as_deref().unwrap_or("-"),
+            self.alpn.as_deref().unwrap_or("-"))
+    }
+}
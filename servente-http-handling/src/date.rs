@@ -0,0 +1,87 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Caching of the `Date` header field. The `Date` only has a granularity of
+//! seconds, so formatting it for every response is wasteful; instead, the
+//! formatted value is reused for all responses within the same second.
+//!
+//! # References
+//! * [RFC 9110 Section 6.6.1](https://www.rfc-editor.org/rfc/rfc9110.html#name-date)
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use servente_http::HeaderValue;
+
+static DATE_CACHE: DateCache = DateCache::new();
+
+/// Get the `Date` header value for the current time.
+pub fn current_date() -> HeaderValue {
+    HeaderValue::SharedString(DATE_CACHE.get(SystemTime::now()))
+}
+
+struct DateCache {
+    /// The second since the UNIX epoch, and the formatted date of that second.
+    cached: RwLock<Option<(u64, Arc<str>)>>,
+}
+
+impl DateCache {
+    const fn new() -> Self {
+        Self {
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Get the formatted date of the given time, only formatting it if it is in
+    /// a different second than the previous call.
+    fn get(&self, time: SystemTime) -> Arc<str> {
+        let second = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+
+        if let Ok(cached) = self.cached.read() {
+            if let Some((cached_second, formatted)) = cached.as_ref() {
+                if *cached_second == second {
+                    return Arc::clone(formatted);
+                }
+            }
+        }
+
+        let formatted: Arc<str> = Arc::from(httpdate::fmt_http_date(time));
+        if let Ok(mut cached) = self.cached.write() {
+            *cached = Some((second, Arc::clone(&formatted)));
+        }
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_same_second_reuses_formatted_date() {
+        let cache = DateCache::new();
+        let time = UNIX_EPOCH + Duration::from_secs(1_684_000_000);
+
+        let first = cache.get(time);
+        for millis in 0..1000 {
+            let second = cache.get(time + Duration::from_millis(millis));
+            assert!(Arc::ptr_eq(&first, &second), "date was formatted again within the same second");
+        }
+
+        assert_eq!(first.as_ref(), "Sat, 13 May 2023 17:46:40 GMT");
+    }
+
+    #[test]
+    fn test_next_second_reformats_date() {
+        let cache = DateCache::new();
+        let time = UNIX_EPOCH + Duration::from_secs(1_684_000_000);
+
+        let first = cache.get(time);
+        let next = cache.get(time + Duration::from_secs(1));
+        assert!(!Arc::ptr_eq(&first, &next));
+        assert_eq!(next.as_ref(), "Sat, 13 May 2023 17:46:41 GMT");
+    }
+}
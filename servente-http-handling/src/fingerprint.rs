@@ -0,0 +1,141 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Fingerprinted assets, of which the file name contains a hash of their
+//! contents, e.g. `app.a1b2c3.js`. Bundlers emit these, so the files can be
+//! cached forever, but the pages might refer to them using the pretty name,
+//! e.g. `/assets/app.js`.
+
+use std::path::Path;
+
+use crate::find_request_path_in_wwwroot;
+
+/// The `Cache-Control` of fingerprinted assets. The contents of such a file
+/// never change, since a change results in a different file name.
+///
+/// # References
+/// * [RFC 8246](https://www.rfc-editor.org/rfc/rfc8246.html)
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The minimum number of hexadecimal digits of a fingerprint, so that e.g.
+/// the `min` in `app.min.js` isn't mistaken for one.
+const MIN_FINGERPRINT_LENGTH: usize = 6;
+
+/// Resolves the pretty names of the fingerprinted assets below a directory
+/// of the document root to the files on disk, e.g. `/assets/app.js` to
+/// `assets/app.a1b2c3.js`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssetFingerprints {
+    /// The request path of the directory, e.g. `/assets/`.
+    prefix: String,
+
+    /// The extensions of the files that are fingerprinted, e.g. `js`.
+    extensions: Vec<String>,
+}
+
+impl AssetFingerprints {
+    /// Create the rule for the assets below the `prefix` having one of the
+    /// `extensions`, e.g. `AssetFingerprints::new("/assets/", &["js", "css"])`.
+    pub fn new(prefix: impl Into<String>, extensions: &[&str]) -> Self {
+        Self {
+            prefix: prefix.into(),
+            extensions: extensions.iter().map(|extension| extension.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    /// Resolves the request target to the fingerprinted asset in the `root`
+    /// it refers to, or [`FingerprintedTarget::Fingerprinted`] when it names
+    /// an existing fingerprinted asset itself. Returns `None` when the rule
+    /// doesn't apply to the target, e.g. when a file with the pretty name
+    /// exists.
+    ///
+    /// When multiple fingerprinted versions exist, e.g. of older builds, the
+    /// most recently modified one is used.
+    pub fn resolve(&self, root: &Path, request_target: &str) -> Option<FingerprintedTarget> {
+        let relative_path = request_target.strip_prefix(&self.prefix)?;
+        let file_name = relative_path.rsplit_once('/').map_or(relative_path, |(_, file_name)| file_name);
+        let (stem, extension) = file_name.rsplit_once('.')?;
+        if stem.is_empty() || !self.extensions.iter().any(|ext| ext.eq_ignore_ascii_case(extension)) {
+            return None;
+        }
+
+        let path = find_request_path_in_wwwroot(root, request_target).ok()?;
+        if strip_fingerprint(file_name).is_some() {
+            return path.is_file().then_some(FingerprintedTarget::Fingerprinted);
+        }
+
+        if path.exists() {
+            return None;
+        }
+
+        let stem = urlencoding::decode(stem).ok()?;
+        let newest = std::fs::read_dir(path.parent()?).ok()?
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry.file_name().to_str()
+                    .and_then(strip_fingerprint)
+                    .is_some_and(|(entry_stem, entry_extension)| entry_stem == stem && entry_extension.eq_ignore_ascii_case(extension))
+            })
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.file_name())))
+            .max()?;
+
+        let fingerprinted_file_name = newest.1.into_string().ok()?;
+        let directory = &request_target[..request_target.len() - file_name.len()];
+        Some(FingerprintedTarget::Resolved(format!("{directory}{}", urlencoding::encode(&fingerprinted_file_name))))
+    }
+}
+
+/// The result of [`AssetFingerprints::resolve`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FingerprintedTarget {
+    /// The request target itself is fingerprinted.
+    Fingerprinted,
+
+    /// The pretty name resolved to this fingerprinted request target.
+    Resolved(String),
+}
+
+/// Splits a fingerprinted file name into the stem and the extension, e.g.
+/// `app.a1b2c3.js` into `app` and `js`.
+fn strip_fingerprint(file_name: &str) -> Option<(&str, &str)> {
+    let (rest, extension) = file_name.rsplit_once('.')?;
+    let (stem, fingerprint) = rest.rsplit_once('.')?;
+    let is_fingerprint = fingerprint.len() >= MIN_FINGERPRINT_LENGTH
+        && fingerprint.bytes().all(|byte| byte.is_ascii_hexdigit());
+    (is_fingerprint && !stem.is_empty()).then_some((stem, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_fingerprint() {
+        assert_eq!(strip_fingerprint("app.a1b2c3.js"), Some(("app", "js")));
+        assert_eq!(strip_fingerprint("vendor.min.0123456789abcdef.css"), Some(("vendor.min", "css")));
+        assert_eq!(strip_fingerprint("app.min.js"), None);
+        assert_eq!(strip_fingerprint("app.a1b2.js"), None);
+        assert_eq!(strip_fingerprint("app.js"), None);
+        assert_eq!(strip_fingerprint(".a1b2c3.js"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("assets/fonts")).unwrap();
+        std::fs::write(root.path().join("assets/app.a1b2c3.js"), "").unwrap();
+        std::fs::write(root.path().join("assets/fonts/inter.0f0f0f0f.woff2"), "").unwrap();
+        std::fs::write(root.path().join("assets/style.css"), "").unwrap();
+
+        let fingerprints = AssetFingerprints::new("/assets/", &["js", "css", "woff2"]);
+        let resolve = |target| fingerprints.resolve(root.path(), target);
+        assert_eq!(resolve("/assets/app.js"), Some(FingerprintedTarget::Resolved("/assets/app.a1b2c3.js".into())));
+        assert_eq!(resolve("/assets/fonts/inter.woff2"), Some(FingerprintedTarget::Resolved("/assets/fonts/inter.0f0f0f0f.woff2".into())));
+        assert_eq!(resolve("/assets/app.a1b2c3.js"), Some(FingerprintedTarget::Fingerprinted));
+        assert_eq!(resolve("/assets/app.d4e5f6.js"), None);
+        assert_eq!(resolve("/assets/style.css"), None);
+        assert_eq!(resolve("/assets/missing.js"), None);
+        assert_eq!(resolve("/assets/app.html"), None);
+        assert_eq!(resolve("/app.js"), None);
+    }
+}
@@ -2,16 +2,20 @@
 // All Rights Reserved.
 
 use std::{
-    collections::HashMap
+    collections::HashMap,
+    sync::Arc,
 };
 
+use async_trait::async_trait;
 use servente_http::{
+    BodyKind,
     HeaderName,
     Method,
     Request,
     Response,
     StatusCode,
 };
+use tokio::io::{AsyncBufRead, AsyncSeekExt};
 
 use itertools::Itertools;
 
@@ -19,14 +23,61 @@ type HandlerReturnType = Result<Response, anyhow::Error>;
 
 pub type Handler = &'static dyn Fn(&Request) -> HandlerReturnType;
 
+/// The body of a request, as read by a [`StreamingHandler`].
+pub type RequestBody<'a> = dyn AsyncBufRead + Unpin + Send + 'a;
+
+/// A handler that reads the body of the request itself, instead of receiving
+/// it in [`Request::body`], e.g. to proxy a large upload without buffering it.
+///
+/// The body is delimited by the transport, so reading it until the end never
+/// reads into the next request. The transfer coding is decoded, but the
+/// content coding isn't, and the trailer section is discarded. When the
+/// handler doesn't read the whole body, the connection is closed after the
+/// response.
+#[async_trait]
+pub trait StreamingHandler: Send + Sync {
+    async fn handle(&self, request: &Request, body: &mut RequestBody<'_>) -> Result<Response, anyhow::Error>;
+}
+
+#[derive(Clone)]
+enum HandlerKind {
+    /// Invoked after the body was read into [`Request::body`].
+    Buffered(Handler),
+
+    Streaming(Arc<dyn StreamingHandler>),
+}
+
 #[derive(Clone)]
 struct HandlerInfo {
-    handler: Handler,
+    handler: HandlerKind,
+
+    /// Keep the body as it was received in [`Request::raw_body`].
+    keep_raw_body: bool,
+
+    /// Overrides [`crate::ServenteSettings::max_request_body_size`].
+    max_body_size: Option<usize>,
+
+    /// Never compress the response, see [`HandlerController::disable_compression`].
+    disable_compression: bool,
 }
 
+/// Manages the handlers that are registered by the embedder.
+///
+/// ## Precedence
+/// When a request is received, the following are tried in order:
+/// 1. Exact routes, registered using [`HandlerController::register`]
+/// 2. Patterns (not supported yet)
+/// 3. Static files in the `wwwroot`
+/// 4. The fallback handler, registered using
+///    [`HandlerController::register_fallback`]
+/// 5. The not-found handler, registered using
+///    [`HandlerController::register_not_found`]
+/// 6. `404 Not Found`
 #[derive(Clone, Default)]
 pub struct HandlerController {
     handlers: HashMap<String, HashMap<Method, HandlerInfo>>,
+    fallback: Option<Handler>,
+    not_found: Option<Handler>,
 }
 
 unsafe impl Send for HandlerController {}
@@ -36,30 +87,134 @@ impl HandlerController {
     pub fn new() -> Self {
         Self {
             handlers: HashMap::new(),
+            fallback: None,
+            not_found: None,
         }
     }
 
+    /// Invokes the handler of the request, if one is registered for the path.
+    ///
+    /// A [`StreamingHandler`] reads the `body` the transport passes, or the
+    /// buffered [`Request::body`] when there is none, e.g. because the
+    /// transport doesn't support streaming.
     #[must_use]
-    pub fn check_handle(&self, request: &Request) -> Option<Result<Response, anyhow::Error>> {
-        if let Some(handler_info) = self.handlers.get(request.target.as_str()) {
-            let handled = handler_info.get(&request.method).map(|handler_info| {
-                // TODO use block_on or something alike to avoid blocking the whole thread/task.
-                (handler_info.handler)(request)
-            });
-
-            match handled {
-                Some(response) => Some(response),
-                None => Some(Ok(Response::with_status_and_string_body(StatusCode::MethodNotAllowed, "Method Not Allowed"))),
+    pub async fn check_handle(&self, request: &Request, body: Option<&mut RequestBody<'_>>) -> Option<Result<Response, anyhow::Error>> {
+        let handler_info = self.handlers.get(request.target.as_str())?;
+
+        // HEAD is answered like GET, the transport omits the body.
+        let handler_info_for_method = match request.method {
+            Method::Head => handler_info.get(&Method::Head).or_else(|| handler_info.get(&Method::Get)),
+            _ => handler_info.get(&request.method),
+        };
+
+        let Some(handler_info) = handler_info_for_method else {
+            return Some(Ok(crate::method_not_allowed(&allowed_methods(handler_info))));
+        };
+
+        match &handler_info.handler {
+            // TODO use block_on or something alike to avoid blocking the whole thread/task.
+            HandlerKind::Buffered(handler) => Some(handler(request)),
+            HandlerKind::Streaming(handler) => {
+                if let Some(body) = body {
+                    return Some(handler.handle(request, body).await);
+                }
+
+                let mut buffered: &[u8] = match &request.body {
+                    Some(BodyKind::Bytes(bytes)) => bytes,
+                    Some(BodyKind::String(string)) => string.as_bytes(),
+
+                    // The body was spilled to a temporary file.
+                    Some(BodyKind::File { handle, .. }) => {
+                        let mut file = match handle.try_clone().await {
+                            Ok(file) => file,
+                            Err(error) => return Some(Err(error.into())),
+                        };
+                        if let Err(error) = file.rewind().await {
+                            return Some(Err(error.into()));
+                        }
+                        let mut file = tokio::io::BufReader::new(file);
+                        return Some(handler.handle(request, &mut file).await);
+                    }
+                    _ => &[],
+                };
+                Some(handler.handle(request, &mut buffered).await)
             }
-        } else {
-            None
         }
     }
 
+    /// Invokes the fallback handler, if one is registered.
+    #[must_use]
+    pub fn check_handle_fallback(&self, request: &Request) -> Option<Result<Response, anyhow::Error>> {
+        self.fallback.map(|handler| handler(request))
+    }
+
+    /// Invokes the not-found handler, if one is registered.
+    #[must_use]
+    pub fn check_handle_not_found(&self, request: &Request) -> Option<Result<Response, anyhow::Error>> {
+        self.not_found.map(|handler| handler(request))
+    }
+
     pub fn register(&mut self, path: String, handler: Handler, allowed_method: Method) {
-        let info = HandlerInfo {
-            handler,
-        };
+        self.register_info(path, allowed_method, HandlerInfo {
+            handler: HandlerKind::Buffered(handler),
+            keep_raw_body: false,
+            max_body_size: None,
+            disable_compression: false,
+        });
+    }
+
+    /// Registers a handler that receives the body exactly as it was received
+    /// in [`Request::raw_body`], alongside the parsed body. This is useful for
+    /// verifying the signature of webhooks, but costs a copy of the body, so
+    /// it's only done for these routes.
+    pub fn register_with_raw_body(&mut self, path: String, handler: Handler, allowed_method: Method) {
+        self.register_info(path, allowed_method, HandlerInfo {
+            handler: HandlerKind::Buffered(handler),
+            keep_raw_body: true,
+            max_body_size: None,
+            disable_compression: false,
+        });
+    }
+
+    /// Registers a handler that reads the body of the request itself, see
+    /// [`StreamingHandler`]. The [`Self::set_max_body_size`] still applies.
+    pub fn register_streaming(&mut self, path: String, handler: Arc<dyn StreamingHandler>, allowed_method: Method) {
+        self.register_info(path, allowed_method, HandlerInfo {
+            handler: HandlerKind::Streaming(handler),
+            keep_raw_body: false,
+            max_body_size: None,
+            disable_compression: false,
+        });
+    }
+
+    /// Overrides the [`crate::ServenteSettings::max_request_body_size`] for
+    /// the handler of this path and method, e.g. to accept large uploads on
+    /// a single route, or to only accept small bodies on another.
+    ///
+    /// # Panics
+    /// When no handler is registered for the path and method.
+    pub fn set_max_body_size(&mut self, path: &str, method: Method, max_body_size: usize) {
+        self.registered_info_mut(path, method).max_body_size = Some(max_body_size);
+    }
+
+    /// Never compress the responses of the handler of this path and method,
+    /// even when the body is compressible and the client accepts it, e.g.
+    /// for downloads that are already compressed, or endpoints where CPU time
+    /// matters more than bandwidth.
+    ///
+    /// # Panics
+    /// When no handler is registered for the path and method.
+    pub fn disable_compression(&mut self, path: &str, method: Method) {
+        self.registered_info_mut(path, method).disable_compression = true;
+    }
+
+    fn registered_info_mut(&mut self, path: &str, method: Method) -> &mut HandlerInfo {
+        self.handlers.get_mut(path)
+            .and_then(|handlers| handlers.get_mut(&method))
+            .unwrap_or_else(|| panic!("no handler is registered for {} {path}", method.as_string()))
+    }
+
+    fn register_info(&mut self, path: String, allowed_method: Method, info: HandlerInfo) {
         match self.handlers.entry(path) {
             std::collections::hash_map::Entry::Occupied(mut entry) => {
                 entry.get_mut().insert(allowed_method, info);
@@ -72,6 +227,53 @@ impl HandlerController {
         }
     }
 
+    /// Whether the handler of the request wants the body as it was received.
+    /// The transport should call this after reading the headers, before the
+    /// body is read.
+    #[must_use]
+    pub fn wants_raw_body(&self, request: &Request) -> bool {
+        self.handlers.get(request.target.as_str())
+            .and_then(|handlers| handlers.get(&request.method))
+            .is_some_and(|handler_info| handler_info.keep_raw_body)
+    }
+
+    /// Whether the handler of the request reads the body itself, in which
+    /// case the transport shouldn't read it. Like [`Self::wants_raw_body`],
+    /// this is used before the body is read.
+    #[must_use]
+    pub fn wants_streaming_body(&self, request: &Request) -> bool {
+        self.handlers.get(request.target.as_str())
+            .and_then(|handlers| handlers.get(&request.method))
+            .is_some_and(|handler_info| matches!(handler_info.handler, HandlerKind::Streaming(_)))
+    }
+
+    /// Whether the response to the request may be compressed by the
+    /// transport, which isn't the case when the handler of the request
+    /// disabled it, see [`Self::disable_compression`].
+    #[must_use]
+    pub fn allows_compression(&self, request: &Request) -> bool {
+        let Some(handlers) = self.handlers.get(request.target.as_str()) else {
+            return true;
+        };
+
+        // HEAD is answered like GET, so the header fields must be the same.
+        let handler_info = match request.method {
+            Method::Head => handlers.get(&Method::Head).or_else(|| handlers.get(&Method::Get)),
+            _ => handlers.get(&request.method),
+        };
+        !handler_info.is_some_and(|handler_info| handler_info.disable_compression)
+    }
+
+    /// The maximum size of the body of the request, if the handler of the
+    /// request overrides the one of the settings. Like
+    /// [`Self::wants_raw_body`], this is used before the body is read.
+    #[must_use]
+    pub fn max_body_size(&self, request: &Request) -> Option<usize> {
+        self.handlers.get(request.target.as_str())
+            .and_then(|handlers| handlers.get(&request.method))
+            .and_then(|handler_info| handler_info.max_body_size)
+    }
+
     /// If the request is referencing a resource which is a handler, returns the
     /// capabilities of that request. Otherwise, returns `None`.
     pub fn check_handle_options(&self, request: &Request) -> Option<Response> {
@@ -82,11 +284,7 @@ impl HandlerController {
         let mut response = Response::with_status(StatusCode::Ok);
         response.headers.set_content_length(0);
 
-        #[allow(unstable_name_collisions)]
-        let allowed_methods: String = handler_info.keys().map(|header| header.as_string())
-            .intersperse(", ")
-            .collect();
-
+        let allowed_methods = crate::format_allowed_methods(&allowed_methods(handler_info));
         response.headers.append_or_override(HeaderName::Allow, allowed_methods.clone().into());
         _ = response.headers.append(HeaderName::AccessControlAllowMethods, allowed_methods.into());
 
@@ -108,4 +306,31 @@ impl HandlerController {
     pub fn register_post(&mut self, path: String, handler: Handler) {
         self.register(path, handler, Method::Post);
     }
+
+    /// Registers a catch-all handler that is invoked when no route and no
+    /// static file matches the request, e.g. to serve the `index.html` of a
+    /// single-page application that uses client-side routing.
+    pub fn register_fallback(&mut self, handler: Handler) {
+        self.fallback = Some(handler);
+    }
+
+    /// Registers a handler that produces the response when the resource
+    /// can't be found, e.g. to suggest similar pages. The handler should
+    /// normally respond with `404 Not Found`, but the status of the
+    /// response it returns is used as-is.
+    pub fn register_not_found(&mut self, handler: Handler) {
+        self.not_found = Some(handler);
+    }
+}
+
+/// The methods the handlers of a path support. `HEAD` is answered like `GET`
+/// when it has no handler of its own, and `OPTIONS` is always answered.
+fn allowed_methods(handler_info: &HashMap<Method, HandlerInfo>) -> Vec<Method> {
+    let implicit_head = handler_info.contains_key(&Method::Get).then_some(Method::Head);
+    handler_info.keys().cloned()
+        .chain(implicit_head)
+        .chain(std::iter::once(Method::Options))
+        .sorted()
+        .dedup()
+        .collect()
 }
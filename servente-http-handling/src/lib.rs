@@ -1,10 +1,24 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+pub mod canonical_host;
+pub mod client_limit;
 pub mod config;
+pub mod connection;
+pub mod date;
+pub mod fingerprint;
 pub mod handler;
+pub mod logging;
+pub mod maintenance;
+pub mod memory;
 pub mod middleware;
+pub mod rate_limit;
+pub mod reload;
 pub mod responses;
+pub mod rewrite;
+pub mod stats;
+pub mod transform;
+pub mod validation;
 
 use std::path::PathBuf;
 use std::{
@@ -17,26 +31,92 @@ use middleware::ExchangeState;
 use servente_http::{HttpParseError, lists::find_best_match_in_weighted_list};
 
 use servente_http::*;
-use servente_resources::{MediaType, static_resources, CachedFileDetails, cache};
+use servente_resources::{ContentCoding, ContentEncodedVersions, MediaType, static_resources, CachedFileDetails, cache, embedded::EmbeddedFiles};
+
+pub use client_limit::{
+    ClientConnectionLimit,
+    ClientLimitExceeded,
+};
 
 pub use config::{
+    ClientHintsSettings,
+    DocumentSource,
+    EnvironmentError,
+    FileBackend,
+    HeaderOrder,
+    HstsConfig,
+    SecurityHeaderSetting,
+    SecurityHeaderSettings,
     ServenteConfig,
     ServenteSettings,
+    SymlinkPolicy,
+};
+
+pub use connection::{
+    ConnectionInfo,
+    ExchangeId,
+    Scheme,
+    TlsParameters,
+};
+
+pub use fingerprint::AssetFingerprints;
+
+pub use logging::RequestLogger;
+
+pub use maintenance::MaintenanceMode;
+
+pub use memory::MemoryBudget;
+
+pub use middleware::{AuthMiddleware, LoggingMiddleware, Middleware};
+
+pub use rate_limit::RateLimiter;
+
+pub use reload::ReloadableConfig;
+
+pub use rewrite::{
+    RewriteAction,
+    RewriteRule,
+};
+
+pub use stats::{
+    ServerStats,
+    StatsSnapshot,
 };
 
-pub use middleware::Middleware;
+pub use transform::BodyTransform;
+
+pub use validation::{
+    AuthScheme,
+    AuthValidator,
+    RequestValidator,
+    SensitivePathValidator,
+};
 
 /// Checks if the request is not modified and returns a 304 response if it isn't.
-fn check_not_modified(request: &Request, path: &Path, modified_date: SystemTime) -> Option<Response> {
+///
+/// When the strong `content_hash_etag` of the file is known, `If-None-Match`
+/// is compared against that instead of the weak, modification-time based
+/// ETag, and `If-Modified-Since` is ignored when `If-None-Match` is present,
+/// since the modification time might not be reliable.
+fn check_not_modified(request: &Request, path: &Path, modified_date: SystemTime, content_hash_etag: Option<&str>) -> Option<Response> {
     if let Some(etag) = request.headers.get(&HeaderName::IfNoneMatch) {
         if let Some(etag_as_str) = etag.as_str_no_convert() {
-            if etag_as_str == format_system_time_as_weak_etag(modified_date) {
+            let matches = match content_hash_etag {
+                Some(content_hash_etag) => entity_tag_list_contains(etag_as_str, content_hash_etag),
+                None => etag_as_str == format_system_time_as_weak_etag(modified_date),
+            };
+
+            if matches {
                 let mut response = Response::with_status_and_string_body(StatusCode::NotModified, String::new());
                 response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::from_path(path.to_string_lossy().as_ref()).clone()));
-                response.headers.append_or_override(HeaderName::ETag, etag.clone());
+                response.headers.append_or_override(HeaderName::ETag, content_hash_etag.map_or_else(|| etag.clone(), |tag| tag.to_owned().into()));
                 return Some(response);
             }
         }
+
+        if content_hash_etag.is_some() {
+            return None;
+        }
     }
 
     if let Some(if_modified_since) = request.headers.get(&HeaderName::IfModifiedSince) {
@@ -59,16 +139,32 @@ fn check_not_modified(request: &Request, path: &Path, modified_date: SystemTime)
     None
 }
 
+/// Checks if the `If-None-Match` list contains the `etag`, using the weak
+/// comparison.
+///
+/// # References
+/// * [RFC 9110 Section 13.1.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-if-none-match)
+fn entity_tag_list_contains(list: &str, etag: &str) -> bool {
+    list.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
 /// Finds the file as provided by the request path in the specified `wwwroot`.
 ///
 /// This function also validates the contents of the request path, hence
 /// returning an error of type [`Response`] when it occurs.
 pub fn find_request_path_in_wwwroot(root: &Path, request_target: &str) -> Result<PathBuf, Response> {
-    let Ok(url_decoded) = urlencoding::decode(&request_target[1..]) else {
+    let url_decoded = urlencoding::decode_binary(&request_target.as_bytes()[1..]);
+    let Some(normalized) = normalize_path(&url_decoded) else {
+        return Err(Response::with_status_and_string_body(StatusCode::Forbidden, "Forbidden"));
+    };
+
+    let Some(relative_path) = path_from_bytes(normalized) else {
         return Err(Response::with_status_and_string_body(StatusCode::BadRequest, "Bad Request"));
     };
 
-    let path = root.join(url_decoded.into_owned());
+    let path = root.join(relative_path);
     if !path.starts_with(&root) {
         return Err(Response::with_status_and_string_body(StatusCode::Forbidden, format!("Forbidden\n{}\n{}", root.display(), path.display())));
     }
@@ -82,14 +178,120 @@ pub fn find_request_path_in_wwwroot(root: &Path, request_target: &str) -> Result
     Ok(path)
 }
 
+/// Checks the path in the `wwwroot` against the symlink policy, returning a
+/// `403 Forbidden` response if it may not be served.
+fn check_symlink_policy(root: &Path, path: &Path, policy: SymlinkPolicy) -> Option<Response> {
+    let forbidden = || Some(Response::with_status_and_string_body(StatusCode::Forbidden, "Forbidden"));
+
+    match policy {
+        SymlinkPolicy::Follow => None,
+        SymlinkPolicy::RejectAll => {
+            let Ok(relative_path) = path.strip_prefix(root) else {
+                return forbidden();
+            };
+
+            let mut current = root.to_path_buf();
+            for component in relative_path.components() {
+                current.push(component);
+                match std::fs::symlink_metadata(&current) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => return forbidden(),
+                    Ok(_) => (),
+                    // The remainder doesn't exist, so it will be not found.
+                    Err(_) => break,
+                }
+            }
+            None
+        }
+        SymlinkPolicy::RejectEscaping => {
+            // Paths that don't resolve can't be served anyway.
+            let (Ok(canonical_root), Ok(canonical_path)) = (std::fs::canonicalize(root), std::fs::canonicalize(path)) else {
+                return None;
+            };
+
+            if canonical_path.starts_with(canonical_root) {
+                None
+            } else {
+                forbidden()
+            }
+        }
+    }
+}
+
+/// Normalizes the percent-decoded path (without the leading slash), so that
+/// equivalent paths map to the same file and cache entry. Dot segments are
+/// removed and duplicate slashes are collapsed, but a trailing slash is kept.
+///
+/// Returns `None` when a `..` segment would escape the root.
+///
+/// # References
+/// * [RFC 3986 Section 5.2.4](https://www.rfc-editor.org/rfc/rfc3986.html#section-5.2.4)
+fn normalize_path(path: &[u8]) -> Option<Vec<u8>> {
+    let mut segments: Vec<&[u8]> = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in path.split(|byte| *byte == b'/') {
+        trailing_slash = true;
+        match segment {
+            b"" | b"." => (),
+            b".." => {
+                segments.pop()?;
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = segments.join(&b'/');
+    if trailing_slash && !normalized.is_empty() {
+        normalized.push(b'/');
+    }
+    Some(normalized)
+}
+
+/// Percent-decodes and normalizes the path of a request target the way the
+/// file is looked up in the `wwwroot`, so that rules for a part of the site
+/// see the same path, e.g. `/%61dmin//x` and `/pub/../admin/x` are both
+/// `/admin/x`.
+///
+/// Returns `None` when a `..` segment would escape the root, which the file
+/// server rejects with a `403 Forbidden`.
+pub fn normalize_request_path(path: &str) -> Option<Vec<u8>> {
+    let decoded = urlencoding::decode_binary(path.trim_start_matches('/').as_bytes());
+    let mut normalized = normalize_path(&decoded)?;
+    normalized.insert(0, b'/');
+    Some(normalized)
+}
+
+/// Converts the percent-decoded request target to a path. On Unix, paths are
+/// arbitrary bytes, so file names that aren't valid UTF-8 can be served as
+/// well.
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+/// Converts the percent-decoded request target to a path, which must be valid
+/// UTF-8 on this platform.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
 /// Finishes a response for an error response.
-pub async fn finish_response_error(response: &mut Response) {
+pub async fn finish_response_error(response: &mut Response, settings: &ServenteSettings) {
     response.headers.append_or_override(HeaderName::Connection, HeaderValue::from("close"));
-    finish_response_general(response).await
+    if response.body.is_some() && !response.headers.contains(&HeaderName::ContentType) {
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+    }
+
+    finish_response_general(response, settings).await
 }
 
 /// Finishes a response for both normal and error response.
-async fn finish_response_general(response: &mut Response) {
+async fn finish_response_general(response: &mut Response, settings: &ServenteSettings) {
     if let Some(body) = &response.body {
         if !response.headers.contains(&HeaderName::LastModified) {
             if let BodyKind::File { metadata, ..} = body {
@@ -100,40 +302,166 @@ async fn finish_response_general(response: &mut Response) {
         }
     }
 
-    response.headers.append_or_override(HeaderName::Server, HeaderValue::from("servente"));
+    // Without a Content-Length, clients have to read the body until the
+    // connection is closed, so always supply it when the length is known.
+    if !response.headers.contains(&HeaderName::ContentLength) {
+        if let Some(length) = in_memory_body_length(response) {
+            response.headers.set_content_length(length);
+        }
+    }
 
-    #[cfg(feature = "http2")]
-    { _ = response.headers.append(HeaderName::AltSvc, HeaderValue::from("h2=\":8080\"")) }
+    for (name, value) in settings.default_headers.iter() {
+        if !response.headers.contains(name) {
+            _ = response.headers.append(name.clone(), value.clone());
+        }
+    }
 
-    #[cfg(feature = "http3")]
-    { _ = response.headers.append(HeaderName::AltSvc, HeaderValue::from("h3=\":8080\"")) }
+    // HTTP/2 is negotiated using ALPN on the same connection, so only HTTP/3
+    // (which runs over UDP) needs to be advertised.
+    //
+    // # References
+    // * [RFC 7838](https://www.rfc-editor.org/rfc/rfc7838.html)
+    if let Some(port) = settings.http3_port {
+        _ = response.headers.append(HeaderName::AltSvc, HeaderValue::from(format!("h3=\":{port}\"")));
+    }
 
-    response.headers.append_or_override(HeaderName::XFrameOptions, "DENY".into());
-    response.headers.append_or_override(HeaderName::XXSSProtection, "X-XSS-Protection: 1; mode=block".into());
-    response.headers.append_or_override(HeaderName::XContentTypeOptions, "nosniff".into());
+    let security_headers = &settings.security_headers;
+    apply_security_header(response, HeaderName::XFrameOptions, &security_headers.x_frame_options, "DENY");
+    apply_security_header(response, HeaderName::XXSSProtection, &security_headers.x_xss_protection, "1; mode=block");
+    apply_security_header(response, HeaderName::XContentTypeOptions, &security_headers.x_content_type_options, "nosniff");
 
     if !response.headers.contains(&HeaderName::Connection) {
         _ = response.headers.append(HeaderName::Connection, HeaderValue::from("keep-alive"));
     }
 
     if !response.headers.contains(&HeaderName::Date) {
-        response.headers.append_or_override(HeaderName::Date, SystemTime::now().into());
+        response.headers.append_or_override(HeaderName::Date, date::current_date());
     }
 }
 
+/// A cached body might not have a version in the selected content coding, so
+/// the body has to be compressed now. When that fails, the uncompressed version
+/// is sent without the `Content-Encoding`, since the client would otherwise try
+/// to decode uncompressed data.
+fn resolve_cached_body_encoding(response: &mut Response, encode: fn(&ContentCoding, &Vec<u8>) -> Option<Vec<u8>>) {
+    let Some(BodyKind::CachedBytes(versions, Some(coding))) = &response.body else {
+        return;
+    };
+
+    if versions.try_get_version(Some(*coding)).is_some() {
+        return;
+    }
+
+    match encode(coding, &versions.uncompressed) {
+        Some(encoded) => response.body = Some(BodyKind::Bytes(encoded)),
+        None => {
+            response.body = Some(BodyKind::CachedBytes(Arc::clone(versions), None));
+            response.headers.remove(&HeaderName::ContentEncoding);
+        }
+    }
+
+    // The length of the selected version wasn't known yet.
+    response.headers.remove(&HeaderName::ContentLength);
+}
+
+/// Returns the length of the body, if it is stored in memory.
+fn in_memory_body_length(response: &Response) -> Option<usize> {
+    match response.body.as_ref()? {
+        BodyKind::Bytes(bytes) => Some(bytes.len()),
+        BodyKind::CachedBytes(versions, coding) => Some(versions.get_version(*coding).len()),
+        BodyKind::StaticString(string) => Some(string.len()),
+        BodyKind::String(string) => Some(string.len()),
+        BodyKind::File { .. } | BodyKind::Shared(..) | BodyKind::Stream(..) => None,
+    }
+}
+
+/// Sets the security header according to its setting, unless the handler
+/// already set it, e.g. to `X-Frame-Options: SAMEORIGIN` for a page that is
+/// meant to be embedded.
+fn apply_security_header(response: &mut Response, name: HeaderName, setting: &SecurityHeaderSetting, default: &'static str) {
+    if response.headers.contains(&name) {
+        return;
+    }
+
+    match setting {
+        SecurityHeaderSetting::Default => _ = response.headers.append(name, default.into()),
+        SecurityHeaderSetting::Custom(value) => _ = response.headers.append(name, value.clone().into()),
+        SecurityHeaderSetting::Omit => (),
+    }
+}
+
+/// Returns whether the `Content-Type` of the response is `text/html`.
+fn is_html(response: &Response) -> bool {
+    response.headers.get(&HeaderName::ContentType).is_some_and(|media_type| {
+        let media_type = media_type.as_str_may_convert();
+        media_type.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("text/html")
+    })
+}
+
+/// Sets the policy header of an HTML response, unless the handler (or e.g.
+/// the welcome page) already set one.
+fn apply_html_policy(response: &mut Response, name: HeaderName, policy: &Option<String>) {
+    if let Some(policy) = policy {
+        if !response.headers.contains(&name) {
+            _ = response.headers.append(name, policy.clone().into());
+        }
+    }
+}
+
+/// Asks for the client hints using the header of an HTML response, unless the
+/// handler already set it.
+fn apply_client_hints(response: &mut Response, name: HeaderName, hints: &[HeaderName]) {
+    if hints.is_empty() || response.headers.contains(&name) {
+        return;
+    }
+
+    let value = hints.iter().map(HeaderName::to_string_h1).collect::<Vec<_>>().join(", ");
+    _ = response.headers.append(name, value.into());
+}
+
 /// Finishes a response for a normal (OK) response.
-pub async fn finish_response_normal(request: &Request, response: &mut Response) {
+pub async fn finish_response_normal(request: &Request, response: &mut Response, settings: &ServenteSettings) {
     if response.body.is_some() {
         if !response.headers.contains(&HeaderName::ContentType) {
-            response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::from_path(request.target.as_str()).clone()));
+            // Error messages don't have the media type of the target, e.g.
+            // a `404 Not Found` for `/logo.png` isn't an image.
+            let media_type = match response.status.class() {
+                StatusCodeClass::ClientError | StatusCodeClass::ServerError => MediaType::PLAIN_TEXT,
+                _ => MediaType::from_path(request.target.as_str()).clone(),
+            };
+            response.headers.set_content_type(media_type);
         }
 
+        resolve_cached_body_encoding(response, ContentCoding::encode);
+        transform::apply_body_transforms(response, &settings.body_transforms);
+
         if response.status.class() == StatusCodeClass::Success && !response.headers.contains(&HeaderName::CacheControl) {
             _ = response.headers.append(HeaderName::CacheControl, HeaderValue::from("max-age=120"));
         }
+
+        if is_html(response) {
+            let security_headers = &settings.security_headers;
+            apply_html_policy(response, HeaderName::ContentSecurityPolicy, &security_headers.content_security_policy);
+            apply_html_policy(response, HeaderName::ContentSecurityPolicyReportOnly, &security_headers.content_security_policy_report_only);
+            apply_client_hints(response, HeaderName::AcceptCH, &settings.client_hints.accept);
+            apply_client_hints(response, HeaderName::CriticalCH, &settings.client_hints.critical);
+        }
     }
 
-    finish_response_general(response).await
+    finish_response_general(response, settings).await
+}
+
+/// Adds the `Strict-Transport-Security` header configured by
+/// [`ServenteSettings::hsts`]. Browsers ignore it on plaintext connections,
+/// so the transport only adds it to responses sent over TLS.
+pub fn finish_response_strict_transport_security(response: &mut Response, settings: &ServenteSettings, connection_info: &ConnectionInfo) {
+    if connection_info.scheme != Scheme::Https {
+        return;
+    }
+
+    if let Some(hsts) = &settings.hsts {
+        response.headers.append_or_override(HeaderName::StrictTransportSecurity, hsts.header_value().into());
+    }
 }
 
 /// Handle an OPTIONS request.
@@ -152,10 +480,14 @@ async fn handle_options(request: &Request, settings: &ServenteSettings) -> Respo
         return response;
     }
 
-    // TODO: support static resources.
-    // if let RequestTarget::Origin { path, .. } = &request.target {
-    //
-    // }
+    if let RequestTarget::Origin { path, .. } = &request.target {
+        if find_static_resource(path.as_str(), settings).is_some() {
+            let mut response = Response::with_status(StatusCode::Ok);
+            _ = response.headers.append(HeaderName::Allow, format_allowed_methods(&STATIC_RESOURCE_METHODS).into());
+            response.headers.set_content_length(0);
+            return response;
+        }
+    }
 
     Response::not_found("Not Found")
 }
@@ -169,31 +501,303 @@ fn handle_options_asterisk() -> Response {
     response
 }
 
+/// The methods the static resources support. `HEAD` is answered like `GET`.
+const STATIC_RESOURCE_METHODS: [Method; 3] = [Method::Get, Method::Head, Method::Options];
+
+/// Formats the methods a resource supports as the value of the `Allow`
+/// header.
+///
+/// # References
+/// * [RFC 9110 Section 10.2.1](https://www.rfc-editor.org/rfc/rfc9110.html#name-allow)
+pub(crate) fn format_allowed_methods(methods: &[Method]) -> String {
+    methods.iter().map(Method::as_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Creates the `405 Method Not Allowed` response, of which both the `Allow`
+/// header and the body list the methods the resource does support.
+///
+/// # References
+/// * [RFC 9110 Section 15.5.6](https://www.rfc-editor.org/rfc/rfc9110.html#name-405-method-not-allowed)
+pub(crate) fn method_not_allowed(allowed_methods: &[Method]) -> Response {
+    let allowed_methods = format_allowed_methods(allowed_methods);
+    let mut response = Response::with_status_and_string_body(StatusCode::MethodNotAllowed,
+        format!("Method Not Allowed\nAllowed methods: {allowed_methods}"));
+    _ = response.headers.append(HeaderName::Allow, allowed_methods.into());
+    response
+}
+
+/// The kind of static resource a request target refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StaticResource {
+    File,
+    Directory,
+}
+
+/// Finds the static file or directory the request target refers to.
+fn find_static_resource(request_target: &str, settings: &ServenteSettings) -> Option<StaticResource> {
+    match &settings.document_source {
+        DocumentSource::Embedded(files) => {
+            let url_decoded = urlencoding::decode_binary(&request_target.as_bytes()[1..]);
+            let path = String::from_utf8(normalize_path(&url_decoded)?).ok()?;
+            if files.contains(&path) {
+                Some(StaticResource::File)
+            } else {
+                files.is_directory(&path).then_some(StaticResource::Directory)
+            }
+        }
+        DocumentSource::FileSystem => {
+            let path = find_request_path_in_wwwroot(settings.document_root.as_deref()?, request_target).ok()?;
+            match std::fs::metadata(path) {
+                Ok(metadata) if metadata.is_dir() => Some(StaticResource::Directory),
+                Ok(_) => Some(StaticResource::File),
+                Err(_) => None,
+            }
+        }
+    }
+}
+
+/// Redirects the request for a directory to the path with a trailing slash,
+/// keeping the query, see [`ServenteSettings::redirect_directories`].
+fn redirect_to_directory(path: &str, query: &str) -> Response {
+    let mut location = format!("{path}/");
+    if !query.is_empty() {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    Response::redirect(StatusCode::MovedPermanently, location)
+}
+
 /// Handles a `HttpParseError`.
 ///
 /// Servers SHOULD explain the error to the client, but this might be a security
 /// risk, so we might want to make this optional.
 pub async fn handle_parse_error(error: HttpParseError) -> Response {
+    if let HttpParseError::UnsupportedVersion { major, minor } = error {
+        let body = format!("<h1>HTTP Version Not Supported</h1>
+<hr>
+<p>HTTP/{major}.{minor} is not supported, use HTTP/1.1 instead.</p>");
+        let mut response = Response::with_status_and_string_body(StatusCode::HTTPVersionNotSupported, body);
+        response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::HTML));
+        return response;
+    }
+
     let body = format!("<h1>Bad Request<h1>
 <hr>
 <p>{}</p>", error.as_ref());
-    let mut response = Response::with_status_and_string_body(StatusCode::BadRequest, body);
+    let status = match error {
+        HttpParseError::UnknownTransferCoding => StatusCode::NotImplemented,
+        HttpParseError::BodyTooLarge => StatusCode::ContentTooLarge,
+        HttpParseError::UnknownContentCoding => StatusCode::UnsupportedMediaType,
+        _ => StatusCode::BadRequest,
+    };
+    let mut response = Response::with_status_and_string_body(status, body);
     response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::HTML));
+
+    // Tell the client which content codings it can use instead.
+    //
+    // # References
+    // * [RFC 9110 Section 15.5.16](https://www.rfc-editor.org/rfc/rfc9110.html#section-15.5.16)
+    if error == HttpParseError::UnknownContentCoding {
+        response.headers.append_or_override(HeaderName::AcceptEncoding, HeaderValue::from("br, gzip"));
+    }
     response
 }
 
+/// Checks whether a request with `Expect: 100-continue` would be rejected
+/// regardless of its body, e.g. because it is too large. The transport then
+/// sends this final response instead of `100 Continue`, so the client doesn't
+/// have to transmit the body at all.
+///
+/// # References
+/// * [RFC 9110 Section 10.1.1](https://www.rfc-editor.org/rfc/rfc9110.html#section-10.1.1)
+pub async fn check_expect_continue(request: &Request, settings: &ServenteSettings) -> Option<Response> {
+    if let Some(response) = validation::validate_request(request, &settings.request_validators) {
+        return Some(response);
+    }
+
+    let max_body_size = settings.handler_controller.max_body_size(request)
+        .unwrap_or(settings.max_request_body_size);
+    let content_length = request.headers.get(&HeaderName::ContentLength)?.parse_number()?;
+    if content_length > max_body_size {
+        return Some(handle_parse_error(HttpParseError::BodyTooLarge).await);
+    }
+
+    None
+}
+
+/// Checks whether the `Expect` header of the request contains an expectation
+/// other than `100-continue`, which can't be met, and returns the
+/// `417 Expectation Failed` response if it should be rejected.
+///
+/// # References
+/// * [RFC 9110 Section 10.1.1](https://www.rfc-editor.org/rfc/rfc9110.html#section-10.1.1)
+pub fn check_unknown_expectations(request: &Request, settings: &ServenteSettings) -> Option<Response> {
+    if settings.unknown_expectations == config::UnknownExpectationHandling::Ignore {
+        return None;
+    }
+
+    let expect = request.headers.get(&HeaderName::Expect)?.as_str_may_convert();
+    let unknown = servente_http::lists::parse_http_list(&expect)
+        .find(|expectation| !expectation.eq_ignore_ascii_case("100-continue"))?;
+    Some(Response::with_status_and_string_body(StatusCode::ExpectationFailed,
+        format!("The expectation \"{unknown}\" can't be met")))
+}
+
+/// Checks whether the request passes the [`ServenteSettings::request_validators`]
+/// and the [`ServenteSettings::rate_limiter`]. This is part of handling every
+/// request, but the transport calls it itself for requests it doesn't hand to
+/// [`handle_request`], such as a WebSocket handshake.
+pub fn check_request_admission(request: &Request, settings: &ServenteSettings) -> Option<Response> {
+    validation::validate_request(request, &settings.request_validators)
+        .or_else(|| settings.rate_limiter.as_deref().and_then(rate_limit::check_rate_limit))
+}
+
 /// Handles a request.
-pub async fn handle_request(request: &Request, settings: &ServenteSettings) -> Response {
-    let mut exchange_state = ExchangeState {
-        request,
-        response: handle_request_inner(request, settings).await,
+pub async fn handle_request(request: &mut Request, settings: &ServenteSettings, connection: &ConnectionInfo) -> Response {
+    handle_request_with_body(request, None, settings, connection).await
+}
+
+/// Handles a request of which the body wasn't read yet, because its handler
+/// reads the body itself. See [`handler::StreamingHandler`].
+pub async fn handle_streaming_request(request: &mut Request, body: &mut handler::RequestBody<'_>, settings: &ServenteSettings,
+        connection: &ConnectionInfo) -> Response {
+    handle_request_with_body(request, Some(body), settings, connection).await
+}
+
+async fn handle_request_with_body(request: &mut Request, body: Option<&mut handler::RequestBody<'_>>, settings: &ServenteSettings,
+        connection: &ConnectionInfo) -> Response {
+    let id = connection.next_exchange_id();
+    let mut original_target = None;
+    let response = if let Some(response) = check_request_admission(request, settings) {
+        response
+    } else if let Some(response) = maintenance::check_maintenance(request, &settings.maintenance_mode).await {
+        response
+    } else {
+        match rewrite::apply_rewrite_rules(&request.target, &settings.rewrite_rules) {
+            rewrite::RewriteOutcome::Unchanged => handle_request_with_middleware(request, body, settings, connection, id).await,
+            rewrite::RewriteOutcome::Internal(target) => {
+                original_target = Some(std::mem::replace(&mut request.target, target));
+
+                // The rewritten target is validated as well, otherwise e.g.
+                // `/v1/admin` would bypass the validators of `/admin`.
+                match validation::validate_request(request, &settings.request_validators) {
+                    Some(response) => response,
+                    None => handle_request_with_middleware(request, body, settings, connection, id).await,
+                }
+            }
+            rewrite::RewriteOutcome::Response(response) => *response,
+        }
     };
 
+    // The rewrite is internal, so the caller and the loggers still see the
+    // target the client requested.
+    if let Some(original_target) = original_target {
+        request.target = original_target;
+    }
+
+    if !settings.request_loggers.is_empty() {
+        let entry = logging::AccessLogEntry::new(id, request, &response, &settings.redacted_headers);
+        for logger in &settings.request_loggers {
+            logger.log(&entry);
+        }
+    }
+
+    response
+}
+
+async fn handle_request_with_middleware(request: &Request, body: Option<&mut handler::RequestBody<'_>>, settings: &ServenteSettings,
+        connection: &ConnectionInfo, id: ExchangeId) -> Response {
+    // Early data can be replayed, so only safe methods are processed. The
+    // client can retry the others after the handshake completed.
+    //
+    // # References
+    // * [RFC 8470 Section 5.2](https://www.rfc-editor.org/rfc/rfc8470.html#name-the-425-too-early-status-co)
+    if connection.early_data && !request.method.is_safe() {
+        return Response::with_status_and_string_body(StatusCode::TooEarly, "Too Early");
+    }
+
+    if let Some(response) = canonical_host::check_canonical_host(request, connection, &settings.canonical_host) {
+        return response;
+    }
+
+    let started = std::time::Instant::now();
+    let mut body = body;
+    let mut redirected_request = None;
+    for _ in 0..=middleware::MAX_INTERNAL_REDIRECTS {
+        let request = redirected_request.as_ref().unwrap_or(request);
+        let mut exchange_state = ExchangeState {
+            id,
+            request,
+            connection,
+            response: match respond_with_middleware(request, connection, settings).await {
+                Some(response) => response,
+                None => handle_request_inner(request, body.take(), settings).await,
+            },
+            started,
+            internal_redirect: None,
+        };
+
+        if let Some(response) = invoke_middleware(&mut exchange_state, settings).await {
+            return response;
+        }
+
+        let Some(target) = exchange_state.internal_redirect else {
+            return exchange_state.response;
+        };
+        let redirected = create_internal_redirect_request(request, target);
+
+        // The validators only saw the target the client requested, so e.g. a
+        // script can't redirect into an area that requires authentication.
+        if let Some(response) = validation::validate_request(&redirected, &settings.request_validators) {
+            return response;
+        }
+        redirected_request = Some(redirected);
+    }
+
+    #[cfg(feature = "debugging")]
+    println!("[Middleware] Exceeded {} internal redirects of {}", middleware::MAX_INTERNAL_REDIRECTS, request.target.as_str());
+
+    Response::with_status_and_string_body(StatusCode::InternalServerError, "Internal Server Error")
+}
+
+/// The request for the target of an internal redirect, which is requested
+/// using `GET` like Apache and nginx do, since the body was consumed already.
+fn create_internal_redirect_request(request: &Request, target: RequestTarget) -> Request {
+    let mut headers = request.headers.clone();
+    headers.remove(&HeaderName::ContentLength);
+    headers.remove(&HeaderName::ContentType);
+    headers.remove(&HeaderName::TransferEncoding);
+
+    Request {
+        method: Method::Get,
+        target,
+        version: request.version,
+        headers,
+        body: None,
+        raw_body: None,
+    }
+}
+
+/// The response of the first middleware that responds to the request before
+/// it is handled, see [`Middleware::respond`].
+async fn respond_with_middleware(request: &Request, connection: &ConnectionInfo, settings: &ServenteSettings) -> Option<Response> {
+    for middleware in &settings.middleware {
+        if let Some(response) = middleware.respond(request, connection).await {
+            return Some(response);
+        }
+    }
+    None
+}
+
+/// Invokes the middleware in order, until one redirects internally. Returns
+/// the response that should be sent instead when a middleware failed.
+async fn invoke_middleware(exchange_state: &mut ExchangeState<'_>, settings: &ServenteSettings) -> Option<Response> {
     for middleware in &settings.middleware {
         let mut middleware = Arc::clone(middleware);
         let middleware = dyn_clone::arc_make_mut(&mut middleware);
 
-        if let Err(e) = middleware.invoke(&mut exchange_state).await {
+        if let Err(e) = middleware.invoke(exchange_state).await {
             #[cfg(debug_assertions)]
             match e {
                 middleware::MiddlewareError::RecoverableError(e) => {
@@ -215,7 +819,7 @@ pub async fn handle_request(request: &Request, settings: &ServenteSettings) -> R
                         )
                     );
                     response.headers.set_content_type(MediaType::HTML);
-                    return response;
+                    return Some(response);
                 }
             }
 
@@ -223,84 +827,208 @@ pub async fn handle_request(request: &Request, settings: &ServenteSettings) -> R
             match e {
                 middleware::MiddlewareError::RecoverableError(_) => (),
                 middleware::MiddlewareError::UnrecoverableError(_) => {
-                    return Response::with_status_and_string_body(StatusCode::ServiceUnavailable, "Service Unavailable");
+                    return Some(Response::with_status_and_string_body(StatusCode::ServiceUnavailable, "Service Unavailable"));
                 }
             }
         }
+
+        if exchange_state.internal_redirect.is_some() {
+            break;
+        }
     }
 
-    exchange_state.response
+    None
+}
+
+/// Converts the result of a handler into a response.
+async fn handle_handler_result(result: Result<Response, anyhow::Error>) -> Response {
+    match result {
+        Ok(res) => res,
+        Err(e) => {
+            #[cfg(feature = "debugging")]
+            println!("[HTTP] Failed to invoke handler: {:#?}", e);
+
+            match e.downcast_ref::<std::io::Error>() {
+                Some(error) => Response::from_io_error(error),
+                None => Response::with_status_and_string_body(StatusCode::InternalServerError, "Internal Server Error"),
+            }
+        }
+    }
 }
 
-async fn handle_request_inner(request: &Request, settings: &ServenteSettings) -> Response {
+async fn handle_request_inner(request: &Request, body: Option<&mut handler::RequestBody<'_>>, settings: &ServenteSettings) -> Response {
     if request.method == Method::Options {
         return handle_options(request, settings).await;
     }
 
     // Method is not OPTIONS, so a request-target of "*" is not allowed anymore.
+    //
+    // # References
+    // * [RFC 9112 Section 3.2.4](https://www.rfc-editor.org/rfc/rfc9112.html#name-asterisk-form)
     if request.target == RequestTarget::Asterisk {
-        return Response::with_status_and_string_body(StatusCode::BadRequest, "Invalid Target");
+        return Response::with_status_and_string_body(StatusCode::BadRequest,
+            "The asterisk-form request target (*) is only allowed for OPTIONS requests");
     }
 
     let controller = settings.handler_controller.clone();
-    if let Some(result) = controller.check_handle(request) {
-        return match result {
-            Ok(res) => res,
-            Err(e) => {
-                #[cfg(feature = "debugging")]
-                println!("[HTTP] Failed to invoke handler: {:#?}", e);
-                _ = e;
-
-                let mut response = Response::with_status_and_string_body(StatusCode::InternalServerError, "Internal Server Error");
-                finish_response_general(&mut response).await;
-                response
-            }
-        };
+    if let Some(result) = controller.check_handle(request, body).await {
+        return handle_handler_result(result).await;
     }
 
-    if let RequestTarget::Origin { path, .. } = &request.target {
+    if let RequestTarget::Origin { path, query } = &request.target {
         let request_target = path.as_str();
-        if request.method != Method::Get {
-            let mut response = Response::with_status_and_string_body(StatusCode::MethodNotAllowed, "Method Not Allowed");
-            _ = response.headers.append(HeaderName::Allow, "GET".into());
-            return response;
+        if !STATIC_RESOURCE_METHODS.contains(&request.method) {
+            return method_not_allowed(&STATIC_RESOURCE_METHODS);
         }
 
-        let Ok(current_directory) = current_dir() else {
-            return handle_welcome_page(request, request_target).await;
-        };
+        if settings.redirect_directories && !request_target.ends_with('/')
+                && find_static_resource(request_target, settings) == Some(StaticResource::Directory) {
+            return redirect_to_directory(request_target, query);
+        }
+
+        if let DocumentSource::Embedded(files) = &settings.document_source {
+            return serve_from_embedded_files(request, &controller, files, request_target, &settings.index_files).await;
+        }
 
-        let root = current_directory.join("wwwroot");
-        let path = match find_request_path_in_wwwroot(&root, request_target) {
-            Ok(path) => path,
-            Err(response) => return response,
+        let root = match &settings.document_root {
+            Some(document_root) => document_root.clone(),
+            None => match current_dir() {
+                Ok(current_directory) => current_directory.join("wwwroot"),
+                Err(_) => return handle_welcome_page(request, request_target).await,
+            },
         };
 
-        if let Some(served_file_response) = serve_file(request, &path).await {
-            return served_file_response;
+        let fingerprinted = settings.asset_fingerprints.iter()
+            .find_map(|fingerprints| fingerprints.resolve(&root, request_target));
+        let request_target = match &fingerprinted {
+            Some(fingerprint::FingerprintedTarget::Resolved(target)) => target.as_str(),
+            _ => request_target,
         };
 
-        if let Ok(metadata) = std::fs::metadata(&path) {
-            if metadata.is_dir() {
-                let path = path.join("index.html");
-                if let Ok(metadata) = std::fs::metadata(&path) {
-                    if metadata.is_file() {
-                        if let Some(served_file_response) = serve_file(request, &path).await {
-                            return served_file_response;
-                        }
+        let mut response = serve_from_wwwroot(request, &controller, &root, request_target, &settings.index_files, settings.file_backend, settings.symlink_policy).await;
+        if fingerprinted.is_some() && matches!(response.status, StatusCode::Ok | StatusCode::PartialContent | StatusCode::NotModified) {
+            response.headers.append_or_override(HeaderName::CacheControl, HeaderValue::from(fingerprint::IMMUTABLE_CACHE_CONTROL));
+        }
+        return response;
+    }
+
+    Response::with_status_and_string_body(StatusCode::BadRequest,
+        "Only origin-form request targets (e.g. /index.html) are supported for this method")
+}
+
+/// Serves the `request_target` from the `root`, or one of the fallbacks if
+/// the corresponding file doesn't exist.
+async fn serve_from_wwwroot(request: &Request, controller: &handler::HandlerController, root: &Path, request_target: &str,
+        index_files: &[String], file_backend: FileBackend, symlink_policy: SymlinkPolicy) -> Response {
+    let path = match find_request_path_in_wwwroot(root, request_target) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+
+    if let Some(response) = check_symlink_policy(root, &path, symlink_policy) {
+        return response;
+    }
+
+    if let Some(served_file_response) = serve_file(request, &path, file_backend).await {
+        return served_file_response;
+    };
+
+    if let Ok(metadata) = std::fs::metadata(&path) {
+        if metadata.is_dir() {
+            let candidates: Vec<PathBuf> = index_files.iter()
+                .map(|index_file| path.join(index_file))
+                .filter(|path| std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()))
+                .collect();
+
+            if let Some(path) = select_index_file(request, &candidates) {
+                if let Some(response) = check_symlink_policy(root, path, symlink_policy) {
+                    return response;
+                }
+
+                if let Some(mut served_file_response) = serve_file(request, path, file_backend).await {
+                    if candidates.len() > 1 {
+                        _ = served_file_response.headers.append(HeaderName::Vary, "Accept".into());
                     }
+                    return served_file_response;
                 }
             }
         }
+    }
+
+    if let Some(result) = controller.check_handle_fallback(request) {
+        return handle_handler_result(result).await;
+    }
 
-        if !root.join("/index.html").exists() {
-            return handle_welcome_page(request, request_target).await;
+    if !root.join("index.html").exists() {
+        return handle_welcome_page(request, request_target).await;
+    }
+
+    if let Some(result) = controller.check_handle_not_found(request) {
+        return handle_handler_result(result).await;
+    }
+
+    Response::with_status_and_string_body(StatusCode::NotFound, "Not Found")
+}
+
+/// Serves the `request_target` from the embedded `files`, or one of the
+/// fallbacks if the corresponding file isn't embedded.
+async fn serve_from_embedded_files(request: &Request, controller: &handler::HandlerController, files: &EmbeddedFiles, request_target: &str,
+        index_files: &[String]) -> Response {
+    let url_decoded = urlencoding::decode_binary(&request_target.as_bytes()[1..]);
+    let Some(path) = normalize_path(&url_decoded).and_then(|path| String::from_utf8(path).ok()) else {
+        return Response::with_status_and_string_body(StatusCode::Forbidden, "Forbidden");
+    };
+
+    if let Some(cached) = files.get(&path) {
+        return serve_cached_versions(request, Path::new(&path), cached, None);
+    }
+
+    if files.is_directory(&path) {
+        let directory = Path::new(&path);
+        let candidates: Vec<PathBuf> = index_files.iter()
+            .map(|index_file| directory.join(index_file))
+            .filter(|path| path.to_str().is_some_and(|path| files.contains(path)))
+            .collect();
+
+        if let Some(index_path) = select_index_file(request, &candidates) {
+            if let Some(cached) = index_path.to_str().and_then(|path| files.get(path)) {
+                let mut response = serve_cached_versions(request, index_path, cached, None);
+                if candidates.len() > 1 {
+                    _ = response.headers.append(HeaderName::Vary, "Accept".into());
+                }
+                return response;
+            }
         }
+    }
+
+    if let Some(result) = controller.check_handle_fallback(request) {
+        return handle_handler_result(result).await;
+    }
 
-        return Response::with_status_and_string_body(StatusCode::NotFound, "Not Found");
+    if let Some(result) = controller.check_handle_not_found(request) {
+        return handle_handler_result(result).await;
     }
 
-    Response::with_status_and_string_body(StatusCode::BadRequest, "Invalid Target")
+    Response::with_status_and_string_body(StatusCode::NotFound, "Not Found")
+}
+
+/// Selects the index file of which the media type is the best match for the
+/// `Accept` header of the request. Falls back to the first candidate when the
+/// client has no preference, or accepts none of them.
+fn select_index_file<'a>(request: &Request, candidates: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    if candidates.len() > 1 {
+        if let Some(accept) = request.headers.get(&HeaderName::Accept) {
+            let media_types: Vec<&str> = candidates.iter()
+                .map(|candidate| MediaType::from_path(&candidate.to_string_lossy()).essence())
+                .collect();
+
+            if let Some(index) = servente_http::lists::find_best_match_in_media_range_list(&accept.as_str_may_convert(), &media_types) {
+                return candidates.get(index);
+            }
+        }
+    }
+
+    candidates.first()
 }
 
 /// Serves the welcome page to the client if the `wwwroot/index.html` file does
@@ -325,7 +1053,7 @@ async fn handle_welcome_page(request: &Request, request_target: &str) -> Respons
     _ = response.headers.append(HeaderName::CacheControl, "public, max-age=600".into());
     _ = response.headers.append(HeaderName::ContentSecurityPolicy, "default-src 'self'; upgrade-insecure-requests; style-src-elem 'self' 'unsafe-inline'".into());
 
-    response.body = Some(BodyKind::StaticString(static_resources::WELCOME_HTML));
+    let mut versions = &*static_resources::WELCOME_HTML_VERSIONS;
     _ = response.headers.append(HeaderName::ContentLanguage, "en".into());
     response.headers.append_or_override(HeaderName::LastModified, HeaderValue::from(SystemTime::UNIX_EPOCH));
     response.headers.append_or_override(HeaderName::ETag, "welcome-en".into());
@@ -343,7 +1071,7 @@ async fn handle_welcome_page(request: &Request, request_target: &str) -> Respons
                 if let Some(accepted_languages) = accepted_languages.as_str_no_convert() {
                     match find_best_match_in_weighted_list(accepted_languages, &["nl", "en"], 0.0) {
                         Some(0) => {
-                            response.body = Some(BodyKind::StaticString(static_resources::WELCOME_HTML_NL));
+                            versions = &*static_resources::WELCOME_HTML_NL_VERSIONS;
                             response.headers.append_or_override(HeaderName::ContentLanguage, "nl".into());
                             response.headers.append_or_override(HeaderName::ETag, "welcome-nl".into());
                             if request_etag == Some("welcome-nl") {
@@ -359,37 +1087,50 @@ async fn handle_welcome_page(request: &Request, request_target: &str) -> Respons
         }
         "/welcome.en.html" => (),
         "/welcome.nl.html" => {
-            response.body = Some(BodyKind::StaticString(static_resources::WELCOME_HTML_NL));
+            versions = &*static_resources::WELCOME_HTML_NL_VERSIONS;
             response.headers.append_or_override(HeaderName::ContentLanguage, "nl".into());
         }
         _ => return Response::with_status_and_string_body(StatusCode::NotFound, "Not Found"),
     }
 
+    let encoding = request.headers.get(&HeaderName::AcceptEncoding)
+        .and_then(|accept_encoding| accept_encoding.as_str_no_convert())
+        .and_then(|accept_encoding| versions.determine_best_version_from_accept_encoding(accept_encoding));
+    if let Some(encoding) = encoding {
+        response.headers.append_or_override(HeaderName::ContentEncoding, encoding.into());
+    }
+
+    _ = response.headers.append(HeaderName::Vary, "Accept-Encoding".into());
+    response.body = Some(BodyKind::CachedBytes(Arc::clone(versions), encoding));
     response
 }
 
-async fn serve_file(request: &Request, path: &Path) -> Option<Response> {
+async fn serve_file(request: &Request, path: &Path, file_backend: FileBackend) -> Option<Response> {
     if let Some(response) = serve_file_from_cache(request, path) {
         return Some(response);
     }
 
-    serve_file_from_disk(path).await
+    serve_file_from_disk(request, path, file_backend).await
 }
 
-/// Serves a file from the cache if it is available.
-async fn serve_file_from_disk(path: &Path) -> Option<Response> {
+/// Serves a file from the disk, using the given backend.
+async fn serve_file_from_disk(request: &Request, path: &Path, file_backend: FileBackend) -> Option<Response> {
     // Check if the file is allowed to be served. The cache already checked
     // this, but we need to check it again for files that are not cached.
     if !servente_resources::is_file_allowed_to_be_served(path.to_string_lossy().as_ref()) {
         return None;
     }
 
-    let Ok(file) = tokio::fs::File::open(path).await else {
-        return None;
+    // A missing file isn't an error, since one of the fallbacks might apply.
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(error) if matches!(error.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory) => return None,
+        Err(error) => return Some(Response::from_io_error(&error)),
     };
 
-    let Ok(metadata) = file.metadata().await else {
-        return None;
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(error) => return Some(Response::from_io_error(&error)),
     };
 
     if !metadata.is_file() {
@@ -409,23 +1150,61 @@ async fn serve_file_from_disk(path: &Path) -> Option<Response> {
 
     cache::maybe_cache_file(path).await;
 
+    let modified_date = metadata.modified().ok();
+    let cache_status = CacheStatus::forwarded(CACHE_NAME, CacheForwardReason::UriMiss);
+
+    // Validate the same way as cached files, so conditional requests don't
+    // depend on whether the file happens to be cached.
+    if let Some(modified_date) = modified_date {
+        if let Some(mut not_modified_response) = check_not_modified(request, path, modified_date, None) {
+            not_modified_response.headers.append_or_override(HeaderName::CacheStatus, cache_status.with_forward_status(304).into());
+            return Some(not_modified_response);
+        }
+    }
+
     let mut response = Response::with_status(StatusCode::Ok);
+    response.headers.append_or_override(HeaderName::CacheStatus, cache_status.into());
 
-    if let Ok(modified_date) = metadata.modified() {
+    // This also sets the weak, modification-time based ETag, which is the
+    // same one the cache uses.
+    if let Some(modified_date) = modified_date {
         response.headers.set_last_modified(modified_date);
     }
 
-    response.body = Some(BodyKind::File { handle: file, metadata });
+    response.body = Some(open_file_body(file, metadata, file_backend).await);
     response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::from_path(path.to_string_lossy().as_ref()).clone()));
 
     Some(response)
 }
 
+/// Creates the body for the opened file, according to the `file_backend`.
+async fn open_file_body(file: tokio::fs::File, metadata: std::fs::Metadata, file_backend: FileBackend) -> BodyKind {
+    match file_backend {
+        FileBackend::Read => BodyKind::File { handle: file, metadata },
+
+        #[cfg(all(unix, feature = "mmap"))]
+        FileBackend::Mmap => {
+            if !cache::is_file_stable(&metadata) {
+                return BodyKind::File { handle: file, metadata };
+            }
+
+            let file = file.into_std().await;
+            match servente_resources::mmap::MappedFile::map(&file) {
+                Ok(mapped) => BodyKind::Shared(Arc::new(mapped)),
+                Err(_) => BodyKind::File { handle: tokio::fs::File::from_std(file), metadata },
+            }
+        }
+    }
+}
+
+/// The name of the file cache in the `Cache-Status` header.
+const CACHE_NAME: &str = "ServenteCache";
+
 /// Serves a file from the cache if it is available.
 fn serve_file_from_cache(request: &Request, path: &Path) -> Option<Response> {
-    let Some(cached) = cache::FILE_CACHE.get(path.to_string_lossy().as_ref()) else {
-        return None
-    };
+    // The cache is keyed by UTF-8 paths, see `cache::maybe_cache_file`.
+    let path_str = path.to_str()?;
+    let cached = cache::FILE_CACHE.get(path_str)?;
 
     #[cfg(feature = "convert-markdown")]
     let cached = match &cached.value().cache_details {
@@ -436,14 +1215,30 @@ fn serve_file_from_cache(request: &Request, path: &Path) -> Option<Response> {
     #[cfg(not(feature = "convert-markdown"))]
     let cached = Arc::clone(cached.value());
 
+    Some(serve_cached_versions(request, path, cached, Some(CacheStatus::hit(CACHE_NAME).with_detail("MEMORY"))))
+}
+
+/// Serves the in-memory versions of the file, using the compressed version
+/// the client prefers.
+fn serve_cached_versions(request: &Request, path: &Path, cached: Arc<ContentEncodedVersions>, cache_status: Option<CacheStatus>) -> Response {
+    let content_hash_etag = cached.content_hash_etag();
     if let Some(modified_date) = cached.modified_date {
-        if let Some(not_modified_response) = check_not_modified(request, path, modified_date) {
-            return Some(not_modified_response);
+        if let Some(mut not_modified_response) = check_not_modified(request, path, modified_date, content_hash_etag.as_deref()) {
+            if let Some(cache_status) = cache_status {
+                not_modified_response.headers.append_or_override(HeaderName::CacheStatus, cache_status.into());
+            }
+            return not_modified_response;
         }
     }
 
     let mut response = Response::with_status(StatusCode::Ok);
 
+    // The strong ETag takes precedence over the weak one that is set with
+    // the `Last-Modified` below.
+    if let Some(content_hash_etag) = content_hash_etag {
+        response.headers.append_or_override(HeaderName::ETag, content_hash_etag.into());
+    }
+
     let encoding = if let Some(accept_encoding) = request.headers.get(&HeaderName::AcceptEncoding) {
         if let Some(accept_encoding) = accept_encoding.as_str_no_convert() {
             cached.determine_best_version_from_accept_encoding(accept_encoding)
@@ -464,7 +1259,9 @@ fn serve_file_from_cache(request: &Request, path: &Path) -> Option<Response> {
         response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::from_path(path.to_string_lossy().as_ref()).clone()));
     }
 
-    response.headers.append_or_override(HeaderName::CacheStatus, "ServenteCache; hit; detail=MEMORY".into());
+    if let Some(cache_status) = cache_status {
+        response.headers.append_or_override(HeaderName::CacheStatus, cache_status.into());
+    }
     if let Some(modified_date) = cached.modified_date {
         response.headers.set_last_modified(modified_date);
     }
@@ -477,7 +1274,7 @@ fn serve_file_from_cache(request: &Request, path: &Path) -> Option<Response> {
 
     response.body = Some(BodyKind::CachedBytes(cached, encoding));
 
-    Some(response)
+    response
 }
 
 /// Serve the welcome page response with a 304 Not Modified status code.
@@ -495,3 +1292,1013 @@ fn serve_welcome_page_not_modified(request: &Request) -> Response {
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use handler::HandlerController;
+    use middleware::MiddlewareError;
+
+    const SPA_SHELL: &str = "<!DOCTYPE html><div id=\"app\"></div>";
+
+    fn serve_spa_shell(_: &Request) -> Result<Response, anyhow::Error> {
+        Ok(Response::with_status_and_string_body(StatusCode::Ok, SPA_SHELL))
+    }
+
+    fn serve_api(_: &Request) -> Result<Response, anyhow::Error> {
+        Ok(Response::with_status_and_string_body(StatusCode::Ok, "api"))
+    }
+
+    fn create_request(path: &str) -> Request {
+        Request {
+            method: Method::Get,
+            target: RequestTarget::parse(path).unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    fn index_files() -> Vec<String> {
+        vec![String::from("index.html")]
+    }
+
+    fn create_settings() -> ServenteSettings {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/api".into(), &serve_api);
+        handler_controller.register_fallback(&serve_spa_shell);
+        ServenteSettings::new(handler_controller)
+    }
+
+    #[tokio::test]
+    async fn test_fallback_handler_unmatched_path() {
+        let response = handle_request(&mut create_request("/app/route"), &create_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(matches!(response.body, Some(BodyKind::StaticString(SPA_SHELL))));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_handler_exact_route_takes_precedence() {
+        let response = handle_request(&mut create_request("/api"), &create_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+    }
+
+    fn serve_custom_not_found(request: &Request) -> Result<Response, anyhow::Error> {
+        Ok(Response::with_status_and_string_body(StatusCode::NotFound, format!("Nothing at {}", request.target.as_str())))
+    }
+
+    fn serve_gone(_: &Request) -> Result<Response, anyhow::Error> {
+        Ok(Response::with_status_and_string_body(StatusCode::Gone, "Gone"))
+    }
+
+    fn create_wwwroot() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.html"), "<!DOCTYPE html>").unwrap();
+        root
+    }
+
+    #[tokio::test]
+    async fn test_not_found_without_handler() {
+        let root = create_wwwroot();
+        let response = serve_from_wwwroot(&create_request("/missing"), &HandlerController::new(), root.path(), "/missing", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("Not Found"))));
+    }
+
+    #[tokio::test]
+    async fn test_not_found_handler_custom_body() {
+        let root = create_wwwroot();
+        let mut controller = HandlerController::new();
+        controller.register_not_found(&serve_custom_not_found);
+
+        let response = serve_from_wwwroot(&create_request("/missing"), &controller, root.path(), "/missing", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+        match response.body {
+            Some(BodyKind::String(body)) => assert_eq!(body, "Nothing at /missing"),
+            body => panic!("unexpected body: {body:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_found_handler_overrides_status() {
+        let root = create_wwwroot();
+        let mut controller = HandlerController::new();
+        controller.register_not_found(&serve_gone);
+
+        let response = serve_from_wwwroot(&create_request("/missing"), &controller, root.path(), "/missing", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::Gone);
+
+        let response = serve_from_wwwroot(&create_request("/index.html"), &controller, root.path(), "/index.html", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_welcome_page_gzip() {
+        let mut request = create_request("/");
+        request.headers.append_or_override(HeaderName::AcceptEncoding, "gzip".into());
+
+        let response = handle_welcome_page(&request, "/").await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get(&HeaderName::ContentEncoding).and_then(|value| value.as_str_no_convert()), Some("gzip"));
+        assert!(response.headers.get_all(&HeaderName::Vary).any(|value| value.as_str_no_convert() == Some("Accept-Encoding")));
+
+        let Some(BodyKind::CachedBytes(versions, encoding)) = &response.body else {
+            panic!("unexpected body: {:?}", response.body);
+        };
+        assert_eq!(*encoding, Some(ContentCoding::Gzip));
+        assert!(versions.get_version(*encoding).starts_with(&[0x1f, 0x8b]));
+        assert_eq!(versions.uncompressed, static_resources::WELCOME_HTML.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_welcome_page_uncompressed_without_accept_encoding() {
+        let response = handle_welcome_page(&create_request("/"), "/").await;
+        assert!(!response.headers.contains(&HeaderName::ContentEncoding));
+        assert!(matches!(response.body, Some(BodyKind::CachedBytes(_, None))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_file_with_non_utf8_name() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = create_wwwroot();
+        let file_name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        std::fs::write(root.path().join(file_name), "latin-1").unwrap();
+
+        let controller = HandlerController::new();
+        let response = serve_from_wwwroot(&create_request("/caf%E9.txt"), &controller, root.path(), "/caf%E9.txt", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(matches!(response.body, Some(BodyKind::File { .. })));
+
+        let response = serve_from_wwwroot(&create_request("/caf%E8.txt"), &controller, root.path(), "/caf%E8.txt", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+
+        let response = serve_from_wwwroot(&create_request("/%2E%2E/caf%E9.txt"), &controller, root.path(), "/%2E%2E/caf%E9.txt", &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_default() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_normal(&create_request("/"), &mut response, &create_settings()).await;
+        assert_eq!(response.headers.get(&HeaderName::XFrameOptions).unwrap().as_str_no_convert(), Some("DENY"));
+        assert_eq!(response.headers.get(&HeaderName::XContentTypeOptions).unwrap().as_str_no_convert(), Some("nosniff"));
+    }
+
+    #[tokio::test]
+    async fn test_content_security_policy_for_html() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.html"), "<h1>Hello</h1>").unwrap();
+        std::fs::write(root.path().join("style.css"), "h1 { color: red; }").unwrap();
+        let mut settings = create_settings();
+        settings.document_root = Some(root.path().to_path_buf());
+
+        let mut response = handle_request(&mut create_request("/index.html"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        finish_response_normal(&create_request("/index.html"), &mut response, &settings).await;
+        assert!(!response.headers.contains(&HeaderName::ContentSecurityPolicy));
+
+        settings.security_headers.content_security_policy = Some(config::STARTER_CONTENT_SECURITY_POLICY.into());
+        settings.security_headers.content_security_policy_report_only = Some("default-src 'none'".into());
+        for (target, policy, report_only_policy) in [
+            ("/index.html", Some(config::STARTER_CONTENT_SECURITY_POLICY), Some("default-src 'none'")),
+            ("/style.css", None, None),
+        ] {
+            let mut response = handle_request(&mut create_request(target), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            finish_response_normal(&create_request(target), &mut response, &settings).await;
+            assert_eq!(response.headers.get(&HeaderName::ContentSecurityPolicy).and_then(|value| value.as_str_no_convert()), policy, "{target}");
+            assert_eq!(response.headers.get(&HeaderName::ContentSecurityPolicyReportOnly).and_then(|value| value.as_str_no_convert()), report_only_policy, "{target}");
+        }
+
+        let mut response = Response::with_status_and_string_body(StatusCode::Ok, "<h1>Hello</h1>");
+        response.headers.set_content_type(MediaType::HTML);
+        _ = response.headers.append(HeaderName::ContentSecurityPolicy, "default-src 'self' cdn.example.com".into());
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::ContentSecurityPolicy).unwrap().as_str_no_convert(), Some("default-src 'self' cdn.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_client_hints() {
+        let mut settings = create_settings();
+        settings.client_hints = ClientHintsSettings {
+            accept: vec![HeaderName::SecChUaPlatform, HeaderName::Other("Sec-CH-Viewport-Width".into())],
+            critical: vec![HeaderName::SecChUaPlatform],
+        };
+
+        let mut response = Response::with_status_and_string_body(StatusCode::Ok, "<h1>Hello</h1>");
+        response.headers.set_content_type(MediaType::HTML);
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::AcceptCH).unwrap().as_str_no_convert(), Some("Sec-Ch-Ua-Platform, Sec-CH-Viewport-Width"));
+        assert_eq!(response.headers.get(&HeaderName::CriticalCH).unwrap().as_str_no_convert(), Some("Sec-Ch-Ua-Platform"));
+
+        let mut response = Response::with_status_and_string_body(StatusCode::Ok, "h1 { color: red; }");
+        finish_response_normal(&create_request("/style.css"), &mut response, &settings).await;
+        assert!(!response.headers.contains(&HeaderName::AcceptCH));
+
+        let mut request = create_request("/");
+        _ = request.headers.append(HeaderName::SecChUa, r#""Chromium";v="124", "Not-A.Brand";v="99""#.into());
+        _ = request.headers.append(HeaderName::SecChUaMobile, "?0".into());
+        _ = request.headers.append(HeaderName::SecChUaPlatform, r#""Linux""#.into());
+        let brands = request.sec_ch_ua().unwrap();
+        assert_eq!((brands[0].brand.as_str(), brands[0].version.as_deref()), ("Chromium", Some("124")));
+        assert_eq!(request.sec_ch_ua_mobile(), Some(false));
+        assert_eq!(request.sec_ch_ua_platform().as_deref(), Some("Linux"));
+    }
+
+    #[test]
+    fn test_strict_transport_security() {
+        let mut settings = create_settings();
+        let tls = ConnectionInfo::new(Scheme::Https);
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_strict_transport_security(&mut response, &settings, &tls);
+        assert!(!response.headers.contains(&HeaderName::StrictTransportSecurity));
+
+        settings.hsts = Some(HstsConfig { max_age: std::time::Duration::from_secs(31536000), include_subdomains: true, preload: true });
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_strict_transport_security(&mut response, &settings, &ConnectionInfo::new(Scheme::Http));
+        assert!(!response.headers.contains(&HeaderName::StrictTransportSecurity));
+
+        finish_response_strict_transport_security(&mut response, &settings, &tls);
+        assert_eq!(response.headers.get(&HeaderName::StrictTransportSecurity).unwrap().as_str_no_convert(),
+            Some("max-age=31536000; includeSubDomains; preload"));
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_omit_x_frame_options() {
+        let mut settings = create_settings();
+        settings.security_headers.x_frame_options = SecurityHeaderSetting::Omit;
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert!(!response.headers.contains(&HeaderName::XFrameOptions));
+        assert_eq!(response.headers.get(&HeaderName::XContentTypeOptions).unwrap().as_str_no_convert(), Some("nosniff"));
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_override_x_frame_options() {
+        let mut settings = create_settings();
+        settings.security_headers.x_frame_options = SecurityHeaderSetting::Custom("SAMEORIGIN".into());
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_error(&mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::XFrameOptions).unwrap().as_str_no_convert(), Some("SAMEORIGIN"));
+    }
+
+    #[tokio::test]
+    async fn test_default_headers_keep_handler_values() {
+        let mut settings = create_settings();
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append_or_override(HeaderName::XFrameOptions, "SAMEORIGIN".into());
+        response.headers.append_or_override(HeaderName::Server, "my-app".into());
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::XFrameOptions).unwrap().as_str_no_convert(), Some("SAMEORIGIN"));
+        assert_eq!(response.headers.get(&HeaderName::Server).unwrap().as_str_no_convert(), Some("my-app"));
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::Server).unwrap().as_str_no_convert(), Some(config::DEFAULT_SERVER_HEADER));
+
+        settings.default_headers.remove(&HeaderName::Server);
+        settings.default_headers.append_or_override(HeaderName::Other("X-Powered-By".into()), "coffee".into());
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_normal(&create_request("/"), &mut response, &settings).await;
+        assert!(!response.headers.contains(&HeaderName::Server));
+        assert_eq!(response.headers.get(&HeaderName::Other("X-Powered-By".into())).unwrap().as_str_no_convert(), Some("coffee"));
+    }
+
+    fn fail(_: &Request) -> Result<Response, anyhow::Error> {
+        Err(anyhow::anyhow!("the handler failed"))
+    }
+
+    fn assert_has_length_and_type(response: &Response) {
+        let length = response.headers.get(&HeaderName::ContentLength)
+            .unwrap_or_else(|| panic!("{:?} has no Content-Length", response.status))
+            .parse_number();
+        assert_eq!(length, in_memory_body_length(response), "{:?}", response.status);
+        assert!(response.headers.contains(&HeaderName::ContentType), "{:?} has no Content-Type", response.status);
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_have_content_length_and_type() {
+        let mut response = handle_parse_error(HttpParseError::InvalidContentLength).await;
+        finish_response_error(&mut response, &create_settings()).await;
+        assert_eq!(response.status, StatusCode::BadRequest);
+        assert_has_length_and_type(&response);
+
+        let response = responses::create_request_timeout(&create_settings()).await;
+        assert_has_length_and_type(&response);
+
+        let root = create_wwwroot();
+        let controller = HandlerController::new();
+        for target in ["/logo.png", "/%2E%2E/secret.txt"] {
+            let request = create_request(target);
+            let mut response = serve_from_wwwroot(&request, &controller, root.path(), target, &index_files(), FileBackend::default(), SymlinkPolicy::default()).await;
+            finish_response_normal(&request, &mut response, &create_settings()).await;
+            assert!(matches!(response.status, StatusCode::NotFound | StatusCode::Forbidden), "{:?}", response.status);
+            assert_eq!(response.headers.get(&HeaderName::ContentType).unwrap().as_str_may_convert(), MediaType::PLAIN_TEXT.as_str());
+            assert_has_length_and_type(&response);
+        }
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/fail".into(), &fail);
+        let settings = ServenteSettings::new(handler_controller);
+        let mut request = create_request("/fail");
+        let mut response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        finish_response_normal(&request, &mut response, &settings).await;
+        assert_eq!(response.status, StatusCode::InternalServerError);
+        assert_has_length_and_type(&response);
+    }
+
+    #[tokio::test]
+    async fn test_request_loggers_see_redacted_headers() {
+        struct AuthorizationLogger(std::sync::Mutex<Option<String>>);
+
+        impl RequestLogger for AuthorizationLogger {
+            fn log(&self, entry: &logging::AccessLogEntry<'_>) {
+                *self.0.lock().unwrap() = entry.request_header(&HeaderName::Authorization).map(|value| value.into_owned());
+            }
+        }
+
+        let logger = Arc::new(AuthorizationLogger(Default::default()));
+        let mut settings = create_settings();
+        settings.request_loggers.push(Arc::clone(&logger) as Arc<dyn RequestLogger>);
+
+        let mut request = create_request("/api");
+        request.headers.append_or_override(HeaderName::Authorization, "Basic dXNlcjpwYXNz".into());
+        handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+
+        assert_eq!(logger.0.lock().unwrap().as_deref(), Some(logging::REDACTED));
+    }
+
+    fn create_cached_response_without_gzip_version() -> Response {
+        let versions = servente_resources::ContentEncodedVersions {
+            uncompressed: b"uncompressed body".to_vec(),
+            ..Default::default()
+        };
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append_or_override(HeaderName::ContentEncoding, ContentCoding::Gzip.into());
+        response.body = Some(BodyKind::CachedBytes(Arc::new(versions), Some(ContentCoding::Gzip)));
+        response
+    }
+
+    #[test]
+    fn test_resolve_cached_body_encoding_compresses_missing_version() {
+        let mut response = create_cached_response_without_gzip_version();
+        resolve_cached_body_encoding(&mut response, ContentCoding::encode);
+
+        assert!(matches!(response.body, Some(BodyKind::Bytes(_))));
+        assert!(response.headers.contains(&HeaderName::ContentEncoding));
+    }
+
+    #[tokio::test]
+    async fn test_encode_failure_falls_back_to_uncompressed() {
+        let mut response = create_cached_response_without_gzip_version();
+        resolve_cached_body_encoding(&mut response, |_, _| None);
+        finish_response_general(&mut response, &create_settings()).await;
+
+        assert!(!response.headers.contains(&HeaderName::ContentEncoding));
+        assert!(matches!(response.body, Some(BodyKind::CachedBytes(_, None))));
+        assert_eq!(response.headers.get(&HeaderName::ContentLength).and_then(HeaderValue::parse_number), Some(b"uncompressed body".len()));
+    }
+
+    #[tokio::test]
+    async fn test_non_origin_targets_are_rejected() {
+        let settings = ServenteSettings::new(HandlerController::new());
+        for (target, reason) in [("*", "asterisk-form"), ("http://example.com/index.html", "origin-form")] {
+            let mut request = create_request(target);
+            let mut response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            finish_response_normal(&request, &mut response, &settings).await;
+
+            assert_eq!(response.status, StatusCode::BadRequest);
+            assert_has_length_and_type(&response);
+            let Some(BodyKind::StaticString(body)) = response.body else {
+                panic!("unexpected body for {target}: {:?}", response.body);
+            };
+            assert!(body.contains(reason), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_options_asterisk_is_allowed() {
+        let mut request = create_request("*");
+        request.method = Method::Options;
+        let response = handle_request(&mut request, &create_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(response.headers.contains(&HeaderName::Allow));
+    }
+
+    #[tokio::test]
+    async fn test_early_data_only_allows_safe_methods() {
+        let mut connection = ConnectionInfo::new(Scheme::Https);
+        connection.early_data = true;
+
+        let response = handle_request(&mut create_request("/api"), &create_settings(), &connection).await;
+        assert_eq!(response.status, StatusCode::Ok);
+
+        let mut request = create_request("/api");
+        request.method = Method::Post;
+        let response = handle_request(&mut request, &create_settings(), &connection).await;
+        assert_eq!(response.status, StatusCode::TooEarly);
+
+        connection.early_data = false;
+        let response = handle_request(&mut request, &create_settings(), &connection).await;
+        assert_ne!(response.status, StatusCode::TooEarly);
+    }
+
+    #[cfg(all(unix, feature = "mmap"))]
+    #[tokio::test]
+    async fn test_file_backends_serve_the_same_bytes() {
+        use tokio::io::AsyncReadExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("large.bin");
+        let contents: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        // Only files that haven't been modified recently are mapped.
+        let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+
+        let mut read_body = Vec::new();
+        let Some(BodyKind::File { mut handle, .. }) = serve_file_from_disk(&create_request("/large.bin"), &path, FileBackend::Read).await.unwrap().body else {
+            panic!("the read backend should produce a File body");
+        };
+        handle.read_to_end(&mut read_body).await.unwrap();
+
+        let Some(BodyKind::Shared(mapped)) = serve_file_from_disk(&create_request("/large.bin"), &path, FileBackend::Mmap).await.unwrap().body else {
+            panic!("the mmap backend should produce a Shared body");
+        };
+
+        assert!(read_body == contents);
+        assert!((*mapped).as_ref() == contents.as_slice());
+    }
+
+    #[cfg(all(unix, feature = "mmap"))]
+    #[tokio::test]
+    async fn test_mmap_backend_reads_unstable_files() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("fresh.txt");
+        std::fs::write(&path, "just written").unwrap();
+
+        let response = serve_file_from_disk(&create_request("/fresh.txt"), &path, FileBackend::Mmap).await.unwrap();
+        assert!(matches!(response.body, Some(BodyKind::File { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_alt_svc_advertises_configured_http3_port() {
+        let mut settings = create_settings();
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_general(&mut response, &settings).await;
+        assert!(!response.headers.contains(&HeaderName::AltSvc));
+
+        settings.http3_port = Some(8443);
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_general(&mut response, &settings).await;
+        assert_eq!(response.headers.get(&HeaderName::AltSvc).unwrap().as_str_may_convert(), "h3=\":8443\"");
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases: [(&str, Option<&str>); 11] = [
+            ("a/./b", Some("a/b")),
+            ("a//b", Some("a/b")),
+            ("a/../a/b", Some("a/b")),
+            ("/a/b", Some("a/b")),
+            ("a/b/", Some("a/b/")),
+            ("a/b/.", Some("a/b/")),
+            ("a/b/..", Some("a/")),
+            ("", Some("")),
+            ("a/..", Some("")),
+            ("..", None),
+            ("a/../../b", None),
+        ];
+
+        for (path, expected) in cases {
+            let normalized = normalize_path(path.as_bytes());
+            assert_eq!(normalized.as_deref(), expected.map(str::as_bytes), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_equivalent_request_targets_find_the_same_path() {
+        let root = Path::new("/srv/wwwroot");
+        let expected = find_request_path_in_wwwroot(root, "/a/b").unwrap();
+        for target in ["/a/./b", "/a//b", "/a/../a/b", "/a/%2e/b"] {
+            assert_eq!(find_request_path_in_wwwroot(root, target).unwrap(), expected, "{target}");
+        }
+
+        let response = find_request_path_in_wwwroot(root, "/a/../../etc/passwd").unwrap_err();
+        assert_eq!(response.status, StatusCode::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_rules_are_applied_before_routing() {
+        let mut settings = create_settings();
+        settings.rewrite_rules = vec![
+            RewriteRule::internal("/v1/*", "/$1"),
+            RewriteRule::redirect("/old", "/api", StatusCode::PermanentRedirect),
+        ];
+
+        let mut request = create_request("/v1/api");
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+        assert_eq!(request.target, RequestTarget::parse("/v1/api").unwrap());
+
+        let response = handle_request(&mut create_request("/old"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::PermanentRedirect);
+        assert_eq!(response.headers.get(&HeaderName::Location).unwrap().as_str_no_convert(), Some("/api"));
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_targets_are_validated() {
+        let mut settings = create_settings();
+        settings.rewrite_rules = vec![
+            RewriteRule::internal("/v1/*", "/$1"),
+            RewriteRule::internal("/dotenv", "/.env"),
+        ];
+        let auth = AuthValidator::new().with_area("/api", AuthScheme::bearer_tokens("api", ["secret-token"]));
+        settings.request_validators.push(Arc::new(auth));
+
+        let mut request = create_request("/v1/api");
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Unauthorized);
+        assert_eq!(request.target, RequestTarget::parse("/v1/api").unwrap());
+
+        let mut request = create_request("/v1/api");
+        request.headers.append_or_override(HeaderName::Authorization, "Bearer secret-token".into());
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+
+        let response = handle_request(&mut create_request("/dotenv"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let mut settings = create_settings();
+        settings.maintenance_mode = Arc::new(MaintenanceMode::new().with_bypass_path("/healthz"));
+
+        let maintenance_mode = Arc::clone(&settings.maintenance_mode);
+        maintenance_mode.set_enabled(true);
+
+        let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::ServiceUnavailable);
+        assert_eq!(response.headers.get(&HeaderName::RetryAfter).unwrap().as_str_no_convert(), Some("300"));
+
+        maintenance_mode.set_enabled(false);
+        let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_bypass_and_page() {
+        let mut page = tempfile::Builder::new().suffix(".html").tempfile().unwrap();
+        std::io::Write::write_all(&mut page, b"<h1>Back soon</h1>").unwrap();
+
+        let mut settings = create_settings();
+        settings.maintenance_mode = Arc::new(MaintenanceMode::new()
+            .with_page(page.path())
+            .with_bypass_path("/api")
+            .with_retry_after(std::time::Duration::from_secs(60)));
+        settings.maintenance_mode.set_enabled(true);
+
+        let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+
+        let response = handle_request(&mut create_request("/"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::ServiceUnavailable);
+        assert_eq!(response.headers.get(&HeaderName::RetryAfter).unwrap().as_str_no_convert(), Some("60"));
+        assert!(matches!(&response.body, Some(BodyKind::Bytes(body)) if body == b"<h1>Back soon</h1>"));
+    }
+
+    #[tokio::test]
+    async fn test_head_is_routed_like_get() {
+        let mut request = create_request("/api");
+        request.method = Method::Head;
+        let response = handle_request(&mut request, &create_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+
+        let mut request = create_request("/index.html");
+        request.method = Method::Post;
+        let response = handle_request(&mut request, &create_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.headers.get(&HeaderName::Allow).unwrap().as_str_no_convert(), Some("GET, HEAD, OPTIONS"));
+        assert!(matches!(&response.body, Some(BodyKind::String(body)) if body.ends_with("GET, HEAD, OPTIONS")));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_directories() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("docs")).unwrap();
+        std::fs::write(root.path().join("docs/index.html"), "<a href=\"intro.html\">Intro</a>").unwrap();
+        let mut settings = create_settings();
+        settings.document_root = Some(root.path().to_path_buf());
+
+        for (target, redirect_directories, status, location) in [
+            ("/docs?page=2", false, StatusCode::Ok, None),
+            ("/docs?page=2", true, StatusCode::MovedPermanently, Some("/docs/?page=2")),
+            ("/docs", true, StatusCode::MovedPermanently, Some("/docs/")),
+            ("/docs/", true, StatusCode::Ok, None),
+            ("/docs/index.html", true, StatusCode::Ok, None),
+        ] {
+            settings.redirect_directories = redirect_directories;
+            let response = handle_request(&mut create_request(target), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            assert_eq!(response.status, status, "{target}");
+            assert_eq!(response.headers.get(&HeaderName::Location).and_then(|location| location.as_str_no_convert()), location, "{target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_asset_fingerprints() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("assets")).unwrap();
+        std::fs::write(root.path().join("assets/app.a1b2c3.js"), "console.log(1);").unwrap();
+        std::fs::write(root.path().join("assets/about.html"), "<h1>About</h1>").unwrap();
+        let mut settings = create_settings();
+        settings.document_root = Some(root.path().to_path_buf());
+
+        // Without an index file, missing files would be answered with the
+        // welcome page.
+        std::fs::write(root.path().join("index.html"), "<h1>Home</h1>").unwrap();
+
+        for (target, fingerprinted, immutable) in [
+            ("/assets/app.js", false, false),
+            ("/assets/app.js", true, true),
+            ("/assets/app.a1b2c3.js", true, true),
+            ("/assets/app.d4e5f6.js", true, false),
+            ("/assets/about.html", true, false),
+        ] {
+            settings.asset_fingerprints = if fingerprinted { vec![AssetFingerprints::new("/assets/", &["js", "css"])] } else { Vec::new() };
+            let response = handle_request(&mut create_request(target), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            assert_eq!(response.headers.get(&HeaderName::ContentType).and_then(|value| value.as_str_no_convert()).is_some_and(|media_type| media_type.contains("javascript")), immutable, "{target}");
+            let cache_control = response.headers.get(&HeaderName::CacheControl).and_then(|value| value.as_str_no_convert());
+            assert_eq!(cache_control == Some(fingerprint::IMMUTABLE_CACHE_CONTROL), immutable, "{target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowed_methods_match_options() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.html"), "<h1>Hello</h1>").unwrap();
+        let mut settings = create_settings();
+        settings.document_root = Some(root.path().to_path_buf());
+
+        for (target, method, status) in [
+            ("/index.html", Method::Options, StatusCode::Ok),
+            ("/index.html", Method::Post, StatusCode::MethodNotAllowed),
+            ("/api", Method::Options, StatusCode::Ok),
+            ("/api", Method::Post, StatusCode::MethodNotAllowed),
+        ] {
+            let mut request = create_request(target);
+            request.method = method;
+            let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            assert_eq!(response.status, status, "{target}");
+            assert_eq!(response.headers.get(&HeaderName::Allow).unwrap().as_str_no_convert(), Some("GET, HEAD, OPTIONS"), "{target}");
+        }
+
+        let mut request = create_request("/missing.html");
+        request.method = Method::Options;
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get_of_uncached_file() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("page.txt");
+        std::fs::write(&path, "contents").unwrap();
+
+        let response = serve_file_from_disk(&create_request("/page.txt"), &path, FileBackend::Read).await.unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+        let etag = response.headers.get(&HeaderName::ETag).unwrap().clone();
+        let last_modified = response.headers.get(&HeaderName::LastModified).unwrap().clone();
+
+        let mut request = create_request("/page.txt");
+        request.headers.append_or_override(HeaderName::IfNoneMatch, etag.clone());
+        let response = serve_file_from_disk(&request, &path, FileBackend::Read).await.unwrap();
+        assert_eq!(response.status, StatusCode::NotModified);
+        assert_eq!(response.headers.get(&HeaderName::ETag), Some(&etag));
+
+        let mut request = create_request("/page.txt");
+        request.headers.append_or_override(HeaderName::IfModifiedSince, last_modified);
+        let response = serve_file_from_disk(&request, &path, FileBackend::Read).await.unwrap();
+        assert_eq!(response.status, StatusCode::NotModified);
+
+        let mut request = create_request("/page.txt");
+        request.headers.append_or_override(HeaderName::IfNoneMatch, "W/0".into());
+        let response = serve_file_from_disk(&request, &path, FileBackend::Read).await.unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    /// Creates a `wwwroot` with a symlink to a file inside of it, and one to a
+    /// file outside of it.
+    #[cfg(unix)]
+    fn create_wwwroot_with_symlinks() -> tempfile::TempDir {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().join("wwwroot");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("index.html"), "index").unwrap();
+        std::fs::write(directory.path().join("secret.txt"), "secret").unwrap();
+
+        std::os::unix::fs::symlink(root.join("index.html"), root.join("inside.html")).unwrap();
+        std::os::unix::fs::symlink(directory.path().join("secret.txt"), root.join("escaping.txt")).unwrap();
+        directory
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_policies() {
+        let directory = create_wwwroot_with_symlinks();
+        let root = directory.path().join("wwwroot");
+        let controller = HandlerController::new();
+
+        let cases = [
+            (SymlinkPolicy::Follow, "/inside.html", StatusCode::Ok),
+            (SymlinkPolicy::Follow, "/escaping.txt", StatusCode::Ok),
+            (SymlinkPolicy::RejectAll, "/index.html", StatusCode::Ok),
+            (SymlinkPolicy::RejectAll, "/inside.html", StatusCode::Forbidden),
+            (SymlinkPolicy::RejectAll, "/escaping.txt", StatusCode::Forbidden),
+            (SymlinkPolicy::RejectEscaping, "/inside.html", StatusCode::Ok),
+            (SymlinkPolicy::RejectEscaping, "/escaping.txt", StatusCode::Forbidden),
+            (SymlinkPolicy::RejectEscaping, "/missing.txt", StatusCode::NotFound),
+            (SymlinkPolicy::Deny, "/inside.html", StatusCode::Forbidden),
+            (SymlinkPolicy::FollowWithinRoot, "/inside.html", StatusCode::Ok),
+            (SymlinkPolicy::FollowWithinRoot, "/escaping.txt", StatusCode::Forbidden),
+        ];
+
+        for (policy, target, expected) in cases {
+            let response = serve_from_wwwroot(&create_request(target), &controller, &root, target, &index_files(), FileBackend::default(), policy).await;
+            assert_eq!(response.status, expected, "{policy:?} {target}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlinked_directory_index_is_checked() {
+        let directory = create_wwwroot_with_symlinks();
+        let root = directory.path().join("wwwroot");
+        std::fs::create_dir(directory.path().join("outside")).unwrap();
+        std::fs::write(directory.path().join("outside").join("index.html"), "outside").unwrap();
+        std::os::unix::fs::symlink(directory.path().join("outside"), root.join("assets")).unwrap();
+
+        let controller = HandlerController::new();
+        let response = serve_from_wwwroot(&create_request("/assets/"), &controller, &root, "/assets/", &index_files(), FileBackend::default(), SymlinkPolicy::RejectEscaping).await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+
+        let response = serve_from_wwwroot(&create_request("/assets/"), &controller, &root, "/assets/", &index_files(), FileBackend::default(), SymlinkPolicy::Follow).await;
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_global_rate_limit() {
+        let mut settings = create_settings();
+        settings.rate_limiter = Some(Arc::new(RateLimiter::new(1.0, 2)));
+
+        let mut statuses = Vec::new();
+        for _ in 0..5 {
+            let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            if response.status == StatusCode::TooManyRequests {
+                assert_eq!(response.headers.get(&HeaderName::RetryAfter).unwrap().as_str_no_convert(), Some("1"));
+            }
+            statuses.push(response.status);
+        }
+
+        assert_eq!(statuses, [
+            StatusCode::Ok,
+            StatusCode::Ok,
+            StatusCode::TooManyRequests,
+            StatusCode::TooManyRequests,
+            StatusCode::TooManyRequests,
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_paths_are_rejected_before_handling() {
+        let settings = create_settings();
+
+        let response = handle_request(&mut create_request("/.env"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Forbidden);
+
+        let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+    }
+
+    #[tokio::test]
+    async fn test_index_is_negotiated_using_accept() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("index.html"), "<!DOCTYPE html>").unwrap();
+        std::fs::write(root.path().join("index.json"), "{}").unwrap();
+        let index_files = [String::from("index.html"), String::from("index.json")];
+
+        for (accept, expected_media_type) in [
+            (None, "text/html"),
+            (Some("*/*"), "text/html"),
+            (Some("text/html"), "text/html"),
+            (Some("application/json"), "application/json"),
+            (Some("text/html;q=0.9, application/json"), "application/json"),
+            (Some("image/png"), "text/html"),
+        ] {
+            let mut request = create_request("/");
+            if let Some(accept) = accept {
+                request.headers.append_or_override(HeaderName::Accept, accept.into());
+            }
+
+            let response = serve_from_wwwroot(&request, &HandlerController::new(), root.path(), "/", &index_files, FileBackend::default(), SymlinkPolicy::default()).await;
+            assert_eq!(response.status, StatusCode::Ok, "{accept:?}");
+            let content_type = response.headers.get(&HeaderName::ContentType).unwrap().as_str_may_convert().into_owned();
+            assert!(content_type.starts_with(expected_media_type), "{accept:?}: {content_type}");
+            assert!(response.headers.get_all(&HeaderName::Vary).any(|value| value.as_str_no_convert() == Some("Accept")), "{accept:?}");
+        }
+
+        // Without alternatives, the response doesn't vary on Accept.
+        std::fs::remove_file(root.path().join("index.html")).unwrap();
+        let response = serve_from_wwwroot(&create_request("/"), &HandlerController::new(), root.path(), "/", &index_files, FileBackend::default(), SymlinkPolicy::default()).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(!response.headers.get_all(&HeaderName::Vary).any(|value| value.as_str_no_convert() == Some("Accept")));
+    }
+
+    #[tokio::test]
+    async fn test_serve_from_embedded_files() {
+        let files = Arc::new(EmbeddedFiles::new(&[
+            ("index.html", b"<!DOCTYPE html>"),
+            ("css/style.css", b"body { color: red; }"),
+        ]));
+        let mut settings = ServenteSettings::new(HandlerController::new());
+        settings.document_source = DocumentSource::Embedded(Arc::clone(&files));
+        let connection = ConnectionInfo::new(Scheme::Http);
+
+        let response = handle_request(&mut create_request("/css/style.css"), &settings, &connection).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get(&HeaderName::ContentType).unwrap().as_str_no_convert(), Some("text/css; charset=utf-8"));
+        assert!(response.headers.contains(&HeaderName::LastModified));
+        assert!(matches!(response.body, Some(BodyKind::CachedBytes(..))));
+
+        // The files can be revalidated using the ETag based on the load time.
+        let mut request = create_request("/css/style.css");
+        let etag = format_system_time_as_weak_etag(files.get("css/style.css").unwrap().modified_date.unwrap());
+        request.headers.append_or_override(HeaderName::IfNoneMatch, etag.into());
+        let response = handle_request(&mut request, &settings, &connection).await;
+        assert_eq!(response.status, StatusCode::NotModified);
+
+        let response = handle_request(&mut create_request("/"), &settings, &connection).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(response.headers.get(&HeaderName::ContentType).unwrap().as_str_may_convert().starts_with("text/html"));
+
+        let response = handle_request(&mut create_request("/wwwroot/index.html"), &settings, &connection).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_content_hash_etag() {
+        let modified_date = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let mut versions = ContentEncodedVersions::create(b"body { color: red; }".to_vec());
+        versions.modified_date = Some(modified_date);
+        versions.compute_content_hash();
+        let versions = Arc::new(versions);
+        let etag = versions.content_hash_etag().unwrap();
+        let path = Path::new("style.css");
+
+        let response = serve_cached_versions(&create_request("/style.css"), path, Arc::clone(&versions), None);
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get(&HeaderName::ETag).unwrap().as_str_no_convert(), Some(etag.as_str()));
+        assert!(response.headers.contains(&HeaderName::LastModified));
+
+        for if_none_match in [etag.clone(), format!("W/{etag}"), format!("\"other\", {etag}"), String::from("*")] {
+            let mut request = create_request("/style.css");
+            request.headers.append_or_override(HeaderName::IfNoneMatch, if_none_match.clone().into());
+            let response = serve_cached_versions(&request, path, Arc::clone(&versions), None);
+            assert_eq!(response.status, StatusCode::NotModified, "{if_none_match}");
+            assert_eq!(response.headers.get(&HeaderName::ETag).unwrap().as_str_no_convert(), Some(etag.as_str()));
+        }
+
+        // The weak ETag of the modification time doesn't validate the file
+        // anymore, and neither does If-Modified-Since next to If-None-Match.
+        let mut request = create_request("/style.css");
+        request.headers.append_or_override(HeaderName::IfNoneMatch, format_system_time_as_weak_etag(modified_date).into());
+        request.headers.append_or_override(HeaderName::IfModifiedSince, HeaderValue::DateTime(modified_date));
+        let response = serve_cached_versions(&request, path, Arc::clone(&versions), None);
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_handler_io_errors() {
+        for (kind, status) in [
+            (std::io::ErrorKind::NotFound, StatusCode::NotFound),
+            (std::io::ErrorKind::PermissionDenied, StatusCode::Forbidden),
+            (std::io::ErrorKind::TimedOut, StatusCode::GatewayTimeout),
+            (std::io::ErrorKind::BrokenPipe, StatusCode::InternalServerError),
+        ] {
+            let response = handle_handler_result(Err(std::io::Error::new(kind, "upstream").into())).await;
+            assert_eq!(response.status, status, "{kind:?}");
+        }
+
+        let response = handle_handler_result(Err(anyhow::anyhow!("not an I/O error"))).await;
+        assert_eq!(response.status, StatusCode::InternalServerError);
+    }
+
+    #[derive(Clone)]
+    struct RedirectMiddleware {
+        from: &'static str,
+        to: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for RedirectMiddleware {
+        fn debug_identifier(&self) -> &str {
+            "RedirectMiddleware"
+        }
+
+        async fn invoke(&mut self, state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+            if let RequestTarget::Origin { path, .. } = &state.request.target {
+                if path.as_str() == self.from {
+                    state.redirect_internally(RequestTarget::parse(self.to).unwrap());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_internal_redirects() {
+        let mut settings = create_settings();
+        settings.middleware.push(Arc::new(RedirectMiddleware { from: "/old", to: "/api" }));
+        settings.middleware.push(Arc::new(RedirectMiddleware { from: "/loop", to: "/loop" }));
+
+        // The redirected request is served as a GET.
+        let mut request = create_request("/old");
+        request.method = Method::Post;
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Ok);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+
+        let response = handle_request(&mut create_request("/loop"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::InternalServerError);
+
+        // The redirected request is validated as well.
+        let auth = AuthValidator::new().with_area("/api", AuthScheme::bearer_tokens("api", ["secret-token"]));
+        settings.request_validators.push(Arc::new(auth));
+        let response = handle_request(&mut create_request("/old"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware() {
+        let mut settings = create_settings();
+        settings.middleware.push(Arc::new(RedirectMiddleware { from: "/old", to: "/api" }));
+        settings.middleware.push(Arc::new(AuthMiddleware::new()
+            .with_area("/api", AuthScheme::bearer_tokens("api", ["secret-token"]))));
+
+        for target in ["/api", "/%61pi", "/old"] {
+            let response = handle_request(&mut create_request(target), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+            assert_eq!(response.status, StatusCode::Unauthorized, "{target}");
+            assert!(response.headers.contains(&HeaderName::WwwAuthenticate), "{target}");
+        }
+
+        let mut request = create_request("/api");
+        request.headers.append_or_override(HeaderName::Authorization, "Bearer secret-token".into());
+        let response = handle_request(&mut request, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString("api"))));
+
+        let response = handle_request(&mut create_request("/app/route"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString(SPA_SHELL))));
+    }
+
+    #[derive(Clone)]
+    struct RespondingMiddleware;
+
+    #[async_trait::async_trait]
+    impl Middleware for RespondingMiddleware {
+        fn debug_identifier(&self) -> &str {
+            "RespondingMiddleware"
+        }
+
+        async fn invoke(&mut self, state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+            state.response.headers.append_or_override(HeaderName::Server, "invoked".into());
+            Ok(())
+        }
+
+        async fn respond(&self, request: &Request, _connection: &ConnectionInfo) -> Option<Response> {
+            (request.target.as_str() == "/api").then(|| Response::with_status_and_string_body(StatusCode::Accepted, "responded"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_responds_before_handling() {
+        let mut settings = create_settings();
+        settings.middleware.push(Arc::new(RespondingMiddleware));
+
+        // The handler of /api isn't invoked, but the middleware still is.
+        let response = handle_request(&mut create_request("/api"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert_eq!(response.status, StatusCode::Accepted);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("responded"))));
+        assert_eq!(response.headers.get(&HeaderName::Server).unwrap().as_str_may_convert(), "invoked");
+
+        let response = handle_request(&mut create_request("/app/route"), &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(response.body, Some(BodyKind::StaticString(SPA_SHELL))));
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Logging of the exchanges, e.g. for access logs.
+
+use std::borrow::Cow;
+
+use servente_http::{
+    HeaderMap,
+    HeaderName,
+    HttpVersion,
+    Method,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+use crate::ExchangeId;
+
+/// The value logged in place of a redacted header.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Receives an entry for every request that was handled.
+pub trait RequestLogger: Send + Sync {
+    fn log(&self, entry: &AccessLogEntry<'_>);
+}
+
+/// The headers whose values are never exposed to the [`RequestLogger`]s,
+/// since they contain credentials. By default these are `Authorization`,
+/// `Cookie`, `Proxy-Authorization` and `Set-Cookie`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedactedHeaders(pub Vec<HeaderName>);
+
+impl RedactedHeaders {
+    pub fn contains(&self, header_name: &HeaderName) -> bool {
+        self.0.contains(header_name)
+    }
+}
+
+impl Default for RedactedHeaders {
+    fn default() -> Self {
+        Self(vec![
+            HeaderName::Authorization,
+            HeaderName::Cookie,
+            HeaderName::ProxyAuthorization,
+            HeaderName::SetCookie,
+        ])
+    }
+}
+
+/// A handled request and its response. The header values are only accessible
+/// through methods that apply the [`RedactedHeaders`].
+pub struct AccessLogEntry<'a> {
+    id: ExchangeId,
+    request: &'a Request,
+    response: &'a Response,
+    redacted_headers: &'a RedactedHeaders,
+}
+
+impl<'a> AccessLogEntry<'a> {
+    pub fn new(id: ExchangeId, request: &'a Request, response: &'a Response, redacted_headers: &'a RedactedHeaders) -> Self {
+        Self { id, request, response, redacted_headers }
+    }
+
+    /// Identifies the exchange on its connection, e.g. `conn=42 req=3`.
+    pub fn id(&self) -> ExchangeId {
+        self.id
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.request.method
+    }
+
+    pub fn target(&self) -> &RequestTarget {
+        &self.request.target
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.request.version
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.response.status
+    }
+
+    /// Get the value of a request header, which is [`REDACTED`] if the header
+    /// is sensitive.
+    pub fn request_header(&self, header_name: &HeaderName) -> Option<Cow<'a, str>> {
+        self.header(&self.request.headers, header_name)
+    }
+
+    /// Get the value of a response header, which is [`REDACTED`] if the header
+    /// is sensitive.
+    pub fn response_header(&self, header_name: &HeaderName) -> Option<Cow<'a, str>> {
+        self.header(&self.response.headers, header_name)
+    }
+
+    /// Iterate over the request headers, where the values of sensitive headers
+    /// are [`REDACTED`].
+    pub fn request_headers(&self) -> impl Iterator<Item = (&'a HeaderName, Cow<'a, str>)> + '_ {
+        self.headers(&self.request.headers)
+    }
+
+    /// Iterate over the response headers, where the values of sensitive
+    /// headers are [`REDACTED`].
+    pub fn response_headers(&self) -> impl Iterator<Item = (&'a HeaderName, Cow<'a, str>)> + '_ {
+        self.headers(&self.response.headers)
+    }
+
+    fn header(&self, headers: &'a HeaderMap, header_name: &HeaderName) -> Option<Cow<'a, str>> {
+        let value = headers.get(header_name)?;
+        if self.redacted_headers.contains(header_name) {
+            return Some(Cow::Borrowed(REDACTED));
+        }
+        Some(value.as_str_may_convert())
+    }
+
+    fn headers(&self, headers: &'a HeaderMap) -> impl Iterator<Item = (&'a HeaderName, Cow<'a, str>)> + '_ {
+        headers.iter().map(|(name, value)| {
+            if self.redacted_headers.contains(name) {
+                (name, Cow::Borrowed(REDACTED))
+            } else {
+                (name, value.as_str_may_convert())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CollectingLogger {
+        lines: Mutex<Vec<String>>,
+    }
+
+    impl RequestLogger for CollectingLogger {
+        fn log(&self, entry: &AccessLogEntry<'_>) {
+            for (name, value) in entry.request_headers() {
+                self.lines.lock().unwrap().push(format!("{}: {}", name.to_string_h1(), value));
+            }
+        }
+    }
+
+    fn create_request() -> Request {
+        let mut headers = HeaderMap::new();
+        headers.append_or_override(HeaderName::Authorization, "Bearer secret-token".into());
+        headers.append_or_override(HeaderName::UserAgent, "curl/8.0".into());
+        Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/").unwrap(),
+            version: HttpVersion::Http11,
+            headers,
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    #[test]
+    fn test_authorization_is_redacted() {
+        let request = create_request();
+        let response = Response::with_status(StatusCode::Ok);
+        let redacted_headers = RedactedHeaders::default();
+        let entry = AccessLogEntry::new(ExchangeId::default(), &request, &response, &redacted_headers);
+
+        assert_eq!(entry.request_header(&HeaderName::Authorization).as_deref(), Some(REDACTED));
+        assert_eq!(entry.request_header(&HeaderName::UserAgent).as_deref(), Some("curl/8.0"));
+
+        let logger = CollectingLogger::default();
+        logger.log(&entry);
+        let lines = logger.lines.into_inner().unwrap();
+        assert!(lines.contains(&"Authorization: [REDACTED]".to_string()), "{lines:?}");
+        assert!(!lines.iter().any(|line| line.contains("secret-token")), "{lines:?}");
+    }
+
+    #[test]
+    fn test_redaction_set_is_configurable() {
+        let request = create_request();
+        let response = Response::with_status(StatusCode::Ok);
+        let redacted_headers = RedactedHeaders(vec![HeaderName::UserAgent]);
+        let entry = AccessLogEntry::new(ExchangeId::default(), &request, &response, &redacted_headers);
+
+        assert_eq!(entry.request_header(&HeaderName::Authorization).as_deref(), Some("Bearer secret-token"));
+        assert_eq!(entry.request_header(&HeaderName::UserAgent).as_deref(), Some(REDACTED));
+    }
+}
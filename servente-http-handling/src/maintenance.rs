@@ -0,0 +1,122 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! A maintenance mode, which answers every request with
+//! `503 Service Unavailable`, except for the paths that bypass it (e.g.
+//! health checks).
+//!
+//! # References
+//! * [RFC 9110 Section 15.6.4](https://www.rfc-editor.org/rfc/rfc9110.html#name-503-service-unavailable)
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use servente_http::{
+    BodyKind,
+    HeaderName,
+    HeaderValue,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+use servente_resources::MediaType;
+
+/// The default value of [`MaintenanceMode::retry_after`].
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// The maintenance mode of the server. This is shared between the settings
+/// and the operator by wrapping it in an `Arc`, so it can be toggled without
+/// restarting the server.
+#[derive(Debug, Default)]
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+    page: Option<PathBuf>,
+    bypass_paths: Vec<String>,
+    retry_after: Option<Duration>,
+}
+
+impl MaintenanceMode {
+    /// Create a maintenance mode that is disabled.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Serve this page during maintenance. It is read for every request, so
+    /// it can be changed during the maintenance. When the page can't be read,
+    /// a short message is served instead.
+    pub fn with_page(mut self, path: impl Into<PathBuf>) -> Self {
+        self.page = Some(path.into());
+        self
+    }
+
+    /// Serve this path normally during maintenance, e.g. `/healthz`. The path
+    /// of the request has to match exactly.
+    pub fn with_bypass_path(mut self, path: impl Into<String>) -> Self {
+        self.bypass_paths.push(path.into());
+        self
+    }
+
+    /// The time after which the client should try again, which is sent using
+    /// `Retry-After`. Defaults to [`DEFAULT_RETRY_AFTER`].
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Start or end the maintenance, which takes effect for the next request.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    fn bypasses(&self, request: &Request) -> bool {
+        let RequestTarget::Origin { path, .. } = &request.target else {
+            return false;
+        };
+
+        self.bypass_paths.iter().any(|bypass_path| bypass_path == path)
+    }
+}
+
+/// Produce the maintenance response, if the maintenance mode is enabled and
+/// the request doesn't bypass it.
+pub async fn check_maintenance(request: &Request, maintenance: &MaintenanceMode) -> Option<Response> {
+    if !maintenance.is_enabled() || maintenance.bypasses(request) {
+        return None;
+    }
+
+    let page = match &maintenance.page {
+        Some(path) => tokio::fs::read(path).await.ok().map(|page| (page, path)),
+        None => None,
+    };
+
+    let mut response = match page {
+        Some((page, path)) => {
+            let mut response = Response::with_status(StatusCode::ServiceUnavailable);
+            response.headers.set_content_type(MediaType::from_path(&path.to_string_lossy()).clone());
+            response.headers.set_content_length(page.len());
+            response.body = Some(BodyKind::Bytes(page));
+            response
+        }
+        None => {
+            let mut response = Response::with_status_and_string_body(StatusCode::ServiceUnavailable,
+                "Service Unavailable: the server is undergoing maintenance");
+            response.headers.set_content_type(MediaType::PLAIN_TEXT);
+            response
+        }
+    };
+
+    let retry_after = maintenance.retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+    response.headers.append_or_override(HeaderName::RetryAfter, HeaderValue::from(retry_after.as_secs().to_string()));
+
+    // Caches shouldn't keep serving the maintenance page afterwards.
+    response.headers.append_or_override(HeaderName::CacheControl, HeaderValue::from("no-store"));
+    Some(response)
+}
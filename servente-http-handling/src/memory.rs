@@ -0,0 +1,66 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A limit on the memory that can be used by some kind of state across all
+/// connections, e.g. the HPACK dynamic tables. The budget is shared by
+/// wrapping it in an `Arc`.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// The total amount of bytes that can be reserved.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The amount of bytes that are currently reserved.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Try to reserve `size` bytes, which fails if this would exceed the
+    /// limit. A successful reservation must be given back using
+    /// [`MemoryBudget::release`].
+    pub fn try_reserve(&self, size: usize) -> bool {
+        self.used.fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            used.checked_add(size).filter(|new| *new <= self.limit)
+        }).is_ok()
+    }
+
+    /// Give back `size` bytes that were reserved earlier.
+    pub fn release(&self, size: usize) {
+        let previous = self.used.fetch_sub(size, Ordering::AcqRel);
+        debug_assert!(previous >= size, "released more than was reserved");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_and_release() {
+        let budget = MemoryBudget::new(100);
+        assert!(budget.try_reserve(60));
+        assert!(!budget.try_reserve(41));
+        assert!(budget.try_reserve(40));
+        assert_eq!(budget.used(), 100);
+
+        budget.release(60);
+        assert_eq!(budget.used(), 40);
+        assert!(budget.try_reserve(60));
+        assert!(!budget.try_reserve(usize::MAX));
+    }
+}
@@ -1,22 +1,99 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+use std::time::{Instant, SystemTime};
+
 use async_trait::async_trait;
 use dyn_clone::DynClone;
 
 use servente_http::{
+    BodyKind,
     Request,
+    RequestTarget,
     Response,
 };
 
+use crate::{
+    validation::{AuthScheme, AuthValidator},
+    ConnectionInfo,
+    ExchangeId,
+    RequestValidator,
+};
+
 /// The state of a request that is being handled, and the response that will be
 /// sent accordingly.
 pub struct ExchangeState<'a> {
+    /// Identifies the exchange on its connection.
+    pub id: ExchangeId,
+
     /// The request that's being handled.
     pub request: &'a Request,
 
+    /// The connection the request was received on.
+    pub connection: &'a ConnectionInfo,
+
     /// The response is being generated.
     pub response: Response,
+
+    /// When the handling of the request started.
+    pub started: Instant,
+
+    /// The target the request is re-dispatched to, see
+    /// [`ExchangeState::redirect_internally`].
+    pub internal_redirect: Option<RequestTarget>,
+}
+
+impl<'a> ExchangeState<'a> {
+    /// Serve the response of the `target` instead, as if the client requested
+    /// it, e.g. for the local redirects of CGI scripts. The request is
+    /// re-dispatched using `GET` after this middleware, and the middleware
+    /// following it only sees the re-dispatched request.
+    ///
+    /// # References
+    /// * [RFC 3875 Section 6.2.2](https://www.rfc-editor.org/rfc/rfc3875.html#section-6.2.2)
+    pub fn redirect_internally(&mut self, target: RequestTarget) {
+        self.internal_redirect = Some(target);
+    }
+
+    /// The negotiated TLS version, if the connection is secured.
+    pub fn tls_version(&self) -> Option<&str> {
+        self.connection.tls.as_ref()?.version.as_deref()
+    }
+
+    /// The negotiated TLS cipher suite, if the connection is secured.
+    pub fn cipher_suite(&self) -> Option<&str> {
+        self.connection.tls.as_ref()?.cipher_suite.as_deref()
+    }
+
+    /// The application protocol negotiated using ALPN, if any.
+    pub fn alpn(&self) -> Option<&str> {
+        self.connection.tls.as_ref()?.alpn.as_deref()
+    }
+
+    /// The body exactly as it was received, if the route asked for it. See
+    /// [`Request::raw_body`].
+    pub fn raw_body(&self) -> Option<&[u8]> {
+        self.request.raw_body.as_deref()
+    }
+
+    /// The size of the body of the response in bytes, or `None` for a
+    /// streaming body. The content coding is applied after the middleware
+    /// ran, so this is the size of the uncompressed representation.
+    pub fn response_size(&self) -> Option<u64> {
+        let Some(body) = &self.response.body else {
+            return Some(0);
+        };
+
+        Some(match body {
+            BodyKind::Bytes(bytes) => bytes.len() as u64,
+            BodyKind::CachedBytes(versions, coding) => versions.get_version(*coding).len() as u64,
+            BodyKind::File { metadata, .. } => metadata.len(),
+            BodyKind::Shared(bytes) => (**bytes).as_ref().len() as u64,
+            BodyKind::StaticString(string) => string.len() as u64,
+            BodyKind::String(string) => string.len() as u64,
+            BodyKind::Stream(..) => return None,
+        })
+    }
 }
 
 /// Middleware is a step in the handling of a process.
@@ -38,8 +115,21 @@ pub trait Middleware: DynClone + Send + Sync {
     /// by using the correct structure, or sometimes not at all, e.g.
     /// [Transfer-Encoding](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Transfer-Encoding).
     async fn invoke(&mut self, state: &mut ExchangeState) -> Result<(), MiddlewareError>;
+
+    /// Respond to the request before it is handled, instead of the handlers
+    /// and the file server, e.g. to forward it to another server. The first
+    /// response is used, after which all middleware is invoked as usual.
+    async fn respond(&self, _request: &Request, _connection: &ConnectionInfo) -> Option<Response> {
+        None
+    }
 }
 
+/// The maximum number of internal redirects of a request, see
+/// [`ExchangeState::redirect_internally`]. The request is answered with a
+/// `500 Internal Server Error` when it is redirected more often, e.g. when a
+/// script redirects to itself.
+pub const MAX_INTERNAL_REDIRECTS: usize = 10;
+
 /// `MiddlewareError` is an error that can generate during the invocation of a
 /// middleware component.
 #[derive(Debug)]
@@ -58,3 +148,176 @@ impl From<anyhow::Error> for MiddlewareError {
         MiddlewareError::UnrecoverableError(value)
     }
 }
+
+/// The format of the lines of the [`LoggingMiddleware`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format, e.g.
+    /// `- - - [10/Oct/2023:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`.
+    /// The client address is logged as `-` when it isn't known.
+    #[default]
+    Common,
+
+    /// Fields of `key=value` pairs, e.g. `conn=42 req=3 method=GET
+    /// target=/index.html status=200 size=2326 duration_us=412`.
+    Structured,
+}
+
+/// Logs every exchange as an access log line to the `log` facade, using the
+/// `servente::access` target at the `info` level. The query of the request
+/// target is left out, since it might contain tokens or other secrets.
+#[derive(Clone, Debug, Default)]
+pub struct LoggingMiddleware {
+    format: LogFormat,
+}
+
+impl LoggingMiddleware {
+    /// The target of the log records.
+    pub const TARGET: &'static str = "servente::access";
+
+    pub fn new(format: LogFormat) -> Self {
+        Self { format }
+    }
+
+    fn format_line(&self, state: &ExchangeState, now: SystemTime) -> String {
+        let request = state.request;
+        let size = state.response_size();
+        match self.format {
+            LogFormat::Common => format!("{} - - [{}] \"{} {} {}\" {} {}",
+                state.connection.peer_address.map_or_else(|| String::from("-"), |address| address.ip().to_string()),
+                format_common_log_date(now),
+                request.method.as_string(),
+                request.target.as_str(),
+                request.version.to_http_version(),
+                state.response.status as u16,
+                size.map_or_else(|| String::from("-"), |size| size.to_string())),
+            LogFormat::Structured => format!("{} method={} target={:?} status={} size={} duration_us={}",
+                state.id,
+                request.method.as_string(),
+                request.target.as_str(),
+                state.response.status as u16,
+                size.map_or_else(|| String::from("-"), |size| size.to_string()),
+                state.started.elapsed().as_micros()),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for LoggingMiddleware {
+    fn debug_identifier(&self) -> &str {
+        "LoggingMiddleware"
+    }
+
+    async fn invoke(&mut self, state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+        log::info!(target: Self::TARGET, "{}", self.format_line(state, SystemTime::now()));
+        Ok(())
+    }
+}
+
+/// Requires the clients to authenticate for the protected areas, using the
+/// [`AuthValidator`] it wraps. The `401 Unauthorized` is sent before the
+/// request is handled, see [`Middleware::respond`], unless middleware that
+/// is registered earlier responds first.
+///
+/// It is a [`RequestValidator`] as well. Registering it in
+/// [`ServenteSettings::request_validators`](crate::ServenteSettings::request_validators)
+/// instead rejects the request before any middleware sees it.
+#[derive(Clone, Debug, Default)]
+pub struct AuthMiddleware {
+    validator: AuthValidator,
+}
+
+impl AuthMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Protect the request paths that start with the `prefix`, see
+    /// [`AuthValidator::with_area`].
+    pub fn with_area(self, prefix: impl Into<String>, scheme: AuthScheme) -> Self {
+        Self { validator: self.validator.with_area(prefix, scheme) }
+    }
+
+    pub fn validator(&self) -> &AuthValidator {
+        &self.validator
+    }
+}
+
+impl From<AuthValidator> for AuthMiddleware {
+    fn from(validator: AuthValidator) -> Self {
+        Self { validator }
+    }
+}
+
+#[async_trait]
+impl Middleware for AuthMiddleware {
+    fn debug_identifier(&self) -> &str {
+        "AuthMiddleware"
+    }
+
+    async fn invoke(&mut self, _state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+        Ok(())
+    }
+
+    async fn respond(&self, request: &Request, _connection: &ConnectionInfo) -> Option<Response> {
+        self.validator.validate(request).err().map(|response| *response)
+    }
+}
+
+impl RequestValidator for AuthMiddleware {
+    fn validate(&self, request: &Request) -> Result<(), Box<Response>> {
+        self.validator.validate(request)
+    }
+}
+
+/// Formats the time as in the Common Log Format, e.g.
+/// `10/Oct/2023:13:55:36 +0000`.
+fn format_common_log_date(time: SystemTime) -> String {
+    // e.g. "Tue, 10 Oct 2023 13:55:36 GMT"
+    let http_date = httpdate::fmt_http_date(time);
+    let parts: Vec<&str> = http_date.split(' ').collect();
+    format!("{}/{}/{}:{} +0000", parts[1], parts[2], parts[3], parts[4])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use servente_http::{HeaderMap, HttpVersion, Method, StatusCode};
+
+    use super::*;
+    use crate::Scheme;
+
+    #[test]
+    fn test_logging_middleware_formats() {
+        let request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/index.html?lang=en").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        let connection = ConnectionInfo::new(Scheme::Http);
+        let state = ExchangeState {
+            id: ExchangeId { connection: 42, request: 3 },
+            request: &request,
+            connection: &connection,
+            response: Response::with_status_and_string_body(StatusCode::NotFound, "Not Found"),
+            started: Instant::now(),
+            internal_redirect: None,
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_696_946_136);
+
+        assert_eq!(LoggingMiddleware::new(LogFormat::Common).format_line(&state, now),
+            "- - - [10/Oct/2023:13:55:36 +0000] \"GET /index.html HTTP/1.1\" 404 9");
+
+        let line = LoggingMiddleware::new(LogFormat::Structured).format_line(&state, now);
+        assert!(line.starts_with("conn=42 req=3 method=GET target=\"/index.html\" status=404 size=9 duration_us="), "{line}");
+
+        let connection = ConnectionInfo::new(Scheme::Http).with_peer_address("[::1]:51234".parse().unwrap());
+        let state = ExchangeState { connection: &connection, ..state };
+        assert_eq!(LoggingMiddleware::new(LogFormat::Common).format_line(&state, now),
+            "::1 - - [10/Oct/2023:13:55:36 +0000] \"GET /index.html HTTP/1.1\" 404 9");
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Limits the rate of requests of the server as a whole, regardless of which
+//! clients send them, to protect backends that can only handle so much.
+//! Contrary to the [`crate::ClientConnectionLimit`], this limit is about
+//! requests, not connections.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use servente_http::{
+    HeaderName,
+    HeaderValue,
+    Response,
+    StatusCode,
+};
+use servente_resources::MediaType;
+
+/// A token bucket holding up to `burst` requests, which is refilled with
+/// `rate` requests per second. This is shared across connections by wrapping
+/// it in an `Arc`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second on average, and bursts of up to
+    /// `burst` requests. The bucket starts out full.
+    ///
+    /// # Panics
+    /// When `rate` isn't positive.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(rate > 0.0, "the rate of a RateLimiter must be positive");
+
+        // A burst of zero would reject every request.
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// The number of requests per second on average.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Try to take a request from the bucket. When the bucket is empty,
+    /// returns how long it takes until the next request is allowed.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+    }
+}
+
+/// Produce a `429 Too Many Requests` response if the server received more
+/// requests than the limiter allows.
+///
+/// # References
+/// * [RFC 6585 Section 4](https://www.rfc-editor.org/rfc/rfc6585.html#section-4)
+pub fn check_rate_limit(limiter: &RateLimiter) -> Option<Response> {
+    let wait = limiter.try_acquire().err()?;
+
+    let mut response = Response::with_status_and_string_body(StatusCode::TooManyRequests,
+        "Too Many Requests: the server is receiving more requests than it can handle");
+    response.headers.set_content_type(MediaType::PLAIN_TEXT);
+
+    // Retry-After only has a granularity of seconds, so round up to not
+    // invite the client to retry too early.
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() != 0);
+    response.headers.append_or_override(HeaderName::RetryAfter, HeaderValue::from(retry_after.to_string()));
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_and_refill() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.try_acquire_at(start), Ok(()));
+        }
+        assert_eq!(limiter.try_acquire_at(start), Err(Duration::from_millis(500)));
+
+        // Half a second refills a single request.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.try_acquire_at(later), Ok(()));
+        assert!(limiter.try_acquire_at(later).is_err());
+
+        // The bucket doesn't fill beyond the burst.
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.try_acquire_at(much_later), Ok(()));
+        }
+        assert!(limiter.try_acquire_at(much_later).is_err());
+    }
+
+    #[test]
+    fn test_zero_burst_allows_a_request() {
+        let limiter = RateLimiter::new(1.0, 0);
+        let now = Instant::now();
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+        assert!(limiter.try_acquire_at(now).is_err());
+    }
+}
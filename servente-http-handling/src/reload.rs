@@ -0,0 +1,130 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Replacing the configuration while the server is running, e.g. when the
+//! operator sends a `SIGHUP`.
+
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+
+use crate::ServenteConfig;
+
+/// A [`ServenteConfig`] that can be swapped out while the server is running.
+/// Every new connection uses the configuration that is current at the time it
+/// is accepted; existing connections keep the one they started with.
+#[derive(Clone)]
+pub struct ReloadableConfig {
+    current: Arc<RwLock<ServenteConfig>>,
+}
+
+impl ReloadableConfig {
+    pub fn new(config: ServenteConfig) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    /// Get a copy of the current configuration.
+    pub fn load(&self) -> ServenteConfig {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the configuration with the one produced by `reload`. When
+    /// `reload` fails, the current configuration is kept. Settings that can't
+    /// be changed without restarting, such as the bind address, are retained
+    /// and a message is logged.
+    pub fn reload<F, E>(&self, reload: F) -> Result<(), E>
+            where F: FnOnce() -> Result<ServenteConfig, E> {
+        let mut config = reload()?;
+
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        if config.bind_address != current.bind_address {
+            println!("[Reload] Changing the bind address from {} to {} requires a restart", current.bind_address, config.bind_address);
+            config.bind_address = current.bind_address.clone();
+        }
+
+        *current = config;
+        Ok(())
+    }
+}
+
+impl From<ServenteConfig> for ReloadableConfig {
+    fn from(config: ServenteConfig) -> Self {
+        Self::new(config)
+    }
+}
+
+impl ServenteConfig {
+    /// Reload the configuration every time the process receives a `SIGHUP`,
+    /// using `reload` to create the new configuration, e.g. by calling
+    /// [`ServenteConfig::from_env`]. Building the configuration also reads the
+    /// certificates again.
+    ///
+    /// This must be called from within a Tokio runtime.
+    #[cfg(unix)]
+    pub fn install_sighup_reload<F, E>(self, reload: F) -> std::io::Result<ReloadableConfig>
+            where F: Fn() -> Result<ServenteConfig, E> + Send + 'static,
+                  E: Display {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let config = ReloadableConfig::new(self);
+
+        let reloadable = config.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                match reloadable.reload(&reload) {
+                    Ok(()) => println!("[Reload] Configuration reloaded"),
+                    Err(e) => println!("[Reload] Failed to reload, keeping the current configuration: {e}"),
+                }
+            }
+        });
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::{ServenteSettings, handler::HandlerController};
+
+    fn create_config(read_headers_timeout: Duration, bind_address: &str) -> ServenteConfig {
+        let mut settings = ServenteSettings::new(HandlerController::new());
+        settings.read_headers_timeout = read_headers_timeout;
+
+        let mut config = ServenteConfig::new().build(settings);
+        config.bind_address = bind_address.to_string();
+        config
+    }
+
+    #[test]
+    fn test_reload_takes_effect() {
+        let config = ReloadableConfig::new(create_config(Duration::from_secs(5), "127.0.0.1:8080"));
+
+        config.reload(|| Ok::<_, String>(create_config(Duration::from_secs(30), "127.0.0.1:8080"))).unwrap();
+        assert_eq!(config.load().settings.read_headers_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_reload_keeps_bind_address() {
+        let config = ReloadableConfig::new(create_config(Duration::from_secs(5), "127.0.0.1:8080"));
+
+        config.reload(|| Ok::<_, String>(create_config(Duration::from_secs(30), "0.0.0.0:80"))).unwrap();
+        let loaded = config.load();
+        assert_eq!(loaded.bind_address, "127.0.0.1:8080");
+        assert_eq!(loaded.settings.read_headers_timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_reload_failure_keeps_config() {
+        let config = ReloadableConfig::new(create_config(Duration::from_secs(5), "127.0.0.1:8080"));
+
+        assert!(config.reload(|| Err("invalid configuration")).is_err());
+        assert_eq!(config.load().settings.read_headers_timeout, Duration::from_secs(5));
+    }
+}
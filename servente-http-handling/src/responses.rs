@@ -3,15 +3,31 @@
 
 //! This module creates complete responses for handling common cases.
 
-use servente_http::{Response, StatusCode};
+use servente_http::{HeaderName, HeaderValue, Response, StatusCode};
 use servente_resources::MediaType;
 
+use crate::ServenteSettings;
+
+/// Create a response for a client that has too many connections open.
+///
+/// # References
+/// * [RFC 6585 Section 4](https://www.rfc-editor.org/rfc/rfc6585.html#section-4)
+pub async fn create_too_many_requests(settings: &ServenteSettings) -> Response {
+    let mut response = Response::with_status_and_string_body(StatusCode::TooManyRequests, "Too Many Requests");
+    response.headers.set_content_type(MediaType::PLAIN_TEXT);
+    response.headers.append_or_override(HeaderName::RetryAfter, HeaderValue::from("1"));
+
+    super::finish_response_error(&mut response, settings).await;
+
+    response
+}
+
 /// Create a response for when the request times out.
-pub async fn create_request_timeout() -> Response {
+pub async fn create_request_timeout(settings: &ServenteSettings) -> Response {
     let mut response = Response::with_status_and_string_body(StatusCode::RequestTimeout, "Request Timed Out");
     response.headers.set_content_type(MediaType::PLAIN_TEXT);
 
-    super::finish_response_error(&mut response).await;
+    super::finish_response_error(&mut response, settings).await;
 
     response
 }
@@ -0,0 +1,293 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Rule-based rewriting of request paths before they are routed, either
+//! internally or by redirecting the client, comparable to `mod_rewrite`.
+//!
+//! Patterns match the complete path. A `*` matches any (possibly empty)
+//! sequence of characters, and `\*` matches a literal asterisk. In the
+//! replacement, `$1` to `$9` are substituted with what the wildcards matched,
+//! `$0` with the complete path, and `$$` is a literal dollar sign.
+
+use servente_http::{
+    HeaderName,
+    HeaderValue,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+/// The maximum number of internal rewrites of a single request, which
+/// protects against rules that rewrite each other endlessly.
+pub const MAX_REWRITE_ITERATIONS: usize = 10;
+
+/// What should happen when a [`RewriteRule`] matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewriteAction {
+    /// Route the request as if the client requested the replacement. The
+    /// rules are evaluated again for the new path.
+    Internal,
+
+    /// Redirect the client to the replacement, using the given status code,
+    /// e.g. `301 Moved Permanently`.
+    Redirect(StatusCode),
+}
+
+/// A rule that rewrites the paths matching a pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RewriteRule {
+    pattern: Vec<PatternPart>,
+    replacement: String,
+    action: RewriteAction,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PatternPart {
+    Literal(String),
+    Wildcard,
+}
+
+/// The result of evaluating the rewrite rules for a request.
+#[derive(Debug)]
+pub enum RewriteOutcome {
+    /// No rule matched.
+    Unchanged,
+
+    /// Route the request using this target instead.
+    Internal(RequestTarget),
+
+    /// Respond with this response, e.g. a redirect.
+    Response(Box<Response>),
+}
+
+impl RewriteRule {
+    pub fn new(pattern: &str, replacement: impl Into<String>, action: RewriteAction) -> Self {
+        Self {
+            pattern: parse_pattern(pattern),
+            replacement: replacement.into(),
+            action,
+        }
+    }
+
+    /// Create a rule that rewrites the path internally.
+    pub fn internal(pattern: &str, replacement: impl Into<String>) -> Self {
+        Self::new(pattern, replacement, RewriteAction::Internal)
+    }
+
+    /// Create a rule that redirects the client using the given status code.
+    pub fn redirect(pattern: &str, replacement: impl Into<String>, status: StatusCode) -> Self {
+        Self::new(pattern, replacement, RewriteAction::Redirect(status))
+    }
+
+    /// Apply the rule to the path, returning the substituted replacement if
+    /// the pattern matches.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let mut captures = Vec::new();
+        if !match_pattern(&self.pattern, path, &mut captures) {
+            return None;
+        }
+
+        let mut result = String::with_capacity(self.replacement.len());
+        let mut chars = self.replacement.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                result.push(c);
+                continue;
+            }
+
+            match chars.peek().copied() {
+                Some('$') => {
+                    chars.next();
+                    result.push('$');
+                }
+                Some('0') => {
+                    chars.next();
+                    result.push_str(path);
+                }
+                Some(digit @ '1'..='9') => {
+                    chars.next();
+                    let index = digit as usize - '1' as usize;
+                    result.push_str(captures.get(index).copied().unwrap_or(""));
+                }
+                _ => result.push('$'),
+            }
+        }
+
+        Some(result)
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<PatternPart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'*') => {
+                chars.next();
+                literal.push('*');
+            }
+            '*' => {
+                if !literal.is_empty() {
+                    parts.push(PatternPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(PatternPart::Wildcard);
+            }
+            _ => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(PatternPart::Literal(literal));
+    }
+    parts
+}
+
+/// Match the complete input against the pattern. Wildcards match as little as
+/// possible, so in `/*/*` the first wildcard captures a single segment.
+fn match_pattern<'a>(pattern: &[PatternPart], input: &'a str, captures: &mut Vec<&'a str>) -> bool {
+    let Some((part, rest)) = pattern.split_first() else {
+        return input.is_empty();
+    };
+
+    match part {
+        PatternPart::Literal(literal) => match input.strip_prefix(literal.as_str()) {
+            Some(input) => match_pattern(rest, input, captures),
+            None => false,
+        },
+        PatternPart::Wildcard => {
+            for (index, _) in input.char_indices().chain(std::iter::once((input.len(), ' '))) {
+                captures.push(&input[..index]);
+                if match_pattern(rest, &input[index..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
+
+/// Evaluate the rules for the request target. Only origin-form targets are
+/// rewritten. The query is kept, unless the replacement specifies one.
+pub fn apply_rewrite_rules(target: &RequestTarget, rules: &[RewriteRule]) -> RewriteOutcome {
+    let RequestTarget::Origin { path, query } = target else {
+        return RewriteOutcome::Unchanged;
+    };
+
+    if rules.is_empty() {
+        return RewriteOutcome::Unchanged;
+    }
+
+    let mut path = path.clone();
+    let mut query = query.clone();
+    let mut rewritten = false;
+
+    for _ in 0..MAX_REWRITE_ITERATIONS {
+        let Some((rule, replacement)) = rules.iter()
+                .find_map(|rule| rule.apply(&path).map(|replacement| (rule, replacement))) else {
+            if !rewritten {
+                return RewriteOutcome::Unchanged;
+            }
+            return RewriteOutcome::Internal(RequestTarget::Origin { path, query });
+        };
+
+        let (new_path, new_query) = match replacement.split_once('?') {
+            Some((new_path, new_query)) => (new_path.to_string(), new_query.to_string()),
+            None => (replacement, query),
+        };
+
+        match rule.action {
+            RewriteAction::Redirect(status) => {
+                let mut location = new_path;
+                if !new_query.is_empty() {
+                    location.push('?');
+                    location.push_str(&new_query);
+                }
+
+                let mut response = Response::with_status(status);
+                response.headers.append_or_override(HeaderName::Location, HeaderValue::from(location));
+                response.headers.set_content_length(0);
+                return RewriteOutcome::Response(Box::new(response));
+            }
+            RewriteAction::Internal => {
+                if !new_path.starts_with('/') {
+                    return RewriteOutcome::Response(Box::new(Response::with_status_and_string_body(StatusCode::InternalServerError,
+                        "Internal Server Error: a rewrite rule produced an invalid path")));
+                }
+
+                // A rule that doesn't change anything would match forever.
+                if new_path == path {
+                    return RewriteOutcome::Internal(RequestTarget::Origin { path: new_path, query: new_query });
+                }
+
+                path = new_path;
+                query = new_query;
+                rewritten = true;
+            }
+        }
+    }
+
+    RewriteOutcome::Response(Box::new(Response::with_status_and_string_body(StatusCode::InternalServerError,
+        "Internal Server Error: the rewrite rules are looping")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(value: &str) -> RequestTarget {
+        RequestTarget::parse(value).unwrap()
+    }
+
+    #[test]
+    fn test_apply_captures() {
+        let rule = RewriteRule::internal("/blog/*/*", "/posts/$2?year=$1");
+        assert_eq!(rule.apply("/blog/2023/hello"), Some("/posts/hello?year=2023".to_string()));
+        assert_eq!(rule.apply("/blog/2023/hello/world"), Some("/posts/hello/world?year=2023".to_string()));
+        assert_eq!(rule.apply("/news/2023/hello"), None);
+
+        let rule = RewriteRule::internal("/a\\*", "$0 costs $$5");
+        assert_eq!(rule.apply("/a*"), Some("/a* costs $5".to_string()));
+        assert_eq!(rule.apply("/ab"), None);
+    }
+
+    #[test]
+    fn test_internal_rewrite() {
+        let rules = [
+            RewriteRule::internal("/old/*", "/new/$1"),
+            RewriteRule::internal("/new/*.htm", "/new/$1.html"),
+        ];
+
+        let RewriteOutcome::Internal(rewritten) = apply_rewrite_rules(&target("/old/page.htm?lang=nl"), &rules) else {
+            panic!("expected an internal rewrite");
+        };
+        assert_eq!(rewritten, target("/new/page.html?lang=nl"));
+
+        assert!(matches!(apply_rewrite_rules(&target("/other"), &rules), RewriteOutcome::Unchanged));
+    }
+
+    #[test]
+    fn test_redirect_rewrite() {
+        let rules = [RewriteRule::redirect("/docs/*", "https://docs.example.com/$1", StatusCode::MovedPermanently)];
+
+        let RewriteOutcome::Response(response) = apply_rewrite_rules(&target("/docs/intro?page=2"), &rules) else {
+            panic!("expected a redirect");
+        };
+        assert_eq!(response.status, StatusCode::MovedPermanently);
+        assert_eq!(response.headers.get(&HeaderName::Location).unwrap().as_str_no_convert(), Some("https://docs.example.com/intro?page=2"));
+    }
+
+    #[test]
+    fn test_rewrite_loop_is_stopped() {
+        let rules = [
+            RewriteRule::internal("/a", "/b"),
+            RewriteRule::internal("/b", "/a"),
+        ];
+
+        let RewriteOutcome::Response(response) = apply_rewrite_rules(&target("/a"), &rules) else {
+            panic!("expected an error response");
+        };
+        assert_eq!(response.status, StatusCode::InternalServerError);
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Statistics of the exchanges the server handled, which embedders can
+//! inspect using [`ServerStats::snapshot`].
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use servente_http::{
+    HeaderName,
+    Response,
+    StatusCode,
+};
+
+/// The lowest status code that is counted separately.
+const FIRST_STATUS_CODE: usize = 100;
+
+/// The number of status codes that are counted separately, i.e. `100` to
+/// `599`.
+const STATUS_CODE_COUNT: usize = 500;
+
+/// The counters of the server. This is shared between the connections and
+/// the embedder by wrapping it in an `Arc`.
+///
+/// Recording is lock-free with respect to other recordings. Only
+/// [`ServerStats::snapshot`] and [`ServerStats::reset`] briefly hold off the
+/// recordings, so the snapshot is consistent.
+#[derive(Debug)]
+pub struct ServerStats {
+    gate: RwLock<()>,
+    requests_by_status: Box<[AtomicU64]>,
+    body_bytes: AtomicU64,
+    cache_hits: AtomicU64,
+    active_connections: AtomicUsize,
+    latencies: LatencyHistogram,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self {
+            gate: RwLock::new(()),
+            requests_by_status: (0..STATUS_CODE_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            body_bytes: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            active_connections: AtomicUsize::new(0),
+            latencies: LatencyHistogram::new(),
+        }
+    }
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record an exchange, which took `latency` from receiving the request
+    /// head until the response was sent.
+    pub fn record(&self, exchange: ExchangeRecord, latency: Duration) {
+        let _gate = self.gate.read().unwrap_or_else(|error| error.into_inner());
+
+        if let Some(counter) = (exchange.status as usize).checked_sub(FIRST_STATUS_CODE).and_then(|index| self.requests_by_status.get(index)) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.body_bytes.fetch_add(exchange.body_bytes, Ordering::Relaxed);
+        if exchange.cache_hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.latencies.record(latency);
+    }
+
+    /// Account a connection to be active, until the guard is dropped.
+    pub fn track_connection(self: &Arc<Self>) -> ActiveConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnectionGuard {
+            stats: Arc::clone(self),
+        }
+    }
+
+    /// Copy all counters at a single point in time.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let _gate = self.gate.write().unwrap_or_else(|error| error.into_inner());
+
+        let requests_by_status = self.requests_by_status.iter()
+            .enumerate()
+            .filter_map(|(index, counter)| {
+                let count = counter.load(Ordering::Relaxed);
+                (count != 0).then_some(((index + FIRST_STATUS_CODE) as u16, count))
+            })
+            .collect();
+
+        let latencies: Vec<u64> = self.latencies.buckets.iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+
+        StatsSnapshot {
+            requests_by_status,
+            body_bytes: self.body_bytes.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            latency_p50: LatencyHistogram::percentile(&latencies, 0.50),
+            latency_p95: LatencyHistogram::percentile(&latencies, 0.95),
+        }
+    }
+
+    /// Set all counters back to zero, e.g. to isolate tests from each other.
+    /// The active connections are left alone, since these are still open.
+    pub fn reset(&self) {
+        let _gate = self.gate.write().unwrap_or_else(|error| error.into_inner());
+
+        for counter in self.requests_by_status.iter().chain(self.latencies.buckets.iter()) {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.body_bytes.store(0, Ordering::Relaxed);
+        self.cache_hits.store(0, Ordering::Relaxed);
+    }
+}
+
+/// What is recorded of a response, which is taken before the response is
+/// sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeRecord {
+    pub status: StatusCode,
+
+    /// The size of the body, as announced by the `Content-Length`.
+    pub body_bytes: u64,
+
+    /// Whether the response was served from the file cache.
+    pub cache_hit: bool,
+}
+
+impl ExchangeRecord {
+    pub fn new(response: &Response) -> Self {
+        let body_bytes = response.headers.get(&HeaderName::ContentLength)
+            .and_then(|content_length| content_length.parse_number())
+            .unwrap_or(0);
+
+        let cache_hit = response.headers.get(&HeaderName::CacheStatus)
+            .is_some_and(|cache_status| cache_status.as_str_may_convert().contains("; hit"));
+
+        Self {
+            status: response.status,
+            body_bytes: body_bytes as u64,
+            cache_hit,
+        }
+    }
+}
+
+/// A point-in-time copy of the [`ServerStats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// The number of responses per status code, in the order of the status
+    /// codes. Status codes without responses are omitted.
+    pub requests_by_status: Vec<(u16, u64)>,
+
+    /// The sum of the sizes of the response bodies.
+    pub body_bytes: u64,
+
+    /// The number of responses that were served from the file cache.
+    pub cache_hits: u64,
+
+    pub active_connections: usize,
+
+    /// The median latency, or zero when nothing is recorded. The latencies
+    /// are kept in buckets, so this is accurate to about 12.5%.
+    pub latency_p50: Duration,
+
+    /// The 95th percentile of the latencies, or zero when nothing is
+    /// recorded.
+    pub latency_p95: Duration,
+}
+
+impl StatsSnapshot {
+    /// The number of responses with any status.
+    pub fn requests(&self) -> u64 {
+        self.requests_by_status.iter().map(|(_, count)| count).sum()
+    }
+
+    /// The number of responses with this status.
+    pub fn requests_with_status(&self, status: StatusCode) -> u64 {
+        self.requests_by_status.iter()
+            .find(|(recorded_status, _)| *recorded_status == status as u16)
+            .map_or(0, |(_, count)| *count)
+    }
+}
+
+/// A connection that is accounted for in [`StatsSnapshot::active_connections`].
+#[derive(Debug)]
+pub struct ActiveConnectionGuard {
+    stats: Arc<ServerStats>,
+}
+
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        self.stats.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The number of buckets per power of two, which determines the precision.
+const SUB_BUCKETS: u64 = 8;
+
+/// A histogram of latencies in microseconds, with logarithmic buckets that
+/// are each split into [`SUB_BUCKETS`] linear ones, similar to an HDR
+/// histogram. Latencies below 8 µs are exact, above that a value is within
+/// 12.5% of the bucket it is counted in.
+#[derive(Debug)]
+struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        let bucket_count = Self::bucket_index(u64::MAX) + 1;
+        Self {
+            buckets: (0..bucket_count).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket_index(micros: u64) -> usize {
+        if micros < SUB_BUCKETS {
+            return micros as usize;
+        }
+
+        let magnitude = u64::from(63 - micros.leading_zeros());
+        let shift = magnitude - SUB_BUCKETS.trailing_zeros() as u64;
+        let sub_bucket = (micros >> shift) - SUB_BUCKETS;
+        (SUB_BUCKETS + shift * SUB_BUCKETS + sub_bucket) as usize
+    }
+
+    /// The highest value that is counted in the bucket.
+    fn bucket_upper_bound(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return index;
+        }
+
+        let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+        let sub_bucket = (index - SUB_BUCKETS) % SUB_BUCKETS;
+        ((SUB_BUCKETS + sub_bucket) << shift).saturating_add((1 << shift) - 1)
+    }
+
+    fn percentile(buckets: &[u64], percentile: f64) -> Duration {
+        let total: u64 = buckets.iter().sum();
+        if total == 0 {
+            return Duration::ZERO;
+        }
+
+        let rank = ((total as f64 * percentile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(Self::bucket_upper_bound(index));
+            }
+        }
+
+        Duration::from_micros(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(stats: &ServerStats, status: StatusCode, latency: Duration) {
+        stats.record(ExchangeRecord { status, body_bytes: 10, cache_hit: status == StatusCode::Ok }, latency);
+    }
+
+    #[test]
+    fn test_bucket_bounds() {
+        for micros in [0, 1, 7, 8, 9, 15, 16, 17, 100, 1_000, 123_456, u64::MAX / 3, u64::MAX] {
+            let index = LatencyHistogram::bucket_index(micros);
+            assert!(micros <= LatencyHistogram::bucket_upper_bound(index), "{micros}");
+            if index > 0 {
+                assert!(micros > LatencyHistogram::bucket_upper_bound(index - 1), "{micros}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_reset() {
+        let stats = Arc::new(ServerStats::new());
+        let connection = stats.track_connection();
+
+        for millis in 1..=100 {
+            record(&stats, StatusCode::Ok, Duration::from_millis(millis));
+        }
+        record(&stats, StatusCode::NotFound, Duration::from_millis(1));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests(), 101);
+        assert_eq!(snapshot.requests_by_status, [(200, 100), (404, 1)]);
+        assert_eq!(snapshot.requests_with_status(StatusCode::NotFound), 1);
+        assert_eq!(snapshot.body_bytes, 1010);
+        assert_eq!(snapshot.cache_hits, 100);
+        assert_eq!(snapshot.active_connections, 1);
+
+        let p50 = snapshot.latency_p50.as_secs_f64();
+        assert!((0.050..0.050 * 1.125).contains(&p50), "{p50}");
+        let p95 = snapshot.latency_p95.as_secs_f64();
+        assert!((0.095..0.095 * 1.125).contains(&p95), "{p95}");
+
+        stats.reset();
+        drop(connection);
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+    }
+}
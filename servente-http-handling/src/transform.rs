@@ -0,0 +1,157 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Post-processing of response bodies, e.g. minification or injecting a
+//! snippet into HTML pages.
+
+use std::sync::Arc;
+
+use servente_http::{
+    BodyKind,
+    HeaderName,
+    HeaderValue,
+    Response,
+};
+use servente_resources::{
+    ContentCoding,
+    MediaType,
+    StreamingEncoder,
+};
+
+/// A transformation of the body of responses.
+///
+/// Transforms are only applied to bodies that are in memory, so files that
+/// are streamed from disk are left alone.
+pub trait BodyTransform: Send + Sync {
+    /// The media types (without parameters, e.g. `text/html`) this transform
+    /// should be applied to.
+    fn media_types(&self) -> &[&str];
+
+    /// Transform the body, or return `None` to leave it unchanged.
+    fn transform(&self, media_type: &MediaType, body: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Apply the transforms to the body of the response, in order.
+pub(crate) fn apply_body_transforms(response: &mut Response, transforms: &[Arc<dyn BodyTransform>]) {
+    if transforms.is_empty() || response.headers.has_cache_control_directive("no-transform") {
+        return;
+    }
+
+    let media_type = match response.headers.get(&HeaderName::ContentType) {
+        Some(HeaderValue::MediaType(media_type)) => media_type.clone(),
+        Some(value) => MediaType::Custom(value.as_str_may_convert().into_owned()),
+        None => return,
+    };
+
+    let essence = media_type.essence();
+    let mut transforms = transforms.iter()
+        .filter(|transform| transform.media_types().iter().any(|ty| ty.eq_ignore_ascii_case(essence)))
+        .peekable();
+    if transforms.peek().is_none() {
+        return;
+    }
+
+    let (body, content_coding) = match &response.body {
+        Some(BodyKind::Bytes(bytes)) => (bytes.as_slice(), None),
+        Some(BodyKind::CachedBytes(versions, content_coding)) => (versions.uncompressed.as_slice(), *content_coding),
+        Some(BodyKind::StaticString(string)) => (string.as_bytes(), None),
+        Some(BodyKind::String(string)) => (string.as_bytes(), None),
+        Some(BodyKind::File { .. } | BodyKind::Shared(..) | BodyKind::Stream(..)) | None => return,
+    };
+
+    let mut transformed: Option<Vec<u8>> = None;
+    for transform in transforms {
+        if let Some(result) = transform.transform(&media_type, transformed.as_deref().unwrap_or(body)) {
+            transformed = Some(result);
+        }
+    }
+
+    let Some(mut transformed) = transformed else {
+        return;
+    };
+
+    // The cached version was already compressed, so compress the transformed
+    // body in the same coding, such that the `Content-Encoding` stays valid.
+    if let Some(content_coding) = content_coding {
+        match compress(content_coding, &transformed) {
+            Ok(compressed) => transformed = compressed,
+            Err(_) => response.headers.remove(&HeaderName::ContentEncoding),
+        }
+    }
+
+    if response.headers.contains(&HeaderName::ContentLength) {
+        response.headers.set_content_length(transformed.len());
+    }
+
+    response.body = Some(BodyKind::Bytes(transformed));
+}
+
+fn compress(content_coding: ContentCoding, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = StreamingEncoder::new(content_coding);
+    let mut compressed = encoder.encode(data)?;
+    compressed.extend(encoder.finish()?);
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use servente_http::StatusCode;
+
+    struct InjectAnalytics;
+
+    impl BodyTransform for InjectAnalytics {
+        fn media_types(&self) -> &[&str] {
+            &["text/html"]
+        }
+
+        fn transform(&self, _: &MediaType, body: &[u8]) -> Option<Vec<u8>> {
+            let body = std::str::from_utf8(body).ok()?;
+            let index = body.rfind("</body>")?;
+            Some(format!("{}<script src=\"/analytics.js\"></script>{}", &body[..index], &body[index..]).into_bytes())
+        }
+    }
+
+    fn create_response(media_type: MediaType, body: &'static str) -> Response {
+        let mut response = Response::with_status_and_string_body(StatusCode::Ok, body);
+        response.headers.set_content_type(media_type);
+        response
+    }
+
+    #[test]
+    fn test_inject_before_body_end() {
+        let mut response = create_response(MediaType::HTML, "<html><body><p>Hi</p></body></html>");
+        apply_body_transforms(&mut response, &[Arc::new(InjectAnalytics)]);
+
+        let Some(BodyKind::Bytes(body)) = &response.body else {
+            panic!("unexpected body: {:?}", response.body);
+        };
+        assert_eq!(std::str::from_utf8(body).unwrap(), "<html><body><p>Hi</p><script src=\"/analytics.js\"></script></body></html>");
+    }
+
+    #[test]
+    fn test_other_media_type_is_left_alone() {
+        let mut response = create_response(MediaType::PLAIN_TEXT, "</body>");
+        apply_body_transforms(&mut response, &[Arc::new(InjectAnalytics)]);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("</body>"))));
+    }
+
+    #[test]
+    fn test_transforms_are_chained() {
+        let mut response = create_response(MediaType::HTML, "<body></body>");
+        apply_body_transforms(&mut response, &[Arc::new(InjectAnalytics), Arc::new(InjectAnalytics)]);
+
+        let Some(BodyKind::Bytes(body)) = &response.body else {
+            panic!("unexpected body: {:?}", response.body);
+        };
+        assert_eq!(std::str::from_utf8(body).unwrap().matches("analytics.js").count(), 2);
+    }
+
+    #[test]
+    fn test_no_transform_is_respected() {
+        let mut response = create_response(MediaType::HTML, "<body></body>");
+        response.headers.append_or_override(HeaderName::CacheControl, "no-transform".into());
+        apply_body_transforms(&mut response, &[Arc::new(InjectAnalytics)]);
+        assert!(matches!(response.body, Some(BodyKind::StaticString("<body></body>"))));
+    }
+}
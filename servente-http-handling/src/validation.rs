@@ -0,0 +1,401 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Validation of requests before they are handled, to plug in simple rules of
+//! a web application firewall, e.g. blocking paths that should never be
+//! served or enforcing a maximum number of headers.
+
+use std::{collections::HashMap, sync::Arc};
+
+use base64::Engine;
+
+use servente_http::{
+    HeaderName,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+/// A check of [`ServenteSettings::request_validators`] that is invoked
+/// before anything else in [`crate::handle_request`].
+///
+/// [`ServenteSettings::request_validators`]: crate::ServenteSettings::request_validators
+pub trait RequestValidator: Send + Sync {
+    /// Validate the request, or return the response the client should get
+    /// instead, e.g. `403 Forbidden`.
+    fn validate(&self, request: &Request) -> Result<(), Box<Response>>;
+}
+
+/// The file names that are never served, because they commonly contain
+/// credentials or server configuration. These are compared ignoring the case.
+pub const SENSITIVE_FILE_NAMES: &[&str] = &[
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    "id_rsa",
+    "web.config",
+    "wp-config.php",
+];
+
+/// The extensions of backups and swap files of editors, which expose the
+/// source of the file they were made of.
+pub const SENSITIVE_FILE_EXTENSIONS: &[&str] = &[
+    "bak",
+    "old",
+    "orig",
+    "swp",
+];
+
+/// Blocks requests for dotfiles and directories (e.g. `/.git/config` or
+/// `/.env`), except for `/.well-known/`, and for the files in
+/// [`SENSITIVE_FILE_NAMES`] and [`SENSITIVE_FILE_EXTENSIONS`].
+///
+/// The path is percent-decoded and normalized before it is checked, so
+/// `/%2Eenv` and `/public/../.env` are blocked as well.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SensitivePathValidator;
+
+impl SensitivePathValidator {
+    fn is_sensitive(segment: &[u8]) -> bool {
+        if segment.starts_with(b".") {
+            return segment != b".well-known";
+        }
+
+        if segment.ends_with(b"~") {
+            return true;
+        }
+
+        let Ok(segment) = std::str::from_utf8(segment) else {
+            return false;
+        };
+
+        if SENSITIVE_FILE_NAMES.iter().any(|name| segment.eq_ignore_ascii_case(name)) {
+            return true;
+        }
+
+        segment.rsplit_once('.').is_some_and(|(_, extension)| {
+            SENSITIVE_FILE_EXTENSIONS.iter().any(|sensitive| extension.eq_ignore_ascii_case(sensitive))
+        })
+    }
+}
+
+impl RequestValidator for SensitivePathValidator {
+    fn validate(&self, request: &Request) -> Result<(), Box<Response>> {
+        let RequestTarget::Origin { path, .. } = &request.target else {
+            return Ok(());
+        };
+
+        let decoded = urlencoding::decode_binary(path.trim_start_matches('/').as_bytes());
+
+        // Paths escaping the root are rejected when the file is looked up.
+        let Some(normalized) = crate::normalize_path(&decoded) else {
+            return Ok(());
+        };
+
+        if normalized.split(|byte| *byte == b'/').any(Self::is_sensitive) {
+            return Err(Box::new(Response::with_status_and_string_body(StatusCode::Forbidden, "Forbidden")));
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the validators in order, returning the response of the first one that
+/// rejects the request.
+pub(crate) fn validate_request(request: &Request, validators: &[std::sync::Arc<dyn RequestValidator>]) -> Option<Response> {
+    validators.iter().find_map(|validator| validator.validate(request).err().map(|response| *response))
+}
+
+/// Validates a bearer token, see [`AuthScheme::Bearer`].
+pub type BearerTokenValidator = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// How the clients authenticate for a protected area of [`AuthValidator`].
+///
+/// # References
+/// * [RFC 9110 Section 11](https://www.rfc-editor.org/rfc/rfc9110.html#name-http-authentication)
+#[derive(Clone)]
+pub enum AuthScheme {
+    /// The `Basic` scheme, of which the passwords are checked against the
+    /// `credentials`, mapping the user names to their passwords.
+    ///
+    /// # References
+    /// * [RFC 7617](https://www.rfc-editor.org/rfc/rfc7617.html)
+    Basic {
+        realm: String,
+        credentials: HashMap<String, String>,
+    },
+
+    /// The `Bearer` scheme, of which the tokens are checked by the
+    /// `validator`, e.g. by looking the token up in a database.
+    ///
+    /// # References
+    /// * [RFC 6750](https://www.rfc-editor.org/rfc/rfc6750.html)
+    Bearer {
+        realm: String,
+        validator: BearerTokenValidator,
+    },
+}
+
+impl AuthScheme {
+    /// The `Basic` scheme for the users with the passwords of `credentials`.
+    pub fn basic(realm: impl Into<String>, credentials: HashMap<String, String>) -> Self {
+        Self::Basic { realm: realm.into(), credentials }
+    }
+
+    /// The `Bearer` scheme, of which the tokens are checked by the `validator`.
+    pub fn bearer<F>(realm: impl Into<String>, validator: F) -> Self
+            where F: Fn(&str) -> bool + Send + Sync + 'static {
+        Self::Bearer { realm: realm.into(), validator: Arc::new(validator) }
+    }
+
+    /// The `Bearer` scheme for a fixed set of `tokens`. The given token is
+    /// compared to every one of them in constant time, so the timing doesn't
+    /// reveal how much of a token was guessed correctly.
+    pub fn bearer_tokens<I, T>(realm: impl Into<String>, tokens: I) -> Self
+            where I: IntoIterator<Item = T>,
+                  T: Into<String> {
+        let tokens: Vec<String> = tokens.into_iter().map(Into::into).collect();
+        Self::bearer(realm, move |token| {
+            tokens.iter().fold(false, |found, expected| {
+                servente_common::constant_time_eq(token.as_bytes(), expected.as_bytes()) | found
+            })
+        })
+    }
+
+    /// Whether the credentials of the `Authorization` header are valid. The
+    /// name of the scheme is case-insensitive.
+    fn is_authorized(&self, authorization: &str) -> bool {
+        let Some((scheme, credentials)) = authorization.trim().split_once(' ') else {
+            return false;
+        };
+        let credentials = credentials.trim();
+
+        match self {
+            Self::Basic { credentials: store, .. } => {
+                if !scheme.eq_ignore_ascii_case("Basic") {
+                    return false;
+                }
+
+                let Some((user, password)) = decode_basic_credentials(credentials) else {
+                    return false;
+                };
+
+                // The password is compared even when the user doesn't exist,
+                // so the timing doesn't reveal which users exist either.
+                let expected = store.get(&user);
+                let password_matches = servente_common::constant_time_eq(
+                    password.as_bytes(),
+                    expected.unwrap_or(&password).as_bytes());
+                expected.is_some() && password_matches
+            }
+            Self::Bearer { validator, .. } => {
+                scheme.eq_ignore_ascii_case("Bearer") && !credentials.is_empty() && validator(credentials)
+            }
+        }
+    }
+
+    /// The `WWW-Authenticate` challenge of the `401 Unauthorized` response.
+    /// When a bearer token was given, the challenge explains that it was
+    /// invalid.
+    fn challenge(&self, authorization: Option<&str>) -> String {
+        match self {
+            Self::Basic { realm, .. } => format!("Basic realm=\"{}\", charset=\"UTF-8\"", escape_quoted_string(realm)),
+            Self::Bearer { realm, .. } if authorization.is_some() => {
+                format!("Bearer realm=\"{}\", error=\"invalid_token\"", escape_quoted_string(realm))
+            }
+            Self::Bearer { realm, .. } => format!("Bearer realm=\"{}\"", escape_quoted_string(realm)),
+        }
+    }
+}
+
+impl std::fmt::Debug for AuthScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The credentials are never exposed.
+        match self {
+            Self::Basic { realm, credentials } => f.debug_struct("Basic")
+                .field("realm", realm)
+                .field("users", &credentials.len())
+                .finish(),
+            Self::Bearer { realm, .. } => f.debug_struct("Bearer")
+                .field("realm", realm)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Decodes the `token68` of the `Basic` scheme into the user name and the
+/// password.
+fn decode_basic_credentials(credentials: &str) -> Option<(String, String)> {
+    let decoded = base64::engine::general_purpose::STANDARD.decode(credentials).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn escape_quoted_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Requires the clients to authenticate for the protected areas, i.e. the
+/// request paths starting with one of the configured prefixes. Requests that
+/// aren't authorized get a `401 Unauthorized` with the `WWW-Authenticate`
+/// challenge of the area, before their handler runs.
+///
+/// Like [`SensitivePathValidator`], the path is percent-decoded and
+/// normalized first, so the area is matched against the file that is served:
+/// `/%61dmin/`, `//admin/` and `/public/../admin/` are all in `/admin/`.
+#[derive(Clone, Debug, Default)]
+pub struct AuthValidator {
+    areas: Vec<(String, AuthScheme)>,
+}
+
+impl AuthValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Protect the request paths that start with the `prefix`, e.g.
+    /// `/admin/`. The first matching area is used.
+    pub fn with_area(mut self, prefix: impl Into<String>, scheme: AuthScheme) -> Self {
+        self.areas.push((prefix.into(), scheme));
+        self
+    }
+
+    fn check(&self, request: &Request) -> Result<(), Box<Response>> {
+        let RequestTarget::Origin { path, .. } = &request.target else {
+            return Ok(());
+        };
+
+        // The file server rejects these as well.
+        let Some(path) = crate::normalize_request_path(path) else {
+            return Err(Box::new(Response::with_status_and_string_body(StatusCode::Forbidden, "Forbidden")));
+        };
+
+        let Some((_, scheme)) = self.areas.iter().find(|(prefix, _)| is_in_area(&path, prefix)) else {
+            return Ok(());
+        };
+
+        let authorization = request.headers.get(&HeaderName::Authorization)
+            .map(|value| value.as_str_may_convert());
+        if authorization.as_deref().is_some_and(|authorization| scheme.is_authorized(authorization)) {
+            return Ok(());
+        }
+
+        let mut response = Response::with_status_and_string_body(StatusCode::Unauthorized, "Unauthorized");
+        response.headers.append_or_override(HeaderName::WwwAuthenticate, scheme.challenge(authorization.as_deref()).into());
+        Err(Box::new(response))
+    }
+}
+
+/// Whether the normalized `path` is in the area of the `prefix`. The
+/// directory itself is part of it too, e.g. `/admin` for `/admin/`, as its
+/// index is served for it.
+fn is_in_area(path: &[u8], prefix: &str) -> bool {
+    path.starts_with(prefix.as_bytes())
+        || prefix.strip_suffix('/').is_some_and(|directory| path == directory.as_bytes())
+}
+
+impl RequestValidator for AuthValidator {
+    fn validate(&self, request: &Request) -> Result<(), Box<Response>> {
+        self.check(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use servente_http::{HeaderMap, HttpVersion, Method};
+
+    use super::*;
+
+    fn validate(path: &str) -> Result<(), Box<Response>> {
+        let request = Request {
+            method: servente_http::Method::Get,
+            target: RequestTarget::parse(path).unwrap(),
+            version: servente_http::HttpVersion::Http11,
+            headers: servente_http::HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        SensitivePathValidator.validate(&request)
+    }
+
+    #[test]
+    fn test_sensitive_paths_are_blocked() {
+        for path in ["/.env", "/.git/config", "/%2Eenv", "/public/../.htpasswd", "/WP-CONFIG.PHP", "/index.php.bak", "/index.html~", "/home/id_rsa"] {
+            let response = validate(path).expect_err(path);
+            assert_eq!(response.status, StatusCode::Forbidden, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_normal_paths_pass() {
+        for path in ["/", "/index.html", "/.well-known/security.txt", "/blog/2023/config.json", "/a.b/c", "/backup"] {
+            assert!(validate(path).is_ok(), "{path}");
+        }
+    }
+
+    fn create_request(target: &str, authorization: Option<&str>) -> Request {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.append_or_override(HeaderName::Authorization, authorization.to_string().into());
+        }
+        Request {
+            method: Method::Get,
+            target: RequestTarget::parse(target).unwrap(),
+            version: HttpVersion::Http11,
+            headers,
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    fn challenge(result: Result<(), Box<Response>>) -> String {
+        let response = result.unwrap_err();
+        assert_eq!(response.status, StatusCode::Unauthorized);
+        response.headers.get(&HeaderName::WwwAuthenticate).unwrap().as_str_may_convert().into_owned()
+    }
+
+    #[test]
+    fn test_auth_basic() {
+        let credentials = HashMap::from([(String::from("admin"), String::from("hunter2"))]);
+        let auth = AuthValidator::new().with_area("/admin/", AuthScheme::basic("Admin Area", credentials));
+
+        assert!(auth.validate(&create_request("/index.html", None)).is_ok());
+        assert!(auth.validate(&create_request("/admin/", Some("Basic YWRtaW46aHVudGVyMg=="))).is_ok());
+        assert!(auth.validate(&create_request("/admin/users", Some("basic  YWRtaW46aHVudGVyMg=="))).is_ok());
+
+        assert_eq!(challenge(auth.validate(&create_request("/admin/", None))), "Basic realm=\"Admin Area\", charset=\"UTF-8\"");
+        for authorization in ["Basic YWRtaW46aHVudGVyMw==", "Basic Z3Vlc3Q6aHVudGVyMg==", "Basic !!!", "Bearer YWRtaW46aHVudGVyMg==", "Basic"] {
+            challenge(auth.validate(&create_request("/admin/", Some(authorization))));
+        }
+    }
+
+    #[test]
+    fn test_auth_bearer() {
+        let auth = AuthValidator::new().with_area("/api/", AuthScheme::bearer_tokens("api", ["secret-token", "other-token"]));
+
+        assert!(auth.validate(&create_request("/api/users", Some("Bearer secret-token"))).is_ok());
+        assert!(auth.validate(&create_request("/api/users", Some("Bearer other-token"))).is_ok());
+        assert_eq!(challenge(auth.validate(&create_request("/api/users", None))), "Bearer realm=\"api\"");
+        assert_eq!(challenge(auth.validate(&create_request("/api/users", Some("Bearer guessed-token")))),
+            "Bearer realm=\"api\", error=\"invalid_token\"");
+    }
+
+    #[test]
+    fn test_auth_areas_are_matched_on_the_normalized_path() {
+        let auth = AuthValidator::new().with_area("/admin/", AuthScheme::bearer_tokens("admin", ["secret-token"]));
+
+        for path in ["/admin/secret.html", "/admin", "/%61dmin/secret.html", "/%2Fadmin/x", "//admin/x", "/pub/../admin/x", "/./admin/x", "/admin/./x"] {
+            challenge(auth.validate(&create_request(path, None)));
+            assert!(auth.validate(&create_request(path, Some("Bearer secret-token"))).is_ok(), "{path}");
+        }
+
+        for path in ["/", "/administrator", "/pub/admin/x", "/admin.html"] {
+            assert!(auth.validate(&create_request(path, None)).is_ok(), "{path}");
+        }
+
+        let response = auth.validate(&create_request("/../admin/x", Some("Bearer secret-token"))).unwrap_err();
+        assert_eq!(response.status, StatusCode::Forbidden);
+    }
+}
@@ -34,6 +34,30 @@ impl From<io::Error> for Error {
 /// An error that can occur while parsing an HTTP request.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, AsRefStr)]
 pub enum HttpParseError {
+    /// The client sent more data directly after the body than indicated by
+    /// the `Content-Length`, and the server is configured to reject this.
+    ///
+    /// ## Example:
+    /// ```text
+    /// Content-Length: 3
+    ///
+    /// abcdef
+    /// ```
+    BodyExceedsContentLength,
+
+    /// The request body is larger than the server accepts, which should be
+    /// answered with `413 Content Too Large`.
+    BodyTooLarge,
+
+    /// A chunk of a `chunked` request body announced a size larger than the
+    /// server accepts, e.g. to force it to allocate a huge buffer.
+    ///
+    /// ## Example:
+    /// ```text
+    /// FFFFFFFFFFFF
+    /// ```
+    ChunkTooLarge,
+
     /// The header didn't contain a colon, it's only the name.
     ///
     /// ## Example:
@@ -53,6 +77,28 @@ pub enum HttpParseError {
     /// ```
     HeaderTooLarge,
 
+    /// The connection was closed before the last chunk and the trailer
+    /// section of a `chunked` body were received.
+    ///
+    /// ## Example:
+    /// ```text
+    /// 3\r\nabc\r\n
+    /// ```
+    IncompleteChunkedBody,
+
+    /// The connection was closed before the request-line and the header
+    /// section were terminated by an empty line.
+    ///
+    /// ## Example:
+    /// ```text
+    /// GET / HTTP/1.1\r\nHost: example.com\r\n
+    /// ```
+    IncompleteRequest,
+
+    /// The body couldn't be decoded using the content codings of the
+    /// `Content-Encoding`, e.g. because it wasn't compressed at all.
+    InvalidContentEncoding,
+
     /// The `Content-Length` field was malformed, meaning it contained non-numeric
     /// characters, was too large, was negative, or was the empty string.
     ///
@@ -62,7 +108,8 @@ pub enum HttpParseError {
     /// ```
     InvalidContentLength,
 
-    /// The line ended with CR but not followed by an LF.
+    /// The line ended with CR but not followed by an LF, or with a bare LF
+    /// while those aren't accepted, see `LineEndingHandling`.
     ///
     /// ## Example:
     /// ```text
@@ -91,6 +138,15 @@ pub enum HttpParseError {
     /// ```
     InvalidHttpVersion,
 
+    /// The chunk-size line of a `chunked` body was malformed, i.e. it didn't
+    /// start with hexadecimal digits.
+    ///
+    /// ## Example:
+    /// ```text
+    /// 0x1A
+    /// ```
+    InvalidChunkSize,
+
     /// The request-target format is unknown.
     ///
     /// ## Syntax
@@ -142,6 +198,47 @@ pub enum HttpParseError {
 
     InvalidHttp2PriUpgradeBody,
 
+    /// The `Transfer-Encoding` is malformed, or `chunked` isn't the final
+    /// coding.
+    ///
+    /// ## Example:
+    /// ```text
+    /// Transfer-Encoding: chunked, gzip
+    /// ```
+    InvalidTransferEncoding,
+
+    /// The `Content-Encoding` of the request body contains a coding that isn't
+    /// supported, which should be answered with `415 Unsupported Media Type`.
+    ///
+    /// ## Example:
+    /// ```text
+    /// Content-Encoding: deflate
+    /// ```
+    UnknownContentCoding,
+
+    /// The `Transfer-Encoding` contains a coding that isn't supported, which
+    /// should be answered with `501 Not Implemented`.
+    ///
+    /// ## Example:
+    /// ```text
+    /// Transfer-Encoding: br, chunked
+    /// ```
+    UnknownTransferCoding,
+
+    /// The HTTP version is syntactically valid, but isn't supported on this
+    /// transport, which should be answered with
+    /// `505 HTTP Version Not Supported`.
+    ///
+    /// ## Examples:
+    /// ```text
+    /// HTTP/0.9
+    /// HTTP/3.0
+    /// ```
+    UnsupportedVersion {
+        major: u8,
+        minor: u8,
+    },
+
     HeaderMapInsertionError(HeaderMapInsertionError),
 }
 
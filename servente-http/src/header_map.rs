@@ -10,11 +10,26 @@ use crate::{
     HeaderName,
     HeaderValue,
     SecFetchDest,
+    lists::parse_http_list,
 };
 
+/// The `Set-Cookie` field can't be combined into a single comma-separated
+/// field, so every value has to be kept and transmitted separately.
+///
+/// # References
+/// * [RFC 6265 Section 3](https://www.rfc-editor.org/rfc/rfc6265.html#section-3)
+/// * [RFC 9110 Section 5.3](https://www.rfc-editor.org/rfc/rfc9110.html#section-5.3)
+static SET_COOKIE: HeaderName = HeaderName::SetCookie;
+
 #[derive(Clone, Debug, Default)]
 pub struct HeaderMap {
+    /// The fields, where the values of duplicate fields are combined.
     headers: HashMap<HeaderName, HeaderValue>,
+
+    /// The separate values of fields that were appended more than once, in
+    /// the order they were appended.
+    duplicate_values: HashMap<HeaderName, Vec<HeaderValue>>,
+
     set_cookie_values: Vec<HeaderValue>,
 }
 
@@ -26,10 +41,11 @@ impl HeaderMap {
     /// Append a header to the list of headers.
     ///
     /// If the field with the given `name` was already present, the values are
-    /// concatenated with `, `, as specified by the HTTP specification.
+    /// concatenated with `, `, as specified by the HTTP specification. The
+    /// separate values are retained as well, see [`HeaderMap::get_all`].
     ///
     /// # References
-    ///
+    /// * [RFC 9110 Section 5.3](https://www.rfc-editor.org/rfc/rfc9110.html#section-5.3)
     pub fn append(&mut self, name: HeaderName, value: HeaderValue) -> Result<(), HeaderMapInsertionError> {
         if name == HeaderName::SetCookie {
             self.set_cookie_values.push(value);
@@ -37,14 +53,17 @@ impl HeaderMap {
         }
 
         match self.headers.entry(name) {
-            hashbrown::hash_map::Entry::Occupied(e) => {
+            hashbrown::hash_map::Entry::Occupied(mut e) => {
                 if e.key() == &HeaderName::ContentLength {
                     return Err(HeaderMapInsertionError::MultipleContentLength);
                 }
 
-                e.replace_entry_with(|_, old_value| {
-                    Some(HeaderValue::from(format!("{}, {}", old_value.as_str_may_convert(), value.as_str_may_convert())))
-                });
+                let combined = HeaderValue::from(format!("{}, {}", e.get().as_str_may_convert(), value.as_str_may_convert()));
+                let old_value = e.insert(combined);
+
+                self.duplicate_values.entry(e.key().clone())
+                    .or_insert_with(|| vec![old_value])
+                    .push(value);
             }
             hashbrown::hash_map::Entry::Vacant(e) => {
                 e.insert(value);
@@ -55,11 +74,21 @@ impl HeaderMap {
     }
 
     pub fn append_or_override(&mut self, name: HeaderName, value: HeaderValue) {
+        if name == HeaderName::SetCookie {
+            self.set_cookie_values = vec![value];
+            return;
+        }
+
+        self.duplicate_values.remove(&name);
         self.headers.insert(name, value);
     }
 
     #[must_use]
     pub fn contains(&self, header_name: &HeaderName) -> bool {
+        if header_name == &HeaderName::SetCookie {
+            return !self.set_cookie_values.is_empty();
+        }
+
         for (name, _) in &self.headers {
             if name == header_name {
                 return true;
@@ -69,13 +98,23 @@ impl HeaderMap {
         false
     }
 
+    /// Returns the number of fields, where every value of a duplicate field
+    /// (e.g. `Set-Cookie`) is counted as a separate field.
     #[must_use]
     pub fn len(&self) -> usize {
-        self.headers.len()
+        let duplicates: usize = self.duplicate_values.values().map(|values| values.len() - 1).sum();
+        self.headers.len() + duplicates + self.set_cookie_values.len()
     }
 
+    /// Get the value of the field with the given name. If the field was
+    /// appended multiple times, the values are combined, except for
+    /// `Set-Cookie`, for which this returns the first value.
     #[must_use]
     pub fn get(&self, header_name: &HeaderName) -> Option<&HeaderValue> {
+        if header_name == &HeaderName::SetCookie {
+            return self.set_cookie_values.first();
+        }
+
         for (name, value) in &self.headers {
             if name == header_name {
                 return Some(value);
@@ -85,25 +124,86 @@ impl HeaderMap {
         None
     }
 
+    /// Get all the separate values of the field with the given name, in the
+    /// order they were appended.
+    pub fn get_all(&self, header_name: &HeaderName) -> impl Iterator<Item = &HeaderValue> {
+        let values: &[HeaderValue] = if header_name == &HeaderName::SetCookie {
+            &self.set_cookie_values
+        } else if let Some(values) = self.duplicate_values.get(header_name) {
+            values
+        } else if let Some(value) = self.headers.get(header_name) {
+            std::slice::from_ref(value)
+        } else {
+            &[]
+        };
+
+        values.iter()
+    }
+
+    /// Returns whether one of the `Cache-Control` fields contains the given
+    /// directive, e.g. `no-transform`. Directive names are case-insensitive.
+    ///
+    /// # References
+    /// * [RFC 9111 Section 5.2](https://www.rfc-editor.org/rfc/rfc9111.html#name-cache-control)
+    pub fn has_cache_control_directive(&self, directive: &str) -> bool {
+        self.get_all(&HeaderName::CacheControl).any(|value| {
+            parse_http_list(&value.as_str_may_convert())
+                .any(|element| element.split('=').next().unwrap_or("").trim().eq_ignore_ascii_case(directive))
+        })
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.headers.is_empty()
+        self.headers.is_empty() && self.set_cookie_values.is_empty()
     }
 
+    /// Iterate over all the fields. Every value of a duplicate field is
+    /// yielded separately, which is required for e.g. `Set-Cookie`, since
+    /// these must never be combined.
     pub fn iter(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
-        self.headers.iter()
+        self.headers.keys()
+            .flat_map(|name| self.get_all(name).map(move |value| (name, value)))
+            .chain(self.set_cookie_values.iter().map(|value| (&SET_COOKIE, value)))
+    }
+
+    /// Iterate over all the fields like [`HeaderMap::iter`], but in a
+    /// deterministic order: grouped by [`HeaderName::field_group`], and
+    /// alphabetically within a group. The values of duplicate fields keep the
+    /// order they were appended in.
+    pub fn iter_canonical(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        let mut fields: Vec<_> = self.iter().collect();
+        fields.sort_by(|(a, _), (b, _)| {
+            a.field_group().cmp(&b.field_group())
+                .then_with(|| a.to_string_lowercase().cmp(&b.to_string_lowercase()))
+        });
+        fields.into_iter()
     }
 
     pub fn remove(&mut self, header_name: &HeaderName) {
+        if header_name == &HeaderName::SetCookie {
+            self.set_cookie_values.clear();
+            return;
+        }
+
+        self.duplicate_values.remove(header_name);
         self.headers.retain(|name, _| name != header_name);
     }
 }
 
 impl IntoIterator for HeaderMap {
     type Item = (HeaderName, HeaderValue);
-    type IntoIter = hashbrown::hash_map::IntoIter<HeaderName, HeaderValue>;
+    type IntoIter = std::vec::IntoIter<(HeaderName, HeaderValue)>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.headers.into_iter()
+        let mut fields = Vec::with_capacity(self.len());
+        for (name, value) in self.headers {
+            match self.duplicate_values.get(&name) {
+                Some(values) => fields.extend(values.iter().map(|value| (name.clone(), value.clone()))),
+                None => fields.push((name, value)),
+            }
+        }
+
+        fields.extend(self.set_cookie_values.into_iter().map(|value| (HeaderName::SetCookie, value)));
+        fields.into_iter()
     }
 }
 
@@ -151,3 +251,102 @@ impl HeaderMap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiple_set_cookie_are_kept_separate() {
+        let mut map = HeaderMap::new();
+        map.append(HeaderName::SetCookie, "a=1".into()).unwrap();
+        map.append(HeaderName::SetCookie, "b=2; Secure".into()).unwrap();
+        map.append(HeaderName::Server, "servente".into()).unwrap();
+
+        let cookies: Vec<_> = map.iter()
+            .filter(|(name, _)| **name == HeaderName::SetCookie)
+            .map(|(_, value)| value.as_str_no_convert().unwrap())
+            .collect();
+        assert_eq!(cookies, ["a=1", "b=2; Secure"]);
+        assert_eq!(map.len(), 3);
+        assert!(map.contains(&HeaderName::SetCookie));
+        assert_eq!(map.get(&HeaderName::SetCookie), Some(&HeaderValue::from("a=1")));
+
+        let cookies = map.into_iter()
+            .filter(|(name, _)| *name == HeaderName::SetCookie)
+            .count();
+        assert_eq!(cookies, 2);
+    }
+
+    #[test]
+    fn test_set_cookie_override_and_remove() {
+        let mut map = HeaderMap::new();
+        map.append(HeaderName::SetCookie, "a=1".into()).unwrap();
+        map.append(HeaderName::SetCookie, "b=2".into()).unwrap();
+
+        map.append_or_override(HeaderName::SetCookie, "c=3".into());
+        assert_eq!(map.iter().map(|(_, value)| value.clone()).collect::<Vec<_>>(), [HeaderValue::from("c=3")]);
+
+        map.remove(&HeaderName::SetCookie);
+        assert!(map.is_empty());
+        assert!(!map.contains(&HeaderName::SetCookie));
+    }
+
+    #[test]
+    fn test_get_all_duplicate_link() {
+        let mut map = HeaderMap::new();
+        map.append(HeaderName::Link, "</style.css>; rel=preload; as=style".into()).unwrap();
+        map.append(HeaderName::Link, "</script.js>; rel=preload; as=script".into()).unwrap();
+
+        let links: Vec<_> = map.get_all(&HeaderName::Link).collect();
+        assert_eq!(links, [
+            &HeaderValue::from("</style.css>; rel=preload; as=style"),
+            &HeaderValue::from("</script.js>; rel=preload; as=script"),
+        ]);
+
+        assert_eq!(map.get(&HeaderName::Link).unwrap().as_str_no_convert(),
+            Some("</style.css>; rel=preload; as=style, </script.js>; rel=preload; as=script"));
+
+        assert_eq!(map.iter().count(), 2);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.clone().into_iter().count(), 2);
+
+        map.append_or_override(HeaderName::Link, "</other.css>; rel=preload; as=style".into());
+        assert_eq!(map.get_all(&HeaderName::Link).count(), 1);
+    }
+
+    #[test]
+    fn test_get_all_single_and_absent() {
+        let mut map = HeaderMap::new();
+        map.append(HeaderName::Server, "servente".into()).unwrap();
+        assert_eq!(map.get_all(&HeaderName::Server).collect::<Vec<_>>(), [&HeaderValue::from("servente")]);
+        assert_eq!(map.get_all(&HeaderName::Link).count(), 0);
+    }
+
+    #[test]
+    fn test_iter_canonical() {
+        let mut map = HeaderMap::new();
+        map.append(HeaderName::Other("X-Custom".into()), "1".into()).unwrap();
+        map.append(HeaderName::ContentType, "text/html".into()).unwrap();
+        map.append(HeaderName::SetCookie, "b=2".into()).unwrap();
+        map.append(HeaderName::Server, "servente".into()).unwrap();
+        map.append(HeaderName::ContentLength, "0".into()).unwrap();
+        map.append(HeaderName::SetCookie, "a=1".into()).unwrap();
+        map.append(HeaderName::Date, "Thu, 01 Jan 1970 00:00:00 GMT".into()).unwrap();
+        map.append(HeaderName::ContentSecurityPolicy, "default-src 'self'".into()).unwrap();
+
+        let fields: Vec<_> = map.iter_canonical()
+            .map(|(name, value)| format!("{}: {}", name.to_string_h1(), value.as_str_no_convert().unwrap()))
+            .collect();
+        assert_eq!(fields, [
+            "Date: Thu, 01 Jan 1970 00:00:00 GMT",
+            "Server: servente",
+            "Set-Cookie: b=2",
+            "Set-Cookie: a=1",
+            "Content-Length: 0",
+            "Content-Type: text/html",
+            "Content-Security-Policy: default-src 'self'",
+            "X-Custom: 1",
+        ]);
+    }
+}
@@ -11,6 +11,7 @@ pub enum HeaderName {
     Other(String),
 
     Accept,
+    AcceptCH,
     AcceptCharset,
     AcceptEncoding,
     AcceptLanguage,
@@ -41,6 +42,7 @@ pub enum HeaderName {
     ContentSecurityPolicy,
     ContentSecurityPolicyReportOnly,
     ContentType,
+    CriticalCH,
     CrossOriginResourcePolicy,
     Date,
     DNT,
@@ -104,6 +106,7 @@ pub enum HeaderName {
     WwwAuthenticate,
     XContentTypeOptions,
     XForwaredFor,
+    XForwardedProto,
     XFrameOptions,
     XRequestedWith,
     XXSSProtection,
@@ -111,6 +114,7 @@ pub enum HeaderName {
 
 static STRING_TO_HEADER_NAME_MAP: phf::Map<UniCase<&'static str>, HeaderName> = phf_map!(
     UniCase::ascii("accept") => HeaderName::Accept,
+    UniCase::ascii("accept-ch") => HeaderName::AcceptCH,
     UniCase::ascii("accept-charset") => HeaderName::AcceptCharset,
     UniCase::ascii("accept-encoding") => HeaderName::AcceptEncoding,
     UniCase::ascii("accept-language") => HeaderName::AcceptLanguage,
@@ -141,6 +145,7 @@ static STRING_TO_HEADER_NAME_MAP: phf::Map<UniCase<&'static str>, HeaderName> =
     UniCase::ascii("content-security-policy") => HeaderName::ContentSecurityPolicy,
     UniCase::ascii("content-security-policy-report-only") => HeaderName::ContentSecurityPolicyReportOnly,
     UniCase::ascii("content-type") => HeaderName::ContentType,
+    UniCase::ascii("critical-ch") => HeaderName::CriticalCH,
     UniCase::ascii("cross-origin-resource-policy") => HeaderName::CrossOriginResourcePolicy,
     UniCase::ascii("date") => HeaderName::Date,
     UniCase::ascii("dnt") => HeaderName::DNT,
@@ -204,6 +209,7 @@ static STRING_TO_HEADER_NAME_MAP: phf::Map<UniCase<&'static str>, HeaderName> =
     UniCase::ascii("www-authenticate") => HeaderName::WwwAuthenticate,
     UniCase::ascii("x-content-type-options") => HeaderName::XContentTypeOptions,
     UniCase::ascii("x-forwarded-for") => HeaderName::XForwaredFor,
+    UniCase::ascii("x-forwarded-proto") => HeaderName::XForwardedProto,
     UniCase::ascii("x-frame-options") => HeaderName::XFrameOptions,
     UniCase::ascii("x-requested-with") => HeaderName::XRequestedWith,
     UniCase::ascii("x-xss-protection") => HeaderName::XXSSProtection,
@@ -237,6 +243,51 @@ impl HeaderName {
         }
     }
 
+    /// The group this field belongs to, used for serializing the fields in a
+    /// canonical order.
+    pub fn field_group(&self) -> HeaderFieldGroup {
+        match self {
+            HeaderName::CacheControl
+                | HeaderName::Connection
+                | HeaderName::Date
+                | HeaderName::KeepAlive
+                | HeaderName::Pragma
+                | HeaderName::Trailer
+                | HeaderName::TransferEncoding
+                | HeaderName::Upgrade
+                | HeaderName::Via => HeaderFieldGroup::General,
+
+            HeaderName::AcceptCH
+                | HeaderName::AcceptRanges
+                | HeaderName::Age
+                | HeaderName::AltSvc
+                | HeaderName::CacheStatus
+                | HeaderName::CriticalCH
+                | HeaderName::ETag
+                | HeaderName::Location
+                | HeaderName::ProxyAuthenticate
+                | HeaderName::ProxyStatus
+                | HeaderName::RetryAfter
+                | HeaderName::Server
+                | HeaderName::SetCookie
+                | HeaderName::Vary
+                | HeaderName::WwwAuthenticate => HeaderFieldGroup::Response,
+
+            HeaderName::Allow
+                | HeaderName::ContentDisposition
+                | HeaderName::ContentEncoding
+                | HeaderName::ContentLanguage
+                | HeaderName::ContentLength
+                | HeaderName::ContentLocation
+                | HeaderName::ContentRange
+                | HeaderName::ContentType
+                | HeaderName::Expires
+                | HeaderName::LastModified => HeaderFieldGroup::Representation,
+
+            _ => HeaderFieldGroup::Other,
+        }
+    }
+
     /// Returns whether or not this field is an CGI extension field. These may
     /// convey information between a CGI script and a web server.
     ///
@@ -261,6 +312,7 @@ impl HeaderName {
             HeaderName::Other(str) => str,
 
             HeaderName::Accept => "Accept",
+            HeaderName::AcceptCH => "Accept-CH",
             HeaderName::AcceptCharset => "Accept-Charset",
             HeaderName::AcceptEncoding => "Accept-Encoding",
             HeaderName::AcceptLanguage => "Accept-Language",
@@ -291,6 +343,7 @@ impl HeaderName {
             HeaderName::ContentSecurityPolicy => "Content-Security-Policy",
             HeaderName::ContentSecurityPolicyReportOnly => "Content-Security-Policy-Report-Only",
             HeaderName::ContentType => "Content-Type",
+            HeaderName::CriticalCH => "Critical-CH",
             HeaderName::CrossOriginResourcePolicy => "Cross-Origin-Resource-Policy",
             HeaderName::Date => "Date",
             HeaderName::DNT => "DNT",
@@ -354,6 +407,7 @@ impl HeaderName {
             HeaderName::WwwAuthenticate => "WWW-Authenticate",
             HeaderName::XContentTypeOptions => "X-Content-Type-Options",
             HeaderName::XForwaredFor => "X-Forwarded-For",
+            HeaderName::XForwardedProto => "X-Forwarded-Proto",
             HeaderName::XFrameOptions => "X-Frame-Options",
             HeaderName::XRequestedWith => "X-Requested-With",
             HeaderName::XXSSProtection => "X-XSS-Protection",
@@ -371,6 +425,7 @@ impl HeaderName {
             HeaderName::Other(str) => return Cow::Owned(str.to_ascii_lowercase()),
 
             HeaderName::Accept => "accept",
+            HeaderName::AcceptCH => "accept-ch",
             HeaderName::AcceptCharset => "accept-charset",
             HeaderName::AcceptEncoding => "accept-encoding",
             HeaderName::AcceptLanguage => "accept-language",
@@ -401,6 +456,7 @@ impl HeaderName {
             HeaderName::ContentSecurityPolicy => "content-security-policy",
             HeaderName::ContentSecurityPolicyReportOnly => "content-security-policy-report-only",
             HeaderName::ContentType => "content-type",
+            HeaderName::CriticalCH => "critical-ch",
             HeaderName::CrossOriginResourcePolicy => "cross-origin-resource-policy",
             HeaderName::Date => "date",
             HeaderName::DNT => "dnt",
@@ -464,6 +520,7 @@ impl HeaderName {
             HeaderName::WwwAuthenticate => "www-authenticate",
             HeaderName::XContentTypeOptions => "x-content-type-options",
             HeaderName::XForwaredFor => "x-forwarded-for",
+            HeaderName::XForwardedProto => "x-forwarded-proto",
             HeaderName::XFrameOptions => "x-frame-options",
             HeaderName::XRequestedWith => "x-requested-with",
             HeaderName::XXSSProtection => "x-xss-protection",
@@ -471,6 +528,24 @@ impl HeaderName {
     }
 }
 
+/// The groups of fields, in the order they are serialized in when the
+/// canonical order is requested. This follows the recommendation of the
+/// (obsolete) HTTP/1.1 specification to send the general fields first,
+/// followed by the response fields, and ending with the entity
+/// (representation) fields.
+///
+/// # References
+/// * [RFC 2616 Section 4.2](https://www.rfc-editor.org/rfc/rfc2616.html#section-4.2)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HeaderFieldGroup {
+    General,
+    Response,
+    Representation,
+
+    /// Extension fields, e.g. `Content-Security-Policy` or custom fields.
+    Other,
+}
+
 /// This class defines which specification and/or behavior the field name
 /// belongs to.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
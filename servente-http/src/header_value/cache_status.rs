@@ -0,0 +1,233 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The `Cache-Status` header, which describes how the caches along the path
+//! of the response handled the request.
+//!
+//! ### References
+//! * [RFC 9211](https://www.rfc-editor.org/rfc/rfc9211.html)
+
+use std::fmt::{Display, Write};
+
+/// A single member of the `Cache-Status` list, describing how one cache
+/// handled the request, e.g. `ServenteCache; hit; detail=MEMORY`.
+///
+/// A response is either a `hit` or forwarded (`fwd`), never both, which the
+/// constructors enforce.
+///
+/// ### References
+/// * [RFC 9211 Section 2](https://www.rfc-editor.org/rfc/rfc9211.html#name-the-cache-status-http-respo)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheStatus {
+    cache: String,
+    outcome: CacheOutcome,
+    ttl: Option<i64>,
+    key: Option<String>,
+    detail: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum CacheOutcome {
+    Hit,
+    Forwarded {
+        reason: CacheForwardReason,
+        status: Option<u16>,
+    },
+}
+
+/// The reason the cache forwarded the request, i.e. the `fwd` parameter.
+///
+/// ### References
+/// * [RFC 9211 Section 2.2](https://www.rfc-editor.org/rfc/rfc9211.html#name-the-fwd-parameter)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CacheForwardReason {
+    /// The cache was configured to not handle this request.
+    Bypass,
+
+    /// The request method's semantics require the request to be forwarded.
+    Method,
+
+    /// The cache did not contain any responses that matched the request URI.
+    UriMiss,
+
+    /// The cache contained a response matching the URI, but not its `Vary`.
+    VaryMiss,
+
+    /// The cache did not contain any responses that could be used.
+    Miss,
+
+    /// The cache was able to select a response, but the request's semantics
+    /// didn't allow it to be used, e.g. `Cache-Control: no-cache`.
+    Request,
+
+    /// The cache was able to select a response, but it was stale.
+    Stale,
+
+    /// The cache was able to select a partial response, but it didn't
+    /// contain all the requested ranges.
+    Partial,
+}
+
+impl CacheForwardReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bypass => "bypass",
+            Self::Method => "method",
+            Self::UriMiss => "uri-miss",
+            Self::VaryMiss => "vary-miss",
+            Self::Miss => "miss",
+            Self::Request => "request",
+            Self::Stale => "stale",
+            Self::Partial => "partial",
+        }
+    }
+}
+
+impl CacheStatus {
+    /// The response was served from the `cache` without contacting the next
+    /// hop or the origin.
+    pub fn hit(cache: impl Into<String>) -> Self {
+        Self::with_outcome(cache.into(), CacheOutcome::Hit)
+    }
+
+    /// The `cache` forwarded the request towards the origin.
+    pub fn forwarded(cache: impl Into<String>, reason: CacheForwardReason) -> Self {
+        Self::with_outcome(cache.into(), CacheOutcome::Forwarded { reason, status: None })
+    }
+
+    fn with_outcome(cache: String, outcome: CacheOutcome) -> Self {
+        Self {
+            cache,
+            outcome,
+            ttl: None,
+            key: None,
+            detail: None,
+        }
+    }
+
+    /// The status code of the response the next hop returned to the forwarded
+    /// request, i.e. the `fwd-status` parameter. Ignored for hits, as the
+    /// parameter is only meaningful together with `fwd`.
+    pub fn with_forward_status(mut self, status_code: u16) -> Self {
+        if let CacheOutcome::Forwarded { status, .. } = &mut self.outcome {
+            *status = Some(status_code);
+        }
+        self
+    }
+
+    /// The remaining freshness lifetime of the response in seconds, which is
+    /// negative for stale responses.
+    pub fn with_ttl(mut self, ttl: i64) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// The implementation-specific representation of the stored response's
+    /// cache key.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Additional, implementation-specific information, e.g. `MEMORY`.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Whether the response was a hit.
+    pub fn is_hit(&self) -> bool {
+        self.outcome == CacheOutcome::Hit
+    }
+}
+
+impl Display for CacheStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_token_or_string(f, &self.cache)?;
+
+        match &self.outcome {
+            CacheOutcome::Hit => f.write_str("; hit")?,
+            CacheOutcome::Forwarded { reason, status } => {
+                write!(f, "; fwd={}", reason.as_str())?;
+                if let Some(status) = status {
+                    write!(f, "; fwd-status={status}")?;
+                }
+            }
+        }
+
+        if let Some(ttl) = self.ttl {
+            write!(f, "; ttl={ttl}")?;
+        }
+
+        if let Some(key) = &self.key {
+            f.write_str("; key=")?;
+            write_string(f, key)?;
+        }
+
+        if let Some(detail) = &self.detail {
+            f.write_str("; detail=")?;
+            write_token_or_string(f, detail)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the value can be serialized as a structured field token.
+///
+/// ### References
+/// * [RFC 8941 Section 3.3.4](https://www.rfc-editor.org/rfc/rfc8941.html#name-tokens)
+fn is_structured_token(value: &str) -> bool {
+    let mut bytes = value.bytes();
+    let Some(first) = bytes.next() else {
+        return false;
+    };
+
+    (first.is_ascii_alphabetic() || first == b'*')
+        && bytes.all(|byte| crate::syntax::is_token_character(byte) || byte == b':' || byte == b'/')
+}
+
+fn write_token_or_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    if is_structured_token(value) {
+        return f.write_str(value);
+    }
+    write_string(f, value)
+}
+
+/// Writes the value as a structured field string. Such a string can only
+/// contain printable ASCII characters, so the other characters are left out.
+///
+/// ### References
+/// * [RFC 8941 Section 3.3.3](https://www.rfc-editor.org/rfc/rfc8941.html#name-strings)
+fn write_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for character in value.chars().filter(|character| (' '..='~').contains(character)) {
+        if character == '"' || character == '\\' {
+            f.write_char('\\')?;
+        }
+        f.write_char(character)?;
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_hit() {
+        assert_eq!(CacheStatus::hit("ServenteCache").with_detail("MEMORY").to_string(), "ServenteCache; hit; detail=MEMORY");
+        assert_eq!(CacheStatus::hit("ServenteCache").with_ttl(-12).with_key("/index.html").to_string(),
+            "ServenteCache; hit; ttl=-12; key=\"/index.html\"");
+        assert_eq!(CacheStatus::hit("Example Cache").with_forward_status(200).to_string(), "\"Example Cache\"; hit");
+    }
+
+    #[test]
+    fn serialize_miss() {
+        assert_eq!(CacheStatus::forwarded("ServenteCache", CacheForwardReason::UriMiss).to_string(), "ServenteCache; fwd=uri-miss");
+        assert_eq!(CacheStatus::forwarded("ServenteCache", CacheForwardReason::Stale).with_forward_status(304).with_detail("disk read").to_string(),
+            "ServenteCache; fwd=stale; fwd-status=304; detail=\"disk read\"");
+        assert_eq!(CacheStatus::forwarded("ServenteCache", CacheForwardReason::Miss).with_key("/say \"hi\"\u{e9}").to_string(),
+            "ServenteCache; fwd=miss; key=\"/say \\\"hi\\\"\"");
+    }
+}
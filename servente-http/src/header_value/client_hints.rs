@@ -0,0 +1,112 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The user agent client hints, which browsers send in the requests after a
+//! response asked for them using `Accept-CH`.
+//!
+//! ### References
+//! * [RFC 8942](https://www.rfc-editor.org/rfc/rfc8942.html)
+//! * [User-Agent Client Hints](https://wicg.github.io/ua-client-hints/)
+
+use super::forwarded::{parse_token_or_quoted_string, split_outside_quotes};
+
+/// A single brand of the `Sec-CH-UA` header, e.g. `"Chromium";v="124"`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct UserAgentBrand {
+    /// The name of the brand, e.g. `Chromium`.
+    pub brand: String,
+
+    /// The significant version of the brand, e.g. `124`.
+    pub version: Option<String>,
+}
+
+impl UserAgentBrand {
+    /// Parse the `Sec-CH-UA` header into its brands. Returns `None` when the
+    /// header is malformed.
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        let brands: Vec<Self> = split_outside_quotes(value, ',')?
+            .into_iter()
+            .filter(|brand| !brand.is_empty())
+            .map(Self::parse)
+            .collect::<Option<_>>()?;
+
+        if brands.is_empty() {
+            return None;
+        }
+
+        Some(brands)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = split_outside_quotes(value, ';')?.into_iter();
+        let mut brand = Self {
+            brand: parse_structured_string(parts.next()?)?,
+            version: None,
+        };
+
+        for parameter in parts {
+            // Other parameters are allowed, but aren't understood.
+            if let Some(version) = parameter.strip_prefix("v=") {
+                brand.version = Some(parse_structured_string(version)?);
+            }
+        }
+
+        Some(brand)
+    }
+}
+
+/// Parse a structured field string, e.g. the `"Windows"` value of the
+/// `Sec-CH-UA-Platform` header.
+///
+/// ### References
+/// * [RFC 8941 Section 3.3.3](https://www.rfc-editor.org/rfc/rfc8941.html#name-strings)
+pub fn parse_structured_string(value: &str) -> Option<String> {
+    if !value.starts_with('"') {
+        return None;
+    }
+    parse_token_or_quoted_string(value)
+}
+
+/// Parse a structured field boolean, e.g. the `?1` value of the
+/// `Sec-CH-UA-Mobile` header.
+///
+/// ### References
+/// * [RFC 8941 Section 3.3.6](https://www.rfc-editor.org/rfc/rfc8941.html#name-booleans)
+pub fn parse_structured_boolean(value: &str) -> Option<bool> {
+    match value {
+        "?0" => Some(false),
+        "?1" => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sec_ch_ua() {
+        let brands = UserAgentBrand::parse_list(r#""Chromium";v="124", "Google Chrome";v="124", "Not(A:Brand";v="99""#).unwrap();
+        assert_eq!(brands, vec![
+            UserAgentBrand { brand: "Chromium".into(), version: Some("124".into()) },
+            UserAgentBrand { brand: "Google Chrome".into(), version: Some("124".into()) },
+            UserAgentBrand { brand: "Not(A:Brand".into(), version: Some("99".into()) },
+        ]);
+
+        assert_eq!(UserAgentBrand::parse_list(r#""Firefox""#), Some(vec![UserAgentBrand { brand: "Firefox".into(), version: None }]));
+        assert_eq!(UserAgentBrand::parse_list(""), None);
+        assert_eq!(UserAgentBrand::parse_list("Chromium;v=124"), None);
+        assert_eq!(UserAgentBrand::parse_list(r#""Chromium;v="124""#), None);
+    }
+
+    #[test]
+    fn parse_structured_values() {
+        assert_eq!(parse_structured_string(r#""Windows""#).as_deref(), Some("Windows"));
+        assert_eq!(parse_structured_string(r#""say \"hi\"""#).as_deref(), Some("say \"hi\""));
+        assert_eq!(parse_structured_string("Windows"), None);
+
+        assert_eq!(parse_structured_boolean("?1"), Some(true));
+        assert_eq!(parse_structured_boolean("?0"), Some(false));
+        assert_eq!(parse_structured_boolean("1"), None);
+    }
+}
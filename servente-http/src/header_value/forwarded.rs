@@ -0,0 +1,358 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The `Forwarded` and `Via` headers, which describe the proxies a message
+//! passed through.
+
+use std::{
+    fmt::{Display, Write},
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::syntax::{is_token_character, is_whitespace_character};
+
+/// A single element of the `Forwarded` header, added by one proxy.
+///
+/// ### References
+/// * [RFC 7239 Section 4](https://www.rfc-editor.org/rfc/rfc7239.html#section-4)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ForwardedElement {
+    /// The node that made the request to the proxy, i.e. the client.
+    pub for_node: Option<String>,
+
+    /// The interface the request came in at the proxy.
+    pub by: Option<String>,
+
+    /// The `Host` request header as received by the proxy.
+    pub host: Option<String>,
+
+    /// The protocol used to make the request to the proxy, e.g. `https`.
+    pub proto: Option<String>,
+}
+
+impl ForwardedElement {
+    /// Parse the `Forwarded` header into its elements, ordered from the first
+    /// proxy to the last. Returns `None` when the header is malformed.
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        let elements: Vec<Self> = split_outside_quotes(value, ',')?
+            .into_iter()
+            .filter(|element| !element.is_empty())
+            .map(Self::parse)
+            .collect::<Option<_>>()?;
+
+        if elements.is_empty() {
+            return None;
+        }
+
+        Some(elements)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut element = Self::default();
+        for pair in split_outside_quotes(value, ';')? {
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (name, value) = pair.split_once('=')?;
+            if name.is_empty() || !name.bytes().all(is_token_character) {
+                return None;
+            }
+
+            let field = match name.to_ascii_lowercase().as_str() {
+                "for" => &mut element.for_node,
+                "by" => &mut element.by,
+                "host" => &mut element.host,
+                "proto" => &mut element.proto,
+                // Extensions are allowed, but aren't understood.
+                _ => continue,
+            };
+
+            // A parameter MUST NOT occur more than once per element.
+            if field.is_some() {
+                return None;
+            }
+            *field = Some(parse_token_or_quoted_string(value)?);
+        }
+
+        Some(element)
+    }
+
+    /// The IP address of the client, when the `for` parameter contains one,
+    /// and not an obfuscated identifier or `unknown`.
+    pub fn for_ip(&self) -> Option<IpAddr> {
+        let node = self.for_node.as_deref()?;
+        if let Ok(address) = node.parse::<IpAddr>() {
+            return Some(address);
+        }
+        if let Ok(address) = node.parse::<SocketAddr>() {
+            return Some(address.ip());
+        }
+
+        // An IPv6 address without a port.
+        node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+    }
+}
+
+impl Display for ForwardedElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs = [
+            ("for", &self.for_node),
+            ("by", &self.by),
+            ("host", &self.host),
+            ("proto", &self.proto),
+        ];
+
+        let mut first = true;
+        for (name, value) in pairs {
+            let Some(value) = value else {
+                continue;
+            };
+
+            if !first {
+                f.write_char(';')?;
+            }
+            first = false;
+
+            f.write_str(name)?;
+            f.write_char('=')?;
+            write_token_or_quoted_string(f, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single entry of the `Via` header, added by one intermediary.
+///
+/// ### References
+/// * [RFC 9110 Section 7.6.3](https://www.rfc-editor.org/rfc/rfc9110.html#name-via)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ViaEntry {
+    /// The name of the protocol, which is omitted for HTTP.
+    pub protocol_name: Option<String>,
+
+    /// The version of the protocol, e.g. `1.1`.
+    pub protocol_version: String,
+
+    /// The host (and optionally port) or pseudonym of the intermediary.
+    pub received_by: String,
+
+    /// The comment, without the surrounding parentheses.
+    pub comment: Option<String>,
+}
+
+impl ViaEntry {
+    /// Parse the `Via` header into its entries, ordered from the first
+    /// intermediary to the last. Returns `None` when the header is malformed.
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        let entries: Vec<Self> = split_outside_quotes(value, ',')?
+            .into_iter()
+            .filter(|entry| !entry.is_empty())
+            .map(Self::parse)
+            .collect::<Option<_>>()?;
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        Some(entries)
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let (protocol, rest) = value.split_once(is_whitespace_character)?;
+        let rest = rest.trim_start_matches(is_whitespace_character);
+
+        let (protocol_name, protocol_version) = match protocol.split_once('/') {
+            Some((name, version)) => (Some(name.to_string()), version),
+            None => (None, protocol),
+        };
+
+        let (received_by, comment) = match rest.split_once(is_whitespace_character) {
+            Some((received_by, comment)) => {
+                let comment = comment.trim_matches(is_whitespace_character);
+                let comment = comment.strip_prefix('(')?.strip_suffix(')')?;
+                (received_by, Some(comment.to_string()))
+            }
+            None => (rest, None),
+        };
+
+        if protocol_version.is_empty() || received_by.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            protocol_name,
+            protocol_version: protocol_version.to_string(),
+            received_by: received_by.to_string(),
+            comment,
+        })
+    }
+}
+
+impl Display for ViaEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(protocol_name) = &self.protocol_name {
+            write!(f, "{protocol_name}/")?;
+        }
+        write!(f, "{} {}", self.protocol_version, self.received_by)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " ({comment})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the value on the separator, except when it is inside a quoted string
+/// or a comment. The parts are trimmed. Returns `None` when a quoted string or
+/// comment isn't terminated.
+pub(crate) fn split_outside_quotes(value: &str, separator: char) -> Option<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut comment_depth = 0usize;
+    let mut escaped = false;
+
+    for (index, character) in value.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match character {
+            '\\' if in_quotes || comment_depth != 0 => escaped = true,
+            '"' if comment_depth == 0 => in_quotes = !in_quotes,
+            '(' if !in_quotes => comment_depth += 1,
+            ')' if !in_quotes && comment_depth != 0 => comment_depth -= 1,
+            _ if character == separator && !in_quotes && comment_depth == 0 => {
+                parts.push(value[start..index].trim_matches(is_whitespace_character));
+                start = index + character.len_utf8();
+            }
+            _ => (),
+        }
+    }
+
+    if in_quotes || comment_depth != 0 {
+        return None;
+    }
+
+    parts.push(value[start..].trim_matches(is_whitespace_character));
+    Some(parts)
+}
+
+pub(crate) fn parse_token_or_quoted_string(value: &str) -> Option<String> {
+    let Some(quoted) = value.strip_prefix('"') else {
+        if value.is_empty() || !value.bytes().all(is_token_character) {
+            return None;
+        }
+        return Some(value.to_string());
+    };
+
+    let quoted = quoted.strip_suffix('"')?;
+    let mut result = String::with_capacity(quoted.len());
+    let mut characters = quoted.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => result.push(characters.next()?),
+            '"' => return None,
+            _ => result.push(character),
+        }
+    }
+    Some(result)
+}
+
+fn write_token_or_quoted_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    if !value.is_empty() && value.bytes().all(is_token_character) {
+        return f.write_str(value);
+    }
+
+    f.write_char('"')?;
+    for character in value.chars() {
+        if character == '"' || character == '\\' {
+            f.write_char('\\')?;
+        }
+        f.write_char(character)?;
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_forwarded_multiple_elements() {
+        let elements = ForwardedElement::parse_list(
+            "for=192.0.2.43;proto=https, For=\"[2001:db8:cafe::17]:4711\";by=203.0.113.60;host=example.com, for=unknown"
+        ).unwrap();
+
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0], ForwardedElement {
+            for_node: Some("192.0.2.43".into()),
+            proto: Some("https".into()),
+            ..Default::default()
+        });
+        assert_eq!(elements[1].for_node.as_deref(), Some("[2001:db8:cafe::17]:4711"));
+        assert_eq!(elements[1].by.as_deref(), Some("203.0.113.60"));
+        assert_eq!(elements[1].host.as_deref(), Some("example.com"));
+
+        assert_eq!(elements[0].for_ip(), Some("192.0.2.43".parse().unwrap()));
+        assert_eq!(elements[1].for_ip(), Some("2001:db8:cafe::17".parse().unwrap()));
+        assert_eq!(elements[2].for_ip(), None);
+    }
+
+    #[test]
+    fn parse_forwarded_quoted_separators() {
+        let elements = ForwardedElement::parse_list("for=\"_hidden,;\\\"x\";proto=http").unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].for_node.as_deref(), Some("_hidden,;\"x"));
+        assert_eq!(elements[0].to_string(), "for=\"_hidden,;\\\"x\";proto=http");
+    }
+
+    #[test]
+    fn parse_forwarded_invalid() {
+        assert_eq!(ForwardedElement::parse_list(""), None);
+        assert_eq!(ForwardedElement::parse_list("for"), None);
+        assert_eq!(ForwardedElement::parse_list("for=\"unterminated"), None);
+        assert_eq!(ForwardedElement::parse_list("for=[::1]"), None);
+        assert_eq!(ForwardedElement::parse_list("for=a;for=b"), None);
+    }
+
+    #[test]
+    fn forwarded_display() {
+        let element = ForwardedElement {
+            for_node: Some("[2001:db8::1]:4711".into()),
+            proto: Some("https".into()),
+            ..Default::default()
+        };
+        assert_eq!(element.to_string(), "for=\"[2001:db8::1]:4711\";proto=https");
+        assert_eq!(ForwardedElement::parse_list(&element.to_string()), Some(vec![element]));
+    }
+
+    #[test]
+    fn via_round_trip() {
+        let value = "1.0 fred, 1.1 p.example.net, HTTP/2.0 proxy.example.com:8080 (Servente, v0.3)";
+        let entries = ViaEntry::parse_list(value).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], ViaEntry {
+            protocol_name: None,
+            protocol_version: "1.0".into(),
+            received_by: "fred".into(),
+            comment: None,
+        });
+        assert_eq!(entries[2].protocol_name.as_deref(), Some("HTTP"));
+        assert_eq!(entries[2].received_by, "proxy.example.com:8080");
+        assert_eq!(entries[2].comment.as_deref(), Some("Servente, v0.3"));
+
+        let serialized: Vec<String> = entries.iter().map(ToString::to_string).collect();
+        assert_eq!(serialized.join(", "), value);
+    }
+
+    #[test]
+    fn via_invalid() {
+        assert_eq!(ViaEntry::parse_list(""), None);
+        assert_eq!(ViaEntry::parse_list("1.1"), None);
+        assert_eq!(ViaEntry::parse_list("1.1 proxy (unterminated"), None);
+    }
+}
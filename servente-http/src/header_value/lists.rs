@@ -121,6 +121,66 @@ pub fn find_best_match_in_weighted_list(header_field_value: &str, supported_valu
     best_match.map(|(best_supported_values_index, _) | best_supported_values_index)
 }
 
+/// Finds the best matching media type for the `Accept` field-value, returning
+/// `None` if the client accepts none of them. Media ranges like `text/*` and
+/// `*/*` are supported, and the most specific range matching a media type
+/// determines its weight. When media types are equally acceptable, the first
+/// supported one is chosen.
+///
+/// The `supported_values` are essences (without parameters), e.g.
+/// `text/html`.
+///
+/// # References
+/// * [RFC 9110 Section 12.5.1](https://www.rfc-editor.org/rfc/rfc9110.html#name-accept)
+pub fn find_best_match_in_media_range_list(header_field_value: &str, supported_values: &[&str]) -> Option<usize> {
+    let list: Vec<_> = parse_http_weighted_list(header_field_value)
+        .collect();
+
+    let mut best_match: Option<(usize, f32)> = None;
+
+    for (supported_value_index, supported_value_name) in supported_values.iter().enumerate() {
+        let Some((supported_type, _)) = supported_value_name.split_once('/') else {
+            continue;
+        };
+
+        // The specificity is 2 for an exact match, 1 for `type/*`, and 0 for
+        // `*/*`.
+        let field_weight = list.iter()
+            .filter_map(|entry| {
+                let name = entry.name.trim_matches(is_whitespace_character);
+                let specificity = if UniCase::ascii(name) == UniCase::ascii(*supported_value_name) {
+                    2
+                } else if name.strip_suffix("/*").is_some_and(|range_type| UniCase::ascii(range_type) == UniCase::ascii(supported_type)) {
+                    1
+                } else if name == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, entry.weight))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, weight)| weight)
+            .unwrap_or(0.0);
+
+        if field_weight == 0.0 {
+            continue;
+        }
+
+        let better = if let Some((_, best_weight)) = best_match {
+            best_weight < field_weight
+        } else {
+            true
+        };
+
+        if better {
+            best_match = Some((supported_value_index, field_weight));
+        }
+    }
+
+    best_match.map(|(best_supported_values_index, _) | best_supported_values_index)
+}
+
 /// This function parses a field-value and returns an iterator of list elements
 /// for HTTP. The iterator will never return the empty string, as those cannot
 /// occur in HTTP lists and will be ignored.
@@ -227,6 +287,20 @@ mod tests {
         assert_eq!(find_best_match_in_weighted_list(input_user_agent, input_server, 1.0), expected);
     }
 
+    #[rstest]
+    #[case("application/json", &["text/html", "application/json"], Some(1))]
+    #[case("text/html", &["text/html", "application/json"], Some(0))]
+    #[case("*/*", &["text/html", "application/json"], Some(0))]
+    #[case("application/*", &["text/html", "application/json"], Some(1))]
+    #[case("text/html;q=0.5, application/json", &["text/html", "application/json"], Some(1))]
+    #[case("*/*;q=0.1, application/json;q=0", &["application/json", "text/html"], Some(1))]
+    #[case("TEXT/HTML", &["text/html"], Some(0))]
+    #[case("image/png", &["text/html", "application/json"], None)]
+    #[case("", &["text/html"], None)]
+    fn test_find_best_match_in_media_range_list(#[case] accept: &str, #[case] supported: &[&str], #[case] expected: Option<usize>) {
+        assert_eq!(find_best_match_in_media_range_list(accept, supported), expected);
+    }
+
     #[rstest]
     #[case("en-US", &["en-US"])]
     #[case("foo,bar", &["foo", "bar"])]
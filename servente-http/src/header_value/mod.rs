@@ -1,10 +1,18 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+pub mod cache_status;
+pub mod client_hints;
+pub mod forwarded;
 pub mod lists;
 pub mod sec_fetch_dest;
+pub mod transfer_coding;
 
+pub use cache_status::*;
+pub use client_hints::*;
+pub use forwarded::*;
 pub use sec_fetch_dest::*;
+pub use transfer_coding::*;
 
 use std::borrow::Cow;
 use std::{time::SystemTime, sync::Arc};
@@ -168,6 +176,12 @@ impl From<Arc<str>> for HeaderValue {
     }
 }
 
+impl From<CacheStatus> for HeaderValue {
+    fn from(cache_status: CacheStatus) -> HeaderValue {
+        HeaderValue::String(cache_status.to_string())
+    }
+}
+
 impl From<ContentCoding> for HeaderValue {
     fn from(content_coding: ContentCoding) -> HeaderValue {
         HeaderValue::ContentCoding(content_coding)
@@ -0,0 +1,180 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::fmt::Display;
+
+use crate::HeaderValue;
+
+/// A transfer coding is applied to the message body to ensure safe transport
+/// through the network, as opposed to a content coding, which is a property of
+/// the representation.
+///
+/// ### References
+/// * [RFC 9112 Section 7](https://www.rfc-editor.org/rfc/rfc9112.html#name-transfer-codings)
+/// * [IANA HTTP Transfer Coding Registry](https://www.iana.org/assignments/http-parameters/http-parameters.xhtml#transfer-coding)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TransferCoding {
+    Chunked,
+    Compress,
+    Deflate,
+    Gzip,
+}
+
+impl TransferCoding {
+    /// Parse a transfer coding, ignoring any parameters.
+    ///
+    /// ### Note
+    /// Transfer coding names are case-insensitive, and `x-gzip` and
+    /// `x-compress` are aliases of `gzip` and `compress` respectively.
+    pub fn parse(value: &str) -> Option<Self> {
+        let name = value.split(';').next().unwrap_or("").trim();
+        [
+            ("chunked", TransferCoding::Chunked),
+            ("compress", TransferCoding::Compress),
+            ("x-compress", TransferCoding::Compress),
+            ("deflate", TransferCoding::Deflate),
+            ("gzip", TransferCoding::Gzip),
+            ("x-gzip", TransferCoding::Gzip),
+        ].into_iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|(_, coding)| coding)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferCoding::Chunked => "chunked",
+            TransferCoding::Compress => "compress",
+            TransferCoding::Deflate => "deflate",
+            TransferCoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// The value of the `Transfer-Encoding` header: the codings in the order they
+/// were applied to the message body.
+///
+/// ### References
+/// * [RFC 9112 Section 6.1](https://www.rfc-editor.org/rfc/rfc9112.html#name-transfer-encoding)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TransferCodingList {
+    codings: Vec<TransferCoding>,
+}
+
+/// The reason a `Transfer-Encoding` header is invalid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransferCodingError {
+    /// The header doesn't contain any coding.
+    Empty,
+
+    /// `chunked` was applied more than once, or wasn't the last coding, so the
+    /// length of the message can't be determined.
+    ChunkedNotLast,
+
+    /// The coding isn't known to (or supported by) this server.
+    Unknown,
+}
+
+impl TransferCodingList {
+    /// The list containing only `chunked`.
+    pub fn chunked() -> Self {
+        Self { codings: vec![TransferCoding::Chunked] }
+    }
+
+    /// Create a list where `coding` is applied first, and `chunked` last.
+    pub fn with_chunked(coding: TransferCoding) -> Self {
+        debug_assert_ne!(coding, TransferCoding::Chunked);
+        Self { codings: vec![coding, TransferCoding::Chunked] }
+    }
+
+    /// Parse the value of the `Transfer-Encoding` header of a request.
+    ///
+    /// ### RFC 9112 Section 6.3
+    /// > If a Transfer-Encoding header field is present in a request and the
+    /// > chunked transfer coding is not the final encoding, the message body
+    /// > length cannot be determined reliably; the server MUST respond with
+    /// > the 400 (Bad Request) status code and then close the connection.
+    pub fn parse(value: &str) -> Result<Self, TransferCodingError> {
+        let mut codings = Vec::new();
+        for coding in value.split(',') {
+            let coding = coding.trim();
+            if coding.is_empty() {
+                continue;
+            }
+
+            codings.push(TransferCoding::parse(coding).ok_or(TransferCodingError::Unknown)?);
+        }
+
+        match codings.iter().position(|coding| *coding == TransferCoding::Chunked) {
+            _ if codings.is_empty() => Err(TransferCodingError::Empty),
+            Some(index) if index == codings.len() - 1 => Ok(Self { codings }),
+            _ => Err(TransferCodingError::ChunkedNotLast),
+        }
+    }
+
+    pub fn codings(&self) -> &[TransferCoding] {
+        &self.codings
+    }
+
+    /// Returns whether the final coding is `chunked`.
+    pub fn is_chunked(&self) -> bool {
+        self.codings.last() == Some(&TransferCoding::Chunked)
+    }
+}
+
+impl Display for TransferCodingList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, coding) in self.codings.iter().enumerate() {
+            if index != 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(coding.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl From<TransferCodingList> for HeaderValue {
+    fn from(value: TransferCodingList) -> Self {
+        if value.codings == [TransferCoding::Chunked] {
+            return HeaderValue::StaticString("chunked");
+        }
+
+        HeaderValue::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("chunked", &[TransferCoding::Chunked])]
+    #[case("Chunked", &[TransferCoding::Chunked])]
+    #[case("gzip, chunked", &[TransferCoding::Gzip, TransferCoding::Chunked])]
+    #[case("x-gzip,chunked", &[TransferCoding::Gzip, TransferCoding::Chunked])]
+    #[case("deflate, gzip, chunked", &[TransferCoding::Deflate, TransferCoding::Gzip, TransferCoding::Chunked])]
+    fn parse_valid(#[case] input: &str, #[case] expected: &[TransferCoding]) {
+        let list = TransferCodingList::parse(input).unwrap();
+        assert_eq!(list.codings(), expected);
+        assert!(list.is_chunked());
+    }
+
+    #[rstest]
+    #[case("", TransferCodingError::Empty)]
+    #[case(" , ", TransferCodingError::Empty)]
+    #[case("gzip", TransferCodingError::ChunkedNotLast)]
+    #[case("chunked, gzip", TransferCodingError::ChunkedNotLast)]
+    #[case("chunked, chunked", TransferCodingError::ChunkedNotLast)]
+    #[case("br, chunked", TransferCodingError::Unknown)]
+    #[case("identity", TransferCodingError::Unknown)]
+    fn parse_invalid(#[case] input: &str, #[case] expected: TransferCodingError) {
+        assert_eq!(TransferCodingList::parse(input), Err(expected));
+    }
+
+    #[test]
+    fn to_header_value() {
+        assert_eq!(HeaderValue::from(TransferCodingList::chunked()), HeaderValue::StaticString("chunked"));
+        assert_eq!(HeaderValue::from(TransferCodingList::with_chunked(TransferCoding::Gzip)).as_str_no_convert(), Some("gzip, chunked"));
+    }
+}
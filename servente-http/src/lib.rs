@@ -9,11 +9,13 @@ pub mod header_map;
 pub mod header_name;
 pub mod header_value;
 pub mod method;
+pub mod problem;
 pub mod range;
 pub mod request;
 pub mod request_target;
 pub mod response;
 pub mod status;
+pub mod streaming;
 pub mod syntax;
 pub mod version;
 
@@ -25,11 +27,13 @@ pub use header_map::*;
 pub use header_name::*;
 pub use header_value::*;
 pub use method::*;
+pub use problem::ProblemDetails;
 pub use range::*;
 pub use request::*;
 pub use request_target::*;
 pub use response::*;
 pub use status::*;
+pub use streaming::{BodySink, StreamingBody};
 pub use version::*;
 
 use servente_resources::{
@@ -45,10 +49,24 @@ pub enum BodyKind {
         handle: tokio::fs::File,
         metadata: std::fs::Metadata,
     },
+
+    /// Bytes that are shared between responses, e.g. a memory-mapped file.
+    /// These are served the same way as a `File`.
+    Shared(Arc<dyn SharedBytes>),
     StaticString(&'static str),
+
+    /// A body that is generated while it is being sent, see
+    /// [`Response::streaming`].
+    Stream(StreamingBody),
     String(String),
 }
 
+/// The contents of a [`BodyKind::Shared`] body.
+pub trait SharedBytes: AsRef<[u8]> + std::fmt::Debug + Send + Sync {}
+
+impl<T> SharedBytes for T
+        where T: AsRef<[u8]> + std::fmt::Debug + Send + Sync {}
+
 impl From<&'static str> for BodyKind {
     fn from(value: &'static str) -> Self {
         Self::StaticString(value)
@@ -105,6 +105,28 @@ impl Method {
             Self::VersionControl => "VERSION-CONTROL",
         }
     }
+
+    /// Whether the method is safe, meaning it is essentially read-only and
+    /// doesn't change the state of the server. Unknown methods aren't safe.
+    ///
+    /// # References
+    /// * [RFC 9110 - Section 9.2.1. Safe Methods](https://www.rfc-editor.org/rfc/rfc9110.html#name-safe-methods)
+    /// * [IANA Hypertext Transfer Protocol (HTTP) Method Registry](https://www.iana.org/assignments/http-methods/http-methods.xhtml)
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Self::Get | Self::Head | Self::Options | Self::PropFind | Self::Report | Self::Search | Self::Trace)
+    }
+
+    /// Whether the method is idempotent, meaning that sending the same
+    /// request multiple times has the same effect as sending it once. Such
+    /// requests can be retried when the connection fails before the response
+    /// is received. Unknown methods aren't idempotent.
+    ///
+    /// # References
+    /// * [RFC 9110 - Section 9.2.2. Idempotent Methods](https://www.rfc-editor.org/rfc/rfc9110.html#name-idempotent-methods)
+    /// * [IANA Hypertext Transfer Protocol (HTTP) Method Registry](https://www.iana.org/assignments/http-methods/http-methods.xhtml)
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, Self::Other(..) | Self::Connect | Self::Lock | Self::Patch | Self::Post)
+    }
 }
 
 static METHOD_MAP: phf::Map<&'static str, Method> = phf_map!(
@@ -199,4 +221,28 @@ mod tests {
     fn test_case_sensitivity(#[case] input: &str, #[case] expected: Method) {
         assert_eq!(Method::from(input), expected);
     }
+
+    #[rstest]
+    #[case(Method::Get, true)]
+    #[case(Method::Head, true)]
+    #[case(Method::Options, true)]
+    #[case(Method::Post, false)]
+    #[case(Method::Delete, false)]
+    #[case(Method::Other(String::from("GETLIKE")), false)]
+    #[test]
+    fn test_is_safe(#[case] method: Method, #[case] expected: bool) {
+        assert_eq!(method.is_safe(), expected);
+    }
+
+    #[rstest]
+    #[case(Method::Get, true)]
+    #[case(Method::Put, true)]
+    #[case(Method::Delete, true)]
+    #[case(Method::Post, false)]
+    #[case(Method::Patch, false)]
+    #[case(Method::Other(String::from("GETLIKE")), false)]
+    #[test]
+    fn test_is_idempotent(#[case] method: Method, #[case] expected: bool) {
+        assert_eq!(method.is_idempotent(), expected);
+    }
 }
@@ -0,0 +1,159 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Machine-readable details of errors in HTTP response bodies.
+//!
+//! # References
+//! * [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457.html)
+
+use std::fmt::Write;
+
+use crate::StatusCode;
+
+/// The details of a problem, which are serialized as
+/// `application/problem+json`, see [`crate::Response::problem`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProblemDetails {
+    /// A URI reference identifying the problem type. When this is
+    /// `about:blank`, the problem has no semantics beyond the status code.
+    pub problem_type: String,
+
+    /// A short summary of the problem type. When `None`, the reason phrase of
+    /// the status code is used.
+    pub title: Option<String>,
+
+    pub status: StatusCode,
+
+    /// An explanation specific to this occurrence of the problem.
+    pub detail: Option<String>,
+
+    /// A URI reference identifying this occurrence of the problem.
+    pub instance: Option<String>,
+}
+
+impl ProblemDetails {
+    /// Create the details of a problem that is only described by its status
+    /// code, i.e. of the `about:blank` type.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: String::from("about:blank"),
+            title: None,
+            status,
+            detail: None,
+            instance: None,
+        }
+    }
+
+    pub fn with_type(mut self, problem_type: impl Into<String>) -> Self {
+        self.problem_type = problem_type.into();
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Serialize the details to a JSON object. Members that aren't set are
+    /// omitted.
+    pub fn to_json(&self) -> String {
+        let status = self.status.to_string();
+        let title = match &self.title {
+            Some(title) => title.as_str(),
+            None => status.split_once(' ').map_or(status.as_ref(), |(_, reason)| reason),
+        };
+
+        let mut json = String::from("{\"type\":");
+        write_json_string(&mut json, &self.problem_type);
+        json.push_str(",\"title\":");
+        write_json_string(&mut json, title);
+        _ = write!(json, ",\"status\":{}", self.status as u16);
+
+        if let Some(detail) = &self.detail {
+            json.push_str(",\"detail\":");
+            write_json_string(&mut json, detail);
+        }
+
+        if let Some(instance) = &self.instance {
+            json.push_str(",\"instance\":");
+            write_json_string(&mut json, instance);
+        }
+
+        json.push('}');
+        json
+    }
+}
+
+/// Write the value as a quoted JSON string.
+///
+/// # References
+/// * [RFC 8259 Section 7](https://www.rfc-editor.org/rfc/rfc8259.html#section-7)
+fn write_json_string(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => _ = write!(output, "\\u{:04x}", c as u32),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_problem_uses_reason_phrase() {
+        assert_eq!(ProblemDetails::new(StatusCode::NotFound).to_json(),
+            r#"{"type":"about:blank","title":"Not Found","status":404}"#);
+    }
+
+    #[test]
+    fn test_all_members() {
+        let problem = ProblemDetails::new(StatusCode::Forbidden)
+            .with_type("https://example.com/probs/out-of-credit")
+            .with_title("You do not have enough credit.")
+            .with_detail("Your current balance is 30, but that costs 50.")
+            .with_instance("/account/12345/msgs/abc");
+        assert_eq!(problem.to_json(), concat!(
+            r#"{"type":"https://example.com/probs/out-of-credit","title":"You do not have enough credit.","#,
+            r#""status":403,"detail":"Your current balance is 30, but that costs 50.","instance":"/account/12345/msgs/abc"}"#,
+        ));
+    }
+
+    #[test]
+    fn test_problem_response() {
+        let response = crate::Response::problem(ProblemDetails::new(StatusCode::TooManyRequests).with_detail("Slow down"));
+        assert_eq!(response.status, StatusCode::TooManyRequests);
+        assert_eq!(response.headers.get(&crate::HeaderName::ContentType).unwrap().as_str_no_convert(), Some("application/problem+json"));
+
+        let Some(crate::BodyKind::String(body)) = &response.body else {
+            panic!("unexpected body: {:?}", response.body);
+        };
+        assert_eq!(body, r#"{"type":"about:blank","title":"Too Many Requests","status":429,"detail":"Slow down"}"#);
+    }
+
+    #[test]
+    fn test_strings_are_escaped() {
+        let problem = ProblemDetails::new(StatusCode::BadRequest)
+            .with_detail("the \"name\" field\\value\nis\u{1}invalid");
+        assert_eq!(problem.to_json(),
+            r#"{"type":"about:blank","title":"Bad Request","status":400,"detail":"the \"name\" field\\value\nis\u0001invalid"}"#);
+    }
+}
@@ -37,6 +37,12 @@ impl HttpRangeList {
                 let mut range = range.splitn(2, '-');
                 let start = range.next()?.parse().ok()?;
                 let end = range.next()?.parse().ok()?;
+
+                // A range that ends before it starts is invalid, not just
+                // unsatisfiable, see RFC 9110 Section 14.1.1.
+                if end < start {
+                    return None;
+                }
                 Range::Points { start, end }
             };
             ranges.push(range);
@@ -58,6 +64,78 @@ impl HttpRangeList {
     pub fn iter(&self) -> impl Iterator<Item = &Range> {
         self.ranges.iter()
     }
+
+    /// Resolve the ranges that are satisfiable for a representation of the
+    /// given length. The result is sorted, and overlapping or adjacent ranges
+    /// are coalesced, so no byte is sent twice. Returns `None` when none of
+    /// the ranges are satisfiable, which means the response should be
+    /// `416 Range Not Satisfiable`.
+    ///
+    /// ### References
+    /// * [RFC 9110 Section 14.1.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-byte-ranges)
+    #[must_use]
+    pub fn resolve(&self, complete_length: u64) -> Option<Vec<ByteRange>> {
+        let mut ranges: Vec<ByteRange> = self.ranges.iter()
+            .filter_map(|range| range.resolve(complete_length))
+            .collect();
+        ranges.sort();
+
+        let mut coalesced: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match coalesced.last_mut() {
+                Some(previous) if range.first <= previous.last + 1 => {
+                    previous.last = previous.last.max(range.last);
+                }
+                _ => coalesced.push(range),
+            }
+        }
+
+        if coalesced.is_empty() {
+            None
+        } else {
+            Some(coalesced)
+        }
+    }
+}
+
+impl Range {
+    /// Resolve the range for a representation of the given length, or `None`
+    /// if the range isn't satisfiable.
+    #[must_use]
+    pub fn resolve(&self, complete_length: u64) -> Option<ByteRange> {
+        let last = complete_length.checked_sub(1)?;
+        let (first, last) = match *self {
+            Range::Full => (0, last),
+            Range::StartPointToEnd { start } => (start, last),
+            Range::Points { start, end } => (start, end.min(last)),
+            Range::Suffix { suffix: 0 } => return None,
+            Range::Suffix { suffix } => (complete_length.saturating_sub(suffix), last),
+        };
+
+        if first > last {
+            return None;
+        }
+
+        Some(ByteRange { first, last })
+    }
+}
+
+/// A satisfiable range of bytes of a representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteRange {
+    /// The offset of the first byte, inclusive.
+    pub first: u64,
+
+    /// The offset of the last byte, inclusive.
+    pub last: u64,
+}
+
+impl ByteRange {
+    /// The number of bytes in the range.
+    #[allow(clippy::len_without_is_empty)] // A satisfiable range is never empty.
+    pub fn len(&self) -> u64 {
+        self.last - self.first + 1
+    }
 }
 
 /// The `Content-Range` header field indicates where in a full body a partial
@@ -100,3 +178,52 @@ pub enum ContentRangeHeaderValue {
         complete_length: usize
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(value: &str, complete_length: u64) -> Option<Vec<(u64, u64)>> {
+        HttpRangeList::parse(value).unwrap()
+            .resolve(complete_length)
+            .map(|ranges| ranges.iter().map(|range| (range.first, range.last)).collect())
+    }
+
+    #[test]
+    fn test_resolve_single() {
+        assert_eq!(resolve("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(resolve("bytes=900-", 1000), Some(vec![(900, 999)]));
+        assert_eq!(resolve("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(resolve("bytes=-2000", 1000), Some(vec![(0, 999)]));
+        assert_eq!(resolve("bytes=990-2000", 1000), Some(vec![(990, 999)]));
+    }
+
+    #[test]
+    fn test_resolve_unsatisfiable() {
+        assert_eq!(resolve("bytes=1000-", 1000), None);
+        assert_eq!(resolve("bytes=-0", 1000), None);
+        assert_eq!(resolve("bytes=0-", 0), None);
+        assert_eq!(resolve("bytes=1000-1100,2000-", 1000), None);
+
+        // Unsatisfiable ranges are ignored when another one is satisfiable.
+        assert_eq!(resolve("bytes=0-9,2000-", 1000), Some(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn test_resolve_multiple() {
+        assert_eq!(resolve("bytes=0-99,200-299", 1000), Some(vec![(0, 99), (200, 299)]));
+        assert_eq!(resolve("bytes=200-299,0-99", 1000), Some(vec![(0, 99), (200, 299)]));
+    }
+
+    #[test]
+    fn test_resolve_coalesces_overlapping_ranges() {
+        assert_eq!(resolve("bytes=0-99,50-149", 1000), Some(vec![(0, 149)]));
+        assert_eq!(resolve("bytes=0-99,100-199", 1000), Some(vec![(0, 199)]));
+        assert_eq!(resolve("bytes=100-199,0-499,-100", 1000), Some(vec![(0, 499), (900, 999)]));
+    }
+
+    #[test]
+    fn test_parse_rejects_reversed_range() {
+        assert_eq!(HttpRangeList::parse("bytes=99-0"), None);
+    }
+}
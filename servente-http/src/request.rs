@@ -3,10 +3,16 @@
 
 use crate::{
     BodyKind,
+    ForwardedElement,
     HeaderMap,
+    HeaderName,
     HttpVersion,
     Method,
     RequestTarget,
+    UserAgentBrand,
+    ViaEntry,
+    parse_structured_boolean,
+    parse_structured_string,
 };
 
 #[derive(Debug)]
@@ -16,4 +22,45 @@ pub struct Request {
     pub version: HttpVersion,
     pub headers: HeaderMap,
     pub body: Option<BodyKind>,
+
+    /// The body exactly as it was received, before any content coding was
+    /// decoded, e.g. to verify the signature of a webhook. This is only
+    /// kept for the routes that ask for it, see
+    /// `HandlerController::register_with_raw_body`.
+    pub raw_body: Option<Vec<u8>>,
+}
+
+impl Request {
+    /// The parsed `Forwarded` header, from the first proxy to the last.
+    /// Returns `None` when the header is absent or malformed.
+    ///
+    /// Note that these values can be set by anyone, so they should only be
+    /// trusted when they were added by a known proxy.
+    pub fn forwarded(&self) -> Option<Vec<ForwardedElement>> {
+        ForwardedElement::parse_list(&self.headers.get(&HeaderName::Forwarded)?.as_str_may_convert())
+    }
+
+    /// The parsed `Via` header, from the first intermediary to the last.
+    /// Returns `None` when the header is absent or malformed.
+    pub fn via(&self) -> Option<Vec<ViaEntry>> {
+        ViaEntry::parse_list(&self.headers.get(&HeaderName::Via)?.as_str_may_convert())
+    }
+
+    /// The brands of the user agent of the `Sec-CH-UA` client hint. Returns
+    /// `None` when the header is absent or malformed.
+    pub fn sec_ch_ua(&self) -> Option<Vec<UserAgentBrand>> {
+        UserAgentBrand::parse_list(&self.headers.get(&HeaderName::SecChUa)?.as_str_may_convert())
+    }
+
+    /// Whether the user agent prefers a mobile experience, according to the
+    /// `Sec-CH-UA-Mobile` client hint.
+    pub fn sec_ch_ua_mobile(&self) -> Option<bool> {
+        parse_structured_boolean(&self.headers.get(&HeaderName::SecChUaMobile)?.as_str_may_convert())
+    }
+
+    /// The platform of the user agent of the `Sec-CH-UA-Platform` client
+    /// hint, e.g. `Windows`.
+    pub fn sec_ch_ua_platform(&self) -> Option<String> {
+        parse_structured_string(&self.headers.get(&HeaderName::SecChUaPlatform)?.as_str_may_convert())
+    }
 }
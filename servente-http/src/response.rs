@@ -1,6 +1,8 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+use std::{future::Future, io};
+
 use servente_resources::MediaType;
 
 use crate::{
@@ -9,7 +11,10 @@ use crate::{
     HeaderName,
     HeaderValue,
     HttpVersion,
+    ProblemDetails,
     StatusCode,
+    StatusCodeClass,
+    streaming::{BodySink, StreamingBody},
 };
 
 #[derive(Debug)]
@@ -46,6 +51,78 @@ impl Response {
         }
     }
 
+    /// Create a redirect to the `location`, e.g. using `301 Moved
+    /// Permanently` or `307 Temporary Redirect`.
+    ///
+    /// # Panics
+    /// When the `status` isn't a redirection (3xx) status code.
+    ///
+    /// # References
+    /// * [RFC 9110 Section 15.4](https://www.rfc-editor.org/rfc/rfc9110.html#name-redirection-3xx)
+    pub fn redirect(status: StatusCode, location: impl Into<HeaderValue>) -> Self {
+        assert_eq!(status.class(), StatusCodeClass::Redirection, "{status:?} is not a redirection status code");
+
+        let mut response = Self::with_status(status);
+        response.headers.append_or_override(HeaderName::Location, location.into());
+        response.headers.set_content_length(0);
+        response
+    }
+
+    /// Create a `200 OK` response of which the body is written by the
+    /// `producer` while the response is being sent, e.g. a CSV export that is
+    /// written row by row. The length isn't known upfront, so HTTP/1.1 sends
+    /// the body with the `chunked` transfer coding.
+    ///
+    /// When the producer returns an error, the body ends abruptly, so the
+    /// client sees a truncated body instead of a complete one.
+    ///
+    /// ```ignore
+    /// Response::streaming(|mut sink| async move {
+    ///     for row in rows {
+    ///         sink.write(row.to_csv()).await?;
+    ///     }
+    ///     Ok(())
+    /// })
+    /// ```
+    pub fn streaming<F, Fut>(producer: F) -> Self
+            where F: FnOnce(BodySink) -> Fut,
+                  Fut: Future<Output = io::Result<()>> + Send + 'static {
+        let mut response = Self::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::Stream(StreamingBody::new(producer)));
+        response
+    }
+
+    /// Create a response describing the problem as `application/problem+json`,
+    /// with the status code of the problem.
+    ///
+    /// # References
+    /// * [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457.html)
+    pub fn problem(problem: ProblemDetails) -> Self {
+        let mut response = Self::with_status(problem.status);
+        response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(MediaType::PROBLEM_JSON));
+        response.body = Some(BodyKind::String(problem.to_json()));
+        response
+    }
+
+    /// Create the error response for an I/O error, e.g. one that occurred
+    /// while reading a file. The status code depends on the kind of error,
+    /// and the body only contains the error itself in debug builds, since it
+    /// might reveal e.g. paths on the server.
+    pub fn from_io_error(error: &io::Error) -> Self {
+        let (status, message) = match error.kind() {
+            io::ErrorKind::NotFound => (StatusCode::NotFound, "Not Found"),
+            io::ErrorKind::PermissionDenied => (StatusCode::Forbidden, "Forbidden"),
+            io::ErrorKind::TimedOut => (StatusCode::GatewayTimeout, "Gateway Timeout"),
+            _ => (StatusCode::InternalServerError, "Internal Server Error"),
+        };
+
+        #[cfg(debug_assertions)]
+        return Self::with_status_and_string_body(status, format!("{message}\n\n{error}"));
+
+        #[cfg(not(debug_assertions))]
+        Self::with_status_and_string_body(status, message)
+    }
+
     pub fn bad_request(message: &'static str) -> Self {
         let mut response = Self::with_status(StatusCode::BadRequest);
         response.body = Some(BodyKind::StaticString(message));
@@ -64,3 +141,42 @@ impl Response {
         response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirect() {
+        let response = Response::redirect(StatusCode::SeeOther, "/done?id=1");
+        assert_eq!(response.status, StatusCode::SeeOther);
+        assert_eq!(response.headers.get(&HeaderName::Location).unwrap().as_str_no_convert(), Some("/done?id=1"));
+        assert!(response.body.is_none());
+    }
+
+    #[test]
+    fn from_io_error() {
+        for (kind, status) in [
+            (io::ErrorKind::NotFound, StatusCode::NotFound),
+            (io::ErrorKind::PermissionDenied, StatusCode::Forbidden),
+            (io::ErrorKind::TimedOut, StatusCode::GatewayTimeout),
+            (io::ErrorKind::UnexpectedEof, StatusCode::InternalServerError),
+            (io::ErrorKind::Other, StatusCode::InternalServerError),
+        ] {
+            let response = Response::from_io_error(&io::Error::new(kind, "/srv/secret/path"));
+            assert_eq!(response.status, status, "{kind:?}");
+            let body = match &response.body {
+                Some(BodyKind::String(body)) => body.as_str(),
+                Some(BodyKind::StaticString(body)) => body,
+                _ => panic!("{kind:?} has no string body"),
+            };
+            assert_eq!(body.contains("/srv/secret/path"), cfg!(debug_assertions), "{kind:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn redirect_requires_redirection_status() {
+        _ = Response::redirect(StatusCode::Ok, "/");
+    }
+}
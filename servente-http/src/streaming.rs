@@ -0,0 +1,127 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Bodies that are generated while the response is being sent, e.g. a CSV
+//! export that is written row by row. The length of such a body isn't known
+//! upfront, so HTTP/1.1 frames it with the `chunked` transfer coding.
+
+use std::{future::Future, io};
+
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+/// The number of writes that are buffered before [`BodySink::write`] waits
+/// for the client to catch up.
+const CHANNEL_CAPACITY: usize = 8;
+
+/// The body of [`crate::Response::streaming`]. The producer runs in its own
+/// task, and is aborted when the body is dropped, e.g. when the client went
+/// away or the request was a `HEAD` request.
+#[derive(Debug)]
+pub struct StreamingBody {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    task: Option<JoinHandle<io::Result<()>>>,
+}
+
+/// The handle a producer of a [`StreamingBody`] writes the body into.
+#[derive(Debug)]
+pub struct BodySink {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl StreamingBody {
+    /// Spawns the producer, which is given the [`BodySink`] to write the body
+    /// into. When it returns an error, the body is truncated.
+    pub fn new<F, Fut>(producer: F) -> Self
+            where F: FnOnce(BodySink) -> Fut,
+                  Fut: Future<Output = io::Result<()>> + Send + 'static {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        Self {
+            receiver,
+            task: Some(tokio::spawn(producer(BodySink { sender }))),
+        }
+    }
+
+    /// Waits for the next part of the body, which is never empty. Returns
+    /// `None` when the producer finished successfully, and an error when it
+    /// failed or panicked, after which the body must be considered
+    /// incomplete.
+    pub async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(data) = self.receiver.recv().await {
+            return Ok(Some(data));
+        }
+
+        let Some(task) = self.task.take() else {
+            return Ok(None);
+        };
+
+        match task.await {
+            Ok(result) => result.map(|()| None),
+            Err(error) => Err(io::Error::other(error)),
+        }
+    }
+}
+
+impl Drop for StreamingBody {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+impl BodySink {
+    /// Writes the bytes to the body, waiting when the client can't keep up.
+    /// Fails with [`io::ErrorKind::BrokenPipe`] when the body is no longer
+    /// being sent, after which the producer should stop.
+    pub async fn write(&mut self, data: impl Into<Vec<u8>>) -> io::Result<()> {
+        let data = data.into();
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.sender.send(data).await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the streaming body is no longer being sent"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streaming_body_yields_the_writes() {
+        let mut body = StreamingBody::new(|mut sink| async move {
+            sink.write("first").await?;
+            sink.write(Vec::new()).await?;
+            sink.write(b"second".to_vec()).await
+        });
+
+        assert_eq!(body.next().await.unwrap().as_deref(), Some(&b"first"[..]));
+        assert_eq!(body.next().await.unwrap().as_deref(), Some(&b"second"[..]));
+        assert_eq!(body.next().await.unwrap(), None);
+        assert_eq!(body.next().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn streaming_body_reports_a_failed_producer() {
+        let mut body = StreamingBody::new(|mut sink| async move {
+            sink.write("row").await?;
+            Err(io::Error::other("database went away"))
+        });
+
+        assert!(body.next().await.unwrap().is_some());
+        assert_eq!(body.next().await.unwrap_err().to_string(), "database went away");
+    }
+
+    #[tokio::test]
+    async fn streaming_body_reports_a_panicked_producer() {
+        let mut body = StreamingBody::new(|_sink| async move {
+            panic!("the producer panicked");
+        });
+
+        assert!(body.next().await.is_err());
+    }
+}
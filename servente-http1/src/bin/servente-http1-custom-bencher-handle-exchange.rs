@@ -5,6 +5,8 @@ use std::time::{Duration, Instant};
 
 use servente_http_handling::{
     handler::HandlerController,
+    ConnectionInfo,
+    Scheme,
     ServenteSettings,
 };
 
@@ -31,7 +33,50 @@ async fn handle_exchange() {
         read_body_timeout: Duration::from_secs(2),
         read_headers_timeout: Duration::from_secs(2),
         middleware: Vec::new(),
-    }).await.unwrap();
+        canonical_host: Default::default(),
+        excess_body_data: Default::default(),
+        unknown_expectations: Default::default(),
+        line_endings: Default::default(),
+        log_tls_parameters: Default::default(),
+        dynamic_table_budget: Default::default(),
+        header_order: Default::default(),
+        body_transforms: Default::default(),
+        max_parts: servente_http_handling::config::DEFAULT_MAX_PARTS,
+        security_headers: Default::default(),
+        default_headers: Default::default(),
+        hsts: None,
+        request_body_spill_threshold: None,
+        http2_precomputed_header_blocks: false,
+        client_hints: Default::default(),
+        document_root: Default::default(),
+        document_source: Default::default(),
+        request_loggers: Default::default(),
+        redacted_headers: Default::default(),
+        max_chunk_size: servente_http_handling::config::DEFAULT_MAX_CHUNK_SIZE,
+        max_request_body_size: servente_http_handling::config::DEFAULT_MAX_REQUEST_BODY_SIZE,
+        tls_max_early_data_size: 0,
+        file_backend: Default::default(),
+        content_hash_etags: Default::default(),
+        http3_port: None,
+        http2_ping_interval: servente_http_handling::config::DEFAULT_HTTP2_PING_INTERVAL,
+        http2_max_unacknowledged_pings: servente_http_handling::config::DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS,
+        h2c_prior_knowledge: false,
+        client_connection_limit: None,
+        rewrite_rules: Default::default(),
+        maintenance_mode: Default::default(),
+        chunked_transfer_threshold: servente_http_handling::config::DEFAULT_CHUNKED_TRANSFER_THRESHOLD,
+        symlink_policy: Default::default(),
+        redirect_directories: false,
+        asset_fingerprints: Default::default(),
+        shutdown_grace_period: servente_http_handling::config::DEFAULT_SHUTDOWN_GRACE_PERIOD,
+        rate_limiter: None,
+        request_validators: Default::default(),
+        max_keep_alive_requests: servente_http_handling::config::DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+        keep_alive_idle_timeout: servente_http_handling::config::DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT,
+        web_sockets: Default::default(),
+        index_files: vec![String::from("index.html")],
+        stats: Default::default(),
+    }, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
 }
 
 #[tokio::main]
@@ -0,0 +1,231 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Decoding of the `chunked` transfer coding while the body is being read,
+//! for the handlers that read the body themselves, or of the responses of
+//! upstream servers.
+//!
+//! # References
+//! * [RFC 9112 Section 7.1](https://www.rfc-editor.org/rfc/rfc9112.html#name-chunked-transfer-coding)
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use servente_http::HttpParseError;
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+
+use crate::{
+    MaximumLength,
+    read::parse_chunk_size,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Reading the chunk-size line.
+    ChunkSize,
+
+    /// Reading the data of a chunk, of which this many bytes remain.
+    ChunkData(usize),
+
+    /// Reading the CRLF after the data of a chunk.
+    ChunkDataEnd,
+
+    /// Reading the trailer section, which is discarded.
+    Trailers,
+
+    Finished,
+}
+
+/// A reader that yields the decoded data of a `chunked` body, and stops at
+/// the end of the body, so the stream is positioned at the next request.
+///
+/// Like [`crate::read::read_request_body`], every chunk is limited to the
+/// `max_chunk_size`, and the body as a whole to the `max_body_size`. A
+/// malformed body is reported as an [`io::ErrorKind::InvalidData`] error.
+pub struct ChunkedBodyReader<'a, R> {
+    stream: &'a mut R,
+    state: State,
+
+    /// The part of the chunk-size line or trailer line that was read so far.
+    line: Vec<u8>,
+
+    max_chunk_size: usize,
+    remaining_body_size: usize,
+}
+
+impl<'a, R> ChunkedBodyReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    pub fn new(stream: &'a mut R, max_chunk_size: usize, max_body_size: usize) -> Self {
+        Self {
+            stream,
+            state: State::ChunkSize,
+            line: Vec::new(),
+            max_chunk_size,
+            remaining_body_size: max_body_size,
+        }
+    }
+
+    /// Read a line up to the CRLF, which is stored in [`Self::line`]
+    /// excluding the CRLF.
+    fn poll_line(&mut self, cx: &mut Context<'_>, maximum_length: MaximumLength, length_error: HttpParseError) -> Poll<io::Result<()>> {
+        loop {
+            let buffer = ready!(Pin::new(&mut *self.stream).poll_fill_buf(cx))?;
+            if buffer.is_empty() {
+                return Poll::Ready(Err(invalid_data(HttpParseError::IncompleteChunkedBody)));
+            }
+
+            let (consumed, complete) = match buffer.iter().position(|byte| *byte == b'\n') {
+                Some(index) => {
+                    self.line.extend_from_slice(&buffer[..index]);
+                    (index + 1, true)
+                }
+                None => {
+                    self.line.extend_from_slice(buffer);
+                    (buffer.len(), false)
+                }
+            };
+            Pin::new(&mut *self.stream).consume(consumed);
+
+            // The CR is still part of the line.
+            if self.line.len() > maximum_length.0 + 1 {
+                return Poll::Ready(Err(invalid_data(length_error)));
+            }
+
+            if complete {
+                break;
+            }
+        }
+
+        if self.line.pop() != Some(b'\r') {
+            return Poll::Ready(Err(invalid_data(HttpParseError::InvalidCRLF)));
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Process the next line of the framing, i.e. everything except the data
+    /// of the chunks.
+    fn poll_framing(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let (maximum_length, length_error) = match self.state {
+            State::Trailers => (MaximumLength::HEADER, HttpParseError::HeaderTooLarge),
+            _ => (MaximumLength::CHUNK_SIZE_LINE, HttpParseError::InvalidChunkSize),
+        };
+        ready!(self.poll_line(cx, maximum_length, length_error))?;
+        let line = std::mem::take(&mut self.line);
+
+        self.state = match self.state {
+            State::ChunkSize => {
+                let line = std::str::from_utf8(&line).map_err(|_| invalid_data(HttpParseError::InvalidChunkSize))?;
+                match parse_chunk_size(line, self.max_chunk_size).map_err(invalid_data)? {
+                    0 => State::Trailers,
+                    size if size > self.remaining_body_size => {
+                        return Poll::Ready(Err(invalid_data(HttpParseError::BodyTooLarge)));
+                    }
+                    size => {
+                        self.remaining_body_size -= size;
+                        State::ChunkData(size)
+                    }
+                }
+            }
+            State::ChunkDataEnd if line.is_empty() => State::ChunkSize,
+            State::ChunkDataEnd => return Poll::Ready(Err(invalid_data(HttpParseError::InvalidCRLF))),
+            State::Trailers if line.is_empty() => State::Finished,
+            State::Trailers => State::Trailers,
+            State::ChunkData(_) | State::Finished => unreachable!(),
+        };
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn invalid_data(error: HttpParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.as_ref().to_owned())
+}
+
+impl<'a, R> AsyncRead for ChunkedBodyReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let data = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let length = data.len().min(buf.remaining());
+        buf.put_slice(&data[..length]);
+        self.consume(length);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, R> AsyncBufRead for ChunkedBodyReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        while !matches!(this.state, State::ChunkData(_) | State::Finished) {
+            ready!(this.poll_framing(cx))?;
+        }
+
+        let State::ChunkData(remaining) = this.state else {
+            return Poll::Ready(Ok(&[]));
+        };
+
+        let buffer = ready!(Pin::new(&mut *this.stream).poll_fill_buf(cx))?;
+        if buffer.is_empty() {
+            return Poll::Ready(Err(invalid_data(HttpParseError::IncompleteChunkedBody)));
+        }
+
+        let length = buffer.len().min(remaining);
+        Poll::Ready(Ok(&buffer[..length]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        let this = self.get_mut();
+        let State::ChunkData(remaining) = this.state else {
+            return;
+        };
+
+        Pin::new(&mut *this.stream).consume(amount);
+        this.state = match remaining - amount {
+            0 => State::ChunkDataEnd,
+            remaining => State::ChunkData(remaining),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn read_chunked(input: &[u8]) -> (io::Result<Vec<u8>>, Vec<u8>) {
+        let mut stream = std::io::Cursor::new(input);
+        let mut reader = ChunkedBodyReader::new(&mut stream, 10, 13);
+        let mut body = Vec::new();
+        let result = reader.read_to_end(&mut body).await.map(|_| body);
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        (result, rest)
+    }
+
+    #[tokio::test]
+    async fn chunked_body_is_decoded() {
+        let (body, rest) = read_chunked(b"3;name=value\r\nabc\r\nA\r\n0123456789\r\n0\r\nServer-Timing: db;dur=53\r\n\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(body.unwrap(), b"abc0123456789");
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn chunked_body_errors() {
+        for input in [
+            &b"B\r\n0123456789A\r\n0\r\n\r\n"[..],
+            b"A\r\n0123456789\r\n4\r\nabcd\r\n0\r\n\r\n",
+            b"3\r\nabcdef",
+            b"0x3\r\nabc\r\n0\r\n\r\n",
+            b"3\nabc\r\n0\r\n\r\n",
+            b"3\r\nabc\r\n0\r\n",
+        ] {
+            let (body, _) = read_chunked(input).await;
+            assert_eq!(body.map_err(|error| error.kind()), Err(io::ErrorKind::InvalidData), "{}", String::from_utf8_lossy(input));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Support for TLS 1.3 early data (0-RTT), where a resuming client sends its
+//! first request(s) before the handshake is completed.
+//!
+//! # References
+//! * [RFC 8446 Section 2.3](https://www.rfc-editor.org/rfc/rfc8446.html#section-2.3)
+//! * [RFC 8470](https://www.rfc-editor.org/rfc/rfc8470.html)
+
+use std::{
+    io::{self, Read},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+
+use crate::read::BufferedRead;
+
+/// Take the early data the client sent, if the server accepted it.
+pub(crate) fn take_early_data(connection: &mut rustls::ServerConnection) -> Vec<u8> {
+    let mut early_data = Vec::new();
+    if let Some(mut reader) = connection.early_data() {
+        _ = reader.read_to_end(&mut early_data);
+    }
+    early_data
+}
+
+/// A reader that first yields the early data, and then continues with the
+/// stream the rest of the exchanges are received on.
+///
+/// This doesn't buffer anything itself, so the stream can be used directly
+/// again after the early data is consumed, without losing any data.
+pub(crate) struct EarlyDataReader<'a, R> {
+    early_data: Vec<u8>,
+    position: usize,
+    stream: &'a mut R,
+}
+
+impl<'a, R> EarlyDataReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    pub(crate) fn new(early_data: Vec<u8>, stream: &'a mut R) -> Self {
+        Self { early_data, position: 0, stream }
+    }
+
+    /// Whether the next byte that is read is still part of the early data.
+    pub(crate) fn is_in_early_data(&self) -> bool {
+        self.position < self.early_data.len()
+    }
+}
+
+impl<'a, R> AsyncRead for EarlyDataReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.is_in_early_data() {
+            return Pin::new(&mut *this.stream).poll_read(cx, buf);
+        }
+
+        let remaining = &this.early_data[this.position..];
+        let length = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..length]);
+        this.position += length;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, R> AsyncBufRead for EarlyDataReader<'a, R>
+        where R: AsyncBufRead + Unpin {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if !this.is_in_early_data() {
+            return Pin::new(&mut *this.stream).poll_fill_buf(cx);
+        }
+
+        Poll::Ready(Ok(&this.early_data[this.position..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        let this = self.get_mut();
+        if this.is_in_early_data() {
+            this.position += amount;
+        } else {
+            Pin::new(&mut *this.stream).consume(amount);
+        }
+    }
+}
+
+impl<'a, R> BufferedRead for EarlyDataReader<'a, R>
+        where R: BufferedRead {
+    fn buffered(&self) -> &[u8] {
+        if self.position < self.early_data.len() {
+            return &self.early_data[self.position..];
+        }
+        self.stream.buffered()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    #[tokio::test]
+    async fn early_data_is_read_before_the_stream() {
+        let mut stream = std::io::Cursor::new(b"three\nfour".to_vec());
+        let mut reader = EarlyDataReader::new(b"one\ntwo ".to_vec(), &mut stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "one\n");
+        assert!(reader.is_in_early_data());
+
+        // A line may start in the early data and end in the stream.
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "two three\n");
+        assert!(!reader.is_in_early_data());
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "four");
+    }
+
+    #[tokio::test]
+    async fn stream_is_intact_after_early_data() {
+        let mut stream = tokio::io::BufReader::new(&b"second"[..]);
+        let mut reader = EarlyDataReader::new(b"first".to_vec(), &mut stream);
+
+        let mut first = [0; 5];
+        reader.read_exact(&mut first).await.unwrap();
+        assert_eq!(&first, b"first");
+        assert!(!reader.is_in_early_data());
+        drop(reader);
+
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "second");
+    }
+}
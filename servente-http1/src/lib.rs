@@ -1,13 +1,23 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+mod chunked_reader;
+#[cfg(feature = "rustls")]
+mod early_data;
 pub mod read;
+mod server;
+
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub(crate) use read::*;
+pub use chunked_reader::ChunkedBodyReader;
+pub use server::{Server, ServerBuilder};
 
 use tokio::{
     net::{TcpListener, TcpStream},
-    task, io::{split, AsyncWriteExt, AsyncReadExt, BufReader, AsyncBufReadExt, BufWriter, AsyncSeekExt}, time::{Instant, timeout},
+    task::{self, JoinSet}, io::{split, AsyncWriteExt, AsyncReadExt, BufReader, AsyncBufReadExt, BufWriter, AsyncSeekExt}, time::{Instant, timeout},
+    sync::watch,
 };
 
 #[cfg(feature = "ktls")]
@@ -20,9 +30,11 @@ use tokio_rustls::TlsAcceptor;
 use std::sync::Arc;
 
 use std::{
+    future::Future,
     io::{self, SeekFrom},
     mem::swap,
-    time::Duration,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
 };
 
 #[cfg(feature = "ktls")]
@@ -34,31 +46,53 @@ use servente_http::{
 };
 
 use servente_http_handling::{
+    check_expect_continue,
+    check_request_admission,
+    check_unknown_expectations,
     finish_response_error,
     finish_response_normal,
+    finish_response_strict_transport_security,
     handle_parse_error,
-    handle_request, ServenteConfig, responses, ServenteSettings,
+    normalize_request_path,
+    handle_request, handle_streaming_request, HeaderOrder, ReloadableConfig, ServenteConfig, responses, ServenteSettings,
+    ClientLimitExceeded,
+    ConnectionInfo,
+    connection::next_connection_id,
+    stats::ExchangeRecord,
+    config::LineEndingHandling,
 };
 
+use servente_web_sockets::{handshake, WebSocketEndpoint};
+
+#[cfg(any(feature = "rustls", feature = "tls-boring"))]
+use servente_http_handling::TlsParameters;
+
+use servente_http_handling::Scheme;
+
 use servente_http::{
     BodyKind,
+    ByteRange,
     ContentRangeHeaderValue,
     HttpVersion,
     HeaderName,
+    HeaderValue,
     HttpRangeList,
+    RequestTarget,
+    lists::find_best_match_in_weighted_list,
     Method,
-    Range,
     Response,
     Request,
     StatusCode,
     StatusCodeClass,
+    StreamingBody,
+    TransferCoding,
+    TransferCodingList,
 };
 
-use servente_resources::ContentCoding;
-
-/// The threshold at which the response body is transferred using chunked
-/// encoding.
-const TRANSFER_ENCODING_THRESHOLD: u64 = 1_000_000_000_000_000_000; // 1 MiB
+use servente_resources::{
+    ContentCoding,
+    StreamingEncoder,
+};
 
 /// Indicates the maximum length of a certain HTTP entity.
 struct MaximumLength(pub usize);
@@ -72,14 +106,39 @@ impl MaximumLength {
 
     /// The maximum length of a full HTTP header (name + value), excluding the CRLF.
     pub const HEADER: MaximumLength = MaximumLength(4096);
+
+    /// The maximum length of the chunk-size line of a chunked body, excluding
+    /// the CRLF.
+    pub const CHUNK_SIZE_LINE: MaximumLength = MaximumLength(256);
 }
 
 /// The strategy to use for transferring the response body.
 #[derive(Debug, Clone, PartialEq)]
 pub enum TransferStrategy {
-    Chunked,
+    Chunked { content_coding: Option<ContentCoding> },
     Full,
-    Ranges { ranges: HttpRangeList },
+
+    /// The body is sent as-is, and its end is signaled by closing the
+    /// connection. HTTP/1.0 clients don't understand `chunked`, so this is
+    /// used for bodies of which the length isn't known upfront.
+    UntilClose,
+    Ranges {
+        parts: Vec<RangePart>,
+
+        /// Written after the last part of a `multipart/byteranges` body, and
+        /// empty otherwise.
+        closing_boundary: String,
+    },
+}
+
+/// A part of a response body that only contains some ranges of the resource.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangePart {
+    pub range: ByteRange,
+
+    /// The boundary and headers preceding the bytes of this part in a
+    /// `multipart/byteranges` body, and empty otherwise.
+    pub head: String,
 }
 
 #[derive(Debug)]
@@ -88,6 +147,33 @@ pub enum ExchangeError {
     Http2Upgrade,
     TimedOut,
     Io(io::Error),
+
+    /// The server is shutting down, so the connection should be closed.
+    ShuttingDown,
+
+    /// The final response was sent before the body of the request was read,
+    /// so the start of the next request is unknown.
+    BodyNotRead,
+
+    /// The client didn't start a new request within the
+    /// [`ServenteSettings::keep_alive_idle_timeout`].
+    IdleTimeout,
+
+    /// The connection served [`ServenteSettings::max_keep_alive_requests`]
+    /// requests, and the last response was sent with `Connection: close`.
+    KeepAliveLimitReached,
+
+    /// The client asked to close the connection after the exchange, using
+    /// `Connection: close`, or sent both a `Transfer-Encoding` and a
+    /// `Content-Length`. Anything it sent after the request is ignored.
+    ConnectionClose,
+
+    /// The connection was upgraded to a WebSocket, which is closed now.
+    WebSocketClosed,
+
+    /// The response body was sent to an HTTP/1.0 client without a length, so
+    /// the connection must be closed to signal its end.
+    BodyDelimitedByClose,
 }
 
 impl From<io::Error> for ExchangeError {
@@ -156,8 +242,57 @@ impl AsyncWrite for StreamWrapper {
     }
 }
 
-/// Plans out the best `TransferStrategy` for the given response.
-async fn determine_transfer_strategy(response: &mut Response, ranges: Option<HttpRangeList>) -> TransferStrategy {
+/// Evaluates the `If-Range` precondition of the request, which tells whether
+/// the `Range` should be applied. When the representation changed since the
+/// client got its validator, the full representation is sent instead, so the
+/// client doesn't combine parts of different versions.
+///
+/// An entity-tag uses the strong comparison, so a weak ETag never matches,
+/// and an HTTP-date must be exactly the `Last-Modified` date.
+///
+/// # References
+/// * [RFC 9110 Section 13.1.5](https://www.rfc-editor.org/rfc/rfc9110.html#name-if-range)
+fn evaluate_if_range(request: &Request, response: &Response) -> bool {
+    let Some(if_range_value) = request.headers.get(&HeaderName::IfRange) else {
+        return true;
+    };
+    let if_range = if_range_value.as_str_may_convert();
+    let if_range = if_range.trim();
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !if_range.starts_with("W/") && response.headers.get(&HeaderName::ETag)
+            .and_then(|etag| etag.as_str_no_convert())
+            .is_some_and(|etag| etag == if_range);
+    }
+
+    let Ok(date) = TryInto::<SystemTime>::try_into(if_range_value) else {
+        return false;
+    };
+    let Some(last_modified) = response.headers.get(&HeaderName::LastModified) else {
+        return false;
+    };
+    let last_modified: Result<SystemTime, _> = last_modified.try_into();
+
+    // HTTP-dates have a resolution of seconds.
+    let seconds = |time: SystemTime| time.duration_since(SystemTime::UNIX_EPOCH).map(|duration| duration.as_secs()).ok();
+    last_modified.is_ok_and(|last_modified| seconds(last_modified) == seconds(date))
+}
+
+/// Whether the body of the response is delimited by closing the connection,
+/// which is the case for streamed bodies sent to HTTP/1.0 clients, since
+/// these don't understand the `chunked` transfer coding.
+///
+/// # References
+/// * [RFC 9112 Section 6.3](https://www.rfc-editor.org/rfc/rfc9112.html#name-message-body-length)
+fn is_body_delimited_by_close(response: &Response, version: HttpVersion) -> bool {
+    version == HttpVersion::Http10 && matches!(response.body, Some(BodyKind::Stream(..)))
+}
+
+/// Plans out the best `TransferStrategy` for the given response, for a client
+/// using the given HTTP version. The `accept_encoding` and `te` are the
+/// values of these headers, when the response may be compressed.
+async fn determine_transfer_strategy(response: &mut Response, ranges: Option<HttpRangeList>, accept_encoding: Option<&str>, te: Option<&str>,
+        version: HttpVersion, settings: &ServenteSettings) -> TransferStrategy {
     let Some(body) = &response.body else {
         if response.status.class() != StatusCodeClass::Informational {
             response.headers.set_content_length(0);
@@ -165,56 +300,23 @@ async fn determine_transfer_strategy(response: &mut Response, ranges: Option<Htt
         return TransferStrategy::Full;
     };
 
+    // HTTP/1.0 clients don't understand `chunked`, so files are always sent
+    // with their length.
+    let chunked_transfer_threshold = if version == HttpVersion::Http10 {
+        u64::MAX
+    } else {
+        settings.chunked_transfer_threshold
+    };
+
     match body {
         BodyKind::File { metadata, .. } => {
             let file_size = metadata.len();
-            if let Some(ranges) = ranges {
-                response.status = StatusCode::PartialContent;
-                if let Some(range) = ranges.first_and_only() {
-                    match range {
-                        Range::Full => {
-                            response.headers.set_content_range(ContentRangeHeaderValue::Range {
-                                start: 0,
-                                end: (file_size - 1) as _,
-                                complete_length: Some(file_size as _),
-                            });
-                        }
-                        Range::Points { start, end } => {
-                            response.headers.set_content_range(ContentRangeHeaderValue::Range {
-                                start: start as _,
-                                end: end as _,
-                                complete_length: Some(file_size as _),
-                            });
-                        }
-                        Range::StartPointToEnd { start } => {
-                            response.headers.set_content_range(ContentRangeHeaderValue::Range {
-                                start: start as _,
-                                end: (file_size - 1) as _,
-                                complete_length: Some(file_size as _),
-                            });
-                        }
-                        Range::Suffix { suffix } => {
-                            response.headers.set_content_range(ContentRangeHeaderValue::Range {
-                                start: (file_size - suffix) as _,
-                                end: (file_size - 1) as _,
-                                complete_length: Some(file_size as _),
-                            });
-                        }
-                    }
-                } else {
-                    todo!("Support multiple ranges");
-                }
-
-                return TransferStrategy::Ranges { ranges };
-            }
-
-            if file_size > TRANSFER_ENCODING_THRESHOLD {
-                response.headers.append_or_override(HeaderName::TransferEncoding, "chunked".into());
-                return TransferStrategy::Chunked;
-            }
+            determine_file_transfer_strategy(response, file_size, ranges, accept_encoding, te, chunked_transfer_threshold)
+        }
 
-            response.headers.set_content_length(file_size as _);
-            TransferStrategy::Full
+        BodyKind::Shared(bytes) => {
+            let file_size = (**bytes).as_ref().len() as u64;
+            determine_file_transfer_strategy(response, file_size, ranges, accept_encoding, te, chunked_transfer_threshold)
         }
 
         BodyKind::CachedBytes(bytes, coding) => {
@@ -222,6 +324,13 @@ async fn determine_transfer_strategy(response: &mut Response, ranges: Option<Htt
             TransferStrategy::Full
         }
 
+        // The length isn't known upfront, so ranges can't be served either.
+        BodyKind::Stream(..) if is_body_delimited_by_close(response, version) => {
+            response.headers.append_or_override(HeaderName::Connection, HeaderValue::from("close"));
+            TransferStrategy::UntilClose
+        }
+        BodyKind::Stream(..) => determine_chunked_transfer_strategy(response, accept_encoding, te),
+
         BodyKind::Bytes(bytes) => {
             response.headers.set_content_length(bytes.len());
             TransferStrategy::Full
@@ -239,29 +348,284 @@ async fn determine_transfer_strategy(response: &mut Response, ranges: Option<Htt
     }
 }
 
-/// Discards the full request.
-async fn discard_request(stream: &mut TcpStream) -> Result<(), Error> {
+/// Plans out the `TransferStrategy` for a body that is read from a file, or
+/// from the bytes of a mapped file.
+fn determine_file_transfer_strategy(response: &mut Response, file_size: u64, ranges: Option<HttpRangeList>, accept_encoding: Option<&str>, te: Option<&str>,
+        chunked_transfer_threshold: u64) -> TransferStrategy {
+    if let Some(ranges) = ranges {
+        let Some(ranges) = ranges.resolve(file_size) else {
+            response.status = StatusCode::RangeNotSatisfiable;
+            response.headers.set_content_range(ContentRangeHeaderValue::Unsatisfied {
+                complete_length: file_size as _,
+            });
+            response.headers.set_content_length(0);
+            response.body = None;
+            return TransferStrategy::Full;
+        };
+
+        response.status = StatusCode::PartialContent;
+        if let [range] = ranges.as_slice() {
+            response.headers.set_content_range(ContentRangeHeaderValue::Range {
+                start: range.first as _,
+                end: range.last as _,
+                complete_length: Some(file_size as _),
+            });
+            response.headers.set_content_length(range.len() as _);
+
+            return TransferStrategy::Ranges {
+                parts: vec![RangePart { range: *range, head: String::new() }],
+                closing_boundary: String::new(),
+            };
+        }
+
+        return determine_multipart_ranges_strategy(response, file_size, ranges);
+    }
+
+    if file_size > chunked_transfer_threshold {
+        return determine_chunked_transfer_strategy(response, accept_encoding, te);
+    }
+
+    response.headers.set_content_length(file_size as _);
+    TransferStrategy::Full
+}
+
+/// Plans out the `TransferStrategy` for a body that is sent using the
+/// `chunked` transfer coding, which can be compressed while it is sent.
+///
+/// When the client accepts the `gzip` transfer coding in its `TE` header,
+/// the body is sent with `Transfer-Encoding: gzip, chunked`, which leaves
+/// the representation intact. Otherwise, it might be compressed using a
+/// content coding of the `Accept-Encoding`.
+///
+/// # References
+/// * [RFC 9110 Section 10.1.4](https://www.rfc-editor.org/rfc/rfc9110.html#name-te)
+fn determine_chunked_transfer_strategy(response: &mut Response, accept_encoding: Option<&str>, te: Option<&str>) -> TransferStrategy {
+    if accepts_gzip_transfer_coding(response, te) {
+        response.headers.append_or_override(HeaderName::TransferEncoding, TransferCodingList::with_chunked(TransferCoding::Gzip).into());
+        return TransferStrategy::Chunked { content_coding: Some(ContentCoding::Gzip) };
+    }
+
+    response.headers.append_or_override(HeaderName::TransferEncoding, TransferCodingList::chunked().into());
+
+    let content_coding = determine_streaming_content_coding(response, accept_encoding);
+    if let Some(content_coding) = content_coding {
+        response.headers.append_or_override(HeaderName::ContentEncoding, content_coding.into());
+        _ = response.headers.append(HeaderName::Vary, "Accept-Encoding".into());
+    }
+
+    TransferStrategy::Chunked { content_coding }
+}
+
+/// Plans out a `multipart/byteranges` body for the given ranges, which replaces
+/// the `Content-Type` of the response. Each part is labeled with the original
+/// `Content-Type`.
+///
+/// # References
+/// * [RFC 9110 Section 14.6](https://www.rfc-editor.org/rfc/rfc9110.html#name-media-type-multipart-byteran)
+fn determine_multipart_ranges_strategy(response: &mut Response, file_size: u64, ranges: Vec<ByteRange>) -> TransferStrategy {
+    let boundary = generate_multipart_boundary();
+    let content_type = response.headers.get(&HeaderName::ContentType)
+            .map(|content_type| content_type.as_str_may_convert().into_owned());
+
+    let parts: Vec<RangePart> = ranges.into_iter()
+        .map(|range| {
+            let mut head = format!("\r\n--{boundary}\r\n");
+            if let Some(content_type) = &content_type {
+                head += &format!("Content-Type: {content_type}\r\n");
+            }
+            head += &format!("Content-Range: bytes {}-{}/{file_size}\r\n\r\n", range.first, range.last);
+            RangePart { range, head }
+        })
+        .collect();
+    let closing_boundary = format!("\r\n--{boundary}--\r\n");
+
+    let content_length = parts.iter()
+        .map(|part| part.head.len() as u64 + part.range.len())
+        .sum::<u64>() + closing_boundary.len() as u64;
+    response.headers.set_content_length(content_length as _);
+    response.headers.append_or_override(HeaderName::ContentType, HeaderValue::from(format!("multipart/byteranges; boundary={boundary}")));
+
+    TransferStrategy::Ranges { parts, closing_boundary }
+}
+
+/// Generate a boundary for a multipart body. It is random, so it's very
+/// unlikely to appear in the body.
+fn generate_multipart_boundary() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Every RandomState has different (random) keys.
+    let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    format!("servente-{random:016x}")
+}
+
+/// Whether the client accepts the `gzip` transfer coding for a streamed body,
+/// according to the `TE` header of the request.
+fn accepts_gzip_transfer_coding(response: &Response, te: Option<&str>) -> bool {
+    te.is_some_and(|te| find_best_match_in_weighted_list(te, &[TransferCoding::Gzip.as_str()], 0.0).is_some())
+        && is_streamed_body_compressible(response)
+}
+
+/// Whether a streamed body may be compressed. Bodies that are already
+/// encoded or that aren't compressible, e.g. images, aren't compressed.
+fn is_streamed_body_compressible(response: &Response) -> bool {
+    if response.headers.contains(&HeaderName::ContentEncoding) {
+        return false;
+    }
+
+    // The handler (or upstream) explicitly asked for the body to be left
+    // alone.
+    if response.headers.has_cache_control_directive("no-transform") {
+        return false;
+    }
+
+    let Some(HeaderValue::MediaType(media_type)) = response.headers.get(&HeaderName::ContentType) else {
+        return false;
+    };
+
+    media_type.is_compressible()
+}
+
+/// Determines the content coding to compress a streamed body with, based on
+/// the `Accept-Encoding` of the request. Bodies that aren't compressible, e.g.
+/// images, aren't compressed.
+fn determine_streaming_content_coding(response: &Response, accept_encoding: Option<&str>) -> Option<ContentCoding> {
+    let accept_encoding = accept_encoding?;
+
+    if !is_streamed_body_compressible(response) {
+        return None;
+    }
+
+    const SUPPORTED_CODINGS: [ContentCoding; 2] = [ContentCoding::Brotli, ContentCoding::Gzip];
+    let supported_names = SUPPORTED_CODINGS.map(|coding| coding.http_identifier());
+    find_best_match_in_weighted_list(accept_encoding, &supported_names, 0.0)
+        .map(|index| SUPPORTED_CODINGS[index])
+}
+
+/// Serializes the status line and the header fields of the response,
+/// including the empty line that terminates the header section.
+fn serialize_response_head(response: &Response, header_order: HeaderOrder) -> String {
+    let mut response_text = String::with_capacity(1024);
+    response_text.push_str("HTTP/1.1 ");
+    response_text.push_str(&response.status.to_string());
+    response_text.push_str("\r\n");
+
+    let mut append_field = |name: &HeaderName, value: &HeaderValue| {
+        response_text.push_str(name.to_string_h1());
+        response_text.push_str(": ");
+        value.append_to_message(&mut response_text);
+        response_text.push_str("\r\n");
+    };
+
+    match header_order {
+        HeaderOrder::Stored => response.headers.iter().for_each(|(name, value)| append_field(name, value)),
+        HeaderOrder::Canonical => response.headers.iter_canonical().for_each(|(name, value)| append_field(name, value)),
+    }
+
+    response_text.push_str("\r\n");
+    response_text
+}
+
+/// Sends an interim (1xx) response, which consists of only the status line
+/// and the header fields; it never contains content, and servers mustn't send
+/// a `Content-Length` or `Transfer-Encoding` in a 1xx response. Any number of
+/// these can precede the final response.
+///
+/// # References
+/// * [RFC 9110 Section 15.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-informational-1xx)
+/// * [RFC 9110 Section 8.6](https://www.rfc-editor.org/rfc/rfc9110.html#section-8.6-8)
+async fn send_informational_response<R>(stream: &mut R, mut response: Response, header_order: HeaderOrder) -> Result<(), io::Error>
+        where R: AsyncWriteExt + Unpin {
+    debug_assert!(response.body.is_none(), "Informational responses can't have content");
+
+    response.headers.remove(&HeaderName::ContentLength);
+    response.headers.remove(&HeaderName::TransferEncoding);
+
+    stream.write_all(serialize_response_head(&response, header_order).as_bytes()).await?;
+    stream.flush().await
+}
+
+/// A plaintext request that was sent to the TLS port, of which only the
+/// parts needed to redirect the client to the `https` scheme are kept.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DiscardedRequest {
+    /// The request-target of the request-line, e.g. `/index.html`.
+    target: Option<String>,
+
+    /// The value of the `Host` header.
+    host: Option<String>,
+}
+
+impl DiscardedRequest {
+    /// Returns the `https` URL of the request, or `None` if the request
+    /// didn't contain a (valid) origin-form target and `Host`.
+    fn https_location(&self) -> Option<String> {
+        let host = self.host.as_deref()
+            .filter(|host| !host.is_empty() && host.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-.:[]".contains(&byte)))?;
+        let target = self.target.as_deref()
+            .filter(|target| target.starts_with('/') && target.bytes().all(servente_http::syntax::is_request_target_character))?;
+        Some(format!("https://{host}{target}"))
+    }
+}
+
+/// Discards the full request, keeping the request-target and the `Host`.
+async fn discard_request<S>(stream: &mut S) -> Result<DiscardedRequest, Error>
+        where S: AsyncReadExt + Unpin {
     let mut buffer = BufReader::new(stream);
+    let request_line = read_crlf_line(&mut buffer, MaximumLength::HEADER, LineEndingHandling::Lenient).await?;
+    let mut request = DiscardedRequest {
+        target: request_line.split(' ').nth(1).map(str::to_owned),
+        host: None,
+    };
+
     loop {
-        let line = read_crlf_line(&mut buffer, MaximumLength::HEADER).await?;
+        let line = read_crlf_line(&mut buffer, MaximumLength::HEADER, LineEndingHandling::Lenient).await?;
         if line.is_empty() {
-            return Ok(());
+            return Ok(request);
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                request.host = Some(value.trim().to_owned());
+            }
         }
     }
 }
 
 /// Reads a single response, handles it and sends the response back to the
 /// client.
-pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &ServenteSettings) -> Result<(), ExchangeError>
-        where R: AsyncBufReadExt + Unpin,
-              W: AsyncWriteExt + Unpin {
-    #[cfg(feature = "debugging")]
+///
+/// Pipelined requests are supported by calling this function repeatedly on
+/// the same `reader` and `writer`. The request body is always fully consumed
+/// before the request is handled, so the `reader` is positioned at the start
+/// of the next request when this function returns `Ok`. The response is also
+/// written and flushed completely before returning, which means responses are
+/// sent in the order the requests were received in.
+///
+/// # References
+/// * [RFC 9112 Section 9.3.2](https://www.rfc-editor.org/rfc/rfc9112.html#name-pipelining)
+pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &ServenteSettings, connection_info: &ConnectionInfo) -> Result<(), ExchangeError>
+        where R: AsyncBufReadExt + BufferedRead + Unpin + Send,
+              W: AsyncWriteExt + Unpin + Send {
+    handle_exchange_until_shutdown(reader, writer, settings, connection_info, None, false).await
+}
+
+/// Handle an exchange, unless the server started shutting down before the
+/// client started sending the request. An exchange that is in flight when
+/// the server starts shutting down is answered with `Connection: close`, as
+/// is the `last` exchange of the connection and an exchange of which the
+/// client asked to close the connection.
+async fn handle_exchange_until_shutdown<R, W>(reader: &mut R, writer: &mut W, settings: &ServenteSettings, connection_info: &ConnectionInfo,
+        mut shutdown: Option<&mut watch::Receiver<bool>>, last: bool) -> Result<(), ExchangeError>
+        where R: AsyncBufReadExt + BufferedRead + Unpin + Send,
+              W: AsyncWriteExt + Unpin + Send {
+    wait_for_next_request(reader, settings.keep_alive_idle_timeout, shutdown.as_deref_mut()).await?;
+
     let start_full = Instant::now();
 
-    let request = match timeout(settings.read_headers_timeout, read_request_excluding_body(reader)).await {
+    let request = match timeout(settings.read_headers_timeout, read_request_excluding_body(reader, settings.line_endings)).await {
         Ok(request) => request,
         Err(_) => {
-            _ = send_response(writer, responses::create_request_timeout().await, None).await;
+            _ = send_response(writer, responses::create_request_timeout(settings).await, None, settings).await;
             return Err(ExchangeError::TimedOut);
         }
     };
@@ -272,8 +636,8 @@ pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &Se
             match error {
                 Error::ParseError(error) => {
                     let mut response = handle_parse_error(error).await;
-                    finish_response_error(&mut response).await;
-                    send_response(writer, response, None).await?;
+                    finish_response_error(&mut response, settings).await;
+                    send_response(writer, response, None, settings).await?;
                     return Err(ExchangeError::MalformedData);
                 }
                 Error::Other(error) => {
@@ -288,36 +652,88 @@ pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &Se
     // server to know that it contains a body using `Content-Length` or a
     // related mechanism, but it actually does.
     if request.method == Method::Pri {
-        return handle_pri_method(reader, writer, request).await;
+        return handle_pri_method(reader, writer, request, settings, connection_info).await;
     }
 
-    // TODO some handlers might prefer to read the body themselves.
-    let body_result = match timeout(settings.read_body_timeout, read_request_body(reader, &mut request)).await {
-        Ok(body_result) => body_result,
-        Err(_) => {
-            _ = send_response(writer, responses::create_request_timeout().await, None).await;
-            return Err(ExchangeError::TimedOut);
+    // The connection is no longer HTTP/1.1 after the handshake, so it is
+    // handled before the body would be read.
+    if let Some(endpoint) = find_web_socket_endpoint(&request, settings) {
+        return handle_web_socket_upgrade(reader, writer, request, endpoint, settings, shutdown).await;
+    }
+
+    if let Some(mut response) = check_unknown_expectations(&request, settings) {
+        finish_response_error(&mut response, settings).await;
+        send_response(writer, response, None, settings).await?;
+        return Err(ExchangeError::BodyNotRead);
+    }
+
+    if expects_continue(&request) {
+        if let Some(mut response) = check_expect_continue(&request, settings).await {
+            finish_response_error(&mut response, settings).await;
+            send_response(writer, response, None, settings).await?;
+            return Err(ExchangeError::BodyNotRead);
         }
-    };
 
-    if let Err(error) = body_result {
-        match error {
-            Error::ParseError(error) => {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        writer.flush().await?;
+    }
+
+    #[cfg(feature = "debugging")]
+    let start_handling;
+
+    // Whether the whole body was read, so the next request starts right after
+    // it. This is only unknown when the handler reads the body itself.
+    let body_consumed;
+
+    let mut response = if settings.handler_controller.wants_streaming_body(&request) {
+        let mut body = match read::streamed_request_body(reader, &request, settings) {
+            Ok(body) => body,
+            Err(error) => {
                 let mut response = handle_parse_error(error).await;
-                finish_response_error(&mut response).await;
-                send_response(writer, response, None).await?;
+                finish_response_error(&mut response, settings).await;
+                send_response(writer, response, None, settings).await?;
                 return Err(ExchangeError::MalformedData);
             }
-            Error::Other(error) => {
-                return Err(error.into());
+        };
+
+        #[cfg(feature = "debugging")]
+        { start_handling = Instant::now(); }
+        let response = handle_streaming_request(&mut request, &mut *body, settings, connection_info).await;
+
+        // Don't wait for the rest of a body the handler didn't read, since
+        // the client might not even send it.
+        body_consumed = matches!(timeout(Duration::ZERO, body.fill_buf()).await, Ok(Ok(buffer)) if buffer.is_empty());
+        response
+    } else {
+        let body_result = match timeout(settings.read_body_timeout, read_request_body(reader, &mut request, settings)).await {
+            Ok(body_result) => body_result,
+            Err(_) => {
+                _ = send_response(writer, responses::create_request_timeout(settings).await, None, settings).await;
+                return Err(ExchangeError::TimedOut);
+            }
+        };
+
+        if let Err(error) = body_result {
+            match error {
+                Error::ParseError(error) => {
+                    let mut response = handle_parse_error(error).await;
+                    finish_response_error(&mut response, settings).await;
+                    send_response(writer, response, None, settings).await?;
+                    return Err(ExchangeError::MalformedData);
+                }
+                Error::Other(error) => {
+                    return Err(error.into());
+                }
             }
         }
-    }
 
-    #[cfg(feature = "debugging")]
-    let start_handling = Instant::now();
-    let mut response = handle_request(&request, settings).await;
-    finish_response_normal(&request, &mut response).await;
+        #[cfg(feature = "debugging")]
+        { start_handling = Instant::now(); }
+        body_consumed = true;
+        handle_request(&mut request, settings, connection_info).await
+    };
+    finish_response_normal(&request, &mut response, settings).await;
+    finish_response_strict_transport_security(&mut response, settings, connection_info);
 
     if let Some(BodyKind::File { metadata, .. }) = &response.body {
         if !metadata.is_file() {
@@ -329,23 +745,32 @@ pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &Se
                 response.body = Some(BodyKind::StaticString("Warning: tried to send a non-file as response body"));
             }
 
-            finish_response_error(&mut response).await;
-            send_response(writer, response, None).await?;
+            finish_response_error(&mut response, settings).await;
+            send_response(writer, response, None, settings).await?;
+
+            return if body_consumed { Ok(()) } else { Err(ExchangeError::BodyNotRead) };
+        }
+    }
 
-            return Ok(());
+    // HTTP/1.0 clients don't understand informational responses, so these
+    // must not be sent to them (RFC 9110 Section 15.2).
+    let prelude_response = std::mem::take(&mut response.prelude_response);
+    if request.version != HttpVersion::Http10 {
+        for response in prelude_response {
+            send_response(writer, response, None, settings).await?;
         }
     }
 
-    for response in response.prelude_response {
-        send_response(writer, response, None).await?;
+    let shutting_down = shutdown.is_some_and(|shutdown| *shutdown.borrow());
+    let client_closes = requests_close(&request);
+    let delimited_by_close = is_body_delimited_by_close(&response, request.version);
+    if shutting_down || last || client_closes || !body_consumed || delimited_by_close {
+        response.headers.append_or_override(HeaderName::Connection, HeaderValue::from("close"));
     }
-    response.prelude_response = Vec::new();
 
-    let sent_body = send_response(writer, response,
-        request.headers.get(&HeaderName::Range)
-                .and_then(|range| range.as_str_no_convert())
-                .and_then(HttpRangeList::parse)
-    ).await?;
+    let exchange_record = ExchangeRecord::new(&response);
+    let sent_body = send_response(writer, response, Some(&request), settings).await?;
+    settings.stats.record(exchange_record, start_full.elapsed());
 
     #[cfg(feature = "debugging")]
     println!("{:?}>: {:?} (f={}ms, h={}ms, b={}ms)", request.method, request.target, start_full.elapsed().as_millis(), start_handling.elapsed().as_millis(), sent_body.as_millis());
@@ -353,15 +778,175 @@ pub async fn handle_exchange<R, W>(reader: &mut R, writer: &mut W, settings: &Se
     #[cfg(not(feature = "debugging"))]
     { _ = sent_body }
 
+    if shutting_down {
+        return Err(ExchangeError::ShuttingDown);
+    }
+
+    if last {
+        return Err(ExchangeError::KeepAliveLimitReached);
+    }
+
+    if client_closes {
+        return Err(ExchangeError::ConnectionClose);
+    }
+
+    if !body_consumed {
+        return Err(ExchangeError::BodyNotRead);
+    }
+
+    if delimited_by_close {
+        return Err(ExchangeError::BodyDelimitedByClose);
+    }
+
     Ok(())
 }
 
+/// Whether the connection is closed after the response, because the client
+/// sent the `close` connection option. Any bytes the client sent after this
+/// request are therefore not a pipelined request, and are ignored.
+///
+/// This is also the case for a request with both a `Transfer-Encoding` and a
+/// `Content-Length`, which might be an attempt at request smuggling, since
+/// an intermediary might have framed the request differently.
+///
+/// # References
+/// * [RFC 9112 Section 9.6](https://www.rfc-editor.org/rfc/rfc9112.html#name-tear-down)
+/// * [RFC 9112 Section 6.1](https://www.rfc-editor.org/rfc/rfc9112.html#section-6.1-15)
+pub(crate) fn requests_close(request: &Request) -> bool {
+    if request.headers.contains(&HeaderName::TransferEncoding) && request.headers.contains(&HeaderName::ContentLength) {
+        return true;
+    }
+
+    request.headers.get_all(&HeaderName::Connection)
+        .any(|value| {
+            value.as_str_may_convert()
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+        })
+}
+
+/// Whether the client waits for `100 Continue` before sending the body. This
+/// is ignored for HTTP/1.0 clients, which don't know about interim responses,
+/// and for requests without a body.
+///
+/// # References
+/// * [RFC 9110 Section 10.1.1](https://www.rfc-editor.org/rfc/rfc9110.html#section-10.1.1)
+fn expects_continue(request: &Request) -> bool {
+    if request.version != HttpVersion::Http11 {
+        return false;
+    }
+
+    let Some(expect) = request.headers.get(&HeaderName::Expect) else {
+        return false;
+    };
+
+    if !expect.as_str_may_convert().trim().eq_ignore_ascii_case("100-continue") {
+        return false;
+    }
+
+    request.headers.contains(&HeaderName::TransferEncoding)
+        || request.headers.get(&HeaderName::ContentLength).and_then(HeaderValue::parse_number).is_some_and(|length| length != 0)
+}
+
+/// Wait for the client to start sending the next request. Fails when the
+/// connection is idle for longer than the `idle_timeout`, or when the server
+/// starts shutting down first.
+async fn wait_for_next_request<R>(reader: &mut R, idle_timeout: Duration, shutdown: Option<&mut watch::Receiver<bool>>) -> Result<(), ExchangeError>
+        where R: AsyncBufReadExt + Unpin {
+    // Errors and EOF are reported when the request is read.
+    let idle = async {
+        timeout(idle_timeout, reader.fill_buf()).await
+            .map(|_| ())
+            .map_err(|_| ExchangeError::IdleTimeout)
+    };
+
+    match shutdown {
+        Some(shutdown) => tokio::select! {
+            biased;
+            _ = wait_for_shutdown(shutdown) => Err(ExchangeError::ShuttingDown),
+            result = idle => result,
+        },
+        None => idle.await,
+    }
+}
+
+/// Resolves when the server starts shutting down. When the sender is dropped
+/// without shutting down, this never resolves.
+async fn wait_for_shutdown(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        if shutdown.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Finds the endpoint of [`ServenteSettings::web_sockets`] for the request, if
+/// it is a WebSocket handshake. Like the file server, the registered paths are
+/// matched against the percent-decoded and normalized path.
+fn find_web_socket_endpoint<'a>(request: &Request, settings: &'a ServenteSettings) -> Option<&'a WebSocketEndpoint> {
+    if settings.web_sockets.is_empty() || !handshake::is_upgrade_request(request) {
+        return None;
+    }
+
+    let RequestTarget::Origin { path, .. } = &request.target else {
+        return None;
+    };
+    let path = normalize_request_path(path)?;
+    settings.web_sockets.find(std::str::from_utf8(&path).ok()?)
+}
+
+/// Upgrades the connection to a WebSocket, and exchanges its messages until
+/// it is closed. When the server starts shutting down, the WebSocket is
+/// closed with `1001 Going Away`.
+///
+/// # References
+/// * [RFC 6455 Section 4.2](https://www.rfc-editor.org/rfc/rfc6455.html#section-4.2)
+async fn handle_web_socket_upgrade<R, W>(reader: &mut R, writer: &mut W, request: Request, endpoint: &WebSocketEndpoint,
+        settings: &ServenteSettings, shutdown: Option<&mut watch::Receiver<bool>>) -> Result<(), ExchangeError>
+        where R: AsyncBufReadExt + Unpin + Send,
+              W: AsyncWriteExt + Unpin + Send {
+    let handshake = match check_request_admission(&request, settings) {
+        Some(response) => Err(Box::new(response)),
+        None => handshake::accept_handshake(&request, endpoint),
+    };
+
+    let handshake = match handshake {
+        Ok(handshake) => handshake,
+        Err(mut response) => {
+            finish_response_error(&mut response, settings).await;
+            send_response(writer, *response, None, settings).await?;
+            return Err(ExchangeError::BodyNotRead);
+        }
+    };
+
+    send_response(writer, handshake.response, None, settings).await?;
+
+    let mut handler = endpoint.create_handler(&request);
+    let connection = servente_web_sockets::http1::handle_connection(reader, writer, handler.as_mut(), endpoint, handshake.deflate);
+    match shutdown {
+        Some(shutdown) => tokio::select! {
+            result = connection => result?,
+            _ = wait_for_shutdown(shutdown) => {
+                let frame = servente_web_sockets::frame::serialize_close_frame(Some(servente_web_sockets::frame::close_code::GOING_AWAY), "");
+                writer.write_all(&frame).await?;
+                writer.flush().await?;
+            }
+        },
+        None => connection.await?,
+    }
+
+    Err(ExchangeError::WebSocketClosed)
+}
+
 /// The 'PRI' method is used for upgrading HTTP/1.1 connections to HTTP/2. It
 /// achieves this by using a special preface:
 /// ```text
 /// PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n
 /// ```
-async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Request) -> Result<(), ExchangeError>
+///
+/// On plaintext connections, this is only accepted when
+/// [`ServenteSettings::h2c_prior_knowledge`] is enabled.
+async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Request, settings: &ServenteSettings, connection_info: &ConnectionInfo) -> Result<(), ExchangeError>
         where R: AsyncBufReadExt + Unpin,
               W: AsyncWriteExt + Unpin {
     fn validate(index: usize, byte: u8) -> Result<(), Error> {
@@ -379,8 +964,8 @@ async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Reques
         let mut response = Response::with_status_and_string_body(StatusCode::BadRequest,
             "Invalid HTTP/2 PRI upgrade body");
         response.headers.append_or_override(HeaderName::Connection, "close".into());
-        finish_response_error(&mut response).await;
-        send_response(writer, response, None).await?;
+        finish_response_error(&mut response, settings).await;
+        send_response(writer, response, None, settings).await?;
         return Err(ExchangeError::MalformedData);
     }
 
@@ -388,8 +973,8 @@ async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Reques
         let mut response = Response::with_status_and_string_body(StatusCode::HTTPVersionNotSupported,
                 "Invalid HTTP upgrade using PRI: expected version HTTP/2.0");
         response.headers.append_or_override(HeaderName::Connection, "close".into());
-        finish_response_error(&mut response).await;
-        send_response(writer, response, None).await?;
+        finish_response_error(&mut response, settings).await;
+        send_response(writer, response, None, settings).await?;
         return Err(ExchangeError::MalformedData);
     }
 
@@ -398,11 +983,15 @@ async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Reques
         let mut response = Response::with_status_and_string_body(StatusCode::BadRequest,
             "Invalid preface start request");
         response.headers.append_or_override(HeaderName::Connection, "close".into());
-        finish_response_error(&mut response).await;
-        send_response(writer, response, None).await?;
+        finish_response_error(&mut response, settings).await;
+        send_response(writer, response, None, settings).await?;
         return Err(ExchangeError::MalformedData);
     }
 
+    if connection_info.scheme == Scheme::Http && !settings.h2c_prior_knowledge {
+        return handle_pri_method_http2_not_enabled(writer).await;
+    }
+
     // Notify the caller that the HTTP connection should be upgraded to version
     // HTTP/2.
     #[cfg(feature = "http2")]
@@ -412,10 +1001,11 @@ async fn handle_pri_method<R, W>(reader: &mut R, writer: &mut W, request: Reques
     return handle_pri_method_http2_not_enabled(writer).await;
 }
 
-#[cfg(not(feature = "http2"))]
+/// Tell the HTTP/2 client to use HTTP/1.1 instead, using the
+/// `HTTP_1_1_REQUIRED` error code.
 async fn handle_pri_method_http2_not_enabled<W>(writer: &mut W) -> Result<(), ExchangeError>
         where W: AsyncWriteExt + Unpin {
-    const FRAME_HTTP_1_1_REQUIRED: &'static [u8; 35] = &[
+    const FRAME_HTTP_1_1_REQUIRED: &[u8; 35] = &[
         // Settings Acknowledge
         0x00, 0x00, 0x00,       // length = 0
         0x04,                   // type = 0x04 SETTINGS
@@ -429,37 +1019,65 @@ async fn handle_pri_method_http2_not_enabled<W>(writer: &mut W) -> Result<(), Ex
         0x00, 0x00, 0x00, 0x00, // reserved = 0, stream = 0
 
         // Goaway
-        0x00, 0x00, 0x04,       // length = 4 (stream + error code)
+        0x00, 0x00, 0x08,       // length = 8 (stream + error code)
         0x07,                   // type = 0x07 GOAWAY
         0x00,                   // flags = 0
         0x00, 0x00, 0x00, 0x00, // reserved = 0, stream = 0
         0x00, 0x00, 0x00, 0x00, // reserved = 0, last stream = 0
-        0x0d, 0x00, 0x00, 0x00, // error code = 0x0d HTTP_1_1_REQUIRED
+        0x00, 0x00, 0x00, 0x0d, // error code = 0x0d HTTP_1_1_REQUIRED
     ];
 
     writer.write_all(FRAME_HTTP_1_1_REQUIRED).await?;
     Err(ExchangeError::MalformedData)
 }
 
-/// Process a single socket connection.
-async fn process_socket(stream: TcpStream, config: ServenteConfig) {
+/// How long to keep discarding the data the client sends after it asked to
+/// close the connection.
+const LINGERING_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Process a single socket connection of the client at `peer_address`. When
+/// `shutdown` becomes `true`, the connection is closed after the current
+/// exchange.
+async fn process_socket(stream: TcpStream, peer_address: SocketAddr, config: ServenteConfig, mut shutdown: watch::Receiver<bool>) {
+    let connection_id = next_connection_id();
+
+    // The guard is held until the connection is closed.
+    let mut over_client_limit = false;
+    let _client_guard = match &config.settings.client_connection_limit {
+        Some(limit) => {
+            let guard = limit.try_acquire(peer_address.ip());
+            if guard.is_none() {
+                if limit.exceeded() == ClientLimitExceeded::Close {
+                    return;
+                }
+                over_client_limit = true;
+            }
+            guard
+        }
+        _ => None,
+    };
+    let _active_connection = config.settings.stats.track_connection();
+
     #[cfg(any(feature = "rustls", feature = "tls-boring"))]
-    let stream = {
+    let (stream, tls_parameters, early_data) = {
         let mut stream = stream;
         let mut buf = [0u8; 4];
 
         if let Ok(length) = stream.peek(&mut buf).await {
             if length >= 3 && &buf[0..3] == b"GET" {
-                if let Err(e) = discard_request(&mut stream).await {
-                    #[cfg(feature = "debugging")]
-                    println!("Client Error discarding non-HTTPS: {:?}", e);
-
-                    #[cfg(not(feature = "debugging"))]
-                    { _ = e }
-                    return;
-                }
+                let request = match discard_request(&mut stream).await {
+                    Ok(request) => request,
+                    Err(e) => {
+                        #[cfg(feature = "debugging")]
+                        println!("Client Error discarding non-HTTPS: {:?}", e);
+
+                        #[cfg(not(feature = "debugging"))]
+                        { _ = e }
+                        return;
+                    }
+                };
 
-                if send_http_upgrade(&mut stream).await.is_err() {
+                if send_http_upgrade(&mut stream, &request).await.is_err() {
                     _ = stream.shutdown().await;
                     return;
                 }
@@ -467,19 +1085,37 @@ async fn process_socket(stream: TcpStream, config: ServenteConfig) {
         }
 
         #[cfg(feature = "rustls")]
-        {
+        let mut stream = {
             let acceptor = TlsAcceptor::from(Arc::clone(&config.tls_config));
             match acceptor.accept(stream).await {
                 Ok(stream) => stream,
                 Err(_) => return,
             }
-        }
+        };
+
+        #[cfg(feature = "rustls")]
+        let tls_parameters = rustls_tls_parameters(stream.get_ref().1);
+
+        #[cfg(feature = "rustls")]
+        let early_data = early_data::take_early_data(stream.get_mut().1);
 
         #[cfg(feature = "tls-boring")]
-        match tokio_boring::accept(&config.tls_config, stream).await {
+        let stream = match tokio_boring::accept(&config.tls_config, stream).await {
             Ok(stream) => stream,
             Err(_) => return,
+        };
+
+        #[cfg(feature = "tls-boring")]
+        let tls_parameters = boring_tls_parameters(stream.ssl());
+
+        #[cfg(feature = "tls-boring")]
+        let early_data = Vec::<u8>::new();
+
+        if config.settings.log_tls_parameters {
+            println!("[TLS] {peer_address} conn={connection_id}: {tls_parameters}");
         }
+
+        (stream, tls_parameters, early_data)
     };
 
     #[cfg(feature = "ktls")]
@@ -495,15 +1131,52 @@ async fn process_socket(stream: TcpStream, config: ServenteConfig) {
         },
     };
 
+    #[cfg(any(feature = "rustls", feature = "tls-boring"))]
+    let connection_info = ConnectionInfo::with_tls(tls_parameters).with_peer_address(peer_address).with_id(connection_id);
+
+    #[cfg(not(any(feature = "rustls", feature = "tls-boring")))]
+    let connection_info = ConnectionInfo::new(Scheme::Http).with_peer_address(peer_address).with_id(connection_id);
+
     let (reader, writer) = split(stream);
     let mut reader = BufReader::new(reader);
     let mut writer = BufWriter::new(writer);
 
+    if over_client_limit {
+        reject_exchange_over_client_limit(&mut reader, &mut writer, &config.settings).await;
+        return;
+    }
+
+    #[cfg(feature = "rustls")]
+    let mut result = handle_early_data_exchanges(&mut reader, &mut writer, early_data, &config.settings, &connection_info).await;
+
+    #[cfg(not(feature = "rustls"))]
+    let mut result = Ok(());
+
+    #[cfg(feature = "tls-boring")]
+    { _ = early_data }
+
+    let mut exchanges = 0;
     loop {
-        if let Err(e) = handle_exchange(&mut reader, &mut writer, &config.settings).await {
+        if let Err(e) = result {
             #[cfg(feature = "http2")]
             if let ExchangeError::Http2Upgrade = e {
-                servente_http2::handle_client(reader, writer, std::sync::Arc::new(config)).await;
+                servente_http2::handle_client(reader, writer, std::sync::Arc::new(config), connection_info).await;
+                return;
+            }
+
+            if let ExchangeError::ShuttingDown | ExchangeError::IdleTimeout | ExchangeError::KeepAliveLimitReached | ExchangeError::WebSocketClosed
+                    | ExchangeError::BodyDelimitedByClose = e {
+                _ = writer.shutdown().await;
+                return;
+            }
+
+            if let ExchangeError::ConnectionClose = e {
+                _ = writer.shutdown().await;
+
+                // Closing the socket with unread data makes the kernel reset
+                // the connection, which might discard the response before the
+                // client read it, so drain the trailing data for a while.
+                _ = timeout(LINGERING_CLOSE_TIMEOUT, tokio::io::copy(&mut reader, &mut tokio::io::sink())).await;
                 return;
             }
 
@@ -514,15 +1187,97 @@ async fn process_socket(stream: TcpStream, config: ServenteConfig) {
             { _ = e }
             return;
         }
+
+        exchanges += 1;
+        let last = exchanges >= config.settings.max_keep_alive_requests;
+        result = handle_exchange_until_shutdown(&mut reader, &mut writer, &config.settings, &connection_info, Some(&mut shutdown), last).await;
     }
 }
 
-/// Send the HTTPS upgrade to the client.
+/// Answer the first request of a client that has too many connections open
+/// with `429 Too Many Requests`. The request head is read first, so the client
+/// doesn't get a connection reset before it could read the response.
+async fn reject_exchange_over_client_limit<R, W>(reader: &mut R, writer: &mut W, settings: &ServenteSettings)
+        where R: AsyncBufReadExt + Unpin,
+              W: AsyncWriteExt + Unpin {
+    _ = timeout(settings.read_headers_timeout, read_request_excluding_body(reader, settings.line_endings)).await;
+    _ = send_response(writer, responses::create_too_many_requests(settings).await, None, settings).await;
+}
+
+/// Handle the exchanges that were (partially) received as TLS 1.3 early data.
+/// These are marked as such in the [`ConnectionInfo`], so that requests that
+/// aren't safe to replay are rejected.
+#[cfg(feature = "rustls")]
+async fn handle_early_data_exchanges<R, W>(reader: &mut R, writer: &mut W, early_data: Vec<u8>, settings: &ServenteSettings,
+        connection_info: &ConnectionInfo) -> Result<(), ExchangeError>
+        where R: AsyncBufReadExt + BufferedRead + Unpin + Send,
+              W: AsyncWriteExt + Unpin + Send {
+    let mut reader = early_data::EarlyDataReader::new(early_data, reader);
+    let mut connection_info = connection_info.clone();
+    connection_info.early_data = true;
+
+    while reader.is_in_early_data() {
+        if let Err(e) = handle_exchange(&mut reader, writer, settings, &connection_info).await {
+            // The HTTP/2 connection reads from the stream directly, and would
+            // miss the rest of the early data.
+            if matches!(e, ExchangeError::Http2Upgrade) && reader.is_in_early_data() {
+                return Err(ExchangeError::MalformedData);
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Extract the negotiated parameters of a completed rustls handshake.
+#[cfg(feature = "rustls")]
+fn rustls_tls_parameters(connection: &rustls::ServerConnection) -> TlsParameters {
+    TlsParameters {
+        version: connection.protocol_version()
+            .map(|version| version.as_str().map(String::from).unwrap_or_else(|| format!("{version:?}"))),
+        cipher_suite: connection.negotiated_cipher_suite()
+            .map(|suite| suite.suite())
+            .map(|suite| suite.as_str().map(String::from).unwrap_or_else(|| format!("{suite:?}"))),
+        alpn: connection.alpn_protocol()
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+    }
+}
+
+/// Extract the negotiated parameters of a completed BoringSSL handshake.
+#[cfg(feature = "tls-boring")]
+fn boring_tls_parameters(ssl: &boring::ssl::SslRef) -> TlsParameters {
+    TlsParameters {
+        version: Some(ssl.version_str().to_owned()),
+        cipher_suite: ssl.current_cipher().map(|cipher| cipher.name().to_owned()),
+        alpn: ssl.selected_alpn_protocol()
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+    }
+}
+
+/// Send the HTTPS upgrade to the client, which is a `308 Permanent Redirect`
+/// to the `https` scheme, or a `426 Upgrade Required` when the URL can't be
+/// determined because the request lacks a `Host`.
 ///
-/// ### TODO
-/// In the future, we should prefer using a 3xx response, redirecting the client
-/// to the https-scheme.
-async fn send_http_upgrade(stream: &mut TcpStream) -> Result<(), io::Error> {
+/// # References
+/// * [RFC 9110 Section 15.4.9](https://www.rfc-editor.org/rfc/rfc9110.html#name-308-permanent-redirect)
+/// * [RFC 9110 Section 15.5.22](https://www.rfc-editor.org/rfc/rfc9110.html#name-426-upgrade-required)
+async fn send_http_upgrade<W>(stream: &mut W, request: &DiscardedRequest) -> Result<(), io::Error>
+        where W: AsyncWriteExt + Unpin {
+    if let Some(location) = request.https_location() {
+        let message = format!(
+            concat!("HTTP/1.1 308 Permanent Redirect\r\n",
+                    "Location: {}\r\n",
+                    "Content-Length: 0\r\n",
+                    "Connection: close\r\n",
+                    "\r\n"
+            ),
+            location
+        );
+        stream.write_all(message.as_bytes()).await?;
+        return stream.flush().await;
+    }
+
     let body = "HTTPS is required.";
     let message = format!(
         concat!("HTTP/1.1 426 Upgrade Required\r\n",
@@ -541,66 +1296,79 @@ async fn send_http_upgrade(stream: &mut TcpStream) -> Result<(), io::Error> {
 }
 
 /// Send the response to the client.
-async fn send_response<R>(stream: &mut R, mut response: Response, ranges: Option<HttpRangeList>) -> Result<Duration, io::Error>
+async fn send_response<R>(stream: &mut R, mut response: Response, request: Option<&Request>, settings: &ServenteSettings) -> Result<Duration, io::Error>
         where R: AsyncWriteExt + Unpin {
-    let transfer_strategy = determine_transfer_strategy(&mut response, ranges).await;
-
-    let mut response_text = String::with_capacity(1024);
-    response_text.push_str("HTTP/1.1 ");
-    response_text.push_str(&response.status.to_string());
-    response_text.push_str("\r\n");
-
-    for (name, value) in response.headers.iter() {
-        response_text.push_str(name.to_string_h1());
-        response_text.push_str(": ");
-        value.append_to_message(&mut response_text);
-        response_text.push_str("\r\n");
+    if response.status.class() == StatusCodeClass::Informational {
+        send_informational_response(stream, response, settings.header_order).await?;
+        return Ok(Duration::ZERO);
     }
 
-    response_text.push_str("\r\n");
-
-    stream.write_all(response_text.as_bytes()).await?;
-
+    let ranges = request.and_then(|request| request.headers.get(&HeaderName::Range))
+            .and_then(|range| range.as_str_no_convert())
+            .and_then(HttpRangeList::parse)
+            // Ignoring the Range header is allowed, and is cheaper than
+            // serving a lot of (possibly overlapping) parts.
+            .filter(|ranges| ranges.ranges.len() <= settings.max_parts)
+            .filter(|_| request.is_some_and(|request| evaluate_if_range(request, &response)));
+    let accept_encoding = request.filter(|request| settings.handler_controller.allows_compression(request))
+            .and_then(|request| request.headers.get(&HeaderName::AcceptEncoding))
+            .and_then(|accept_encoding| accept_encoding.as_str_no_convert());
+    let te = request.filter(|request| settings.handler_controller.allows_compression(request))
+            .and_then(|request| request.headers.get(&HeaderName::TE))
+            .and_then(|te| te.as_str_no_convert());
+
+    let version = request.map_or(HttpVersion::Http11, |request| request.version);
+    let transfer_strategy = determine_transfer_strategy(&mut response, ranges, accept_encoding, te, version, settings).await;
+
+    stream.write_all(serialize_response_head(&response, settings.header_order).as_bytes()).await?;
+
+    // The head is the same as for GET, including the framing, but the
+    // content is omitted.
+    //
+    // # References
+    // * [RFC 9110 Section 9.3.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-head)
+    if request.is_some_and(|request| request.method == Method::Head) {
+        stream.flush().await?;
+        return Ok(Duration::ZERO);
+    }
 
     let start = Instant::now();
     if let Some(body) = response.body {
         match body {
             BodyKind::File { mut handle, .. } => {
                 match transfer_strategy {
-                    TransferStrategy::Full => transfer_body_full(stream, &mut handle).await?,
-                    TransferStrategy::Chunked => transfer_body_chunked(stream, &mut handle).await?,
-                    TransferStrategy::Ranges { ranges } => {
-                        transfer_body_ranges(stream, &mut handle, ranges).await?
+                    TransferStrategy::Full | TransferStrategy::UntilClose => transfer_body_full(stream, &mut handle).await?,
+                    TransferStrategy::Chunked { content_coding } => transfer_body_chunked(stream, &mut handle, content_coding).await?,
+                    TransferStrategy::Ranges { parts, closing_boundary } => {
+                        transfer_body_ranges(stream, &mut handle, parts, closing_boundary).await?
                     }
                 }
             }
-            BodyKind::Bytes(response) => stream.write_all(&response).await?,
-            BodyKind::CachedBytes(cached_version, encoding) => match encoding {
-                Some(ContentCoding::Brotli) => {
-                    if cached_version.brotli.is_some() {
-                        stream.write_all(cached_version.brotli.as_ref().unwrap()).await?;
-                    } else if let Some(compressed_on_the_fly) = ContentCoding::Brotli.encode(&cached_version.uncompressed) {
-                        stream.write_all(&compressed_on_the_fly).await?;
-                    } else {
-                        // TODO this isn't really a condition we should be in
-                        debug_assert!(false, "Brotli was set as the ContentEncoding, but the cached version was not brotli-compressed and we failed to compress it on the fly.");
-                        stream.write_all(&cached_version.uncompressed).await?;
-                    }
-                }
-                Some(ContentCoding::Gzip) => {
-                    if cached_version.gzip.is_some() {
-                        stream.write_all(cached_version.gzip.as_ref().unwrap()).await?;
-                    } else if let Some(compressed_on_the_fly) = ContentCoding::Gzip.encode(&cached_version.uncompressed) {
-                        stream.write_all(&compressed_on_the_fly).await?;
-                    } else {
-                        // TODO this isn't really a condition we should be in
-                        debug_assert!(false, "Gzip was set as the ContentEncoding, but the cached version was not gzip-compressed and we failed to compress it on the fly.");
-                        stream.write_all(&cached_version.uncompressed).await?;
-                    }
+            BodyKind::Shared(bytes) => {
+                let mut reader = std::io::Cursor::new((*bytes).as_ref());
+                match transfer_strategy {
+                    TransferStrategy::Full | TransferStrategy::UntilClose => stream.write_all(reader.get_ref()).await?,
+                    TransferStrategy::Chunked { content_coding } => transfer_body_chunked(stream, &mut reader, content_coding).await?,
+                    TransferStrategy::Ranges { parts, closing_boundary } => transfer_body_ranges(stream, &mut reader, parts, closing_boundary).await?,
                 }
-                _ => stream.write_all(&cached_version.uncompressed).await?,
+            }
+            BodyKind::Bytes(response) => stream.write_all(&response).await?,
+            BodyKind::CachedBytes(cached_version, encoding) => {
+                // finish_response_normal made sure this version exists.
+                stream.write_all(cached_version.get_version(encoding)).await?
             }
             BodyKind::StaticString(response) => stream.write_all(response.as_bytes()).await?,
+            BodyKind::Stream(mut body) => {
+                let content_coding = match transfer_strategy {
+                    TransferStrategy::Chunked { content_coding } => content_coding,
+                    TransferStrategy::UntilClose => {
+                        transfer_streaming_body_until_close(stream, &mut body).await?;
+                        return Ok(start.elapsed());
+                    }
+                    _ => None,
+                };
+                transfer_streaming_body_chunked(stream, &mut body, content_coding).await?
+            }
             BodyKind::String(response) => stream.write_all(response.as_bytes()).await?,
         }
     }
@@ -608,13 +1376,53 @@ async fn send_response<R>(stream: &mut R, mut response: Response, ranges: Option
     Ok(start.elapsed())
 }
 
-/// Start the HTTPv1 server on the given address.
-pub async fn start(address: &str, config: ServenteConfig) -> io::Result<()> {
-    let listener = TcpListener::bind(address).await?;
+/// Start the HTTPv1 server on the given address. When a [`ReloadableConfig`]
+/// is passed, new connections use the configuration that is current when they
+/// are accepted.
+pub async fn start(address: &str, config: impl Into<ReloadableConfig>) -> io::Result<()> {
+    let server = Server::bind(address).await?.with_config(config);
+    println!("Started listening on {}", address);
+
+    server.serve().await
+}
+
+/// Accept connections on the listener, and serve them until an unrecoverable
+/// error occurs.
+pub(crate) async fn serve(listener: TcpListener, config: ReloadableConfig) -> io::Result<()> {
+    serve_until(listener, config, std::future::pending()).await.map(|_| ())
+}
+
+/// Start the HTTPv1 server on the given address, and serve until `shutdown`
+/// resolves. From then on, no new connections are accepted, idle connections
+/// are closed, and the exchanges in flight are answered with
+/// `Connection: close`.
+///
+/// The connections are given [`ServenteSettings::shutdown_grace_period`] to
+/// finish, after which they are dropped. Returns the number of connections
+/// that were dropped this way.
+pub async fn start_with_shutdown(address: &str, config: impl Into<ReloadableConfig>, shutdown: impl Future<Output = ()>) -> io::Result<usize> {
+    let server = Server::bind(address).await?.with_config(config);
     println!("Started listening on {}", address);
 
+    server.serve_until(shutdown).await
+}
+
+/// Accept connections on the listener until `shutdown` resolves, see
+/// [`start_with_shutdown`].
+pub(crate) async fn serve_until(listener: TcpListener, config: ReloadableConfig, shutdown: impl Future<Output = ()>) -> io::Result<usize> {
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
     loop {
-        let (stream, _) = match listener.accept().await {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            // Reap the finished connections, so the set doesn't keep growing.
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            accepted = listener.accept() => accepted,
+        };
+
+        let (stream, peer_address) = match accepted {
             Ok((stream, addr)) => (stream, addr),
             Err(e) => {
                 #[cfg(unix)]
@@ -629,17 +1437,37 @@ pub async fn start(address: &str, config: ServenteConfig) -> io::Result<()> {
                 continue;
             }
         };
-        let config = config.clone();
-        task::spawn(async move {
-            process_socket(stream, config).await;
+        let config = config.load();
+        let shutdown = shutdown_receiver.clone();
+        connections.spawn(async move {
+            process_socket(stream, peer_address, config, shutdown).await;
         });
     }
+
+    // Stop accepting connections, and close the idle ones.
+    drop(listener);
+    _ = shutdown_sender.send(true);
+
+    let grace_period = config.load().settings.shutdown_grace_period;
+    let drained = timeout(grace_period, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+
+    if drained.is_ok() {
+        return Ok(0);
+    }
+
+    let dropped = connections.len();
+    connections.shutdown().await;
+    Ok(dropped)
 }
 
 /// Transfer the body, using the `Transfer-Encoding: chunked` algorithm.
-async fn transfer_body_chunked<O, I>(output: &mut O, input: &mut I) -> Result<(), io::Error>
+async fn transfer_body_chunked<O, I>(output: &mut O, input: &mut I, content_coding: Option<ContentCoding>) -> Result<(), io::Error>
         where O: AsyncWriteExt + Unpin,
               I: AsyncReadExt + Unpin {
+    let mut encoder = content_coding.map(StreamingEncoder::new);
+
     let mut buf: [u8; 16384] = [0; 16384];
     loop {
         let len = input.read(&mut buf).await?;
@@ -648,11 +1476,14 @@ async fn transfer_body_chunked<O, I>(output: &mut O, input: &mut I) -> Result<()
             break;
         }
 
-        output.write_all(format!("{:X}\r\n", len).as_bytes()).await?;
-
-        output.write_all(&buf[0..len]).await?;
+        match &mut encoder {
+            Some(encoder) => write_chunk(output, &encoder.encode(&buf[0..len])?).await?,
+            None => write_chunk(output, &buf[0..len]).await?,
+        }
+    }
 
-        output.write_all(b"\r\n").await?;
+    if let Some(encoder) = encoder {
+        write_chunk(output, &encoder.finish()?).await?;
     }
 
     output.write_all(b"0\r\n\r\n").await?;
@@ -660,6 +1491,61 @@ async fn transfer_body_chunked<O, I>(output: &mut O, input: &mut I) -> Result<()
     Ok(())
 }
 
+/// Transfer a [`StreamingBody`], using the `Transfer-Encoding: chunked`
+/// algorithm. Every part is flushed, since the producer might take a while
+/// to produce the next one.
+///
+/// When the producer fails, the last chunk isn't written, and the error is
+/// returned so the connection is closed. This way, the client sees that the
+/// body is truncated.
+async fn transfer_streaming_body_chunked<O>(output: &mut O, body: &mut StreamingBody, content_coding: Option<ContentCoding>) -> Result<(), io::Error>
+        where O: AsyncWriteExt + Unpin {
+    let mut encoder = content_coding.map(StreamingEncoder::new);
+
+    while let Some(data) = body.next().await? {
+        match &mut encoder {
+            Some(encoder) => write_chunk(output, &encoder.encode(&data)?).await?,
+            None => write_chunk(output, &data).await?,
+        }
+        output.flush().await?;
+    }
+
+    if let Some(encoder) = encoder {
+        write_chunk(output, &encoder.finish()?).await?;
+    }
+
+    output.write_all(b"0\r\n\r\n").await
+}
+
+/// Transfer a [`StreamingBody`] as-is, for a response of which the end is
+/// signaled by closing the connection. Every part is flushed, like with
+/// [`transfer_streaming_body_chunked`].
+async fn transfer_streaming_body_until_close<O>(output: &mut O, body: &mut StreamingBody) -> Result<(), io::Error>
+        where O: AsyncWriteExt + Unpin {
+    while let Some(data) = body.next().await? {
+        output.write_all(&data).await?;
+        output.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Writes a single chunk of the chunked transfer coding. Empty chunks are
+/// skipped, since those would indicate the last chunk.
+///
+/// # References
+/// * [RFC 9112 Section 7.1](https://www.rfc-editor.org/rfc/rfc9112.html#name-chunked-transfer-coding)
+async fn write_chunk<O>(output: &mut O, data: &[u8]) -> Result<(), io::Error>
+        where O: AsyncWriteExt + Unpin {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    output.write_all(format!("{:X}\r\n", data.len()).as_bytes()).await?;
+    output.write_all(data).await?;
+    output.write_all(b"\r\n").await
+}
+
 /// Transfer the body, using the full contents of the input, without and
 /// `Transfer-Encoding` or `range`s.
 async fn transfer_body_full<O, I>(output: &mut O, input: &mut I) -> Result<(), io::Error>
@@ -689,64 +1575,31 @@ async fn transfer_body_full<O, I>(output: &mut O, input: &mut I) -> Result<(), i
 }
 
 /// Transfer the body, using the ranges specified in the request.
-async fn transfer_body_ranges<O, I>(output: &mut O, input: &mut I, ranges: HttpRangeList) -> Result<(), io::Error>
+async fn transfer_body_ranges<O, I>(output: &mut O, input: &mut I, parts: Vec<RangePart>, closing_boundary: String) -> Result<(), io::Error>
         where O: AsyncWriteExt + Unpin,
               I: AsyncReadExt + AsyncSeekExt + Unpin {
-    for range in ranges.iter() {
-        match range {
-            Range::Full => {
-                return transfer_body_full(output, input).await;
-            }
-            Range::StartPointToEnd { start } => {
-                let mut buf: [u8; 8192] = [0; 8192];
-                input.seek(SeekFrom::Start(*start as _)).await?;
-                loop {
-                    let len = input.read(&mut buf).await?;
-
-                    if len == 0 {
-                        break;
-                    }
-
-                    output.write_all(&buf[0..len]).await?;
-                }
+    let mut buf: [u8; 8192] = [0; 8192];
+    for part in parts {
+        output.write_all(part.head.as_bytes()).await?;
+        input.seek(SeekFrom::Start(part.range.first)).await?;
+
+        let mut remaining = part.range.len();
+        while remaining > 0 {
+            let max = std::cmp::min(buf.len() as u64, remaining) as usize;
+            let len = input.read(&mut buf[0..max]).await?;
+
+            // The file was truncated, so the Content-Length can't be honored
+            // anymore.
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
             }
-            Range::Points { start, end } => {
-                let mut buf: [u8; 8192] = [0; 8192];
-                input.seek(SeekFrom::Start(*start as _)).await?;
-                let mut remaining = (end - start) as usize;
-                while remaining > 0 {
-                    let len = input.read(&mut buf).await?;
 
-                    if len == 0 {
-                        break;
-                    }
-
-                    let len = std::cmp::min(len, remaining);
-                    output.write_all(&buf[0..len]).await?;
-                    remaining -= len;
-                }
-            }
-            Range::Suffix { suffix } => {
-                let mut buf: [u8; 8192] = [0; 8192];
-                let len = input.seek(SeekFrom::End(0)).await?;
-                let start = (len - *suffix) as usize;
-                input.seek(SeekFrom::Start(start as _)).await?;
-                let mut remaining = *suffix as _;
-                while remaining > 0 {
-                    let len = input.read(&mut buf).await?;
-
-                    if len == 0 {
-                        break;
-                    }
-
-                    let len = std::cmp::min(len, remaining);
-                    output.write_all(&buf[0..len]).await?;
-                    remaining -= len;
-                }
-            }
+            output.write_all(&buf[0..len]).await?;
+            remaining -= len as u64;
         }
     }
-    Ok(())
+
+    output.write_all(closing_boundary.as_bytes()).await
 }
 
 #[cfg(test)]
@@ -756,13 +1609,15 @@ mod tests {
     use rstest::rstest;
 
     use super::*;
-    use servente_http_handling::{handler::HandlerController, ServenteSettings};
+    use servente_http_handling::{config::{ExcessBodyDataHandling, UnknownExpectationHandling}, handler::HandlerController, Scheme, ServenteSettings};
 
     use servente_http::{
         HeaderMap,
         RequestTarget
     };
 
+    use servente_resources::MediaType;
+
     /// The connection preface, as defined in [RFC 9113 Section 3.4](https://www.rfc-editor.org/rfc/rfc9113.html#name-http-2-connection-preface).
     #[allow(dead_code)] // Linting doesn't understand that this is used in tests :(
     const HTTP2_CONNECTION_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
@@ -774,6 +1629,49 @@ mod tests {
             read_headers_timeout: Duration::from_secs(5),
             read_body_timeout: Duration::from_secs(5),
             middleware: Vec::new(),
+            canonical_host: Default::default(),
+            excess_body_data: Default::default(),
+            unknown_expectations: Default::default(),
+            line_endings: Default::default(),
+            log_tls_parameters: Default::default(),
+            dynamic_table_budget: Default::default(),
+            header_order: Default::default(),
+            body_transforms: Default::default(),
+            max_parts: servente_http_handling::config::DEFAULT_MAX_PARTS,
+            security_headers: Default::default(),
+            default_headers: Default::default(),
+            hsts: None,
+            request_body_spill_threshold: None,
+            http2_precomputed_header_blocks: false,
+            client_hints: Default::default(),
+            document_root: Default::default(),
+            document_source: Default::default(),
+            request_loggers: Default::default(),
+            redacted_headers: Default::default(),
+            max_chunk_size: servente_http_handling::config::DEFAULT_MAX_CHUNK_SIZE,
+            max_request_body_size: servente_http_handling::config::DEFAULT_MAX_REQUEST_BODY_SIZE,
+            tls_max_early_data_size: 0,
+            file_backend: Default::default(),
+            content_hash_etags: Default::default(),
+            http3_port: None,
+            http2_ping_interval: servente_http_handling::config::DEFAULT_HTTP2_PING_INTERVAL,
+            http2_max_unacknowledged_pings: servente_http_handling::config::DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS,
+            h2c_prior_knowledge: false,
+            client_connection_limit: None,
+            rewrite_rules: Default::default(),
+            maintenance_mode: Default::default(),
+            chunked_transfer_threshold: servente_http_handling::config::DEFAULT_CHUNKED_TRANSFER_THRESHOLD,
+            symlink_policy: Default::default(),
+            redirect_directories: false,
+            asset_fingerprints: Default::default(),
+            shutdown_grace_period: servente_http_handling::config::DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            rate_limiter: None,
+            request_validators: Default::default(),
+            max_keep_alive_requests: servente_http_handling::config::DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+            keep_alive_idle_timeout: servente_http_handling::config::DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT,
+            web_sockets: Default::default(),
+            index_files: vec![String::from("index.html")],
+            stats: Default::default(),
         };
     }
 
@@ -807,7 +1705,7 @@ mod tests {
     #[tokio::test]
     async fn read_headers_name_validation(#[case] line: &str, #[case] expected: HttpParseError) {
         let mut stream = std::io::Cursor::new(format!("{}\r\n\r\n", line));
-        let headers = super::read_headers(&mut stream).await;
+        let headers = super::read_headers(&mut stream, LineEndingHandling::Strict).await;
         assert!(headers.is_err());
         assert!(matches!(headers.err().unwrap(), Error::ParseError(e) if e == expected));
     }
@@ -816,7 +1714,7 @@ mod tests {
     #[tokio::test]
     async fn http2_upgrade_read_request_excluding_body() {
         let mut data = std::io::Cursor::new(HTTP2_CONNECTION_PREFACE);
-        let request = read_request_excluding_body(&mut data).await.unwrap();
+        let request = read_request_excluding_body(&mut data, LineEndingHandling::Strict).await.unwrap();
         assert_eq!(request.method, Method::Pri);
         assert_eq!(request.target, RequestTarget::Asterisk);
         assert_eq!(request.version, HttpVersion::Http2);
@@ -836,20 +1734,1608 @@ mod tests {
             version: HttpVersion::Http2,
             headers: HeaderMap::new(),
             body: None,
+            raw_body: None,
+        };
+        let settings = ServenteSettings {
+            h2c_prior_knowledge: true,
+            ..SETTINGS.clone()
         };
-        let exchange_error = handle_pri_method(&mut data, &mut writer, request).await.unwrap_err();
+        let exchange_error = handle_pri_method(&mut data, &mut writer, request, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap_err();
         assert_eq!(data.position() as usize, DATA.len());
         assert_eq!(writer, Vec::new());
         assert!(matches!(exchange_error, ExchangeError::Http2Upgrade), "Invalid error: {exchange_error:#?} written: {}", String::from_utf8_lossy(writer.as_slice()));
     }
 
     #[cfg(feature = "http2")]
+    #[rstest]
+    #[case(Scheme::Http, true, true)]
+    #[case(Scheme::Http, false, false)]
+    #[case(Scheme::Https, false, true)]
     #[tokio::test]
+    async fn http2_upgrade_handle_exchange(#[case] scheme: Scheme, #[case] h2c_prior_knowledge: bool, #[case] upgrades: bool) {
+        let settings = ServenteSettings {
+            h2c_prior_knowledge,
+            ..SETTINGS.clone()
+        };
 
-    async fn http2_upgrade_handle_exchange() {
-        let mut data = std::io::Cursor::new(HTTP2_CONNECTION_PREFACE);
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(HTTP2_CONNECTION_PREFACE).await.unwrap();
+        let (reader, mut writer) = split(server);
+        let mut reader = BufReader::new(reader);
+
+        let exchange_error = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(scheme)).await.unwrap_err();
+        drop((reader, writer));
+
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        if upgrades {
+            assert!(matches!(exchange_error, ExchangeError::Http2Upgrade), "Invalid error: {exchange_error:#?}");
+            assert_eq!(written, Vec::new());
+        } else {
+            assert!(matches!(exchange_error, ExchangeError::MalformedData), "Invalid error: {exchange_error:#?}");
+
+            // The GOAWAY frame with the HTTP_1_1_REQUIRED error code.
+            assert_eq!(&written[18..21], &[0x00, 0x00, 0x08]);
+            assert_eq!(written[21], 0x07);
+            assert_eq!(&written[written.len() - 4..], &[0x00, 0x00, 0x00, 0x0d]);
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_answered_in_order() {
+        fn first(_: &Request) -> Result<Response, anyhow::Error> {
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, "first"))
+        }
+
+        fn echo(request: &Request) -> Result<Response, anyhow::Error> {
+            let Some(BodyKind::Bytes(body)) = &request.body else {
+                return Ok(Response::with_status(StatusCode::BadRequest));
+            };
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, String::from_utf8(body.clone())?))
+        }
+
+        fn second(_: &Request) -> Result<Response, anyhow::Error> {
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, "second"))
+        }
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/first".into(), &first);
+        handler_controller.register_post("/echo".into(), &echo);
+        handler_controller.register_get("/second".into(), &second);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let mut reader = std::io::Cursor::new(concat!(
+            "GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 7\r\n\r\npayload",
+            "GET /second HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ).as_bytes());
         let mut writer = Vec::new();
-        let exchange_error = handle_exchange(&mut data, &mut writer, &SETTINGS).await.unwrap_err();
-        assert!(matches!(exchange_error, ExchangeError::Http2Upgrade), "Invalid error: {exchange_error:#?} written: {}", String::from_utf8_lossy(writer.as_slice()));
+        for _ in 0..3 {
+            handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+        }
+        assert_eq!(reader.position() as usize, reader.get_ref().len());
+
+        let output = String::from_utf8(writer).unwrap();
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 3, "{output}");
+        assert!(responses[0].starts_with("200 OK\r\n") && responses[0].ends_with("\r\n\r\nfirst"), "{output}");
+        assert!(responses[1].starts_with("200 OK\r\n") && responses[1].ends_with("\r\n\r\npayload"), "{output}");
+        assert!(responses[2].starts_with("200 OK\r\n") && responses[2].ends_with("\r\n\r\nsecond"), "{output}");
+    }
+
+    #[rstest]
+    #[case("br, chunked", "HTTP/1.1 501 Not Implemented\r\n")]
+    #[case("chunked, gzip", "HTTP/1.1 400 Bad Request\r\n")]
+    #[tokio::test]
+    async fn invalid_transfer_encoding_is_rejected(#[case] transfer_encoding: &str, #[case] status_line: &str) {
+        let request = format!("POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: {transfer_encoding}\r\n\r\n");
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+
+        let result = handle_exchange(&mut reader, &mut writer, &SETTINGS, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::MalformedData)));
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with(status_line), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn exchanges_are_recorded_in_stats() {
+        let stats = Arc::new(servente_http_handling::ServerStats::new());
+        let settings = ServenteSettings {
+            stats: Arc::clone(&stats),
+            ..SETTINGS.clone()
+        };
+
+        let mut input = "OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(5);
+        input.push_str("GET * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut reader = std::io::Cursor::new(input.as_bytes());
+        let mut writer = Vec::new();
+        for _ in 0..6 {
+            handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+        }
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests(), 6);
+        assert_eq!(snapshot.requests_with_status(StatusCode::Ok), 5);
+        assert_eq!(snapshot.requests_with_status(StatusCode::BadRequest), 1);
+        assert!(snapshot.latency_p95 >= snapshot.latency_p50);
+
+        stats.reset();
+        assert_eq!(stats.snapshot().requests(), 0);
+    }
+
+    fn echo_body(request: &Request) -> Result<Response, anyhow::Error> {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = match &request.body {
+            Some(BodyKind::Bytes(body)) => Some(BodyKind::Bytes(body.clone())),
+            _ => None,
+        };
+        Ok(response)
+    }
+
+    #[rstest]
+    #[case("HTTP/1.1", "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n")]
+    #[case("HTTP/1.0", "HTTP/1.1 200 OK\r\n")]
+    #[tokio::test]
+    async fn expect_continue_is_sent_before_reading_the_body(#[case] version: &str, #[case] expected_start: &str) {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let request = format!("POST /upload {version}\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 7\r\n\r\npayload");
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with(expected_start), "{output}");
+        assert!(output.ends_with("\r\n\r\npayload"), "{output}");
+    }
+
+    #[rstest]
+    #[case("200-ok", Some("HTTP/1.1 417 Expectation Failed\r\n"))]
+    #[case("100-continue, 200-ok", Some("HTTP/1.1 417 Expectation Failed\r\n"))]
+    #[case("100-Continue", None)]
+    #[tokio::test]
+    async fn unknown_expectations_are_rejected(#[case] expect: &str, #[case] rejection: Option<&str>) {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let request = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: {expect}\r\nContent-Length: 7\r\n\r\npayload");
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+
+        let output = String::from_utf8(writer).unwrap();
+        match rejection {
+            Some(status_line) => {
+                assert!(matches!(result, Err(ExchangeError::BodyNotRead)), "{result:?}");
+                assert!(output.starts_with(status_line), "{output}");
+                assert!(!output.contains("100 Continue"), "{output}");
+            }
+            None => {
+                assert!(result.is_ok(), "{result:?}");
+                assert!(output.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"), "{output}");
+            }
+        }
+
+        let settings = ServenteSettings {
+            unknown_expectations: UnknownExpectationHandling::Ignore,
+            ..settings
+        };
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+        assert!(String::from_utf8(writer).unwrap().contains("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn expect_continue_for_rejected_request_sends_final_response() {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        handler_controller.set_max_body_size("/upload", Method::Post, 4);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let mut reader = std::io::Cursor::new(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 7\r\n\r\n".as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::BodyNotRead)), "{result:?}");
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert!(!output.contains("100 Continue"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn raw_body_is_kept_for_opted_in_routes() {
+        use std::io::Write;
+
+        fn echo_raw_body(request: &Request) -> Result<Response, anyhow::Error> {
+            let mut response = Response::with_status(StatusCode::Ok);
+            response.body = request.raw_body.clone().map(BodyKind::Bytes);
+            Ok(response)
+        }
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_with_raw_body("/webhook".into(), &echo_raw_body, Method::Post);
+        handler_controller.register_post("/other".into(), &echo_raw_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(br#"{"action":"opened"}"#).unwrap();
+        let payload = encoder.finish().unwrap();
+
+        for (path, expected_body) in [("/webhook", payload.as_slice()), ("/other", &[][..])] {
+            let mut request = format!("POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", payload.len()).into_bytes();
+            request.extend_from_slice(&payload);
+
+            let mut reader = std::io::Cursor::new(request);
+            let mut writer = Vec::new();
+            handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+            assert!(writer.starts_with(b"HTTP/1.1 200 OK\r\n"), "{}", String::from_utf8_lossy(&writer));
+            let head_end = writer.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+            assert_eq!(&writer[head_end..], expected_body, "{path}");
+        }
+    }
+
+    #[rstest]
+    #[case("HTTP/0.9")]
+    #[case("HTTP/3.0")]
+    #[tokio::test]
+    async fn unsupported_version_is_rejected(#[case] version: &str) {
+        let request = format!("GET / {version}\r\nHost: localhost\r\n\r\n");
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+
+        let result = handle_exchange(&mut reader, &mut writer, &SETTINGS, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::MalformedData)));
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert!(output.contains(&format!("{version} is not supported")), "{output}");
+    }
+
+    #[tokio::test]
+    async fn oversized_chunk_is_rejected() {
+        let request = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFF\r\n";
+        let mut reader = std::io::Cursor::new(request);
+        let mut writer = Vec::new();
+
+        let result = handle_exchange(&mut reader, &mut writer, &SETTINGS, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::MalformedData)));
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{output}");
+    }
+
+    /// Decodes a body that was sent using the chunked transfer coding.
+    fn decode_chunked(mut data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let line_end = data.windows(2).position(|window| window == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&data[..line_end]).unwrap(), 16).unwrap();
+            data = &data[line_end + 2..];
+            if size == 0 {
+                assert_eq!(data, b"\r\n");
+                return body;
+            }
+
+            body.extend_from_slice(&data[..size]);
+            assert_eq!(&data[size..size + 2], b"\r\n");
+            data = &data[size + 2..];
+        }
+    }
+
+    async fn transfer_large_text_file_chunked(content_coding: Option<ContentCoding>) -> (String, Vec<u8>) {
+        use std::io::Write;
+
+        let original = "The quick brown fox jumps over the lazy dog.\n".repeat(10_000);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(original.as_bytes()).unwrap();
+
+        let mut handle = tokio::fs::File::open(file.path()).await.unwrap();
+        let mut output = Vec::new();
+        transfer_body_chunked(&mut output, &mut handle, content_coding).await.unwrap();
+        (original, decode_chunked(&output))
+    }
+
+    #[tokio::test]
+    async fn transfer_body_chunked_uncompressed() {
+        let (original, body) = transfer_large_text_file_chunked(None).await;
+        assert_eq!(body, original.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn transfer_body_chunked_gzip() {
+        use std::io::Read;
+
+        let (original, body) = transfer_large_text_file_chunked(Some(ContentCoding::Gzip)).await;
+        assert!(body.len() < original.len());
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(body.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, original);
+    }
+
+    #[tokio::test]
+    async fn transfer_body_chunked_brotli() {
+        use std::io::Read;
+
+        let (original, body) = transfer_large_text_file_chunked(Some(ContentCoding::Brotli)).await;
+        assert!(body.len() < original.len());
+
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(body.as_slice(), 4096).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, original);
+    }
+
+    #[rstest]
+    #[case(MediaType::PLAIN_TEXT, Some("gzip"), Some(ContentCoding::Gzip))]
+    #[case(MediaType::PLAIN_TEXT, Some("gzip, br"), Some(ContentCoding::Brotli))]
+    #[case(MediaType::JSON, Some("br;q=0.5, gzip"), Some(ContentCoding::Gzip))]
+    #[case(MediaType::PLAIN_TEXT, Some("identity"), None)]
+    #[case(MediaType::PLAIN_TEXT, None, None)]
+    #[case(MediaType::PNG, Some("gzip, br"), None)]
+    fn streaming_content_coding(#[case] media_type: MediaType, #[case] accept_encoding: Option<&str>, #[case] expected: Option<ContentCoding>) {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(media_type);
+        assert_eq!(determine_streaming_content_coding(&response, accept_encoding), expected);
+    }
+
+    #[rstest]
+    #[case("no-transform")]
+    #[case("public, max-age=60, No-Transform")]
+    fn streaming_content_coding_no_transform(#[case] cache_control: &'static str) {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::JSON);
+        response.headers.append_or_override(HeaderName::CacheControl, cache_control.into());
+        assert_eq!(determine_streaming_content_coding(&response, Some("gzip, br")), None);
+    }
+
+    #[rstest]
+    #[case(MediaType::PLAIN_TEXT, Some("gzip"), true)]
+    #[case(MediaType::PLAIN_TEXT, Some("trailers, gzip;q=0.5"), true)]
+    #[case(MediaType::PLAIN_TEXT, Some("gzip;q=0"), false)]
+    #[case(MediaType::PLAIN_TEXT, Some("trailers"), false)]
+    #[case(MediaType::PLAIN_TEXT, None, false)]
+    #[case(MediaType::PNG, Some("gzip"), false)]
+    fn gzip_transfer_coding(#[case] media_type: MediaType, #[case] te: Option<&str>, #[case] expected: bool) {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(media_type);
+        assert_eq!(accepts_gzip_transfer_coding(&response, te), expected);
+    }
+
+    #[test]
+    fn streaming_content_coding_already_encoded() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::JSON);
+        response.headers.append_or_override(HeaderName::ContentEncoding, ContentCoding::Gzip.into());
+        assert_eq!(determine_streaming_content_coding(&response, Some("br")), None);
+    }
+
+    #[tokio::test]
+    async fn send_response_no_transform_json_is_not_compressed() {
+        let json = format!("[{}0]", "0, ".repeat(10_000));
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, json.as_bytes()).unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+        let metadata = file.metadata().unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::JSON);
+        response.headers.append_or_override(HeaderName::CacheControl, "no-transform".into());
+        response.body = Some(BodyKind::File { handle: tokio::fs::File::from_std(file), metadata });
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/data.json").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        request.headers.append_or_override(HeaderName::AcceptEncoding, "gzip".into());
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &SETTINGS).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("Content-Encoding"), "{}", &output[..200]);
+        assert!(output.ends_with(&json));
+    }
+
+    fn serve_large_json_export(_: &Request) -> Result<Response, anyhow::Error> {
+        let json = format!("[{}0]", "0, ".repeat(10_000));
+        let mut file = tempfile::tempfile()?;
+        std::io::Write::write_all(&mut file, json.as_bytes())?;
+        std::io::Seek::rewind(&mut file)?;
+        let metadata = file.metadata()?;
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::JSON);
+        response.body = Some(BodyKind::File { handle: tokio::fs::File::from_std(file), metadata });
+        Ok(response)
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    #[tokio::test]
+    async fn route_with_compression_disabled_is_not_compressed(#[case] disable_compression: bool) {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/export.json".into(), &serve_large_json_export);
+        if disable_compression {
+            handler_controller.disable_compression("/export.json", Method::Get);
+        }
+        let settings = ServenteSettings {
+            handler_controller,
+            chunked_transfer_threshold: 1024,
+            ..SETTINGS.clone()
+        };
+
+        let mut reader = std::io::Cursor::new(b"GET /export.json HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n".as_slice());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        let output = String::from_utf8_lossy(&writer);
+        let head = &output[..output.find("\r\n\r\n").unwrap()];
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert_eq!(head.contains("Content-Encoding: gzip\r\n"), !disable_compression, "{head}");
+    }
+
+    #[tokio::test]
+    async fn send_response_multiple_set_cookie() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append(HeaderName::SetCookie, "a=1".into()).unwrap();
+        response.headers.append(HeaderName::SetCookie, "b=2; Secure".into()).unwrap();
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, None, &SETTINGS).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let cookies: Vec<_> = output.lines().filter(|line| line.starts_with("Set-Cookie: ")).collect();
+        assert_eq!(cookies, ["Set-Cookie: a=1", "Set-Cookie: b=2; Secure"]);
+    }
+
+    #[tokio::test]
+    async fn send_response_informational_before_final() {
+        let mut output = Vec::new();
+
+        let mut continue_response = Response::with_status(StatusCode::Continue);
+        continue_response.headers.set_content_length(0);
+        send_response(&mut output, continue_response, None, &SETTINGS).await.unwrap();
+
+        let mut early_hints = Response::with_status(StatusCode::EarlyHints);
+        early_hints.headers.append(HeaderName::Link, "</style.css>; rel=preload; as=style".into()).unwrap();
+        send_response(&mut output, early_hints, None, &SETTINGS).await.unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::StaticString("ok"));
+        send_response(&mut output, response, None, &SETTINGS).await.unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), concat!(
+            "HTTP/1.1 100 Continue\r\n",
+            "\r\n",
+            "HTTP/1.1 103 Early Hints\r\n",
+            "Link: </style.css>; rel=preload; as=style\r\n",
+            "\r\n",
+            "HTTP/1.1 200 OK\r\n",
+            "Content-Length: 2\r\n",
+            "\r\n",
+            "ok",
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_response_canonical_header_order() {
+        let settings = ServenteSettings {
+            header_order: HeaderOrder::Canonical,
+            ..SETTINGS.clone()
+        };
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append_or_override(HeaderName::XContentTypeOptions, "nosniff".into());
+        response.headers.append_or_override(HeaderName::ContentType, "text/plain".into());
+        response.headers.append_or_override(HeaderName::Server, "servente".into());
+        response.headers.append_or_override(HeaderName::CacheControl, "no-cache".into());
+        response.body = Some(BodyKind::StaticString("ok"));
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, None, &settings).await.unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), concat!(
+            "HTTP/1.1 200 OK\r\n",
+            "Cache-Control: no-cache\r\n",
+            "Server: servente\r\n",
+            "Content-Length: 2\r\n",
+            "Content-Type: text/plain\r\n",
+            "X-Content-Type-Options: nosniff\r\n",
+            "\r\n",
+            "ok",
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_response_shared_body() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/file.txt").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &SETTINGS).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Length: 10\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n0123456789"), "{output}");
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+        request.headers.append_or_override(HeaderName::Range, "bytes=4-".into());
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &SETTINGS).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n456789"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_large_file_to_http10_client() {
+        let text = "The quick brown fox jumps over the lazy dog.\n".repeat(1_000);
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, text.as_bytes()).unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+        let metadata = file.metadata().unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+        response.body = Some(BodyKind::File { handle: tokio::fs::File::from_std(file), metadata });
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/large.txt").unwrap(),
+            version: HttpVersion::Http10,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        request.headers.append_or_override(HeaderName::AcceptEncoding, "gzip".into());
+
+        let settings = ServenteSettings {
+            chunked_transfer_threshold: 1024,
+            ..SETTINGS.clone()
+        };
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &settings).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let head = &output[..output.find("\r\n\r\n").unwrap()];
+        assert!(!head.contains("Transfer-Encoding"), "{head}");
+        assert!(!head.contains("Content-Encoding"), "{head}");
+        assert!(output.contains(&format!("Content-Length: {}\r\n", text.len())), "{head}");
+        assert!(output.ends_with(&text));
+    }
+
+    fn serve_streamed_rows(_: &Request) -> Result<Response, anyhow::Error> {
+        let mut response = Response::streaming(|mut sink| async move {
+            sink.write("first row\n").await?;
+            sink.write("second row\n").await
+        });
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn streamed_response_to_http10_client_is_delimited_by_close() {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/rows".into(), &serve_streamed_rows);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let mut reader = std::io::Cursor::new(b"GET /rows HTTP/1.0\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n".as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::BodyDelimitedByClose)), "{result:?}");
+
+        let output = String::from_utf8(writer).unwrap();
+        let head = &output[..output.find("\r\n\r\n").unwrap()];
+        assert!(head.contains("Connection: close\r\n"), "{head}");
+        assert!(!head.contains("Transfer-Encoding"), "{head}");
+        assert!(!head.contains("Content-Length"), "{head}");
+        assert!(output.ends_with("\r\n\r\nfirst row\nsecond row\n"), "{output}");
+
+        // HTTP/1.1 clients still get the chunked transfer coding.
+        let mut reader = std::io::Cursor::new(b"GET /rows HTTP/1.1\r\nHost: localhost\r\n\r\n".as_slice());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"), "{output}");
+        assert!(!output.contains("Connection: close\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn streamed_response_with_gzip_transfer_coding() {
+        use std::io::Read;
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/rows".into(), &serve_streamed_rows);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let mut reader = std::io::Cursor::new(b"GET /rows HTTP/1.1\r\nHost: localhost\r\nTE: gzip\r\nConnection: TE\r\nAccept-Encoding: br\r\n\r\n".as_slice());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        let head_end = writer.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&writer[..head_end]);
+        assert!(head.contains("Transfer-Encoding: gzip, chunked\r\n"), "{head}");
+        assert!(!head.contains("Content-Encoding"), "{head}");
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(decode_chunked(&writer[head_end..]).as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, "first row\nsecond row\n");
+    }
+
+    /// Send a response for the `0123456789` text file, with the given `Range`.
+    async fn send_ranged_text_response(range: &'static str) -> String {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/file.txt").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        request.headers.append_or_override(HeaderName::Range, range.into());
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &SETTINGS).await.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[rstest]
+    #[case("\"v1\"", "206 Partial Content")]
+    #[case("\"v2\"", "200 OK")]
+    #[case("W/\"v1\"", "200 OK")]
+    #[case("Tue, 14 Nov 2023 22:13:20 GMT", "206 Partial Content")]
+    #[case("Tue, 14 Nov 2023 22:13:21 GMT", "200 OK")]
+    #[case("Mon, 13 Nov 2023 22:13:20 GMT", "200 OK")]
+    #[case("yesterday", "200 OK")]
+    #[tokio::test]
+    async fn send_response_if_range(#[case] if_range: &'static str, #[case] expected_status: &str) {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+        response.headers.append_or_override(HeaderName::ETag, "\"v1\"".into());
+        response.headers.set_last_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/file.txt").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        request.headers.append_or_override(HeaderName::Range, "bytes=4-".into());
+        request.headers.append_or_override(HeaderName::IfRange, if_range.into());
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &SETTINGS).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(&format!("HTTP/1.1 {expected_status}\r\n")), "{output}");
+        let expected_body = if expected_status == "200 OK" { "0123456789" } else { "456789" };
+        assert!(output.ends_with(&format!("\r\n\r\n{expected_body}")), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_multiple_ranges() {
+        let output = send_ranged_text_response("bytes=6-7,0-1").await;
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{output}");
+
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        let boundary = head.split("multipart/byteranges; boundary=").nth(1).unwrap().split("\r\n").next().unwrap();
+        assert!(head.split("\r\n").any(|line| line == format!("Content-Length: {}", body.len())), "{output}");
+        assert_eq!(body, format!(concat!(
+            "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01",
+            "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 6-7/10\r\n\r\n67",
+            "\r\n--{0}--\r\n",
+        ), boundary));
+    }
+
+    #[tokio::test]
+    async fn send_response_overlapping_ranges_are_coalesced() {
+        let output = send_ranged_text_response("bytes=0-3,2-5").await;
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{output}");
+        assert!(output.contains("Content-Range: bytes 0-5/10\r\n"), "{output}");
+        assert!(output.contains("Content-Length: 6\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n012345"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_unsatisfiable_range() {
+        let output = send_ranged_text_response("bytes=10-,20-30").await;
+        assert!(output.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{output}");
+        assert!(output.contains("Content-Range: bytes */10\r\n"), "{output}");
+        assert!(output.contains("Content-Length: 0\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_too_many_ranges_serves_full_body() {
+        let settings = ServenteSettings {
+            max_parts: 2,
+            ..SETTINGS.clone()
+        };
+
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"0123456789").unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+        let metadata = file.metadata().unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::File { handle: tokio::fs::File::from_std(file), metadata });
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/file.txt").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        request.headers.append_or_override(HeaderName::Range, "bytes=0-1,3-4,6-7".into());
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&request), &settings).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n0123456789"), "{output}");
+    }
+
+    fn create_head_request(range: Option<&'static str>) -> Request {
+        let mut request = Request {
+            method: Method::Head,
+            target: RequestTarget::parse("/file.txt").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        if let Some(range) = range {
+            request.headers.append_or_override(HeaderName::Range, range.into());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn send_response_head_omits_body() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.set_content_type(MediaType::PLAIN_TEXT);
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&create_head_request(None)), &SETTINGS).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert!(output.contains("Content-Length: 10\r\n"), "{output}");
+        assert!(output.contains("Content-Type: text/plain"), "{output}");
+        assert!(output.ends_with("\r\n\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_head_with_range() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::Shared(std::sync::Arc::new(b"0123456789".to_vec())));
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&create_head_request(Some("bytes=2-5"))), &SETTINGS).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{output}");
+        assert!(output.contains("Content-Range: bytes 2-5/10\r\n"), "{output}");
+        assert!(output.contains("Content-Length: 4\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_head_cached_file_keeps_content_encoding() {
+        let versions = Arc::clone(&servente_resources::static_resources::WELCOME_HTML_VERSIONS);
+        let gzip_length = versions.get_version(Some(ContentCoding::Gzip)).len();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append_or_override(HeaderName::ContentEncoding, ContentCoding::Gzip.into());
+        response.body = Some(BodyKind::CachedBytes(versions, Some(ContentCoding::Gzip)));
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, Some(&create_head_request(None)), &SETTINGS).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Encoding: gzip\r\n"), "{output}");
+        assert!(output.contains(&format!("Content-Length: {gzip_length}\r\n")), "{output}");
+        assert!(output.ends_with("\r\n\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_file_above_chunked_transfer_threshold() {
+        let settings = ServenteSettings {
+            chunked_transfer_threshold: 4,
+            ..SETTINGS.clone()
+        };
+
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"0123456789").unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+        let metadata = file.metadata().unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.body = Some(BodyKind::File { handle: tokio::fs::File::from_std(file), metadata });
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, None, &settings).await.unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"), "{output}");
+        assert!(!output.contains("Content-Length"), "{output}");
+        assert!(output.ends_with("\r\n\r\nA\r\n0123456789\r\n0\r\n\r\n"), "{output}");
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn rustls_tls_parameters_are_populated() {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let private_key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der().unwrap());
+
+        let mut server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], private_key)
+            .unwrap();
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&certificate).unwrap();
+        let mut client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(address).await.unwrap();
+            tokio_rustls::TlsConnector::from(Arc::new(client_config))
+                .connect("localhost".try_into().unwrap(), stream).await.unwrap()
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = TlsAcceptor::from(Arc::new(server_config)).accept(stream).await.unwrap();
+        let _client = client.await.unwrap();
+
+        let parameters = rustls_tls_parameters(stream.get_ref().1);
+        assert_eq!(parameters.version.as_deref(), Some("TLSv1_3"));
+        assert!(parameters.cipher_suite.unwrap().starts_with("TLS13_"));
+        assert_eq!(parameters.alpn.as_deref(), Some("http/1.1"));
+
+        let connection = ConnectionInfo::with_tls(rustls_tls_parameters(stream.get_ref().1));
+        assert_eq!(connection.scheme, Scheme::Https);
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn tls_early_data_only_accepts_safe_methods() {
+        use tokio::io::AsyncReadExt;
+
+        fn hello(_: &Request) -> Result<Response, anyhow::Error> {
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, "hello"))
+        }
+
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let private_key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der().unwrap());
+
+        let mut server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], private_key)
+            .unwrap();
+        server_config.max_early_data_size = 16 * 1024;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&certificate).unwrap();
+        let mut client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.enable_early_data = true;
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config)).early_data(true);
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/hello".into(), &hello);
+        handler_controller.register_post("/hello".into(), &hello);
+        let config = ServenteConfig {
+            tls_config: Arc::new(server_config),
+            bind_address: String::new(),
+            settings: ServenteSettings {
+                handler_controller,
+                ..SETTINGS.clone()
+            },
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer_address) = listener.accept().await.unwrap();
+                tokio::spawn(process_socket(stream, peer_address, config.clone(), watch::channel(false).1));
+            }
+        });
+
+        let exchange = |requests: &'static str| {
+            let connector = connector.clone();
+            async move {
+                let stream = TcpStream::connect(address).await.unwrap();
+                let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+                stream.write_all(requests.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+
+                let mut output = Vec::new();
+                // The server might not send a close_notify.
+                _ = stream.read_to_end(&mut output).await;
+                (stream.get_ref().1.is_early_data_accepted(), String::from_utf8(output).unwrap())
+            }
+        };
+
+        // The first connection receives the session ticket to resume with.
+        let (early_data_accepted, output) = exchange("POST /hello HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(!early_data_accepted);
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+
+        let (early_data_accepted, output) = exchange(concat!(
+            "GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "POST /hello HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+        )).await;
+        assert!(early_data_accepted);
+
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 2, "{output}");
+        assert!(responses[0].starts_with("200 OK\r\n"), "{output}");
+        assert!(responses[1].starts_with("425 Too Early\r\n"), "{output}");
+    }
+
+    /// Create a configuration with a self-signed certificate, and a connector
+    /// that trusts the certificate.
+    #[cfg(feature = "rustls")]
+    fn create_tls_config(settings: ServenteSettings) -> (ServenteConfig, tokio_rustls::TlsConnector) {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let private_key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der().unwrap());
+
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.clone()], private_key)
+            .unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&certificate).unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let config = ServenteConfig {
+            tls_config: Arc::new(server_config),
+            bind_address: String::new(),
+            settings,
+        };
+
+        (config, tokio_rustls::TlsConnector::from(Arc::new(client_config)))
+    }
+
+    /// Start a server with a self-signed certificate, returning its address and
+    /// a connector that trusts the certificate.
+    #[cfg(feature = "rustls")]
+    async fn start_tls_server(settings: ServenteSettings) -> (std::net::SocketAddr, tokio_rustls::TlsConnector) {
+        let (config, connector) = create_tls_config(settings);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer_address) = listener.accept().await.unwrap();
+                tokio::spawn(process_socket(stream, peer_address, config.clone(), watch::channel(false).1));
+            }
+        });
+
+        (address, connector)
+    }
+
+    /// Start a server that shuts down gracefully when the returned sender is
+    /// used, with the given grace period.
+    #[cfg(feature = "rustls")]
+    async fn start_tls_server_with_shutdown(grace_period: Duration) -> (std::net::SocketAddr, tokio_rustls::TlsConnector,
+            tokio::sync::oneshot::Sender<()>, task::JoinHandle<io::Result<usize>>) {
+        let (config, connector) = create_tls_config(ServenteSettings {
+            shutdown_grace_period: grace_period,
+            ..SETTINGS.clone()
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(serve_until(listener, config.into(), async move {
+            _ = receiver.await;
+        }));
+
+        (address, connector, sender, server)
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn keep_alive_request_limit_closes_connection() {
+        let (address, connector) = start_tls_server(ServenteSettings {
+            max_keep_alive_requests: 2,
+            ..SETTINGS.clone()
+        }).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+
+        stream.write_all(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(3).as_slice()).await.unwrap();
+
+        let mut output = Vec::new();
+        timeout(Duration::from_secs(1), stream.read_to_end(&mut output)).await.unwrap().unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 2, "{output}");
+        assert!(!responses[0].contains("Connection: close\r\n"), "{output}");
+        assert!(responses[1].contains("Connection: close\r\n"), "{output}");
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn keep_alive_idle_timeout_closes_connection() {
+        let (address, connector) = start_tls_server(ServenteSettings {
+            keep_alive_idle_timeout: Duration::from_millis(100),
+            read_headers_timeout: Duration::from_secs(10),
+            ..SETTINGS.clone()
+        }).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+
+        stream.write_all(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut buffer = [0; 1024];
+        let length = stream.read(&mut buffer).await.unwrap();
+        assert!(buffer[..length].starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // The connection is dropped well before the read_headers_timeout.
+        assert_eq!(timeout(Duration::from_secs(1), stream.read(&mut buffer)).await.unwrap().unwrap(), 0);
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn graceful_shutdown_closes_idle_connections() {
+        let (address, connector, shutdown, server) = start_tls_server_with_shutdown(Duration::from_secs(5)).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+
+        stream.write_all(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut buffer = [0; 1024];
+        let length = stream.read(&mut buffer).await.unwrap();
+        let head = String::from_utf8_lossy(&buffer[..length]).into_owned();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert!(!head.contains("Connection: close"), "{head}");
+
+        shutdown.send(()).unwrap();
+        assert_eq!(timeout(Duration::from_secs(1), stream.read(&mut buffer)).await.unwrap().unwrap(), 0);
+        assert_eq!(server.await.unwrap().unwrap(), 0);
+
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn graceful_shutdown_answers_in_flight_exchange_with_connection_close() {
+        let (address, connector, shutdown, server) = start_tls_server_with_shutdown(Duration::from_secs(5)).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+
+        stream.write_all(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stream.write_all(b"\r\n").await.unwrap();
+
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert_eq!(server.await.unwrap().unwrap(), 0);
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn graceful_shutdown_drops_connections_after_grace_period() {
+        let (address, connector, shutdown, server) = start_tls_server_with_shutdown(Duration::from_millis(100)).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown.send(()).unwrap();
+        assert_eq!(server.await.unwrap().unwrap(), 1);
+    }
+
+    /// Start a server and open an HTTP/2 connection to it, by sending the
+    /// connection preface and an empty SETTINGS frame.
+    #[cfg(all(feature = "rustls", feature = "http2"))]
+    async fn connect_http2(settings: ServenteSettings) -> tokio_rustls::client::TlsStream<TcpStream> {
+        let (address, connector) = start_tls_server(settings).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), stream).await.unwrap();
+        stream.write_all(HTTP2_CONNECTION_PREFACE).await.unwrap();
+        stream.write_all(&[0, 0, 0, 0x04, 0, 0, 0, 0, 0]).await.unwrap();
+        stream.flush().await.unwrap();
+        stream
+    }
+
+    /// Read the next HTTP/2 frame, returning its type, flags and payload.
+    #[cfg(all(feature = "rustls", feature = "http2"))]
+    async fn read_http2_frame(stream: &mut tokio_rustls::client::TlsStream<TcpStream>) -> (u8, u8, Vec<u8>) {
+        use tokio::io::AsyncReadExt;
+
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut payload = vec![0; u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        (header[3], header[4], payload)
+    }
+
+    #[cfg(all(feature = "rustls", feature = "http2"))]
+    #[tokio::test]
+    async fn http2_ping_is_acknowledged_with_the_same_data() {
+        let mut stream = connect_http2(SETTINGS.clone()).await;
+        stream.write_all(&[0, 0, 8, 0x06, 0, 0, 0, 0, 0]).await.unwrap();
+        stream.write_all(b"opaque!!").await.unwrap();
+        stream.flush().await.unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let frame = read_http2_frame(&mut stream).await;
+                if frame.0 == 0x06 {
+                    break frame;
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(frame, (0x06, 0x01, b"opaque!!".to_vec()));
+    }
+
+    #[cfg(all(feature = "rustls", feature = "http2"))]
+    #[tokio::test]
+    async fn http2_connection_is_closed_after_unacknowledged_pings() {
+        let mut stream = connect_http2(ServenteSettings {
+            http2_ping_interval: Some(Duration::from_millis(20)),
+            http2_max_unacknowledged_pings: 2,
+            ..SETTINGS.clone()
+        }).await;
+
+        let pings = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut pings = 0;
+            loop {
+                match read_http2_frame(&mut stream).await {
+                    (0x06, 0x00, payload) => {
+                        assert_eq!(payload, b"servente");
+                        pings += 1;
+                    }
+                    (0x07, _, _) => break pings,
+                    _ => (),
+                }
+            }
+        }).await.unwrap();
+        assert_eq!(pings, 2);
+    }
+
+    #[cfg(feature = "rustls")]
+    #[tokio::test]
+    async fn client_connection_limit_is_per_address() {
+        use servente_http_handling::ClientConnectionLimit;
+        use tokio::io::AsyncReadExt;
+
+        fn hello(_: &Request) -> Result<Response, anyhow::Error> {
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, "hello"))
+        }
+
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_get("/hello".into(), &hello);
+        let (address, connector) = start_tls_server(ServenteSettings {
+            handler_controller,
+            client_connection_limit: Some(Arc::new(ClientConnectionLimit::new(1, ClientLimitExceeded::TooManyRequests))),
+            ..SETTINGS.clone()
+        }).await;
+
+        let connect_from = |local_address: &'static str| {
+            let connector = connector.clone();
+            async move {
+                let socket = tokio::net::TcpSocket::new_v4().unwrap();
+                socket.bind(local_address.parse().unwrap()).unwrap();
+                let stream = socket.connect(address).await.unwrap();
+                connector.connect("localhost".try_into().unwrap(), stream).await.unwrap()
+            }
+        };
+
+        let exchange = |mut stream: tokio_rustls::client::TlsStream<TcpStream>| async move {
+            stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut output = Vec::new();
+            // The server might not send a close_notify.
+            _ = stream.read_to_end(&mut output).await;
+            String::from_utf8(output).unwrap()
+        };
+
+        // The handshake is only done after the connection is accounted for.
+        let _first = connect_from("127.0.0.1:0").await;
+
+        let output = exchange(connect_from("127.0.0.1:0").await).await;
+        assert!(output.starts_with("HTTP/1.1 429 Too Many Requests\r\n"), "{output}");
+
+        let output = exchange(connect_from("127.0.0.2:0").await).await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+    }
+
+    #[rstest]
+    #[case(ExcessBodyDataHandling::NextRequest)]
+    #[case(ExcessBodyDataHandling::Reject)]
+    #[tokio::test]
+    async fn trailing_data_after_connection_close_is_ignored(#[case] excess_body_data: ExcessBodyDataHandling) {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            excess_body_data,
+            ..SETTINGS.clone()
+        };
+
+        let request = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 7\r\n\r\npayloadGET / HTTP/1.1\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::ConnectionClose)), "{result:?}");
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\npayload"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn trailing_data_on_keep_alive_is_the_next_request() {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let request = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 7\r\n\r\npayloadOPTIONS * HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive, close\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::ConnectionClose)), "{result:?}");
+        assert_eq!(reader.position() as usize, request.len());
+
+        let output = String::from_utf8(writer).unwrap();
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 2, "{output}");
+        assert!(responses[0].ends_with("\r\n\r\npayload"), "{output}");
+        assert!(!responses[0].contains("Connection: close\r\n"), "{output}");
+        assert!(responses[1].contains("Connection: close\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn transfer_encoding_with_content_length_closes_the_connection() {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_post("/upload".into(), &echo_body);
+        let settings = ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        };
+
+        let request = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n7\r\npayload\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::ConnectionClose)), "{result:?}");
+
+        // The connection isn't reused for the trailing request.
+        let output = String::from_utf8(writer).unwrap();
+        assert_eq!(output.matches("HTTP/1.1 ").count(), 1, "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\npayload"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn requests_on_a_connection_are_numbered() {
+        #[derive(Default)]
+        struct IdLogger(std::sync::Mutex<Vec<String>>);
+
+        impl servente_http_handling::RequestLogger for IdLogger {
+            fn log(&self, entry: &servente_http_handling::logging::AccessLogEntry<'_>) {
+                self.0.lock().unwrap().push(entry.id().to_string());
+            }
+        }
+
+        let logger = std::sync::Arc::new(IdLogger::default());
+        let mut settings = SETTINGS.clone();
+        settings.request_loggers.push(logger.clone());
+
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nOPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        let connection = ConnectionInfo::new(Scheme::Http).with_id(42);
+        handle_exchange(&mut reader, &mut writer, &settings, &connection).await.unwrap();
+        handle_exchange(&mut reader, &mut writer, &settings, &connection).await.unwrap();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http).with_id(43)).await.unwrap_err();
+
+        assert_eq!(*logger.0.lock().unwrap(), ["conn=42 req=1", "conn=42 req=2"]);
+    }
+
+    struct UploadHandler {
+        read_body: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl servente_http_handling::handler::StreamingHandler for UploadHandler {
+        async fn handle(&self, request: &Request, body: &mut servente_http_handling::handler::RequestBody<'_>) -> Result<Response, anyhow::Error> {
+            assert!(request.body.is_none());
+
+            let mut data = Vec::new();
+            if self.read_body {
+                body.read_to_end(&mut data).await?;
+            }
+            Ok(Response::with_status_and_string_body(StatusCode::Ok, format!("received {}", String::from_utf8(data)?)))
+        }
+    }
+
+    fn create_upload_settings(read_body: bool) -> ServenteSettings {
+        let mut handler_controller = HandlerController::new();
+        handler_controller.register_streaming("/upload".into(), std::sync::Arc::new(UploadHandler { read_body }), Method::Post);
+        ServenteSettings {
+            handler_controller,
+            ..SETTINGS.clone()
+        }
+    }
+
+    #[rstest]
+    #[case("Content-Length: 7\r\n\r\npayload")]
+    #[case("Transfer-Encoding: chunked\r\n\r\n3\r\npay\r\n4;ext\r\nload\r\n0\r\n\r\n")]
+    #[tokio::test]
+    async fn streaming_handler_reads_the_body(#[case] framing: &str) {
+        let settings = create_upload_settings(true);
+        let request = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\n{framing}OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut reader = std::io::Cursor::new(request.as_bytes());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        // The stream is positioned at the next request.
+        handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        let output = String::from_utf8(writer).unwrap();
+        let responses: Vec<&str> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(responses.len(), 2, "{output}");
+        assert!(responses[0].ends_with("\r\n\r\nreceived payload"), "{output}");
+        assert!(!responses[0].contains("Connection: close\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn streaming_handler_not_reading_the_body_closes_the_connection() {
+        let settings = create_upload_settings(false);
+        let request = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 7\r\n\r\npayload";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::BodyNotRead)), "{result:?}");
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert!(output.contains("Connection: close\r\n"), "{output}");
+        assert!(output.ends_with("\r\n\r\nreceived "), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_streaming_body() {
+        let response = Response::streaming(|mut sink| async move {
+            for row in ["id,name\n", "1,alpha\n", "2,beta\n"] {
+                sink.write(row).await?;
+            }
+            Ok(())
+        });
+
+        let mut output = Vec::new();
+        send_response(&mut output, response, None, &SETTINGS).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"), "{output}");
+        assert!(!output.contains("Content-Length"), "{output}");
+        assert!(output.ends_with("\r\n\r\n8\r\nid,name\n\r\n8\r\n1,alpha\n\r\n7\r\n2,beta\n\r\n0\r\n\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn send_response_streaming_body_failure_truncates_body() {
+        let response = Response::streaming(|mut sink| async move {
+            sink.write("1,alpha\n").await?;
+            Err(io::Error::other("the export failed"))
+        });
+
+        let mut output = Vec::new();
+        assert!(send_response(&mut output, response, None, &SETTINGS).await.is_err());
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("\r\n\r\n8\r\n1,alpha\n\r\n"), "{output}");
+    }
+
+    #[rstest]
+    #[case("GET /docs?page=2 HTTP/1.1\r\nHost: example.com:8443\r\n\r\n", Some("https://example.com:8443/docs?page=2"))]
+    #[case("GET / HTTP/1.1\r\nhost: [::1]\r\n\r\n", Some("https://[::1]/"))]
+    #[case("GET / HTTP/1.0\r\n\r\n", None)]
+    #[case("GET * HTTP/1.1\r\nHost: example.com\r\n\r\n", None)]
+    #[case("GET / HTTP/1.1\r\nHost: example.com/evil\r\n\r\n", None)]
+    #[tokio::test]
+    async fn send_http_upgrade_redirects_to_https(#[case] request: &str, #[case] location: Option<&str>) {
+        let request = discard_request(&mut request.as_bytes()).await.unwrap();
+        assert_eq!(request.https_location().as_deref(), location);
+
+        let mut writer = Vec::new();
+        send_http_upgrade(&mut writer, &request).await.unwrap();
+        let response = String::from_utf8(writer).unwrap();
+        match location {
+            Some(location) => {
+                assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"), "{response}");
+                assert!(response.contains(&format!("\r\nLocation: {location}\r\n")), "{response}");
+            }
+            None => assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"), "{response}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn serialize_response_head_security_headers() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        finish_response_error(&mut response, &SETTINGS).await;
+
+        let head = serialize_response_head(&response, HeaderOrder::Stored);
+        for line in ["X-Frame-Options: DENY", "X-XSS-Protection: 1; mode=block", "X-Content-Type-Options: nosniff"] {
+            assert!(head.contains(&format!("\r\n{line}\r\n")), "missing {line:?} in {head:?}");
+        }
+
+        for (name, value) in response.headers.iter() {
+            let value = value.as_str_may_convert();
+            assert!(!value.to_ascii_lowercase().starts_with(&format!("{}:", name.to_string_lowercase())), "{name:?} repeats its name: {value}");
+        }
+    }
+
+    struct WebSocketEchoHandler;
+
+    #[async_trait::async_trait]
+    impl servente_web_sockets::WebSocketFrameHandler for WebSocketEchoHandler {
+        async fn on_text(&mut self, connection: &mut dyn servente_web_sockets::WebSocketConnection, text: String) -> io::Result<()> {
+            connection.send_message_text(&text).await
+        }
+
+        async fn on_binary(&mut self, connection: &mut dyn servente_web_sockets::WebSocketConnection, data: Vec<u8>) -> io::Result<()> {
+            connection.send_message_binary(&data).await
+        }
+    }
+
+    fn create_web_socket_settings() -> ServenteSettings {
+        let mut settings = SETTINGS.clone();
+        settings.web_sockets.register("/echo", WebSocketEndpoint::new(|_| Box::new(WebSocketEchoHandler)));
+        settings
+    }
+
+    #[tokio::test]
+    async fn web_socket_upgrade_exchanges_messages() {
+        let mut input = b"GET /%65cho HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_vec();
+        // A masked "Hi", followed by a masked close with code 1000.
+        input.extend_from_slice(&[0x81, 0x82, 1, 2, 3, 4, b'H' ^ 1, b'i' ^ 2]);
+        input.extend_from_slice(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xE8]);
+
+        let mut reader = std::io::Cursor::new(input.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &create_web_socket_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::WebSocketClosed)), "{result:?}");
+
+        let head_end = writer.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = std::str::from_utf8(&writer[..head_end]).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{head}");
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{head}");
+        assert_eq!(&writer[head_end..], &[0x81, 0x02, b'H', b'i', 0x88, 0x02, 0x03, 0xE8]);
+    }
+
+    #[tokio::test]
+    async fn web_socket_upgrade_rejects_unsupported_versions() {
+        let request = b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 8\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &create_web_socket_settings(), &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::BodyNotRead)), "{result:?}");
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(output.starts_with("HTTP/1.1 426 Upgrade Required\r\n"), "{output}");
+        assert!(output.contains("Sec-WebSocket-Version: 13\r\n"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn web_socket_upgrade_of_unregistered_path_is_handled_normally() {
+        let request = b"GET /other HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let mut reader = std::io::Cursor::new(request.as_slice());
+        let mut writer = Vec::new();
+        handle_exchange(&mut reader, &mut writer, &create_web_socket_settings(), &ConnectionInfo::new(Scheme::Http)).await.unwrap();
+
+        let output = String::from_utf8(writer).unwrap();
+        assert!(!output.starts_with("HTTP/1.1 101"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn web_socket_upgrade_negotiates_permessage_deflate() {
+        let mut settings = SETTINGS.clone();
+        settings.web_sockets.register("/echo", WebSocketEndpoint::new(|_| Box::new(WebSocketEchoHandler)).with_permessage_deflate());
+
+        let mut input = b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n".to_vec();
+        // A compressed "Hello" of RFC 7692 Section 7.2.3.1, unmasked using a
+        // zero mask, followed by a close without a code.
+        input.extend_from_slice(&[0xC1, 0x87, 0, 0, 0, 0, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        input.extend_from_slice(&[0x88, 0x80, 0, 0, 0, 0]);
+
+        let mut reader = std::io::Cursor::new(input.as_slice());
+        let mut writer = Vec::new();
+        let result = handle_exchange(&mut reader, &mut writer, &settings, &ConnectionInfo::new(Scheme::Http)).await;
+        assert!(matches!(result, Err(ExchangeError::WebSocketClosed)), "{result:?}");
+
+        let head_end = writer.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = std::str::from_utf8(&writer[..head_end]).unwrap();
+        assert!(head.contains("Sec-WebSocket-Extensions: permessage-deflate\r\n"), "{head}");
+        assert_eq!(&writer[head_end..], &[0xC1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x88, 0x00]);
     }
 }
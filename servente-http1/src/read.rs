@@ -12,36 +12,82 @@ use servente_http::{
     Method,
     Request,
     RequestTarget,
+    TransferCodingError,
+    TransferCodingList,
     syntax,
 };
 
+use servente_http_handling::{
+    ServenteSettings,
+    config::{ExcessBodyDataHandling, LineEndingHandling},
+    handler::RequestBody,
+};
+
+use servente_resources::ContentCoding;
+
 use tokio::io::{
+    AsyncBufRead,
     AsyncBufReadExt,
+    AsyncRead,
     AsyncReadExt,
+    AsyncSeekExt,
+    AsyncWriteExt,
 };
 
 use crate::{
-    MaximumLength
+    MaximumLength,
+    chunked_reader::ChunkedBodyReader,
 };
 
 use std::io;
 
-/// Consume a `U+000D CARRIAGE RETURN` character (CR) and a `U+000A LINE FEED`
-/// character (LF) from the stream.
-async fn consume_crlf<R>(stream: &mut R) -> Result<(), Error>
-        where R: AsyncBufReadExt + Unpin {
-    _ = consume_exact_verify(stream, 2, |index, byte| {
-        if index == 0 && byte != b'\r' {
-            return Err(Error::ParseError(HttpParseError::InvalidCRLF));
-        }
+/// A reader that knows which bytes it has already received, but that weren't
+/// consumed yet. Unlike [`AsyncBufReadExt::fill_buf`], asking for these never
+/// reads from the underlying stream, so the answer doesn't depend on when the
+/// client's packets arrive.
+pub trait BufferedRead {
+    /// The bytes that are buffered and can be read without waiting.
+    fn buffered(&self) -> &[u8];
+}
 
-        if index == 1 && byte != b'\n' {
-            return Err(Error::ParseError(HttpParseError::InvalidCRLF));
-        }
+impl<R> BufferedRead for tokio::io::BufReader<R>
+        where R: AsyncRead {
+    fn buffered(&self) -> &[u8] {
+        self.buffer()
+    }
+}
 
-        Ok(())
-    }).await?;
-    Ok(())
+impl<T> BufferedRead for std::io::Cursor<T>
+        where T: AsRef<[u8]> {
+    fn buffered(&self) -> &[u8] {
+        let data = self.get_ref().as_ref();
+        &data[(self.position() as usize).min(data.len())..]
+    }
+}
+
+impl BufferedRead for &[u8] {
+    fn buffered(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<R> BufferedRead for &mut R
+        where R: BufferedRead + ?Sized {
+    fn buffered(&self) -> &[u8] {
+        (**self).buffered()
+    }
+}
+
+/// Consume a `U+000D CARRIAGE RETURN` character (CR) and a `U+000A LINE FEED`
+/// character (LF) from the stream, or only the LF when the `line_endings` are
+/// lenient.
+async fn consume_crlf<R>(stream: &mut R, line_endings: LineEndingHandling) -> Result<(), Error>
+        where R: AsyncBufReadExt + Unpin {
+    match stream.read_u8().await? {
+        b'\n' if line_endings == LineEndingHandling::Lenient => Ok(()),
+        b'\r' if stream.read_u8().await? == b'\n' => Ok(()),
+        _ => Err(Error::ParseError(HttpParseError::InvalidCRLF)),
+    }
 }
 
 pub(crate) async fn consume_exact_verify<R>(stream: &mut R, length: usize, byte_validator: fn(usize, u8) -> Result<(), Error>) -> Result<Vec<u8>, Error>
@@ -55,12 +101,12 @@ pub(crate) async fn consume_exact_verify<R>(stream: &mut R, length: usize, byte_
 
     let mut idx = 0;
     while idx != length {
-        let read = stream.read(&mut buffer[idx..(length - idx)]).await?;
+        let read = stream.read(&mut buffer[idx..]).await?;
         if read == 0 {
             return Err(Error::Other(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "EOF")));
         }
-        for i in 0..read {
-            byte_validator(idx + i, buffer[i])?;
+        for (i, byte) in buffer[idx..(idx + read)].iter().enumerate() {
+            byte_validator(idx + i, *byte)?;
         }
         idx += read;
     }
@@ -69,7 +115,7 @@ pub(crate) async fn consume_exact_verify<R>(stream: &mut R, length: usize, byte_
 }
 
 /// Reads a line from the stream, up to the maximum length.
-pub(crate) async fn read_crlf_line<R>(stream: &mut R, maximum_length: MaximumLength) -> Result<String, Error>
+pub(crate) async fn read_crlf_line<R>(stream: &mut R, maximum_length: MaximumLength, line_endings: LineEndingHandling) -> Result<String, Error>
         where R: AsyncBufReadExt + Unpin {
     let mut string = String::new();
 
@@ -83,6 +129,13 @@ pub(crate) async fn read_crlf_line<R>(stream: &mut R, maximum_length: MaximumLen
             return Err(Error::ParseError(HttpParseError::InvalidCRLF));
         }
 
+        if byte == b'\n' {
+            if line_endings == LineEndingHandling::Lenient {
+                return Ok(string);
+            }
+            return Err(Error::ParseError(HttpParseError::InvalidCRLF));
+        }
+
         string.push(byte as char);
     }
 
@@ -90,12 +143,12 @@ pub(crate) async fn read_crlf_line<R>(stream: &mut R, maximum_length: MaximumLen
 }
 
 /// Reads the headers from the stream.
-pub async fn read_headers<R>(stream: &mut R) -> Result<HeaderMap, Error>
+pub async fn read_headers<R>(stream: &mut R, line_endings: LineEndingHandling) -> Result<HeaderMap, Error>
         where R: AsyncBufReadExt + Unpin {
     let mut header_map = HeaderMap::new();
 
     loop {
-        let line = read_crlf_line(stream, MaximumLength::HEADER).await?;
+        let line = read_crlf_line(stream, MaximumLength::HEADER, line_endings).await?;
         if line.is_empty() {
             return Ok(header_map);
         }
@@ -137,6 +190,12 @@ async fn read_http_version<R>(stream: &mut R) -> Result<HttpVersion, Error>
         b"1.0" => HttpVersion::Http10,
         b"1.1" => HttpVersion::Http11,
         b"2.0" => HttpVersion::Http2,
+        [major @ b'0'..=b'9', b'.', minor @ b'0'..=b'9'] => {
+            return Err(Error::ParseError(HttpParseError::UnsupportedVersion {
+                major: major - b'0',
+                minor: minor - b'0',
+            }));
+        }
         _ => return Err(Error::ParseError(HttpParseError::InvalidHttpVersion)),
     })
 }
@@ -162,48 +221,367 @@ async fn read_string_until_character<R>(stream: &mut R, char: u8, maximum_length
 }
 
 /// Reads the request body from the stream and stores it in the request.
-pub(crate) async fn read_request_body<R>(stream: &mut R, request: &mut Request) -> Result<(), Error>
-        where R: AsyncBufReadExt + Unpin {
-    if let Some(content_length) = request.headers.get(&HeaderName::ContentLength) {
-        request.body = Some(read_request_body_content_length(stream, request, content_length).await?);
+pub(crate) async fn read_request_body<R>(stream: &mut R, request: &mut Request, settings: &ServenteSettings) -> Result<(), Error>
+        where R: AsyncBufReadExt + BufferedRead + Unpin {
+    let max_body_size = settings.handler_controller.max_body_size(request)
+        .unwrap_or(settings.max_request_body_size);
+
+    // The content codings are decoded in memory, and the raw body is a copy
+    // in memory as well, so spilling those to disk wouldn't help.
+    let spill_threshold = settings.request_body_spill_threshold
+        .filter(|_| !request.headers.contains(&HeaderName::ContentEncoding) && !settings.handler_controller.wants_raw_body(request));
+
+    // The Transfer-Encoding overrides the Content-Length, which is possibly
+    // sent to mislead intermediaries (request smuggling).
+    //
+    // RFC 9112 Section 6.3: https://www.rfc-editor.org/rfc/rfc9112.html#section-6.3-2.3
+    if let Some(transfer_encoding) = request.headers.get(&HeaderName::TransferEncoding) {
+        let transfer_encoding = parse_transfer_encoding(transfer_encoding)?;
+        let (body, trailers) = read_request_body_chunked(stream, &transfer_encoding, settings.max_chunk_size, max_body_size, spill_threshold).await?;
+        merge_trailers(request, trailers)?;
+        finish_request_body(request, body, settings, max_body_size).await?;
         return Ok(());
     }
 
-    if request.headers.get(&HeaderName::TransferEncoding).is_some() {
-        request.body = Some(read_request_body_chunked(stream).await?);
+    if let Some(content_length) = request.headers.get(&HeaderName::ContentLength) {
+        let body = read_request_body_content_length(stream, content_length, max_body_size, spill_threshold).await?;
+
+        // When the connection is closed after this request, the excess data
+        // can't be a pipelined request, and is simply ignored.
+        if settings.excess_body_data == ExcessBodyDataHandling::Reject && !crate::requests_close(request) && !stream.buffered().is_empty() {
+            return Err(Error::ParseError(HttpParseError::BodyExceedsContentLength));
+        }
+
+        finish_request_body(request, body, settings, max_body_size).await?;
         return Ok(());
     }
 
     Ok(())
 }
 
-/// Reads the request-body
-async fn read_request_body_content_length<R>(stream: &mut R, request: &Request, content_length: &HeaderValue) -> Result<BodyKind, Error>
-        where R: AsyncBufReadExt + Unpin {
-    let content_length = content_length.parse_number().ok_or(Error::ParseError(HttpParseError::InvalidContentLength))?;
-    let mut body = Vec::with_capacity(content_length);
+/// A request body that is being read. It is kept in memory until it grows
+/// beyond the [`ServenteSettings::request_body_spill_threshold`], after which
+/// it is moved to a temporary file.
+#[derive(Debug)]
+enum BodyBuffer {
+    Memory(Vec<u8>),
+    File {
+        file: tokio::fs::File,
+        length: usize,
+    },
+}
+
+impl BodyBuffer {
+    fn len(&self) -> usize {
+        match self {
+            Self::Memory(body) => body.len(),
+            Self::File { length, .. } => *length,
+        }
+    }
+
+    /// Reads exactly `length` bytes of the body from the stream.
+    async fn read_from<R>(&mut self, stream: &mut R, length: usize, spill_threshold: Option<usize>) -> Result<(), Error>
+            where R: AsyncBufReadExt + Unpin {
+        if let Self::Memory(body) = self {
+            if spill_threshold.is_some_and(|threshold| body.len() + length > threshold) {
+                // The temporary file is already unlinked, so it is removed
+                // when it is closed, even if we crash.
+                let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+                file.write_all(body).await?;
+                *self = Self::File { file, length: body.len() };
+            }
+        }
+
+        match self {
+            Self::Memory(body) => {
+                let start = body.len();
+                body.resize(start + length, 0);
+                stream.read_exact(&mut body[start..]).await?;
+            }
+            Self::File { file, length: file_length } => {
+                let copied = tokio::io::copy(&mut (&mut *stream).take(length as u64), file).await?;
+                if copied != length as u64 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                *file_length += length;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_transfer_encoding(transfer_encoding: &HeaderValue) -> Result<TransferCodingList, HttpParseError> {
+    TransferCodingList::parse(&transfer_encoding.as_str_may_convert())
+        .map_err(|error| match error {
+            TransferCodingError::Unknown => HttpParseError::UnknownTransferCoding,
+            TransferCodingError::Empty | TransferCodingError::ChunkedNotLast => HttpParseError::InvalidTransferEncoding,
+        })
+}
+
+/// Delimit the body of a request that is read by a [`StreamingHandler`]
+/// instead of [`read_request_body`], so the handler can't read into the next
+/// request. The limits of the body are the same.
+///
+/// [`StreamingHandler`]: servente_http_handling::handler::StreamingHandler
+pub(crate) fn streamed_request_body<'a, R>(stream: &'a mut R, request: &Request, settings: &ServenteSettings) -> Result<Box<RequestBody<'a>>, HttpParseError>
+        where R: AsyncBufRead + Unpin + Send {
+    let max_body_size = settings.handler_controller.max_body_size(request)
+        .unwrap_or(settings.max_request_body_size);
+
+    if let Some(transfer_encoding) = request.headers.get(&HeaderName::TransferEncoding) {
+        // TODO: support decoding the other transfer codings.
+        if parse_transfer_encoding(transfer_encoding)?.codings().len() > 1 {
+            return Err(HttpParseError::UnknownTransferCoding);
+        }
 
-    stream.read_exact(body.as_mut_slice()).await?;
+        return Ok(Box::new(ChunkedBodyReader::new(stream, settings.max_chunk_size, max_body_size)));
+    }
+
+    if let Some(content_length) = request.headers.get(&HeaderName::ContentLength) {
+        let content_length = content_length.parse_number().ok_or(HttpParseError::InvalidContentLength)?;
+        if content_length > max_body_size {
+            return Err(HttpParseError::BodyTooLarge);
+        }
+
+        return Ok(Box::new(stream.take(content_length as u64)));
+    }
+
+    Ok(Box::new(tokio::io::empty()))
+}
+
+/// Store the body in the request. A copy of the body as it was received is
+/// kept for the handlers that ask for it, before the content coding is
+/// decoded.
+async fn finish_request_body(request: &mut Request, body: BodyBuffer, settings: &ServenteSettings, max_body_size: usize) -> Result<(), Error> {
+    let body = match body {
+        BodyBuffer::Memory(body) => body,
+        BodyBuffer::File { mut file, .. } => {
+            file.flush().await?;
+            file.rewind().await?;
+            let metadata = file.metadata().await?;
+            request.body = Some(BodyKind::File { handle: file, metadata });
+            return Ok(());
+        }
+    };
+
+    if settings.handler_controller.wants_raw_body(request) {
+        request.raw_body = Some(body.clone());
+    }
+
+    let body = decode_content_coding(request, body, max_body_size)?;
 
     if let Some(media_type) = request.headers.get(&HeaderName::ContentType) {
         // TODO: correctly parse the Media Type
         if media_type.as_str_no_convert().unwrap().starts_with("text/") {
-            match String::from_utf8(body) {
-                Ok(body) => return Ok(BodyKind::String(body)),
+            request.body = Some(match String::from_utf8(body) {
+                Ok(body) => BodyKind::String(body),
                 // String conversion was impossible (possibly not UTF-8), so just return the bytes.
-                Err(error) => return Ok(BodyKind::Bytes(error.into_bytes())),
+                Err(error) => BodyKind::Bytes(error.into_bytes()),
+            });
+            return Ok(());
+        }
+    }
+
+    request.body = Some(BodyKind::Bytes(body));
+    Ok(())
+}
+
+/// Decode the content codings of the `Content-Encoding` of the body, so the
+/// handlers receive the plain representation. Since compressed data can
+/// expand enormously, the decoded body is limited to the `max_body_size` as
+/// well.
+///
+/// The `Content-Encoding` is removed afterwards, and the `Content-Length` is
+/// updated to the decoded length, so the handlers (e.g. CGI scripts) don't
+/// try to decode the body again.
+///
+/// ### References
+/// * [RFC 9110 Section 8.4](https://www.rfc-editor.org/rfc/rfc9110.html#name-content-encoding)
+fn decode_content_coding(request: &mut Request, mut body: Vec<u8>, max_body_size: usize) -> Result<Vec<u8>, Error> {
+    if !request.headers.contains(&HeaderName::ContentEncoding) {
+        return Ok(body);
+    }
+
+    let codings = request.headers.get_all(&HeaderName::ContentEncoding)
+        .flat_map(|value| {
+            value.as_str_may_convert()
+                .split(',')
+                .map(|coding| coding.trim().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|coding| !coding.is_empty() && !coding.eq_ignore_ascii_case("identity"))
+        .map(|coding| ContentCoding::from_http_identifier(&coding).ok_or(HttpParseError::UnknownContentCoding))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The codings are listed in the order they were applied.
+    if !body.is_empty() {
+        for coding in codings.iter().rev() {
+            body = coding.decode_up_to(&body, max_body_size)
+                .ok_or(Error::ParseError(HttpParseError::InvalidContentEncoding))?;
+
+            if body.len() > max_body_size {
+                return Err(Error::ParseError(HttpParseError::BodyTooLarge));
             }
         }
     }
-    Ok(BodyKind::Bytes(body))
+
+    request.headers.remove(&HeaderName::ContentEncoding);
+    if request.headers.contains(&HeaderName::ContentLength) {
+        request.headers.set_content_length(body.len());
+    }
+
+    Ok(body)
+}
+
+/// Reads the request-body
+async fn read_request_body_content_length<R>(stream: &mut R, content_length: &HeaderValue, max_size: usize, spill_threshold: Option<usize>) -> Result<BodyBuffer, Error>
+        where R: AsyncBufReadExt + Unpin {
+    let content_length = content_length.parse_number().ok_or(Error::ParseError(HttpParseError::InvalidContentLength))?;
+    if content_length > max_size {
+        return Err(Error::ParseError(HttpParseError::BodyTooLarge));
+    }
+    let mut body = BodyBuffer::Memory(Vec::new());
+
+    match body.read_from(stream, content_length, spill_threshold).await {
+        Ok(()) => Ok(body),
+
+        // The connection was closed before the full body was received,
+        // meaning the request is incomplete and shouldn't be handled.
+        Err(Error::Other(error)) if error.kind() == io::ErrorKind::UnexpectedEof => Err(Error::Other(io::Error::new(io::ErrorKind::UnexpectedEof,
+            "connection closed before the body was complete, as indicated by Content-Length"))),
+
+        Err(error) => Err(error),
+    }
 }
 
 /// Reads the body of a request, assuming that the body is encoded using chunked
 /// transfer encoding.
-async fn read_request_body_chunked<R>(_stream: &mut R) -> Result<BodyKind, Error>
+///
+/// Every chunk is limited to the `max_chunk_size`, and the decoded body as a
+/// whole to the `max_body_size`, so a client can't make us allocate a huge
+/// buffer by announcing an enormous chunk size.
+///
+/// Returns the body together with the fields of the trailer section. When the
+/// connection is closed before the last chunk and the trailer section were
+/// received, the body is incomplete and [`HttpParseError::IncompleteChunkedBody`]
+/// is returned.
+///
+/// ### References
+/// * [RFC 9112 Section 7.1](https://www.rfc-editor.org/rfc/rfc9112.html#name-chunked-transfer-coding)
+async fn read_request_body_chunked<R>(stream: &mut R, transfer_encoding: &TransferCodingList, max_chunk_size: usize, max_body_size: usize,
+        spill_threshold: Option<usize>) -> Result<(BodyBuffer, HeaderMap), Error>
+        where R: AsyncBufReadExt + Unpin {
+    let (body, trailers) = decode_chunked_body(stream, max_chunk_size, max_body_size, spill_threshold).await
+        .map_err(|error| match error {
+            Error::Other(error) if error.kind() == io::ErrorKind::UnexpectedEof => Error::ParseError(HttpParseError::IncompleteChunkedBody),
+            error => error,
+        })?;
+
+    // TODO: support decoding the other transfer codings.
+    if transfer_encoding.codings().len() > 1 {
+        return Err(Error::ParseError(HttpParseError::UnknownTransferCoding));
+    }
+
+    Ok((body, trailers))
+}
+
+async fn decode_chunked_body<R>(stream: &mut R, max_chunk_size: usize, max_body_size: usize, spill_threshold: Option<usize>) -> Result<(BodyBuffer, HeaderMap), Error>
+        where R: AsyncBufReadExt + Unpin {
+    let mut body = BodyBuffer::Memory(Vec::new());
+
+    loop {
+        let chunk_size = read_chunk_size(stream, max_chunk_size).await?;
+        if chunk_size == 0 {
+            break;
+        }
+
+        if body.len() + chunk_size > max_body_size {
+            return Err(Error::ParseError(HttpParseError::BodyTooLarge));
+        }
+
+        body.read_from(stream, chunk_size, spill_threshold).await?;
+        consume_crlf(stream, LineEndingHandling::Strict).await?;
+    }
+
+    // The trailer section has the same syntax as the header section, and is
+    // terminated by an empty line as well.
+    let trailers = read_headers(stream, LineEndingHandling::Strict).await?;
+    Ok((body, trailers))
+}
+
+/// Whether the field is allowed in the trailer section. Fields that determine
+/// the framing, routing or handling of the request must be in the header
+/// section, since these would otherwise be processed too late.
+///
+/// ### References
+/// * [RFC 9110 Section 6.5.1](https://www.rfc-editor.org/rfc/rfc9110.html#section-6.5.1)
+fn is_allowed_in_trailer_section(name: &HeaderName) -> bool {
+    !matches!(name,
+        HeaderName::Authorization
+        | HeaderName::CacheControl
+        | HeaderName::Connection
+        | HeaderName::ContentEncoding
+        | HeaderName::ContentLength
+        | HeaderName::ContentRange
+        | HeaderName::ContentType
+        | HeaderName::Expect
+        | HeaderName::Host
+        | HeaderName::IfMatch
+        | HeaderName::IfModifiedSince
+        | HeaderName::IfNoneMatch
+        | HeaderName::IfRange
+        | HeaderName::IfUnmodifiedSince
+        | HeaderName::MaxForwards
+        | HeaderName::ProxyAuthorization
+        | HeaderName::Range
+        | HeaderName::TE
+        | HeaderName::Trailer
+        | HeaderName::TransferEncoding
+    )
+}
+
+/// Merge the fields of the trailer section into the headers of the request,
+/// like the HTTP/2 implementation does. Fields that aren't allowed in the
+/// trailer section are discarded.
+fn merge_trailers(request: &mut Request, trailers: HeaderMap) -> Result<(), Error> {
+    for (name, value) in trailers {
+        if is_allowed_in_trailer_section(&name) {
+            request.headers.append(name, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the chunk-size line of a chunk, and returns the size of the chunk.
+/// Chunk extensions are ignored, since we don't understand any of them.
+///
+/// ### References
+/// * [RFC 9112 Section 7.1](https://www.rfc-editor.org/rfc/rfc9112.html#name-chunked-transfer-coding)
+async fn read_chunk_size<R>(stream: &mut R, max_chunk_size: usize) -> Result<usize, Error>
         where R: AsyncBufReadExt + Unpin {
-    // TODO: support chunked encoding
-    Err(Error::Other(io::Error::new(io::ErrorKind::InvalidData, "TODO: support chunked encoding")))
+    let line = read_crlf_line(stream, MaximumLength::CHUNK_SIZE_LINE, LineEndingHandling::Strict).await?;
+    Ok(parse_chunk_size(&line, max_chunk_size)?)
+}
+
+/// Parses the chunk-size line of a chunk, excluding the CRLF.
+pub(crate) fn parse_chunk_size(line: &str, max_chunk_size: usize) -> Result<usize, HttpParseError> {
+    // chunk-size [ chunk-ext ], where chunk-ext = *( BWS ";" BWS chunk-ext-name [ BWS "=" BWS chunk-ext-val ] )
+    let size = match line.split_once(';') {
+        Some((size, _extensions)) => size.trim_end_matches([' ', '\t']),
+        None => line,
+    };
+
+    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(HttpParseError::InvalidChunkSize);
+    }
+
+    // All digits are hexadecimal, so the only way for parsing to fail is an
+    // overflow, which is definitely too large.
+    match usize::from_str_radix(size, 16) {
+        Ok(size) if size <= max_chunk_size => Ok(size),
+        _ => Err(HttpParseError::ChunkTooLarge),
+    }
 }
 
 /// Read the request-line and headers from the stream, without reading the body.
@@ -211,19 +589,37 @@ async fn read_request_body_chunked<R>(_stream: &mut R) -> Result<BodyKind, Error
 /// We do not read the body here, because the handler might prefer to use their
 /// own method of reading the body. This is especially useful for streaming
 /// data, directly reading and writing without buffering, etc.
-pub(crate) async fn read_request_excluding_body<R>(stream: &mut R) -> Result<Request, Error>
+///
+/// When the connection is closed halfway the request, i.e. before the empty
+/// line terminating the header section, the request is rejected with
+/// [`HttpParseError::IncompleteRequest`], so the client gets a `400` instead
+/// of a connection that is closed without a response.
+pub(crate) async fn read_request_excluding_body<R>(stream: &mut R, line_endings: LineEndingHandling) -> Result<Request, Error>
         where R: AsyncBufReadExt + Unpin {
-    let (method, target, version) = read_request_line(stream).await?;
-    let headers = if version == HttpVersion::Http2 {
-        HeaderMap::new()
-    } else {
-        read_headers(stream).await?
+    if stream.fill_buf().await?.is_empty() {
+        return Err(Error::Other(io::Error::new(io::ErrorKind::UnexpectedEof, "EOF")));
+    }
+
+    let request = async {
+        let (method, target, version) = read_request_line(stream, line_endings).await?;
+        let headers = if version == HttpVersion::Http2 {
+            HeaderMap::new()
+        } else {
+            read_headers(stream, line_endings).await?
+        };
+        Ok(Request { method, target, version, headers, body: None, raw_body: None })
     };
-    Ok(Request { method, target, version, headers, body: None })
+
+    match request.await {
+        Err(Error::Other(error)) if error.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(Error::ParseError(HttpParseError::IncompleteRequest))
+        }
+        result => result,
+    }
 }
 
 /// Read the request-line from the stream.
-async fn read_request_line<R>(stream: &mut R) -> Result<(Method, RequestTarget, HttpVersion), Error>
+async fn read_request_line<R>(stream: &mut R, line_endings: LineEndingHandling) -> Result<(Method, RequestTarget, HttpVersion), Error>
         where R: AsyncBufReadExt + Unpin {
 
     let method = Method::from(read_string_until_character(stream, b' ', MaximumLength::METHOD, HttpParseError::MethodTooLarge,
@@ -232,7 +628,7 @@ async fn read_request_line<R>(stream: &mut R) -> Result<(Method, RequestTarget,
     let target = read_request_target(stream).await?;
     let version = read_http_version(stream).await?;
 
-    consume_crlf(stream).await?;
+    consume_crlf(stream, line_endings).await?;
 
     Ok((method, target, version))
 }
@@ -262,7 +658,7 @@ mod tests {
     #[tokio::test]
     async fn read_request_line_normal() {
         let mut stream = std::io::Cursor::new(b"GET / HTTP/1.1\r\n");
-        let request_line = super::read_request_line(&mut stream).await.unwrap();
+        let request_line = super::read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap();
         assert_eq!(request_line.0, Method::Get);
         assert_eq!(request_line.1 , RequestTarget::Origin { path: "/".to_string(), query: String::new() });
         assert_eq!(request_line.2, HttpVersion::Http11);
@@ -279,15 +675,30 @@ mod tests {
     #[tokio::test]
     async fn read_request_line_methods(#[case] input: &[u8], #[case] expected: Method) {
         let mut stream = std::io::Cursor::new(input);
-        let request_line = super::read_request_line(&mut stream).await.unwrap();
+        let request_line = super::read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap();
         assert_eq!(request_line.0, expected);
         assert_eq!(request_line.2, HttpVersion::Http11);
     }
 
+    #[rstest]
+    #[case(b"GET / HTTP/0.9\r\n", HttpParseError::UnsupportedVersion { major: 0, minor: 9 })]
+    #[case(b"GET / HTTP/3.0\r\n", HttpParseError::UnsupportedVersion { major: 3, minor: 0 })]
+    #[case(b"GET / HTTP/9.9\r\n", HttpParseError::UnsupportedVersion { major: 9, minor: 9 })]
+    #[case(b"GET / HTTP/1.x\r\n", HttpParseError::InvalidHttpVersion)]
+    #[case(b"GET / HTTP/11.\r\n", HttpParseError::InvalidHttpVersion)]
+    #[tokio::test]
+    async fn read_request_line_unsupported_versions(#[case] input: &[u8], #[case] expected: HttpParseError) {
+        let mut stream = std::io::Cursor::new(input);
+        match super::read_request_line(&mut stream, LineEndingHandling::Strict).await {
+            Err(Error::ParseError(error)) => assert_eq!(error, expected),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
     #[tokio::test]
     async fn read_request_line_long_method() {
         let mut stream = std::io::Cursor::new(b"THIS-IS-A-VERY-LONG-METHOD / HTTP/1.1\r\n");
-        let request_line = super::read_request_line(&mut stream).await;
+        let request_line = super::read_request_line(&mut stream, LineEndingHandling::Strict).await;
         assert!(request_line.is_err());
         let error = request_line.err().unwrap();
         match &error {
@@ -296,14 +707,329 @@ mod tests {
         }
     }
 
+    fn create_request_with_content_length(content_length: usize) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.set_content_length(content_length);
+        Request {
+            method: Method::Post,
+            target: RequestTarget::Origin { path: "/".to_string(), query: String::new() },
+            version: HttpVersion::Http11,
+            headers,
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    fn create_settings(excess_body_data: ExcessBodyDataHandling) -> ServenteSettings {
+        let mut settings = ServenteSettings::new(servente_http_handling::handler::HandlerController::new());
+        settings.excess_body_data = excess_body_data;
+        settings
+    }
+
+    #[tokio::test]
+    async fn read_request_body_exact_length() {
+        let mut stream = std::io::Cursor::new(b"abcdef");
+        let mut request = create_request_with_content_length(6);
+        read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::Reject)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abcdef"));
+    }
+
+    #[tokio::test]
+    async fn read_request_body_under_length() {
+        let mut stream = std::io::Cursor::new(b"abc");
+        let mut request = create_request_with_content_length(6);
+        let result = read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::NextRequest)).await;
+        match result {
+            Err(Error::Other(error)) => assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof),
+            _ => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_request_body_over_length_next_request() {
+        let mut stream = std::io::Cursor::new(b"abcGET / HTTP/1.1\r\n");
+        let mut request = create_request_with_content_length(3);
+        read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::NextRequest)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abc"));
+
+        let request_line = read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap();
+        assert_eq!(request_line.0, Method::Get);
+    }
+
+    #[tokio::test]
+    async fn read_request_body_over_length_reject() {
+        let mut stream = std::io::Cursor::new(b"abcdef");
+        let mut request = create_request_with_content_length(3);
+        let result = read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::Reject)).await;
+        assert!(matches!(result, Err(Error::ParseError(HttpParseError::BodyExceedsContentLength))));
+    }
+
+    #[tokio::test]
+    async fn read_request_body_over_length_reject_only_checks_buffered_data() {
+        // The buffer is bypassed for a read as large as its capacity, so the
+        // excess data is still in the stream after the body is read.
+        let mut stream = tokio::io::BufReader::with_capacity(3, std::io::Cursor::new(b"abcdef"));
+        let mut request = create_request_with_content_length(3);
+        read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::Reject)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abc"));
+    }
+
+    fn create_request_with_transfer_encoding(transfer_encoding: &'static str) -> Request {
+        let mut request = create_request_with_content_length(3);
+        request.headers.append_or_override(HeaderName::TransferEncoding, transfer_encoding.into());
+        request
+    }
+
+    #[rstest]
+    #[case("chunked, gzip", HttpParseError::InvalidTransferEncoding)]
+    #[case("gzip", HttpParseError::InvalidTransferEncoding)]
+    #[case("chunked, chunked", HttpParseError::InvalidTransferEncoding)]
+    #[case("br, chunked", HttpParseError::UnknownTransferCoding)]
+    #[tokio::test]
+    async fn read_request_body_invalid_transfer_encoding(#[case] transfer_encoding: &'static str, #[case] expected: HttpParseError) {
+        let mut stream = std::io::Cursor::new(b"abc");
+        let mut request = create_request_with_transfer_encoding(transfer_encoding);
+        let result = read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::NextRequest)).await;
+        match result {
+            Err(Error::ParseError(error)) => assert_eq!(error, expected),
+            _ => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_request_body_transfer_encoding_overrides_content_length() {
+        let mut stream = std::io::Cursor::new(b"abc");
+        let mut request = create_request_with_transfer_encoding("gzip, chunked");
+        let result = read_request_body(&mut stream, &mut request, &create_settings(ExcessBodyDataHandling::NextRequest)).await;
+
+        // The chunked body isn't read as 3 bytes of Content-Length.
+        // Instead, "abc" is the chunk-size line, which is never terminated.
+        assert!(request.body.is_none());
+        assert!(matches!(result, Err(Error::ParseError(HttpParseError::IncompleteChunkedBody))), "Unexpected result: {:?}", result);
+    }
+
+    fn create_settings_with_limits(max_chunk_size: usize, max_request_body_size: usize) -> ServenteSettings {
+        let mut settings = create_settings(ExcessBodyDataHandling::NextRequest);
+        settings.max_chunk_size = max_chunk_size;
+        settings.max_request_body_size = max_request_body_size;
+        settings
+    }
+
+    #[tokio::test]
+    async fn read_request_body_chunked_multiple_chunks() {
+        let mut stream = std::io::Cursor::new(b"3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\nGET / HTTP/1.1\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        read_request_body(&mut stream, &mut request, &create_settings_with_limits(10, 13)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abc0123456789"), "{:?}", request.body);
+
+        let request_line = read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap();
+        assert_eq!(request_line.0, Method::Get);
+    }
+
+    async fn read_file_body(body: Option<BodyKind>) -> Vec<u8> {
+        let Some(BodyKind::File { mut handle, metadata }) = body else {
+            panic!("the body should have been spilled to a file: {body:?}");
+        };
+
+        let mut contents = Vec::new();
+        handle.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(metadata.len(), contents.len() as u64);
+        contents
+    }
+
+    #[tokio::test]
+    async fn read_request_body_spill_to_disk() {
+        let mut settings = create_settings_with_limits(10, 13);
+        settings.request_body_spill_threshold = Some(4);
+
+        let mut stream = std::io::Cursor::new(b"abcdefGET / HTTP/1.1\r\n");
+        let mut request = create_request_with_content_length(6);
+        read_request_body(&mut stream, &mut request, &settings).await.unwrap();
+        assert_eq!(read_file_body(request.body).await, b"abcdef");
+        assert_eq!(read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap().0, Method::Get);
+
+        let mut stream = std::io::Cursor::new(b"3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        read_request_body(&mut stream, &mut request, &settings).await.unwrap();
+        assert_eq!(read_file_body(request.body).await, b"abc0123456789");
+
+        // Within the threshold, the body stays in memory.
+        let mut stream = std::io::Cursor::new(b"abcd");
+        let mut request = create_request_with_content_length(4);
+        read_request_body(&mut stream, &mut request, &settings).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abcd"), "{:?}", request.body);
+
+        let mut stream = std::io::Cursor::new(b"abc");
+        let mut request = create_request_with_content_length(6);
+        let result = read_request_body(&mut stream, &mut request, &settings).await;
+        assert!(matches!(result, Err(Error::Other(ref error)) if error.kind() == io::ErrorKind::UnexpectedEof), "{result:?}");
+
+        let mut stream = std::io::Cursor::new(b"A\r\n0123456789\r\n5\r\nabcde\r\n0\r\n\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        let result = read_request_body(&mut stream, &mut request, &settings).await;
+        assert!(matches!(result, Err(Error::ParseError(HttpParseError::BodyTooLarge))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn read_request_body_chunked_extensions_are_ignored() {
+        let mut stream = std::io::Cursor::new(b"3;name=value\r\nabc\r\n2 ; a ; b=\"c;d\"\r\nde\r\n0;last\r\n\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        read_request_body(&mut stream, &mut request, &create_settings_with_limits(10, 13)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abcde"), "{:?}", request.body);
+    }
+
+    #[tokio::test]
+    async fn read_request_body_chunked_trailer_section() {
+        let mut stream = std::io::Cursor::new(b"3\r\nabc\r\n0\r\nServer-Timing: db;dur=53\r\nContent-Length: 100\r\n\r\nGET / HTTP/1.1\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        read_request_body(&mut stream, &mut request, &create_settings_with_limits(10, 13)).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abc"), "{:?}", request.body);
+
+        assert_eq!(request.headers.get(&HeaderName::ServerTiming).map(|value| value.as_str_may_convert().to_string()), Some("db;dur=53".to_string()));
+
+        // Framing fields aren't allowed in the trailer section.
+        assert!(request.headers.get_all(&HeaderName::ContentLength).all(|value| value.parse_number() == Some(3)));
+
+        let request_line = read_request_line(&mut stream, LineEndingHandling::Strict).await.unwrap();
+        assert_eq!(request_line.0, Method::Get);
+    }
+
+    #[rstest]
+    #[case(b"B\r\n0123456789A\r\n0\r\n\r\n", HttpParseError::ChunkTooLarge)]
+    #[case(b"FFFFFFFFFFFFFFFFFFFFFFFF\r\n", HttpParseError::ChunkTooLarge)]
+    #[case(b"A\r\n0123456789\r\n4\r\nabcd\r\n0\r\n\r\n", HttpParseError::BodyTooLarge)]
+    #[case(b"0x3\r\nabc\r\n0\r\n\r\n", HttpParseError::InvalidChunkSize)]
+    #[case(b"\r\nabc\r\n0\r\n\r\n", HttpParseError::InvalidChunkSize)]
+    #[case(b";ext\r\nabc\r\n0\r\n\r\n", HttpParseError::InvalidChunkSize)]
+    #[case(b"3\r\nabc\r\n0\r\n", HttpParseError::IncompleteChunkedBody)]
+    #[case(b"3\r\nabc\r\n0\r\nExpires: 0\r\n", HttpParseError::IncompleteChunkedBody)]
+    #[case(b"3\r\nab", HttpParseError::IncompleteChunkedBody)]
+    #[case(b"3\r\nabcdef", HttpParseError::InvalidCRLF)]
+    #[tokio::test]
+    async fn read_request_body_chunked_limits(#[case] input: &[u8], #[case] expected: HttpParseError) {
+        let mut stream = std::io::Cursor::new(input);
+        let mut request = create_request_with_transfer_encoding("chunked");
+        let result = read_request_body(&mut stream, &mut request, &create_settings_with_limits(10, 13)).await;
+        match result {
+            Err(Error::ParseError(error)) => assert_eq!(error, expected),
+            _ => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_request_body_per_route_limit() {
+        fn handle_upload(_: &Request) -> Result<servente_http::Response, anyhow::Error> {
+            unreachable!()
+        }
+
+        let mut settings = create_settings_with_limits(10, 4);
+        settings.handler_controller.register_post("/".into(), &handle_upload);
+        settings.handler_controller.set_max_body_size("/", Method::Post, 13);
+
+        // The handler of the route allows a larger body than the settings.
+        let mut stream = std::io::Cursor::new(b"abcdef");
+        let mut request = create_request_with_content_length(6);
+        read_request_body(&mut stream, &mut request, &settings).await.unwrap();
+        assert!(matches!(request.body, Some(BodyKind::Bytes(ref body)) if body == b"abcdef"));
+
+        // The limit applies to the decoded size of all the chunks together.
+        let mut stream = std::io::Cursor::new(b"A\r\n0123456789\r\n4\r\nabcd\r\n0\r\n\r\n");
+        let mut request = create_request_with_transfer_encoding("chunked");
+        let result = read_request_body(&mut stream, &mut request, &settings).await;
+        assert!(matches!(result, Err(Error::ParseError(HttpParseError::BodyTooLarge))), "Unexpected result: {:?}", result);
+
+        // Other routes still use the limit of the settings.
+        let mut stream = std::io::Cursor::new(b"abcdef");
+        let mut request = create_request_with_content_length(6);
+        request.target = RequestTarget::Origin { path: "/other".to_string(), query: String::new() };
+        let result = read_request_body(&mut stream, &mut request, &settings).await;
+        assert!(matches!(result, Err(Error::ParseError(HttpParseError::BodyTooLarge))), "Unexpected result: {:?}", result);
+    }
+
+    fn create_request_with_content_encoding(body: &[u8], content_encoding: &'static str) -> Request {
+        let mut request = create_request_with_content_length(body.len());
+        request.headers.append_or_override(HeaderName::ContentEncoding, content_encoding.into());
+        request
+    }
+
+    #[tokio::test]
+    async fn read_request_body_content_encoding() {
+        fn handle_webhook(_: &Request) -> Result<servente_http::Response, anyhow::Error> {
+            unreachable!()
+        }
+
+        let mut settings = create_settings_with_limits(10, 100);
+        settings.handler_controller.register_with_raw_body("/".into(), &handle_webhook, Method::Post);
+
+        let plain = b"Hello Hello Hello Hello Hello!".to_vec();
+        let encoded = ContentCoding::Gzip.encode(&ContentCoding::Brotli.encode(&plain).unwrap()).unwrap();
+
+        let mut stream = std::io::Cursor::new(encoded.clone());
+        let mut request = create_request_with_content_encoding(&encoded, "br, GZIP");
+        request.headers.append_or_override(HeaderName::ContentType, "text/plain".into());
+        read_request_body(&mut stream, &mut request, &settings).await.unwrap();
+
+        assert!(matches!(request.body, Some(BodyKind::String(ref body)) if body.as_bytes() == plain), "{:?}", request.body);
+        assert_eq!(request.raw_body, Some(encoded));
+        assert!(!request.headers.contains(&HeaderName::ContentEncoding));
+        assert_eq!(request.headers.get(&HeaderName::ContentLength).and_then(|value| value.parse_number()), Some(plain.len()));
+    }
+
+    #[rstest]
+    #[case(b"not gzip".to_vec(), "gzip", HttpParseError::InvalidContentEncoding)]
+    #[case(ContentCoding::Gzip.encode(&vec![b'a'; 101]).unwrap(), "gzip", HttpParseError::BodyTooLarge)]
+    #[case(b"abc".to_vec(), "deflate", HttpParseError::UnknownContentCoding)]
+    #[tokio::test]
+    async fn read_request_body_content_encoding_errors(#[case] body: Vec<u8>, #[case] content_encoding: &'static str, #[case] expected: HttpParseError) {
+        let mut request = create_request_with_content_encoding(&body, content_encoding);
+        let mut stream = std::io::Cursor::new(body);
+        let result = read_request_body(&mut stream, &mut request, &create_settings_with_limits(10, 100)).await;
+        match result {
+            Err(Error::ParseError(error)) => assert_eq!(error, expected),
+            _ => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
     #[cfg(feature = "http2")]
     #[tokio::test]
     async fn http2_upgrade_read_request_line() {
         let mut data = std::io::Cursor::new(HTTP2_CONNECTION_PREFACE);
-        let (method, request_target, version) = read_request_line(&mut data).await.unwrap();
+        let (method, request_target, version) = read_request_line(&mut data, LineEndingHandling::Strict).await.unwrap();
         assert_eq!(method, Method::Pri);
         assert_eq!(request_target, RequestTarget::Asterisk);
         assert_eq!(version, HttpVersion::Http2);
         assert_eq!(data.position() as usize, b"PRI * HTTP/2.0\r\n".len());
     }
+
+    #[rstest]
+    #[case(b"GET / HTTP/1.1\nHost: example.com\n\n", LineEndingHandling::Strict, Err(HttpParseError::InvalidCRLF))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\n\r\n", LineEndingHandling::Strict, Err(HttpParseError::InvalidCRLF))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\r\n\n", LineEndingHandling::Strict, Err(HttpParseError::InvalidCRLF))]
+    #[case(b"GET / HTTP/1.1\nHost: example.com\n\n", LineEndingHandling::Lenient, Ok(()))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\n\r\n", LineEndingHandling::Lenient, Ok(()))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", LineEndingHandling::Lenient, Ok(()))]
+    #[case(b"GET / HTTP/1.1\rHost: example.com\r\n\r\n", LineEndingHandling::Lenient, Err(HttpParseError::InvalidCRLF))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\rAccept: */*\r\n\r\n", LineEndingHandling::Lenient, Err(HttpParseError::InvalidCRLF))]
+    #[case(b"GET / HTTP/1.1\r\nHost: example.com\r\n", LineEndingHandling::Strict, Err(HttpParseError::IncompleteRequest))]
+    #[case(b"GET / HTTP/1.1\nHost: example.com\n", LineEndingHandling::Lenient, Err(HttpParseError::IncompleteRequest))]
+    #[case(b"GET / HTT", LineEndingHandling::Lenient, Err(HttpParseError::IncompleteRequest))]
+    #[tokio::test]
+    async fn read_request_excluding_body_line_endings(#[case] input: &[u8], #[case] line_endings: LineEndingHandling, #[case] expected: Result<(), HttpParseError>) {
+        let mut stream = std::io::Cursor::new(input);
+        match (read_request_excluding_body(&mut stream, line_endings).await, expected) {
+            (Ok(request), Ok(())) => {
+                assert_eq!(request.target, RequestTarget::parse("/").unwrap());
+                assert_eq!(request.headers.get(&HeaderName::Host).unwrap().as_str_no_convert(), Some("example.com"));
+                assert_eq!(stream.position() as usize, input.len());
+            }
+            (Err(Error::ParseError(error)), Err(expected)) => assert_eq!(error, expected),
+            (result, expected) => panic!("{result:?} isn't {expected:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_request_excluding_body_closed_connection() {
+        let result = read_request_excluding_body(&mut tokio::io::empty(), LineEndingHandling::Strict).await;
+        assert!(matches!(result, Err(Error::Other(error)) if error.kind() == io::ErrorKind::UnexpectedEof));
+    }
 }
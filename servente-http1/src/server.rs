@@ -0,0 +1,93 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! A builder for embedding the HTTP/1.1 server in an existing Tokio
+//! application.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+};
+
+use servente_http_handling::ReloadableConfig;
+use tokio::{
+    net::{TcpListener, ToSocketAddrs},
+    task::JoinHandle,
+};
+
+/// A listener that is bound, but doesn't have a configuration yet.
+pub struct ServerBuilder {
+    listener: TcpListener,
+}
+
+impl ServerBuilder {
+    /// Serve the connections using the `config`. When a [`ReloadableConfig`]
+    /// is passed, new connections use the configuration that is current when
+    /// they are accepted.
+    pub fn with_config(self, config: impl Into<ReloadableConfig>) -> Server {
+        Server {
+            listener: self.listener,
+            config: config.into(),
+        }
+    }
+
+    /// The address the listener is bound to, which is useful when binding to
+    /// port `0`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// An HTTP/1.1 server that is ready to accept connections.
+///
+/// ```no_run
+/// # async fn example(config: servente_http_handling::ServenteConfig) -> std::io::Result<()> {
+/// let (address, handle) = servente_http1::Server::bind("127.0.0.1:0").await?
+///     .with_config(config)
+///     .spawn()?;
+/// println!("Listening on {address}");
+/// handle.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    listener: TcpListener,
+    config: ReloadableConfig,
+}
+
+impl Server {
+    /// Bind a listener to the address. Connections are only accepted after
+    /// the server is started using [`Server::serve`] or [`Server::spawn`].
+    pub async fn bind(address: impl ToSocketAddrs) -> io::Result<ServerBuilder> {
+        Ok(ServerBuilder {
+            listener: TcpListener::bind(address).await?,
+        })
+    }
+
+    /// The address the listener is bound to, which is useful when binding to
+    /// port `0`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve connections in the current task, until an unrecoverable error
+    /// occurs.
+    pub async fn serve(self) -> io::Result<()> {
+        crate::serve(self.listener, self.config).await
+    }
+
+    /// Serve connections until `shutdown` resolves, see
+    /// [`crate::start_with_shutdown`].
+    pub async fn serve_until(self, shutdown: impl Future<Output = ()>) -> io::Result<usize> {
+        crate::serve_until(self.listener, self.config, shutdown).await
+    }
+
+    /// Serve connections in a new task. Returns the address the listener is
+    /// bound to, and the handle of the task. Aborting the task stops the
+    /// server.
+    pub fn spawn(self) -> io::Result<(SocketAddr, JoinHandle<io::Result<()>>)> {
+        let address = self.local_addr()?;
+        Ok((address, tokio::spawn(self.serve())))
+    }
+}
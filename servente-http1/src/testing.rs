@@ -0,0 +1,60 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Utilities for running a real server inside test suites and benchmarks.
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+use servente_http_handling::ServenteConfig;
+use tokio::task::JoinHandle;
+
+use crate::Server;
+
+/// A server running the full stack on an ephemeral port of the loopback
+/// interface, in the current Tokio runtime. The server stops when
+/// [`TestServer::shutdown`] is called or when it is dropped.
+///
+/// When TLS is enabled through the `rustls` or `tls-boring` features, clients
+/// must connect using TLS, as with the normal server.
+pub struct TestServer {
+    address: SocketAddr,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TestServer {
+    /// Bind to an ephemeral port and start serving using the `config`.
+    pub async fn start(config: ServenteConfig) -> io::Result<Self> {
+        let (address, handle) = Server::bind((Ipv4Addr::LOCALHOST, 0)).await?
+            .with_config(config)
+            .spawn()?;
+
+        Ok(Self {
+            address,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stop accepting connections, and wait for the listener to be closed.
+    pub async fn shutdown(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+            _ = handle.await;
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.abort();
+        }
+    }
+}
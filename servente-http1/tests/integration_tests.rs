@@ -5,6 +5,7 @@
 
 use std::{
     fs::DirBuilder,
+    net::SocketAddr,
     time::Duration,
     process::{Command, Output},
 };
@@ -28,34 +29,80 @@ fn setup_configuration() -> ServenteConfig {
             read_headers_timeout: Duration::from_secs(10),
             read_body_timeout: Duration::from_secs(10),
             middleware: Vec::new(),
+            canonical_host: Default::default(),
+            excess_body_data: Default::default(),
+            unknown_expectations: Default::default(),
+            line_endings: Default::default(),
+            log_tls_parameters: Default::default(),
+            dynamic_table_budget: Default::default(),
+            header_order: Default::default(),
+            body_transforms: Default::default(),
+            max_parts: servente_http_handling::config::DEFAULT_MAX_PARTS,
+            security_headers: Default::default(),
+            default_headers: Default::default(),
+            hsts: None,
+            request_body_spill_threshold: None,
+            http2_precomputed_header_blocks: false,
+            client_hints: Default::default(),
+            document_root: Default::default(),
+            document_source: Default::default(),
+            request_loggers: Default::default(),
+            redacted_headers: Default::default(),
+            max_chunk_size: servente_http_handling::config::DEFAULT_MAX_CHUNK_SIZE,
+            max_request_body_size: servente_http_handling::config::DEFAULT_MAX_REQUEST_BODY_SIZE,
+            tls_max_early_data_size: 0,
+            file_backend: Default::default(),
+            content_hash_etags: Default::default(),
+            http3_port: None,
+            http2_ping_interval: servente_http_handling::config::DEFAULT_HTTP2_PING_INTERVAL,
+            http2_max_unacknowledged_pings: servente_http_handling::config::DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS,
+            h2c_prior_knowledge: false,
+            client_connection_limit: None,
+            rewrite_rules: Default::default(),
+            maintenance_mode: Default::default(),
+            chunked_transfer_threshold: servente_http_handling::config::DEFAULT_CHUNKED_TRANSFER_THRESHOLD,
+            symlink_policy: Default::default(),
+            redirect_directories: false,
+            asset_fingerprints: Default::default(),
+            shutdown_grace_period: servente_http_handling::config::DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            rate_limiter: None,
+            request_validators: Default::default(),
+            max_keep_alive_requests: servente_http_handling::config::DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+            keep_alive_idle_timeout: servente_http_handling::config::DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT,
+            web_sockets: Default::default(),
+            index_files: vec![String::from("index.html")],
+            stats: Default::default(),
         })
 }
 
-async fn start_server_in_background() -> AbortHandle {
+async fn start_server_in_background() -> (SocketAddr, AbortHandle) {
     let config = setup_configuration();
-    tokio::task::spawn(async {
-        servente_http1::start("127.0.0.1:40626", config).await
-    }).abort_handle()
+    let (address, handle) = servente_http1::Server::bind("127.0.0.1:0").await.unwrap()
+        .with_config(config)
+        .spawn()
+        .unwrap();
+    (address, handle.abort_handle())
 }
 
 #[tokio::test]
 async fn test_curl_integration() {
-    start_server_in_background().await;
+    let (address, _) = start_server_in_background().await;
     tokio::task::yield_now().await;
 
     #[cfg(not(any(feature = "rustls", feature = "tls-boringssl")))]
-    const URL: &str = "http://localhost:40626/";
+    let url = format!("http://localhost:{}/", address.port());
 
     #[cfg(any(feature = "rustls", feature = "tls-boringssl"))]
-    const URL: &str = "https://localhost:40626/";
+    let url = format!("https://localhost:{}/", address.port());
 
-    async fn run_curl() -> Result<Output, tokio::time::error::Elapsed> {
-        let fut = tokio::task::spawn_blocking(|| {
+    async fn run_curl(url: &str) -> Result<Output, tokio::time::error::Elapsed> {
+        let url = url.to_string();
+        let fut = tokio::task::spawn_blocking(move || {
             Command::new("curl")
                 .arg("-k") // Insecure, since the certificate is self-signed
                 .arg("-i") // Include data
                 .arg("-v") // Verbose
-                .arg(URL)
+                .arg(url)
                 .spawn()
                 .expect("Failed to invoke curl")
                 .wait_with_output()
@@ -67,7 +114,7 @@ async fn test_curl_integration() {
         }).await
     }
 
-    let mut curl_output = run_curl().await;
+    let mut curl_output = run_curl(&url).await;
     let mut attempt = 0;
     while curl_output.is_err() || !curl_output.as_ref().unwrap().status.success() {
         attempt += 1;
@@ -77,7 +124,7 @@ async fn test_curl_integration() {
 
         sleep(Duration::from_secs(2)).await;
 
-        curl_output = run_curl().await;
+        curl_output = run_curl(&url).await;
     }
 
     let curl_output = curl_output.expect("cURL invocation timed out");
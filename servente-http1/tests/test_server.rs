@@ -0,0 +1,43 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! An example of using the `TestServer` fixture.
+
+#![cfg(not(any(feature = "rustls", feature = "tls-boring")))]
+
+use servente_http::{Request, Response, StatusCode};
+use servente_http_handling::{handler::HandlerController, ServenteConfig, ServenteSettings};
+use servente_http1::testing::TestServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+fn hello(_: &Request) -> Result<Response, anyhow::Error> {
+    Ok(Response::with_status_and_string_body(StatusCode::Ok, "Hello, world!"))
+}
+
+#[tokio::test]
+async fn test_server_serves_requests() {
+    let mut handler_controller = HandlerController::new();
+    handler_controller.register_get("/hello".into(), &hello);
+    let config = ServenteConfig::new()
+        .with_alpn_list(&["http/1.1"])
+        .build(ServenteSettings::new(handler_controller));
+
+    let server = TestServer::start(config).await.unwrap();
+    let address = server.address();
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    // Closing our side makes the server close the connection after responding.
+    stream.shutdown().await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nHello, world!"), "{response}");
+
+    server.shutdown().await;
+    assert!(TcpStream::connect(address).await.is_err());
+}
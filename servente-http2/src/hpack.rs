@@ -17,6 +17,9 @@ use std::{
 
 use tokio::sync::Mutex;
 
+use servente_http_handling::MemoryBudget;
+use servente_resources::{ContentEncodedVersions, PrecomputedHeaderBlock};
+
 use servente_http::{
     HeaderMap,
     HeaderMapInsertionError,
@@ -174,35 +177,92 @@ impl Compressor {
 
         _ = compress_status_code(&mut data, response.status);
         for (header_name, header_value) in response.headers.iter() {
-            // Connection-specific headers are a HTTP/1.1 feature, since future
-            // version of HTTP, including HTTP/2, manage the connection state in
-            // other ways.
-            // These headers include `Connection`, `Keep-Alive`, etc.
-            if header_name.class() == HeaderNameClass::ConnectionSpecific {
-                continue;
-            }
+            self.compress_header(&mut data, header_name, header_value);
+        }
 
-            let header_value_as_str = header_value.as_str_may_convert();
-            match self.find_header(header_name, header_value, &header_value_as_str) {
-                CompressIndexCandidate::None => {
-                    // Literal Header Field without Indexing — New Name
-                    data.push(0x00);
-                    _ = data.write_hpack_string_huffman(&header_name.to_string_lowercase());
-                    _ = data.write_hpack_string_huffman(&header_value_as_str);
-                }
-                CompressIndexCandidate::NameIndexed(index) => {
-                    // Literal Header Field without Indexing — Indexed Name
-                    _ = data.write_hpack_number(index, 4, 0);
-                    _ = data.write_hpack_string_huffman(&header_value_as_str);
-                }
-                CompressIndexCandidate::FullyIndexed(index) => {
-                    _ = data.write_hpack_number(index, 7, 0x80);
+        data
+    }
+
+    /// Compresses the response serving the cached `versions`, reusing the
+    /// header block of an earlier response with the same status and headers.
+    /// The `Date` header differs between most responses, so it is left out
+    /// of the precomputed block and compressed separately.
+    ///
+    /// Only the static table is used for compressing, so the block can be
+    /// shared between connections, regardless of their dynamic tables.
+    pub fn compress_with_precomputed(&mut self, response: &Response, versions: &ContentEncodedVersions) -> Vec<u8> {
+        let key = precomputed_header_block_key(response);
+        let precomputed = match versions.find_precomputed_header_block(&key) {
+            Some(precomputed) => precomputed.block.clone(),
+            None => {
+                let mut block = Vec::new();
+                _ = compress_status_code(&mut block, response.status);
+                for (header_name, header_value) in response.headers.iter() {
+                    if *header_name != HeaderName::Date {
+                        self.compress_header(&mut block, header_name, header_value);
+                    }
                 }
+
+                versions.store_precomputed_header_block(PrecomputedHeaderBlock { key, block: block.clone() });
+                block
             }
-        }
+        };
 
+        let mut data = precomputed;
+        for (header_name, header_value) in response.headers.iter() {
+            if *header_name == HeaderName::Date {
+                self.compress_header(&mut data, header_name, header_value);
+            }
+        }
         data
     }
+
+    fn compress_header(&self, data: &mut Vec<u8>, header_name: &HeaderName, header_value: &HeaderValue) {
+        // Connection-specific headers are a HTTP/1.1 feature, since future
+        // version of HTTP, including HTTP/2, manage the connection state in
+        // other ways.
+        // These headers include `Connection`, `Keep-Alive`, etc.
+        if header_name.class() == HeaderNameClass::ConnectionSpecific {
+            return;
+        }
+
+        let header_value_as_str = header_value.as_str_may_convert();
+        match self.find_header(header_name, header_value, &header_value_as_str) {
+            CompressIndexCandidate::None => {
+                // Literal Header Field without Indexing — New Name
+                data.push(0x00);
+                _ = data.write_hpack_string_huffman(&header_name.to_string_lowercase());
+                _ = data.write_hpack_string_huffman(&header_value_as_str);
+            }
+            CompressIndexCandidate::NameIndexed(index) => {
+                // Literal Header Field without Indexing — Indexed Name
+                _ = data.write_hpack_number(index, 4, 0);
+                _ = data.write_hpack_string_huffman(&header_value_as_str);
+            }
+            CompressIndexCandidate::FullyIndexed(index) => {
+                _ = data.write_hpack_number(index, 7, 0x80);
+            }
+        }
+    }
+}
+
+/// Identifies the status and the headers of the response the block of
+/// [`Compressor::compress_with_precomputed`] is computed from, i.e. every
+/// header except `Date`.
+fn precomputed_header_block_key(response: &Response) -> Vec<u8> {
+    let mut key = Vec::with_capacity(256);
+    key.extend_from_slice(&(response.status as u16).to_be_bytes());
+    for (header_name, header_value) in response.headers.iter() {
+        if *header_name == HeaderName::Date {
+            continue;
+        }
+
+        key.extend_from_slice(header_name.to_string_lowercase().as_bytes());
+        key.push(0);
+        key.extend_from_slice(header_value.as_str_may_convert().as_bytes());
+        key.push(0);
+    }
+    key
 }
 
 // TODO: some `error`s MUST be conveyed by a `COMPRESSION_ERROR`, and some MUST
@@ -269,6 +329,8 @@ pub enum DecompressionError {
 
     HeaderMapInsertionError(HeaderMapInsertionError),
     InvalidUtf8,
+
+    InsertError(DynamicTableInsertError),
 }
 
 impl From<DynamicTableLookupError> for DecompressionError {
@@ -277,6 +339,12 @@ impl From<DynamicTableLookupError> for DecompressionError {
     }
 }
 
+impl From<DynamicTableInsertError> for DecompressionError {
+    fn from(value: DynamicTableInsertError) -> Self {
+        Self::InsertError(value)
+    }
+}
+
 impl From<HeaderMapInsertionError> for DecompressionError {
     fn from(value: HeaderMapInsertionError) -> Self {
         Self::HeaderMapInsertionError(value)
@@ -288,15 +356,45 @@ pub struct DynamicTable {
     table: VecDeque<(DynamicTableEntry, usize)>,
     current_size: usize,
     max_size: usize,
+
+    /// The memory budget shared with the tables of other connections.
+    budget: Option<Arc<MemoryBudget>>,
 }
 
 impl DynamicTable {
     pub fn new(max_size: usize) -> Self {
+        Self::with_budget(max_size, None)
+    }
+
+    /// Create a table whose entries are accounted for in the given `budget`.
+    ///
+    /// The peer decides which entries are stored, and refers to them by their
+    /// index, so this table can't leave out or evict entries on its own
+    /// without decoding later fields as the wrong ones. When the budget is
+    /// exhausted, [`DynamicTable::insert`] fails instead, which ends the
+    /// connection with a `COMPRESSION_ERROR`, keeping the memory usage of all
+    /// connections combined bounded.
+    pub fn with_budget(max_size: usize, budget: Option<Arc<MemoryBudget>>) -> Self {
         Self {
             table: VecDeque::new(),
             current_size: 0,
             max_size,
+            budget,
+        }
+    }
+
+    /// Evict the oldest entry, returning `false` if the table was empty.
+    fn evict_oldest(&mut self) -> bool {
+        let Some((_, entry_size)) = self.table.pop_back() else {
+            return false;
+        };
+
+        self.current_size -= entry_size;
+        if let Some(budget) = &self.budget {
+            budget.release(entry_size);
         }
+
+        true
     }
 
     /// The static table and the dynamic table are combined into a single index address space.
@@ -362,33 +460,50 @@ impl DynamicTable {
         }
     }
 
-    pub fn insert(&mut self, entry: DynamicTableEntry) {
+    /// Insert the entry, evicting the oldest entries when the table would
+    /// exceed its maximum size.
+    ///
+    /// # References
+    /// * [RFC 7541 - Section 4.4](https://httpwg.org/specs/rfc7541.html#entry.addition)
+    pub fn insert(&mut self, entry: DynamicTableEntry) -> Result<(), DynamicTableInsertError> {
         let entry_size = entry.calculate_size();
         if self.max_size < entry_size {
-            self.table.clear();
-            return;
+            while self.evict_oldest() {}
+            return Ok(());
         }
 
         while self.current_size + entry_size > self.max_size {
-            let Some((_, last_entry_size)) = self.table.pop_back() else {
+            if !self.evict_oldest() {
                 // Table is empty, so the entry will never fit in the table.
-                return;
-            };
+                return Ok(());
+            }
+        }
 
-            self.current_size -= last_entry_size;
+        if let Some(budget) = &self.budget {
+            if !budget.try_reserve(entry_size) {
+                return Err(DynamicTableInsertError::BudgetExhausted);
+            }
         }
 
+        self.current_size += entry_size;
         self.table.push_front((entry, entry_size));
+        Ok(())
     }
 
     pub fn size_update(&mut self, size: usize) {
         while self.current_size > size {
-            let Some((_, last_entry_size)) = self.table.pop_back() else {
+            if !self.evict_oldest() {
                 // Table is empty, so it will never fit in the table.
                 return;
-            };
+            }
+        }
+    }
+}
 
-            self.current_size -= last_entry_size;
+impl Drop for DynamicTable {
+    fn drop(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.release(self.current_size);
         }
     }
 }
@@ -464,6 +579,13 @@ pub enum DynamicTableLookupError {
     PseudoHeaderStatus,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DynamicTableInsertError {
+    /// The memory budget shared with the other tables doesn't allow storing
+    /// the entry, see [`DynamicTable::with_budget`].
+    BudgetExhausted,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct HuffmanEntry {
     code: u32,
@@ -1004,7 +1126,8 @@ pub(super) async fn decode_hpack_header_section(request: super::HeadersInTransit
         target: RequestTarget::parse(path.as_ref().to_owned()).ok_or(DecompressionError::InvalidRequestTarget)?,
         version: HttpVersion::Http2,
         headers: sink.headers,
-        body: None
+        body: None,
+        raw_body: None,
     })
 }
 
@@ -1048,7 +1171,7 @@ async fn decode_hpack_sink<S>(mut request: super::HeadersInTransit, dynamic_tabl
                 let value = Arc::from(value);
                 let header = (HeaderName::from(name), HeaderValue::from(value));
                 validate_header(&header)?;
-                dynamic_table.insert(DynamicTableEntry::Header { name: header.0.clone(), value: header.1.clone() });
+                dynamic_table.insert(DynamicTableEntry::Header { name: header.0.clone(), value: header.1.clone() })?;
                 sink.add_header(header.0, header.1)?;
                 continue;
             }
@@ -1061,24 +1184,24 @@ async fn decode_hpack_sink<S>(mut request: super::HeadersInTransit, dynamic_tabl
             let value = request.read_string()?;
             match dynamic_table.get(index, Some(value))? {
                 DynamicTableEntry::Authority(val) => {
-                    dynamic_table.insert(DynamicTableEntry::Authority(val.clone()));
+                    dynamic_table.insert(DynamicTableEntry::Authority(val.clone()))?;
                     sink.set_authority(val)?;
                 },
                 DynamicTableEntry::Header { name, value } => {
-                    dynamic_table.insert(DynamicTableEntry::Header { name: name.clone(), value: value.clone() });
+                    dynamic_table.insert(DynamicTableEntry::Header { name: name.clone(), value: value.clone() })?;
                     sink.add_header(name, value)?;
                 }
                 DynamicTableEntry::Method(val) => {
-                    dynamic_table.insert(DynamicTableEntry::Method(val.clone()));
+                    dynamic_table.insert(DynamicTableEntry::Method(val.clone()))?;
                     sink.set_method(val)?;
                 }
                 DynamicTableEntry::Path(val) => {
                     validate_path(val.as_ref())?;
-                    dynamic_table.insert(DynamicTableEntry::Path(val.clone()));
+                    dynamic_table.insert(DynamicTableEntry::Path(val.clone()))?;
                     sink.set_path(val)?;
                 }
                 DynamicTableEntry::Scheme(val) => {
-                    dynamic_table.insert(DynamicTableEntry::Scheme(val.clone()));
+                    dynamic_table.insert(DynamicTableEntry::Scheme(val.clone()))?;
                     sink.set_scheme(val)?;
                 }
             }
@@ -1191,7 +1314,7 @@ async fn decode_hpack_sink<S>(mut request: super::HeadersInTransit, dynamic_tabl
                 sink.set_path(val)?;
             }
             DynamicTableEntry::Scheme(val) => {
-                dynamic_table.insert(DynamicTableEntry::Scheme(val.clone()));
+                dynamic_table.insert(DynamicTableEntry::Scheme(val.clone()))?;
                 sink.set_scheme(val)?;
             }
         }
@@ -1560,9 +1683,9 @@ mod tests {
 
     #[test]
     fn test_dynamic_table_some() {
-        let mut table = DynamicTable::new(47 + 47);
-        table.insert(DynamicTableEntry::Method(Method::Connect));
-        table.insert(DynamicTableEntry::Header { name: HeaderName::SecChUaMobile, value: "?0".into() });
+        let mut table = DynamicTable::new(46 + 50);
+        table.insert(DynamicTableEntry::Method(Method::Connect)).unwrap();
+        table.insert(DynamicTableEntry::Header { name: HeaderName::SecChUaMobile, value: "?0".into() }).unwrap();
 
         let first = table.get(STATIC_TABLE.len(), None).unwrap();
         if let DynamicTableEntry::Header { name, value } = first {
@@ -1578,20 +1701,54 @@ mod tests {
     #[test]
     fn test_dynamic_table_eviction() {
         let mut table = DynamicTable::new(44 * 2);
-        table.insert(DynamicTableEntry::Method(Method::Post));
-        table.insert(DynamicTableEntry::Method(Method::Pri));
-        table.insert(DynamicTableEntry::Method(Method::Put));
+        table.insert(DynamicTableEntry::Method(Method::Post)).unwrap();
+        table.insert(DynamicTableEntry::Method(Method::Pri)).unwrap();
+        table.insert(DynamicTableEntry::Method(Method::Put)).unwrap();
 
         assert_eq!(table.get(STATIC_TABLE.len(), None), Ok(DynamicTableEntry::Method(Method::Put)));
         assert_eq!(table.get(STATIC_TABLE.len() + 1, None), Ok(DynamicTableEntry::Method(Method::Pri)));
     }
 
+    #[test]
+    fn test_dynamic_table_global_budget() {
+        let entry = || DynamicTableEntry::Authority("www.example.com".into());
+        let entry_size = entry().calculate_size();
+
+        let budget = Arc::new(MemoryBudget::new(entry_size * 10));
+        let mut tables: Vec<_> = (0..100)
+            .map(|_| DynamicTable::with_budget(4096, Some(Arc::clone(&budget))))
+            .collect();
+
+        for table in &mut tables[..10] {
+            table.insert(entry()).unwrap();
+        }
+        assert_eq!(budget.used(), budget.limit());
+
+        // Neither an empty table nor a table that is using memory already may
+        // evict entries the peer still refers to.
+        for table in &mut tables[..11] {
+            let size = table.current_size;
+            assert_eq!(table.insert(DynamicTableEntry::Path("/index.html".into())), Err(DynamicTableInsertError::BudgetExhausted));
+            assert_eq!(table.current_size, size);
+        }
+        assert_eq!(tables[0].get(STATIC_TABLE.len(), None), Ok(entry()));
+        assert_eq!(budget.used(), budget.limit());
+
+        // Entries that are evicted by the peer free up the memory.
+        tables[0].size_update(0);
+        assert_eq!(tables[0].current_size, 0);
+        tables[10].insert(entry()).unwrap();
+
+        tables.clear();
+        assert_eq!(budget.used(), 0);
+    }
+
     #[test]
     fn test_dynamic_table_insert_max_0() {
         let mut table = DynamicTable::new(0);
         assert_eq!(table.get(STATIC_TABLE.len(), None), Err(DynamicTableLookupError::OutOfBounds));
 
-        table.insert(DynamicTableEntry::Authority("localhost".into()));
+        table.insert(DynamicTableEntry::Authority("localhost".into())).unwrap();
 
         assert_eq!(table.get(STATIC_TABLE.len(), None), Err(DynamicTableLookupError::OutOfBounds));
     }
@@ -1845,4 +2002,116 @@ mod tests {
         compress_status_code(&mut result, status_code).unwrap();
         assert_eq!(result.as_slice(), expected);
     }
+
+    #[tokio::test]
+    async fn test_compress_multiple_set_cookie() {
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append(HeaderName::SetCookie, "a=1".into()).unwrap();
+        response.headers.append(HeaderName::SetCookie, "b=2; Secure".into()).unwrap();
+
+        let data = Compressor::new().compress(&response);
+        assert_eq!(data[0], 0x88, ":status 200 should be fully indexed");
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::Asterisk,
+            version: HttpVersion::Http2,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        let headers = HeadersInTransit {
+            headers: vec![data[1..].to_vec()],
+            cursor: 0,
+        };
+        let dynamic_table = Arc::new(Mutex::new(DynamicTable::new(4096)));
+        decode_hpack_tailer_section(headers, dynamic_table, &mut request).await.unwrap();
+
+        let cookies: Vec<_> = request.headers.iter()
+            .map(|(name, value)| (name.clone(), value.as_str_may_convert().into_owned()))
+            .collect();
+        assert_eq!(cookies, [
+            (HeaderName::SetCookie, "a=1".to_string()),
+            (HeaderName::SetCookie, "b=2; Secure".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_refused_insert_fails_decoding() {
+        let budget = Arc::new(MemoryBudget::new(36));
+        let mut other = DynamicTable::with_budget(4096, Some(Arc::clone(&budget)));
+        other.insert(DynamicTableEntry::Header { name: HeaderName::Other("x-a".into()), value: "1".into() }).unwrap();
+
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::Asterisk,
+            version: HttpVersion::Http2,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+
+        // "x-b: 2" with incremental indexing, followed by a reference to it.
+        let headers = HeadersInTransit {
+            headers: vec![vec![0x40, 0x03, b'x', b'-', b'b', 0x01, b'2', 0xBE]],
+            cursor: 0,
+        };
+        let dynamic_table = Arc::new(Mutex::new(DynamicTable::with_budget(4096, Some(Arc::clone(&budget)))));
+        let result = decode_hpack_tailer_section(headers, dynamic_table, &mut request).await;
+        assert_eq!(result, Err(DecompressionError::InsertError(DynamicTableInsertError::BudgetExhausted)));
+    }
+
+    async fn decode_response_headers(data: &[u8]) -> Vec<(HeaderName, String)> {
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::Asterisk,
+            version: HttpVersion::Http2,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        let headers = HeadersInTransit {
+            headers: vec![data.to_vec()],
+            cursor: 0,
+        };
+        let dynamic_table = Arc::new(Mutex::new(DynamicTable::new(4096)));
+        decode_hpack_tailer_section(headers, dynamic_table, &mut request).await.unwrap();
+
+        let mut headers: Vec<_> = request.headers.iter()
+            .map(|(name, value)| (name.clone(), value.as_str_may_convert().into_owned()))
+            .collect();
+        headers.sort_by(|a, b| a.0.to_string_lowercase().cmp(&b.0.to_string_lowercase()));
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_precomputed() {
+        let versions = ContentEncodedVersions::default();
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.headers.append_or_override(HeaderName::Date, "Tue, 10 Oct 2023 13:55:36 GMT".into());
+        response.headers.append_or_override(HeaderName::ContentType, "text/css".into());
+        response.headers.append_or_override(HeaderName::CacheStatus, "ServenteCache; hit; detail=MEMORY".into());
+        response.headers.append_or_override(HeaderName::Connection, "keep-alive".into());
+
+        let mut compressor = Compressor::new();
+        let fresh = compressor.compress(&response);
+        let computed = compressor.compress_with_precomputed(&response, &versions);
+        assert_eq!(versions.precomputed_header_blocks.read().unwrap().len(), 1);
+
+        response.headers.append_or_override(HeaderName::Date, "Tue, 10 Oct 2023 13:55:37 GMT".into());
+        let reused = compressor.compress_with_precomputed(&response, &versions);
+        assert_eq!(versions.precomputed_header_blocks.read().unwrap().len(), 1);
+
+        assert_eq!(computed[0], 0x88);
+        assert_eq!(reused[0], 0x88);
+        let expected = decode_response_headers(&fresh[1..]).await;
+        assert_eq!(decode_response_headers(&computed[1..]).await, expected);
+        let mut expected = expected;
+        expected.iter_mut().find(|(name, _)| *name == HeaderName::Date).unwrap().1 = "Tue, 10 Oct 2023 13:55:37 GMT".into();
+        assert_eq!(decode_response_headers(&reused[1..]).await, expected);
+
+        response.headers.append_or_override(HeaderName::ContentType, "text/html".into());
+        compressor.compress_with_precomputed(&response, &versions);
+        assert_eq!(versions.precomputed_header_blocks.read().unwrap().len(), 2);
+    }
 }
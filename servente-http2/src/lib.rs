@@ -6,8 +6,11 @@ use std::{sync::Arc, time::{Instant, Duration}, fmt::Debug};
 use servente_http::{
     BodyKind,
     HeaderName,
+    Method,
     Request,
     Response,
+    StatusCode,
+    StatusCodeClass,
 };
 
 use tokio::{
@@ -27,7 +30,10 @@ use tokio::{
 #[cfg(feature = "rustls")]
 use tokio_rustls::server::TlsStream;
 
-use servente_http_handling::ServenteConfig;
+use servente_http_handling::{
+    ConnectionInfo,
+    ServenteConfig,
+};
 
 use self::hpack::DynamicTable;
 
@@ -43,6 +49,10 @@ type StreamType = TlsStream<TcpStream>;
 #[cfg(feature = "tls-boring")]
 type StreamType = tokio_boring::SslStream<TcpStream>;
 
+/// The opaque data of the `PING`s the server sends to check whether the client
+/// is still alive.
+const LIVENESS_PING_PAYLOAD: [u8; 8] = *b"servente";
+
 type Reader = BufReader<ReadHalf<StreamType>>;
 type Writer = BufWriter<WriteHalf<StreamType>>;
 
@@ -184,6 +194,7 @@ impl HeadersInTransit {
 
 struct ConcurrentContext {
     servente_config: Arc<ServenteConfig>,
+    connection_info: Arc<ConnectionInfo>,
     dynamic_table: Arc<Mutex<DynamicTable>>,
     receiver: tokio::sync::mpsc::Receiver<(StreamId, Result<Response, RequestError>)>,
     sender: tokio::sync::mpsc::Sender<(StreamId, Result<Response, RequestError>)>,
@@ -191,11 +202,14 @@ struct ConcurrentContext {
 }
 
 impl ConcurrentContext {
-    pub fn new(servente_config: Arc<ServenteConfig>) -> Self {
+    pub fn new(servente_config: Arc<ServenteConfig>, connection_info: Arc<ConnectionInfo>) -> Self {
         let (sender, receiver) = tokio::sync::mpsc::channel(100);
+        let dynamic_table = DynamicTable::with_budget(SettingKind::HeaderTableSize.default_value().0 as _,
+            servente_config.settings.dynamic_table_budget.clone());
         Self {
             servente_config,
-            dynamic_table: Arc::new(Mutex::new(DynamicTable::new(SettingKind::HeaderTableSize.default_value().0 as _))),
+            connection_info,
+            dynamic_table: Arc::new(Mutex::new(dynamic_table)),
             receiver,
             sender,
             requests: Default::default(),
@@ -263,14 +277,7 @@ impl Connection {
             ],
         }).await?;
 
-        let frame = self.read_frame().await?;
-        let Frame::Settings { settings } = frame else {
-            return Err(ConnectionError::ConnectionError {
-                error_code: ErrorCode::ProtocolError,
-                additional_debug_data: format!("Expected a SETTINGS frame to finish preface, but got a: {}", frame.frame_type())
-            });
-        };
-
+        let settings = read_preface_settings(&mut self.reader, self.settings.maximum_payload_size.0).await?;
         self.settings.apply(settings);
 
         self.streams.insert(StreamId::CONTROL, Stream {
@@ -430,47 +437,7 @@ impl Connection {
                     return Err(ConnectionError::ConnectionError { error_code: ErrorCode::FrameSizeError, additional_debug_data: "SETTINGS frame length should be a multiple of 6".to_string() })
                 }
 
-                let mut settings = Vec::with_capacity((payload_length / 6) as _);
-                for data in payload.chunks_exact(6) {
-                    let kind = u16::from_be_bytes([data[0], data[1]]);
-                    let value = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
-                    settings.push(match kind {
-                        SETTINGS_HEADER_TABLE_SIZE => (SettingKind::HeaderTableSize, SettingValue(value)),
-                        SETTINGS_ENABLE_PUSH => {
-                            if value != 0 && value != 1 {
-                                return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "ENABLE_PUSH invalid value: neither 0 nor 1".to_string() });
-                            }
-                            (SettingKind::EnablePush, SettingValue(value))
-                        }
-                        SETTINGS_MAX_CONCURRENT_STREAMS => (SettingKind::MaxConcurrentStreams, SettingValue(value)),
-                        SETTINGS_INITIAL_WINDOW_SIZE => {
-                            if value > MAXIMUM_FLOW_CONTROL_WINDOW_SIZE {
-                                return Err(ConnectionError::ConnectionError { error_code: ErrorCode::FlowControlError, additional_debug_data: "Maximum flow-control window size exceeded".to_string() });
-                            }
-                            (SettingKind::InitialWindowSize, SettingValue(value))
-                        }
-                        SETTINGS_MAX_FRAME_SIZE => {
-                            if value < SettingKind::MaxFrameSize.default_value().0 {
-                                return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "Maximum allowed frame size less than the initial frame size".into() });
-                            }
-                            if value > MAXIMUM_ALLOWED_FRAME_SIZE {
-                                return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "Maximum allowed frame size exceeded".into() });
-                            }
-                            (SettingKind::MaxFrameSize, SettingValue(value))
-                        }
-                        SETTINGS_MAX_HEADER_LIST_SIZE => (SettingKind::MaxHeaderListSize, SettingValue(value)),
-                        SETTINGS_ENABLE_CONNECT_PROTOCOL => (SettingKind::SettingsEnableConnectProtocol, SettingValue(value)),
-                        SETTINGS_NO_RFC7540_PRIORITIES => (SettingKind::SettingsNoRfc7540Priorities, SettingValue(value)),
-                        SETTINGS_TLS_RENEG_PERMITTED => (SettingKind::TlsRenegotiationPermitted, SettingValue(value)),
-                        _ => {
-                            #[cfg(feature = "debugging")]
-                            println!("[HTTP/2] [Settings] Received unknown setting of type {} with value {}", kind, value);
-                            continue;
-                        }
-                    })
-                }
-
-                Ok(Frame::Settings { settings })
+                Ok(Frame::Settings { settings: parse_settings(&payload)? })
             }
 
             // [RFC 9113 - Section 6.6](https://httpwg.org/specs/rfc9113.html#PUSH_PROMISE)
@@ -574,24 +541,18 @@ impl Connection {
     }
 
     async fn send_response(&mut self, stream_id: StreamId, mut response: Response) -> Result<(), ConnectionError> {
-        let content_length = if let Some(body) = &response.body {
-            match body {
-                BodyKind::Bytes(data) => data.len(),
-                BodyKind::CachedBytes(versions, coding) => {
-                    versions.get_version(*coding).len()
-                }
-                BodyKind::File{ metadata, .. } => metadata.len() as usize,
-                BodyKind::String(data) => data.len(),
-                BodyKind::StaticString(str) => str.len(),
-            }
-        } else {
-            0
-        };
+        let content_length = response.body.as_ref().map_or(Some(0), body_length);
 
-        response.headers.append_or_override(HeaderName::ContentLength, content_length.into());
+        // Responses to HEAD have no body, but keep the Content-Length of the
+        // representation.
+        if let Some(content_length) = content_length {
+            if response.body.is_some() || !response.headers.contains(&HeaderName::ContentLength) {
+                response.headers.append_or_override(HeaderName::ContentLength, content_length.into());
+            }
+        }
 
-        let payload = self.header_compressor.compress(&response);
-        self.send_frame(Frame::Headers { end_headers: true, end_stream: content_length == 0, stream_id, payload }).await?;
+        send_response_headers(&mut self.writer, &mut self.header_compressor, stream_id, &mut response, content_length == Some(0),
+            self.servente_config.settings.http2_precomputed_header_blocks).await?;
 
         if let Some(body) = response.body {
             match body {
@@ -616,6 +577,29 @@ impl Connection {
                     // We are allowed to send an empty DATA frame with END_STREAM set.
                     self.send_frame(Frame::Data { end_stream: true, stream_id, payload: Vec::new() }).await?;
                 }
+                BodyKind::Shared(bytes) => {
+                    self.send_data_frame_from_slice(stream_id, (*bytes).as_ref()).await?;
+                }
+                BodyKind::Stream(mut body) => loop {
+                    match body.next().await {
+                        Ok(Some(data)) => {
+                            for chunk in data.chunks(self.settings.maximum_payload_size.0 as _) {
+                                self.send_frame(Frame::Data { end_stream: false, stream_id, payload: Vec::from(chunk) }).await?;
+                            }
+                            self.writer.flush().await?;
+                        }
+                        Ok(None) => {
+                            self.send_frame(Frame::Data { end_stream: true, stream_id, payload: Vec::new() }).await?;
+                            break;
+                        }
+                        // The body is incomplete, which the client must not
+                        // mistake for the complete body.
+                        Err(_) => {
+                            self.send_frame(Frame::ResetStream { stream_id, error_code: ErrorCode::InternalError }).await?;
+                            break;
+                        }
+                    }
+                },
                 BodyKind::String(data) => {
                     self.send_data_frame_from_slice(stream_id, data.as_bytes()).await?;
                 }
@@ -637,6 +621,107 @@ impl Connection {
     }
 }
 
+/// The length of the body in bytes, as sent in DATA frames. The length of a
+/// streaming body isn't known upfront.
+fn body_length(body: &BodyKind) -> Option<usize> {
+    Some(match body {
+        BodyKind::Bytes(data) => data.len(),
+        BodyKind::CachedBytes(versions, coding) => {
+            versions.get_version(*coding).len()
+        }
+        BodyKind::File{ metadata, .. } => metadata.len() as usize,
+        BodyKind::Shared(bytes) => (**bytes).as_ref().len(),
+        BodyKind::Stream(..) => return None,
+        BodyKind::String(data) => data.len(),
+        BodyKind::StaticString(str) => str.len(),
+    })
+}
+
+/// Parses the payload of a SETTINGS frame, of which the length is a multiple
+/// of 6. Unknown settings are ignored.
+///
+/// ### References
+/// * [RFC 9113 - Section 6.5.1](https://www.rfc-editor.org/rfc/rfc9113.html#name-settings-format)
+fn parse_settings(payload: &[u8]) -> Result<Vec<(SettingKind, SettingValue)>, ConnectionError> {
+    let mut settings = Vec::with_capacity(payload.len() / 6);
+    for data in payload.chunks_exact(6) {
+        let kind = u16::from_be_bytes([data[0], data[1]]);
+        let value = u32::from_be_bytes([data[2], data[3], data[4], data[5]]);
+        settings.push(match kind {
+            SETTINGS_HEADER_TABLE_SIZE => (SettingKind::HeaderTableSize, SettingValue(value)),
+            SETTINGS_ENABLE_PUSH => {
+                if value != 0 && value != 1 {
+                    return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "ENABLE_PUSH invalid value: neither 0 nor 1".to_string() });
+                }
+                (SettingKind::EnablePush, SettingValue(value))
+            }
+            SETTINGS_MAX_CONCURRENT_STREAMS => (SettingKind::MaxConcurrentStreams, SettingValue(value)),
+            SETTINGS_INITIAL_WINDOW_SIZE => {
+                if value > MAXIMUM_FLOW_CONTROL_WINDOW_SIZE {
+                    return Err(ConnectionError::ConnectionError { error_code: ErrorCode::FlowControlError, additional_debug_data: "Maximum flow-control window size exceeded".to_string() });
+                }
+                (SettingKind::InitialWindowSize, SettingValue(value))
+            }
+            SETTINGS_MAX_FRAME_SIZE => {
+                if value < SettingKind::MaxFrameSize.default_value().0 {
+                    return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "Maximum allowed frame size less than the initial frame size".into() });
+                }
+                if value > MAXIMUM_ALLOWED_FRAME_SIZE {
+                    return Err(ConnectionError::ConnectionError { error_code: ErrorCode::ProtocolError, additional_debug_data: "Maximum allowed frame size exceeded".into() });
+                }
+                (SettingKind::MaxFrameSize, SettingValue(value))
+            }
+            SETTINGS_MAX_HEADER_LIST_SIZE => (SettingKind::MaxHeaderListSize, SettingValue(value)),
+            SETTINGS_ENABLE_CONNECT_PROTOCOL => (SettingKind::SettingsEnableConnectProtocol, SettingValue(value)),
+            SETTINGS_NO_RFC7540_PRIORITIES => (SettingKind::SettingsNoRfc7540Priorities, SettingValue(value)),
+            SETTINGS_TLS_RENEG_PERMITTED => (SettingKind::TlsRenegotiationPermitted, SettingValue(value)),
+            _ => {
+                #[cfg(feature = "debugging")]
+                println!("[HTTP/2] [Settings] Received unknown setting of type {} with value {}", kind, value);
+                continue;
+            }
+        })
+    }
+
+    Ok(settings)
+}
+
+/// Reads the SETTINGS frame that the client must send first, after the
+/// connection preface string. The frame header is validated before the
+/// payload is read, so e.g. an oversized or unexpected frame is rejected
+/// without buffering or parsing it.
+///
+/// ### References
+/// * [RFC 9113 - Section 3.4](https://www.rfc-editor.org/rfc/rfc9113.html#section-3.4-6)
+async fn read_preface_settings<R>(reader: &mut R, maximum_payload_size: u32) -> Result<Vec<(SettingKind, SettingValue)>, ConnectionError>
+        where R: AsyncReadExt + Unpin {
+    let mut header = [0; 9];
+    reader.read_exact(&mut header).await?;
+    let payload_length = bits::convert_be_u24_to_u32([header[0], header[1], header[2]]);
+    let frame_type = header[3];
+    let flags = header[4];
+    let stream_id = StreamId(u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF);
+
+    if frame_type != FRAME_TYPE_SETTINGS || flags & 0x01 == 0x01 || stream_id != StreamId::CONTROL {
+        return Err(ConnectionError::ConnectionError {
+            error_code: ErrorCode::ProtocolError,
+            additional_debug_data: format!("Expected a SETTINGS frame to finish preface, but got a frame of type {frame_type:#04x}"),
+        });
+    }
+
+    if payload_length > maximum_payload_size {
+        return Err(ConnectionError::ConnectionError { error_code: ErrorCode::FrameSizeError, additional_debug_data: String::from("Frame larger than maximum payload size") });
+    }
+
+    if !payload_length.is_multiple_of(6) {
+        return Err(ConnectionError::ConnectionError { error_code: ErrorCode::FrameSizeError, additional_debug_data: "SETTINGS frame length should be a multiple of 6".to_string() });
+    }
+
+    let mut payload = vec![0; payload_length as usize];
+    reader.read_exact(&mut payload).await?;
+    parse_settings(&payload)
+}
+
 #[derive(Debug)]
 enum ConnectionError {
     /// The connection is closed.
@@ -855,6 +940,34 @@ async fn send_frame_header<T>(writer: &mut T, frame_type: u8, flags: u8, stream:
     Ok(())
 }
 
+/// Sends the `HEADERS` of the response, preceded by a `HEADERS` frame for
+/// each of the informational (1xx) responses in the prelude, e.g. `103 Early
+/// Hints`. Clients must accept any number of those before the final
+/// response, and ignore the ones they don't understand. `101 Switching
+/// Protocols` doesn't exist in HTTP/2, so it is never sent.
+///
+/// # References
+/// * [RFC 9113 Section 8.1](https://www.rfc-editor.org/rfc/rfc9113.html#name-http-message-framing)
+/// * [RFC 8297](https://www.rfc-editor.org/rfc/rfc8297.html)
+async fn send_response_headers<T>(writer: &mut T, compressor: &mut hpack::Compressor, stream_id: StreamId, response: &mut Response,
+        end_stream: bool, precomputed_header_blocks: bool) -> Result<(), ConnectionError>
+        where T: AsyncWriteExt + Unpin {
+    for prelude_response in std::mem::take(&mut response.prelude_response) {
+        if prelude_response.status.class() != StatusCodeClass::Informational || prelude_response.status == StatusCode::SwitchingProtocols {
+            continue;
+        }
+
+        let payload = compressor.compress(&prelude_response);
+        send_frame(writer, Frame::Headers { end_headers: true, end_stream: false, stream_id, payload }).await?;
+    }
+
+    let payload = match &response.body {
+        Some(BodyKind::CachedBytes(versions, _)) if precomputed_header_blocks => compressor.compress_with_precomputed(response, versions),
+        _ => compressor.compress(response),
+    };
+    send_frame(writer, Frame::Headers { end_headers: true, end_stream, stream_id, payload }).await
+}
+
 async fn send_frame<T>(writer: &mut T, frame: Frame) -> Result<(), ConnectionError>
         where T: AsyncWriteExt + Unpin {
     let flags = frame.flags();
@@ -1024,6 +1137,22 @@ impl StreamId {
     pub const CONTROL: StreamId = StreamId(0);
 }
 
+/// The GOAWAY to send when the connection preface couldn't be completed, or
+/// `None` when the connection is already unusable.
+fn preface_goaway(error: ConnectionError) -> Option<Frame> {
+    let (error_code, additional_debug_data) = match error {
+        ConnectionError::ConnectionError { error_code, additional_debug_data } => (error_code, additional_debug_data),
+        ConnectionError::StreamError { error_code, .. } => (error_code, "Stream Error on preface completion".to_owned()),
+        ConnectionError::Closed | ConnectionError::Io(_) => return None,
+    };
+
+    Some(Frame::GoAway {
+        last_stream_id: StreamId::CONTROL,
+        error_code,
+        additional_debug_data,
+    })
+}
+
 /// Entrypoint of the client connection.
 ///
 /// Returning from this function means the connection should/has been closed.
@@ -1031,34 +1160,19 @@ impl StreamId {
 /// ### HTTP/1.1
 /// When upgraded from HTTP/1.1, this is after the PRI preface stuff, but no
 /// frames are read yet.
-pub async fn handle_client(reader: Reader, writer: Writer, servente_config: Arc<ServenteConfig>) {
+pub async fn handle_client(reader: Reader, writer: Writer, servente_config: Arc<ServenteConfig>, connection_info: ConnectionInfo) {
     let mut connection = Connection::new(reader, writer, servente_config);
 
     if let Err(e) = connection.complete_preface().await {
         #[cfg(feature = "debugging")]
         println!("[HTTP/2] [Preface] Failed to complete preface: {:#?}", e);
-        match e {
-            ConnectionError::ConnectionError { error_code, additional_debug_data } => {
-                _ = connection.send_frame(Frame::GoAway {
-                    last_stream_id: StreamId::CONTROL,
-                    error_code,
-                    additional_debug_data,
-                }).await;
-            }
-            ConnectionError::StreamError { error_code, .. } => {
-                _ = connection.send_frame(Frame::GoAway {
-                    last_stream_id: StreamId::CONTROL,
-                    error_code,
-                    additional_debug_data: "Stream Error on preface completion".to_owned(),
-                }).await;
-            }
-            ConnectionError::Closed => return,
-            ConnectionError::Io(_) => (),
-        };
+        if let Some(frame) = preface_goaway(e) {
+            _ = connection.send_frame_with_flush(frame).await;
+        }
         return;
     }
 
-    let mut concurrent_context = ConcurrentContext::new(Arc::clone(&connection.servente_config));
+    let mut concurrent_context = ConcurrentContext::new(Arc::clone(&connection.servente_config), Arc::new(connection_info));
 
     loop {
         if let Err(e) = handle_client_inner(&mut connection, &mut concurrent_context).await {
@@ -1100,14 +1214,17 @@ pub async fn handle_client(reader: Reader, writer: Writer, servente_config: Arc<
 }
 
 async fn handle_client_inner(connection: &mut Connection, concurrent_context: &mut ConcurrentContext) -> Result<(), ConnectionError> {
+    let ping_interval = connection.servente_config.settings.http2_ping_interval;
     loop {
         tokio::select! {
-            _ = tokio::time::sleep(Duration::from_secs(3)) => {
-                if connection.pings_queued_up > 2 {
-                    connection.send_frame_with_flush(Frame::GoAway { last_stream_id: StreamId::CONTROL, error_code: ErrorCode::NoError, additional_debug_data: String::from("Bye!") }).await?;
+            // A half-open connection never acknowledges our PINGs, so close
+            // the connection when too many are outstanding.
+            _ = tokio::time::sleep(ping_interval.unwrap_or(Duration::ZERO)), if ping_interval.is_some() => {
+                if connection.pings_queued_up >= connection.servente_config.settings.http2_max_unacknowledged_pings {
+                    connection.send_frame_with_flush(Frame::GoAway { last_stream_id: connection.last_stream_id, error_code: ErrorCode::NoError, additional_debug_data: String::from("Bye!") }).await?;
                     return Err(ConnectionError::Closed);
                 }
-                connection.send_frame_with_flush(Frame::Ping { ack: false, payload: *b"servente" }).await?;
+                connection.send_frame_with_flush(Frame::Ping { ack: false, payload: LIVENESS_PING_PAYLOAD }).await?;
                 connection.pings_queued_up += 1;
             }
 
@@ -1242,8 +1359,9 @@ async fn handle_frame(connection: &mut Connection, frame: Frame, concurrent_cont
                     ack: true,
                     payload
                 }).await?;
-            } else {
+            } else if payload == LIVENESS_PING_PAYLOAD {
                 connection.last_ping_acknowledged = Some(Instant::now());
+                connection.pings_queued_up = 0;
             }
         }
 
@@ -1346,17 +1464,18 @@ fn handle_request_invoke_to_background(connection: &mut Connection, concurrent_c
         state: StreamState::HalfClosedRemote,
     });
 
-    concurrent_context.requests.insert(stream_id, tokio::spawn(handle_request(request_in_transit, concurrent_context.sender.clone(), Arc::clone(&concurrent_context.dynamic_table), Arc::clone(&concurrent_context.servente_config))));
+    concurrent_context.requests.insert(stream_id, tokio::spawn(handle_request(request_in_transit, concurrent_context.sender.clone(), Arc::clone(&concurrent_context.dynamic_table), Arc::clone(&concurrent_context.servente_config), Arc::clone(&concurrent_context.connection_info))));
 }
 
 async fn handle_request(request_in_transit: RequestInTransit, sender: tokio::sync::mpsc::Sender<(StreamId, Result<Response, RequestError>)>,
-        dynamic_table: Arc<Mutex<DynamicTable>>, config: Arc<ServenteConfig>) {
+        dynamic_table: Arc<Mutex<DynamicTable>>, config: Arc<ServenteConfig>, connection_info: Arc<ConnectionInfo>) {
     let stream_id = request_in_transit.stream_id;
-    let result = handle_request_inner(request_in_transit, dynamic_table, config).await;
+    let result = handle_request_inner(request_in_transit, dynamic_table, config, connection_info).await;
     _ = sender.send((stream_id, result)).await;
 }
 
-async fn handle_request_inner(mut request_in_transit: RequestInTransit, dynamic_table: Arc<Mutex<DynamicTable>>, config: Arc<ServenteConfig>) -> Result<Response, RequestError> {
+async fn handle_request_inner(mut request_in_transit: RequestInTransit, dynamic_table: Arc<Mutex<DynamicTable>>, config: Arc<ServenteConfig>,
+        connection_info: Arc<ConnectionInfo>) -> Result<Response, RequestError> {
     let data = std::mem::take(&mut request_in_transit.body);
 
     let mut request = request_in_transit.headers.decode(Arc::clone(&dynamic_table)).await?;
@@ -1372,8 +1491,21 @@ async fn handle_request_inner(mut request_in_transit: RequestInTransit, dynamic_
             }
         }
     }
-    let mut response = servente_http_handling::handle_request(&request, &config.settings).await;
-    servente_http_handling::finish_response_normal(&request, &mut response).await;
+    let start = std::time::Instant::now();
+    let mut response = servente_http_handling::handle_request(&mut request, &config.settings, &connection_info).await;
+    servente_http_handling::finish_response_normal(&request, &mut response, &config.settings).await;
+    servente_http_handling::finish_response_strict_transport_security(&mut response, &config.settings, &connection_info);
+    config.settings.stats.record(servente_http_handling::stats::ExchangeRecord::new(&response), start.elapsed());
+
+    // The response is the same as for GET, but the body is omitted.
+    //
+    // # References
+    // * [RFC 9110 Section 9.3.2](https://www.rfc-editor.org/rfc/rfc9110.html#name-head)
+    if request.method == Method::Head {
+        if let Some(content_length) = response.body.take().as_ref().and_then(body_length) {
+            response.headers.append_or_override(HeaderName::ContentLength, content_length.into());
+        }
+    }
     Ok(response)
 }
 
@@ -1381,6 +1513,35 @@ async fn handle_request_inner(mut request_in_transit: RequestInTransit, dynamic_
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_send_response_headers_early_hints() {
+        let mut early_hints = Response::with_status(StatusCode::EarlyHints);
+        early_hints.headers.append(HeaderName::Link, "</style.css>; rel=preload; as=style".into()).unwrap();
+
+        let mut response = Response::with_status(StatusCode::Ok);
+        response.prelude_response = vec![Response::with_status(StatusCode::SwitchingProtocols), early_hints];
+
+        let mut buf = Vec::new();
+        send_response_headers(&mut buf, &mut hpack::Compressor::new(), StreamId(1), &mut response, true, false).await.unwrap();
+        assert!(response.prelude_response.is_empty());
+
+        // The 103 isn't in the static table, so it is a literal with the
+        // `:status` name, of which the Huffman-encoded value is 2 bytes.
+        let length = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize;
+        assert_eq!(buf[3], FRAME_TYPE_HEADERS);
+        assert_eq!(buf[4], 0b0000_0100, "the 103 must not end the stream");
+        assert_eq!(buf[5..9], 1_u32.to_be_bytes());
+        assert_eq!(buf[9..13], [0x08, 0x82, 0x08, 0x19]);
+
+        let final_headers = &buf[9 + length..];
+        assert_eq!(final_headers[..3], [0, 0, 1]);
+        assert_eq!(final_headers[3], FRAME_TYPE_HEADERS);
+        assert_eq!(final_headers[4], 0b0000_0101);
+        assert_eq!(final_headers[5..9], 1_u32.to_be_bytes());
+        assert_eq!(final_headers[9], 0x88, ":status 200 should be fully indexed");
+        assert_eq!(final_headers.len(), 10);
+    }
+
     #[tokio::test]
     async fn test_send_frame() {
         let frame = Frame::Headers { end_headers: true, end_stream: true, stream_id: StreamId(1), payload: vec![0xDE] };
@@ -1397,4 +1558,32 @@ mod tests {
         assert_eq!(buf[5..9], [0x00, 0x00, 0x00, 0x01], "Stream ID incorrect");
         assert_eq!(buf[9], 0xDE, "Incorrect payload");
     }
+
+    #[tokio::test]
+    async fn test_read_preface_settings() {
+        const MAXIMUM_PAYLOAD_SIZE: u32 = 16_384;
+        let cases: [(&str, &[u8], Option<ErrorCode>); 7] = [
+            ("empty SETTINGS", &[0, 0, 0, FRAME_TYPE_SETTINGS, 0, 0, 0, 0, 0], None),
+            ("SETTINGS", &[0, 0, 6, FRAME_TYPE_SETTINGS, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0], None),
+            ("oversized", &[0, 0x40, 0x02, FRAME_TYPE_SETTINGS, 0, 0, 0, 0, 0], Some(ErrorCode::FrameSizeError)),
+            ("partial setting", &[0, 0, 5, FRAME_TYPE_SETTINGS, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0], Some(ErrorCode::FrameSizeError)),
+            ("invalid ENABLE_PUSH", &[0, 0, 6, FRAME_TYPE_SETTINGS, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 2], Some(ErrorCode::ProtocolError)),
+            ("acknowledgement", &[0, 0, 0, FRAME_TYPE_SETTINGS, 1, 0, 0, 0, 0], Some(ErrorCode::ProtocolError)),
+            ("HEADERS", &[0, 0, 1, FRAME_TYPE_HEADERS, 5, 0, 0, 0, 1, 0x82], Some(ErrorCode::ProtocolError)),
+        ];
+
+        for (name, mut data, expected) in cases {
+            let result = read_preface_settings(&mut data, MAXIMUM_PAYLOAD_SIZE).await;
+            let Some(expected) = expected else {
+                assert!(result.is_ok(), "{name}: {result:?}");
+                continue;
+            };
+
+            let goaway = preface_goaway(result.unwrap_err()).unwrap();
+            let mut buf = Vec::new();
+            send_frame(&mut buf, goaway).await.unwrap();
+            assert_eq!(buf[3], FRAME_TYPE_GOAWAY, "{name}");
+            assert_eq!(buf[13..17], (expected as u32).to_be_bytes(), "{name}");
+        }
+    }
 }
@@ -46,9 +46,9 @@ async fn handle_request(send_stream: SendStream, recv_stream: RecvStream) -> io:
     Ok(())
 }
 
-pub async fn start(tls_config: Arc<rustls::ServerConfig>) -> io::Result<()> {
+pub async fn start(tls_config: Arc<rustls::ServerConfig>, port: u16) -> io::Result<()> {
     let config = quinn::ServerConfig::with_crypto(tls_config);
-    let endpoint = quinn::Endpoint::server(config, SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080))
+    let endpoint = quinn::Endpoint::server(config, SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
         .unwrap();
 
     while let Some(connection) = endpoint.accept().await {
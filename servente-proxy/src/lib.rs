@@ -0,0 +1,651 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The `servente-proxy` crate implements a **reverse proxy** for the Servente
+//! webserver, which forwards the requests below configured path prefixes to
+//! upstream HTTP/1.1 servers.
+//!
+//! # References
+//! * [RFC 9110 Section 7.6](https://www.rfc-editor.org/rfc/rfc9110.html#name-message-forwarding)
+
+use std::{io, time::Duration};
+
+use async_trait::async_trait;
+
+use servente_http::{
+    streaming::{BodySink, StreamingBody},
+    BodyKind,
+    HeaderMap,
+    HeaderName,
+    HeaderNameClass,
+    Method,
+    Request,
+    RequestTarget,
+    Response,
+    StatusCode,
+};
+
+use servente_http_handling::{
+    config::{LineEndingHandling, DEFAULT_MAX_CHUNK_SIZE},
+    middleware::{
+        ExchangeState,
+        MiddlewareError,
+    },
+    ConnectionInfo,
+    Middleware,
+};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// The time the upstream server has to accept the connection and send the
+/// head of the response, and the maximum time between two reads of the body.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum length of the status line of the upstream response.
+const MAX_STATUS_LINE_LENGTH: u64 = 8 * 1024;
+
+/// The size of the reads of the upstream response body.
+const BODY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The characters besides the alphanumeric ones that are forwarded in a path
+/// as-is, i.e. the `pchar`s and the `/`. Others are percent-encoded.
+///
+/// # References
+/// * [RFC 3986 Section 3.3](https://www.rfc-editor.org/rfc/rfc3986.html#section-3.3)
+const PATH_CHARACTERS: &[u8] = b"-._~!$&'()*+,;=:@/";
+
+/// The middleware that forwards the requests below the configured path
+/// prefixes to the upstream servers, and responds with the response of the
+/// upstream server. The body of that response is streamed to the client while
+/// it is being received.
+///
+/// The requests are forwarded before they would be handled, so the handlers
+/// and the file server aren't consulted for these. The prefixes are matched
+/// against the percent-decoded and normalized path, like the file server
+/// does, so `/%61pi/` and `/public/../api/` are both forwarded to `/api/`.
+/// The upstream server gets this normalized path as well.
+///
+/// A new connection is opened for every request, over which the request is
+/// forwarded with `Connection: close`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyMiddleware {
+    routes: Vec<ProxyRoute>,
+    timeout: Duration,
+}
+
+/// The requests of which the path starts with the `prefix` are forwarded to
+/// the `upstream` server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ProxyRoute {
+    /// The prefix of the request path, e.g. `/api/`.
+    prefix: String,
+
+    /// The `host:port` of the upstream server, e.g. `127.0.0.1:3000`.
+    upstream: String,
+}
+
+/// How the end of the body of the upstream response is determined.
+///
+/// # References
+/// * [RFC 9112 Section 6.3](https://www.rfc-editor.org/rfc/rfc9112.html#name-message-body-length)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BodyFraming {
+    /// The response doesn't have a body, e.g. a `304 Not Modified`.
+    None,
+    Chunked,
+    ContentLength(u64),
+
+    /// The body ends when the upstream server closes the connection.
+    Close,
+}
+
+impl Default for ProxyMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProxyMiddleware {
+    /// Create the middleware without any routes, see
+    /// [`ProxyMiddleware::with_route`].
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Forward the requests below the `prefix`, e.g. `/api/`, to the
+    /// `upstream` server at `host:port`. The first matching route is used.
+    pub fn with_route(mut self, prefix: impl Into<String>, upstream: impl Into<String>) -> Self {
+        self.routes.push(ProxyRoute {
+            prefix: prefix.into(),
+            upstream: upstream.into(),
+        });
+        self
+    }
+
+    /// Set the timeout, see [`DEFAULT_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn find_route(&self, request: &Request) -> Option<&ProxyRoute> {
+        let RequestTarget::Origin { path, .. } = &request.target else {
+            return None;
+        };
+
+        // Paths that escape the root are rejected by the file server.
+        let path = servente_http_handling::normalize_request_path(path)?;
+        self.routes.iter().find(|route| path.starts_with(route.prefix.as_bytes()))
+    }
+
+    /// The entrypoint for the proxy middleware. Failing to reach the upstream
+    /// server results in a `502 Bad Gateway`, and the upstream server not
+    /// responding in time in a `504 Gateway Timeout`.
+    async fn proxy(&self, request: &Request, connection: &ConnectionInfo) -> Option<Response> {
+        let route = self.find_route(request)?;
+
+        let forwarded = forward_request(&route.upstream, request, connection, self.timeout);
+        Some(match tokio::time::timeout(self.timeout, forwarded).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                if cfg!(debug_assertions) {
+                    Response::with_status_and_string_body(StatusCode::BadGateway, format!("Error: {e}"))
+                } else {
+                    Response::with_status_and_string_body(StatusCode::BadGateway, "Bad Gateway")
+                }
+            }
+            Err(_) => Response::with_status_and_string_body(StatusCode::GatewayTimeout, "Gateway Timeout"),
+        })
+    }
+}
+
+#[async_trait]
+impl Middleware for ProxyMiddleware {
+    fn debug_identifier(&self) -> &str {
+        "servente_proxy::ProxyMiddleware"
+    }
+
+    async fn invoke(&mut self, _state: &mut ExchangeState) -> Result<(), MiddlewareError> {
+        Ok(())
+    }
+
+    async fn respond(&self, request: &Request, connection: &ConnectionInfo) -> Option<Response> {
+        self.proxy(request, connection).await
+    }
+}
+
+/// Forwards the request to the upstream server, and reads the head of the
+/// response. The body is streamed afterwards.
+async fn forward_request(upstream: &str, request: &Request, connection: &ConnectionInfo, timeout: Duration) -> io::Result<Response> {
+    let mut stream = TcpStream::connect(upstream).await?;
+    stream.set_nodelay(true)?;
+
+    let body_length = match &request.body {
+        None => None,
+        Some(BodyKind::File { metadata, .. }) => Some(metadata.len()),
+        Some(BodyKind::Stream(..)) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "streamed request bodies can't be forwarded"));
+        }
+        Some(body) => request_body_bytes(body).map(|data| data.len() as u64),
+    };
+
+    stream.write_all(serialize_request_head(request, connection, body_length).as_bytes()).await?;
+    match &request.body {
+        // The body was spilled to a temporary file, which is streamed instead
+        // of read in memory.
+        Some(BodyKind::File { handle, .. }) => {
+            let mut file = handle.try_clone().await?;
+            file.rewind().await?;
+            tokio::io::copy(&mut file, &mut stream).await?;
+        }
+        Some(body) => {
+            if let Some(data) = request_body_bytes(body) {
+                stream.write_all(data).await?;
+            }
+        }
+        None => (),
+    }
+    stream.flush().await?;
+
+    let mut stream = BufReader::new(stream);
+    let (status, mut headers) = loop {
+        let status = read_status_line(&mut stream).await?;
+        let headers = servente_http1::read::read_headers(&mut stream, LineEndingHandling::Lenient).await
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+
+        // Interim responses, e.g. `103 Early Hints`, aren't forwarded.
+        if !(100..200).contains(&status) {
+            break (status, headers);
+        }
+    };
+
+    let framing = determine_body_framing(request, status, &headers)?;
+    remove_hop_by_hop_fields(&mut headers);
+    headers.remove(&HeaderName::ContentLength);
+
+    // The upstream server might use codes that aren't known, which are
+    // treated as the `x00` code of their class.
+    let status = StatusCode::from_u16(status)
+        .or_else(|| StatusCode::from_u16(status / 100 * 100))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid status code {status}")))?;
+
+    let mut response = Response::with_status(status);
+    response.headers = headers;
+    response.body = stream_body(stream, framing, timeout);
+    Ok(response)
+}
+
+/// The bytes of a request body that is kept in memory.
+fn request_body_bytes(body: &BodyKind) -> Option<&[u8]> {
+    Some(match body {
+        BodyKind::Bytes(data) => data,
+        BodyKind::CachedBytes(versions, coding) => versions.get_version(*coding),
+        BodyKind::Shared(data) => (**data).as_ref(),
+        BodyKind::StaticString(data) => data.as_bytes(),
+        BodyKind::String(data) => data.as_bytes(),
+        BodyKind::File { .. } | BodyKind::Stream(..) => return None,
+    })
+}
+
+/// The path that is forwarded to the upstream server, which is the
+/// normalized path the route was matched against, see
+/// [`servente_http_handling::normalize_request_path`].
+fn forwarded_path(path: &str) -> String {
+    let Some(normalized) = servente_http_handling::normalize_request_path(path) else {
+        return path.to_string();
+    };
+
+    let mut encoded = String::with_capacity(normalized.len());
+    for byte in normalized {
+        if byte.is_ascii_alphanumeric() || PATH_CHARACTERS.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// Serializes the request line and the header section of the forwarded
+/// request. The hop-by-hop fields are left out, and the `X-Forwarded-For`
+/// and `X-Forwarded-Proto` fields describe the client.
+fn serialize_request_head(request: &Request, connection: &ConnectionInfo, body_length: Option<u64>) -> String {
+    let target = match &request.target {
+        RequestTarget::Origin { path, query } if !query.is_empty() => format!("{}?{query}", forwarded_path(path)),
+        RequestTarget::Origin { path, .. } => forwarded_path(path),
+        target => target.as_str().to_string(),
+    };
+
+    let mut head = format!("{} {target} HTTP/1.1\r\n", request.method.as_string());
+
+    let mut headers = request.headers.clone();
+    remove_hop_by_hop_fields(&mut headers);
+    headers.remove(&HeaderName::ContentLength);
+    headers.remove(&HeaderName::XForwaredFor);
+    headers.remove(&HeaderName::XForwardedProto);
+
+    for (name, value) in headers.iter() {
+        head += &format!("{}: {}\r\n", name.to_string_h1(), value.as_str_may_convert());
+    }
+
+    // The address of the client is appended to the list of the proxies the
+    // request passed through already.
+    let forwarded_for = request.headers.get(&HeaderName::XForwaredFor)
+        .map(|value| value.as_str_may_convert().into_owned());
+    let forwarded_for = match (forwarded_for, connection.peer_address) {
+        (Some(forwarded_for), Some(peer_address)) => Some(format!("{forwarded_for}, {}", peer_address.ip())),
+        (None, Some(peer_address)) => Some(peer_address.ip().to_string()),
+        (forwarded_for, None) => forwarded_for,
+    };
+    if let Some(forwarded_for) = forwarded_for {
+        head += &format!("X-Forwarded-For: {forwarded_for}\r\n");
+    }
+    head += &format!("X-Forwarded-Proto: {}\r\n", connection.scheme.as_str());
+
+    if let Some(body_length) = body_length {
+        head += &format!("Content-Length: {body_length}\r\n");
+    }
+
+    head += "Connection: close\r\n\r\n";
+    head
+}
+
+/// Removes the fields that only apply to a single connection, which are
+/// `Connection`, `Keep-Alive`, `TE`, `Transfer-Encoding` and `Upgrade`, and
+/// the fields the `Connection` field names.
+///
+/// # References
+/// * [RFC 9110 Section 7.6.1](https://www.rfc-editor.org/rfc/rfc9110.html#name-connection)
+fn remove_hop_by_hop_fields(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers.get_all(&HeaderName::Connection)
+        .flat_map(|value| value.as_str_may_convert()
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>())
+        .map(HeaderName::from)
+        .collect();
+
+    let connection_specific: Vec<HeaderName> = headers.iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.class() == HeaderNameClass::ConnectionSpecific)
+        .cloned()
+        .collect();
+
+    for name in listed.iter().chain(connection_specific.iter()) {
+        headers.remove(name);
+    }
+}
+
+/// Reads the status line, e.g. `HTTP/1.1 200 OK`, and returns the status
+/// code. The reason phrase is ignored.
+async fn read_status_line<R>(stream: &mut R) -> io::Result<u16>
+        where R: AsyncBufRead + Unpin {
+    let mut line = Vec::new();
+    (&mut *stream).take(MAX_STATUS_LINE_LENGTH).read_until(b'\n', &mut line).await?;
+    parse_status_line(&line)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid status line"))
+}
+
+/// # References
+/// * [RFC 9112 Section 4](https://www.rfc-editor.org/rfc/rfc9112.html#name-status-line)
+fn parse_status_line(line: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(line).ok()?.strip_suffix('\n')?;
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    let (version, rest) = line.split_once(' ')?;
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return None;
+    }
+
+    let code = rest.split(' ').next()?;
+    if code.len() != 3 || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    code.parse().ok().filter(|code| (100..600).contains(code))
+}
+
+/// # References
+/// * [RFC 9112 Section 6.3](https://www.rfc-editor.org/rfc/rfc9112.html#name-message-body-length)
+fn determine_body_framing(request: &Request, status: u16, headers: &HeaderMap) -> io::Result<BodyFraming> {
+    if request.method == Method::Head || status == 204 || status == 304 {
+        return Ok(BodyFraming::None);
+    }
+
+    if let Some(transfer_encoding) = headers.get(&HeaderName::TransferEncoding) {
+        let transfer_encoding = transfer_encoding.as_str_may_convert();
+        let is_chunked = transfer_encoding.rsplit(',')
+            .next()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+
+        // Without chunked as the final coding, the body ends when the
+        // connection is closed.
+        return Ok(if is_chunked { BodyFraming::Chunked } else { BodyFraming::Close });
+    }
+
+    match headers.get(&HeaderName::ContentLength) {
+        Some(content_length) => content_length.as_str_may_convert().trim().parse()
+            .map(BodyFraming::ContentLength)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")),
+        None => Ok(BodyFraming::Close),
+    }
+}
+
+fn stream_body(mut stream: BufReader<TcpStream>, framing: BodyFraming, timeout: Duration) -> Option<BodyKind> {
+    if framing == BodyFraming::None {
+        return None;
+    }
+
+    Some(BodyKind::Stream(StreamingBody::new(move |mut sink| async move {
+        match framing {
+            BodyFraming::None => Ok(()),
+            BodyFraming::Chunked => {
+                let body = servente_http1::ChunkedBodyReader::new(&mut stream, DEFAULT_MAX_CHUNK_SIZE, usize::MAX);
+                copy_body(body, &mut sink, timeout).await.map(|_| ())
+            }
+            BodyFraming::ContentLength(length) => {
+                if copy_body((&mut stream).take(length), &mut sink, timeout).await? != length {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the upstream body is incomplete"));
+                }
+                Ok(())
+            }
+            BodyFraming::Close => copy_body(&mut stream, &mut sink, timeout).await.map(|_| ()),
+        }
+    })))
+}
+
+/// Copies the body to the sink, returning the number of bytes copied.
+async fn copy_body<R>(mut body: R, sink: &mut BodySink, timeout: Duration) -> io::Result<u64>
+        where R: AsyncRead + Unpin {
+    let mut buffer = vec![0; BODY_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let length = tokio::time::timeout(timeout, body.read(&mut buffer)).await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if length == 0 {
+            return Ok(copied);
+        }
+
+        sink.write(&buffer[..length]).await?;
+        copied += length as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use servente_http::HttpVersion;
+    use servente_http_handling::Scheme;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn create_request(target: &str) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.append_or_override(HeaderName::Host, "example.com".into());
+        headers.append_or_override(HeaderName::Connection, "keep-alive, X-Secret".into());
+        headers.append_or_override(HeaderName::KeepAlive, "timeout=5".into());
+        headers.append_or_override(HeaderName::Other("x-secret".into()), "hop".into());
+        headers.append_or_override(HeaderName::XForwardedProto, "https".into());
+        headers.append_or_override(HeaderName::UserAgent, "curl/8.0".into());
+        Request {
+            method: Method::Post,
+            target: RequestTarget::parse(target).unwrap(),
+            version: HttpVersion::Http11,
+            headers,
+            body: Some(BodyKind::Bytes(b"payload".to_vec())),
+            raw_body: None,
+        }
+    }
+
+    async fn proxy(middleware: &ProxyMiddleware, request: &Request) -> Response {
+        let connection = ConnectionInfo::new(Scheme::Http).with_peer_address("192.0.2.1:51234".parse().unwrap());
+        middleware.respond(request, &connection).await
+            .unwrap_or_else(|| Response::with_status(StatusCode::NotFound))
+    }
+
+    /// Accepts a single connection, and responds with the `response` after
+    /// reading the head and body of the request, which are returned.
+    async fn serve_upstream(listener: TcpListener, response: &'static str) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            stream.read_line(&mut head).await.unwrap();
+        }
+
+        let content_length = head.lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+
+        stream.write_all(response.as_bytes()).await.unwrap();
+        head + std::str::from_utf8(&body).unwrap()
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line(b"HTTP/1.1 200 OK\r\n"), Some(200));
+        assert_eq!(parse_status_line(b"HTTP/1.0 404 Not Found\n"), Some(404));
+        assert_eq!(parse_status_line(b"HTTP/1.1 204\r\n"), Some(204));
+        assert_eq!(parse_status_line(b"HTTP/1.1 200 OK"), None);
+        assert_eq!(parse_status_line(b"HTTP/2 200\r\n"), None);
+        assert_eq!(parse_status_line(b"HTTP/1.1 2000 OK\r\n"), None);
+        assert_eq!(parse_status_line(b"HTTP/1.1 700 Weird\r\n"), None);
+    }
+
+    #[test]
+    fn test_serialize_request_head() {
+        let request = create_request("/api/users?page=2");
+        let connection = ConnectionInfo::new(Scheme::Https).with_peer_address("192.0.2.1:51234".parse().unwrap());
+        let head = serialize_request_head(&request, &connection, Some(7));
+
+        assert!(head.starts_with("POST /api/users?page=2 HTTP/1.1\r\n"), "{head}");
+        assert!(head.contains("\r\nHost: example.com\r\n"), "{head}");
+        assert!(head.contains("\r\nUser-Agent: curl/8.0\r\n"), "{head}");
+        assert!(head.contains("\r\nX-Forwarded-For: 192.0.2.1\r\n"), "{head}");
+        assert!(head.contains("\r\nX-Forwarded-Proto: https\r\n"), "{head}");
+        assert!(head.ends_with("\r\nContent-Length: 7\r\nConnection: close\r\n\r\n"), "{head}");
+        assert!(!head.contains("keep-alive") && !head.contains("Keep-Alive"), "{head}");
+        assert!(!head.to_ascii_lowercase().contains("x-secret"), "{head}");
+
+        let mut request = request;
+        request.headers.append_or_override(HeaderName::XForwaredFor, "203.0.113.7".into());
+        let head = serialize_request_head(&request, &connection, None);
+        assert!(head.contains("\r\nX-Forwarded-For: 203.0.113.7, 192.0.2.1\r\n"), "{head}");
+        assert!(!head.contains("Content-Length"), "{head}");
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let middleware = ProxyMiddleware::new().with_route("/api/", listener.local_addr().unwrap().to_string());
+        let upstream = tokio::spawn(serve_upstream(listener, concat!(
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n",
+            "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\nConnection: close, X-Upstream-Hop\r\n",
+            "Keep-Alive: timeout=5\r\nX-Upstream-Hop: 1\r\nContent-Type: text/plain\r\n\r\n",
+            "5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+        )));
+
+        let response = proxy(&middleware, &create_request("/api/users")).await;
+        assert_eq!(response.status, StatusCode::Created);
+        assert!(response.headers.contains(&HeaderName::ContentType));
+        for name in [HeaderName::Connection, HeaderName::KeepAlive, HeaderName::TransferEncoding, HeaderName::Other("x-upstream-hop".into())] {
+            assert!(!response.headers.contains(&name), "{name:?}");
+        }
+
+        let Some(BodyKind::Stream(mut body)) = response.body else {
+            panic!("the body isn't streamed");
+        };
+        let mut data = Vec::new();
+        while let Some(part) = body.next().await.unwrap() {
+            data.extend(part);
+        }
+        assert_eq!(data, b"hello, world");
+
+        let received = upstream.await.unwrap();
+        assert!(received.starts_with("POST /api/users HTTP/1.1\r\n"), "{received}");
+        assert!(received.contains("\r\nX-Forwarded-For: 192.0.2.1\r\n"), "{received}");
+        assert!(received.contains("\r\nX-Forwarded-Proto: http\r\n"), "{received}");
+        assert!(received.ends_with("\r\n\r\npayload"), "{received}");
+    }
+
+    #[tokio::test]
+    async fn test_other_paths_are_left_alone() {
+        let middleware = ProxyMiddleware::new().with_route("/api/", "127.0.0.1:9");
+        let response = proxy(&middleware, &create_request("/index.html")).await;
+        assert_eq!(response.status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_routes_are_matched_on_the_normalized_path() {
+        let middleware = ProxyMiddleware::new().with_route("/api/", "127.0.0.1:9");
+        for path in ["/api/users", "/%61pi/users", "//api/users", "/public/../api/users", "/./api/users"] {
+            assert!(middleware.find_route(&create_request(path)).is_some(), "{path}");
+        }
+        for path in ["/", "/api", "/public/api/users", "/../api/users"] {
+            assert!(middleware.find_route(&create_request(path)).is_none(), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_upstream_is_bad_gateway() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let middleware = ProxyMiddleware::new().with_route("/", address);
+        let response = proxy(&middleware, &create_request("/api/users")).await;
+        assert_eq!(response.status, StatusCode::BadGateway);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upstream_is_gateway_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let middleware = ProxyMiddleware::new()
+            .with_route("/", listener.local_addr().unwrap().to_string())
+            .with_timeout(Duration::from_millis(100));
+
+        // The connection is accepted, but never responded to.
+        let _upstream = tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+
+        let response = proxy(&middleware, &create_request("/api/users")).await;
+        assert_eq!(response.status, StatusCode::GatewayTimeout);
+    }
+
+    #[test]
+    fn test_forwarded_path() {
+        assert_eq!(forwarded_path("/api/users"), "/api/users");
+        assert_eq!(forwarded_path("/%61pi//users/"), "/api/users/");
+        assert_eq!(forwarded_path("/public/../api/a%20b;v=1"), "/api/a%20b;v=1");
+        assert_eq!(forwarded_path("/api/%3F%25"), "/api/%3F%25");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_receives_the_normalized_path() {
+        for (target, expected) in [("/%61pi/users?page=2", "/api/users?page=2"), ("/public/../api/users", "/api/users")] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let middleware = ProxyMiddleware::new().with_route("/api/", listener.local_addr().unwrap().to_string());
+            let upstream = tokio::spawn(serve_upstream(listener, "HTTP/1.1 204 No Content\r\n\r\n"));
+
+            let response = proxy(&middleware, &create_request(target)).await;
+            assert_eq!(response.status, StatusCode::NoContent);
+
+            let received = upstream.await.unwrap();
+            assert!(received.starts_with(&format!("POST {expected} HTTP/1.1\r\n")), "{received}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_bodies_are_forwarded() {
+        let bodies = [
+            BodyKind::Shared(std::sync::Arc::new(b"payload".to_vec())),
+            BodyKind::StaticString("payload"),
+            BodyKind::String(String::from("payload")),
+        ];
+        for body in bodies {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let middleware = ProxyMiddleware::new().with_route("/api/", listener.local_addr().unwrap().to_string());
+            let upstream = tokio::spawn(serve_upstream(listener, "HTTP/1.1 204 No Content\r\n\r\n"));
+
+            let mut request = create_request("/api/users");
+            request.body = Some(body);
+            let response = proxy(&middleware, &request).await;
+            assert_eq!(response.status, StatusCode::NoContent);
+
+            let received = upstream.await.unwrap();
+            assert!(received.contains("\r\nContent-Length: 7\r\n"), "{received}");
+            assert!(received.ends_with("\r\n\r\npayload"), "{received}");
+        }
+    }
+}
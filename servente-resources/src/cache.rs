@@ -11,16 +11,35 @@
 
 use std::{
     collections::HashMap,
+    io,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+        OnceLock,
+    },
     time::Duration,
 };
 
 use lazy_static::lazy_static;
-use stretto::AsyncCache;
-use tokio::io::AsyncReadExt;
+use stretto::{
+    AsyncCache,
+    AsyncCacheBuilder,
+    CacheCallback,
+    DefaultCoster,
+    DefaultKeyBuilder,
+    DefaultUpdateValidator,
+};
+use tokio::{
+    io::AsyncReadExt,
+    task::JoinSet,
+};
 
-use super::compression::ContentEncodedVersions;
+use super::compression::{
+    CompressionPolicy,
+    ContentEncodedVersions,
+};
 
 /// The maximum size of a file that can be cached in memory.
 const FILE_CACHE_MAXIMUM_SIZE: u64 = 50_000_000; // 50 MB
@@ -29,11 +48,85 @@ const FILE_CACHE_MAXIMUM_SIZE: u64 = 50_000_000; // 50 MB
 /// it reduces the memory usage of the server for infrequently requested files.
 const DEFAULT_CACHE_DURATION: Duration = Duration::from_secs(60 * 60);
 
+/// The default maximum size of the compressed versions of all cached files
+/// together.
+pub const DEFAULT_MAXIMUM_COMPRESSED_SIZE: usize = 256_000_000; // 256 MB
+
+/// Options for how files are stored in the cache, see [`start`].
+#[derive(Clone, Debug)]
+pub struct CacheOptions {
+    /// Which compressed versions of the files are precomputed.
+    pub compression_policy: CompressionPolicy,
+
+    /// The maximum number of bytes the compressed versions of all cached files
+    /// may use together. When this is reached, files are cached with only
+    /// their uncompressed version, which is then compressed per request.
+    pub maximum_compressed_size: usize,
+
+    /// Whether the hash of the contents is computed for every cached file,
+    /// which is used as its strong `ETag`.
+    pub content_hash_etags: bool,
+
+    /// How long [`start`] waits for the warm-up to finish. When it takes
+    /// longer, e.g. for a huge `wwwroot`, the server is reported as warmed up
+    /// anyway, and the warm-up continues in the background.
+    pub maximum_warm_up_duration: Duration,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            compression_policy: CompressionPolicy::All,
+            maximum_compressed_size: DEFAULT_MAXIMUM_COMPRESSED_SIZE,
+            content_hash_etags: false,
+            maximum_warm_up_duration: DEFAULT_MAXIMUM_WARM_UP_DURATION,
+        }
+    }
+}
+
+/// The default of [`CacheOptions::maximum_warm_up_duration`].
+pub const DEFAULT_MAXIMUM_WARM_UP_DURATION: Duration = Duration::from_secs(10);
+
+/// The options the cache was started with.
+static CACHE_OPTIONS: OnceLock<CacheOptions> = OnceLock::new();
+
+/// Whether the warm-up finished, or exceeded the maximum duration.
+static WARM_UP_FINISHED: AtomicBool = AtomicBool::new(false);
+
+/// The number of bytes the compressed versions of the cached files use.
+static COMPRESSED_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps [`COMPRESSED_SIZE`] up to date when files leave the cache.
+pub struct CompressedSizeTracker;
+
+impl CacheCallback for CompressedSizeTracker {
+    type Value = Arc<ContentEncodedVersions>;
+
+    fn on_exit(&self, val: Option<Self::Value>) {
+        if let Some(versions) = val {
+            COMPRESSED_SIZE.fetch_sub(versions.compressed_size(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// The type of [`FILE_CACHE`].
+pub type FileCache = AsyncCache<
+    String,
+    Arc<ContentEncodedVersions>,
+    DefaultKeyBuilder<String>,
+    DefaultCoster<Arc<ContentEncodedVersions>>,
+    DefaultUpdateValidator<Arc<ContentEncodedVersions>>,
+    CompressedSizeTracker,
+>;
+
 lazy_static! {
     /// This is the cache that stores the files in memory. It is a static
     /// variable because it needs to be shared between all threads, but is
     /// concurrent.
-    pub static ref FILE_CACHE: AsyncCache<String, Arc<ContentEncodedVersions>> = AsyncCache::new(12960, 1e6 as i64, tokio::spawn).unwrap();
+    pub static ref FILE_CACHE: FileCache = AsyncCacheBuilder::new(12960, 1e6 as i64)
+        .set_callback(CompressedSizeTracker)
+        .finalize(tokio::spawn)
+        .unwrap();
 
     /// This is used to prevent multiple threads from caching the same file at
     /// the same time. It is a HashMap because it's faster to check if a file
@@ -75,15 +168,51 @@ fn arc_unwrap_or_clone<T>(arc: Arc<T>) -> T
     Arc::try_unwrap(arc).unwrap_or_else(|arc| (*arc).clone())
 }
 
-/// Caches all the applicable files on startup.
-fn cache_files_on_startup(path: &Path) -> Result<(), std::io::Error> {
-    for entry in std::fs::read_dir(path)?.flatten() {
-        tokio::task::spawn(async move {
-            maybe_cache_file(&entry.path()).await;
+/// The outcome of [`warm_up`].
+#[derive(Debug, Default)]
+pub struct WarmUpReport {
+    /// The number of files that were stored in the cache.
+    pub cached_files: usize,
+
+    /// The files that couldn't be read, e.g. because they were removed during
+    /// the warm-up. These were skipped.
+    pub failed_files: Vec<(PathBuf, io::Error)>,
+}
+
+/// Caches all the applicable files in the directory. A file that can't be
+/// read is logged and skipped, instead of aborting the warm-up.
+pub async fn warm_up(path: &Path) -> Result<WarmUpReport, io::Error> {
+    let mut entries = tokio::fs::read_dir(path).await?;
+    let mut tasks = JoinSet::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        tasks.spawn(async move {
+            let result = cache_file(path.clone()).await;
+            (path, result)
         });
     }
 
-    Ok(())
+    let mut report = WarmUpReport::default();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok((_, Ok(true))) => report.cached_files += 1,
+            Ok((_, Ok(false))) => (),
+            Ok((path, Err(error))) => {
+                eprintln!("[Cache] Skipped {} during the warm-up: {error}", path.to_string_lossy());
+                report.failed_files.push((path, error));
+            }
+            Err(error) => eprintln!("[Cache] Warm-up task failed: {error}"),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Whether the warm-up of the cache finished, or took longer than the
+/// [`CacheOptions::maximum_warm_up_duration`]. Readiness checks can use this
+/// to only receive traffic after the cache is filled.
+pub fn is_warmed_up() -> bool {
+    WARM_UP_FINISHED.load(Ordering::Relaxed)
 }
 
 /// Checks if a file is appropriate for caching.
@@ -110,6 +239,38 @@ fn is_file_appropriate_for_caching(metadata: &std::fs::Metadata) -> bool {
     metadata.len() <= FILE_CACHE_MAXIMUM_SIZE
 }
 
+/// The time a file must be left unmodified before it's considered stable.
+const STABLE_FILE_AGE: Duration = Duration::from_secs(60);
+
+/// Checks if a file is considered stable, meaning it wasn't modified recently,
+/// so it probably isn't being written to anymore.
+pub fn is_file_stable(metadata: &std::fs::Metadata) -> bool {
+    match metadata.modified().map(|modified| modified.elapsed()) {
+        Ok(Ok(age)) => age >= STABLE_FILE_AGE,
+        _ => false,
+    }
+}
+
+/// Create the versions of a file that is about to be cached, according to the
+/// [`CacheOptions`]. When the compressed versions don't fit within the
+/// maximum compressed size anymore, they are discarded before the
+/// uncompressed version is.
+fn create_cached_versions(data: Vec<u8>) -> ContentEncodedVersions {
+    let options = CACHE_OPTIONS.get_or_init(Default::default);
+    let mut cached = ContentEncodedVersions::create_with_policy(data, options.compression_policy);
+    if options.content_hash_etags {
+        cached.compute_content_hash();
+    }
+
+    let compressed_size = cached.compressed_size();
+    if COMPRESSED_SIZE.fetch_add(compressed_size, Ordering::Relaxed) + compressed_size > options.maximum_compressed_size {
+        COMPRESSED_SIZE.fetch_sub(compressed_size, Ordering::Relaxed);
+        cached.discard_compressed_versions();
+    }
+
+    cached
+}
+
 /// Checks if a file is already in the process of being cached, to avoid cache
 /// races.
 fn is_file_being_cached(path: &Arc<PathBuf>) -> bool {
@@ -133,56 +294,75 @@ pub async fn maybe_cache_file(path: &Path) {
     let path = path.to_owned();
 
     tokio::task::spawn(async move {
-        // Check if the file is allowed to be served.
-        if !super::is_file_allowed_to_be_served(path.to_string_lossy().as_ref()) {
-            return;
-        }
+        _ = cache_file(path).await;
+    });
+}
 
-        let path = Arc::new(path);
+/// Stores the file in the cache, if it is appropriate. Returns whether the
+/// file was cached, or the error when the file couldn't be read.
+async fn cache_file(path: PathBuf) -> Result<bool, io::Error> {
+    // The cache is keyed by strings, and converting a path that isn't
+    // valid UTF-8 lossily could make it collide with another path.
+    let Some(path_str) = path.to_str() else {
+        return Ok(false);
+    };
+
+    // Check if the file is allowed to be served.
+    if !super::is_file_allowed_to_be_served(path_str) {
+        return Ok(false);
+    }
 
-        if is_file_being_cached(&path) {
-            return;
-        }
+    let path = Arc::new(path);
 
-        let start = std::time::Instant::now();
-        let Ok(mut file) = tokio::fs::File::open(path.as_ref()).await else {
-            return;
-        };
+    if is_file_being_cached(&path) {
+        return Ok(false);
+    }
 
-        if let Ok(metadata) = file.metadata().await {
-            if !is_file_appropriate_for_caching(&metadata) {
-                return;
-            }
+    let result = read_file_into_cache(&path).await;
 
-            // Modified date is an `Option` since it might not be available on
-            // some filesystems.
-            let modified_date = metadata.modified().ok();
-            let mut data = Vec::with_capacity(metadata.len() as usize);
+    // The file is cached (or failed to), so another task may cache it again
+    // if appropriately. Files that aren't appropriate for caching stay
+    // marked, so they aren't checked again for every request.
+    if !matches!(result, Ok(false)) {
+        FILE_CACHE_CHECK_FILE_EXISTENCE.lock().unwrap().remove(&path);
+    }
 
-            _ = file.read_to_end(&mut data).await;
+    result
+}
 
-            let mut cached = ContentEncodedVersions::create(data);
-            cached.modified_date = modified_date;
+async fn read_file_into_cache(path: &Arc<PathBuf>) -> Result<bool, io::Error> {
+    let start = std::time::Instant::now();
+    let mut file = tokio::fs::File::open(path.as_ref()).await?;
 
-            // If the infrastructure around FILE_CACHE_CHECK_FILE_EXISTENCE
-            // changes, `the arc_unwrap_or_clone` might be useful.
-            let path_string = path_buf_to_string(arc_unwrap_or_clone(path.clone()));
+    let metadata = file.metadata().await?;
+    if !is_file_appropriate_for_caching(&metadata) {
+        return Ok(false);
+    }
 
-            #[cfg(feature = "convert-markdown")]
-            maybe_convert_markdown_htmlized(&path_string, &mut cached).await;
+    // Modified date is an `Option` since it might not be available on
+    // some filesystems.
+    let modified_date = metadata.modified().ok();
+    let mut data = Vec::with_capacity(metadata.len() as usize);
 
-            // try_insert_with_ttl doesn't panic, so it's safer.
-            // If due to an unfortunate event the file is cached twice, it's
-            // not a big deal.
-            _ = FILE_CACHE.try_insert_with_ttl(path_string, Arc::new(cached), 0, DEFAULT_CACHE_DURATION).await;
+    file.read_to_end(&mut data).await?;
 
-            // The file is cached, so another task may cache it again if
-            // appropriately.
-            FILE_CACHE_CHECK_FILE_EXISTENCE.lock().unwrap().remove(&path);
+    let mut cached = create_cached_versions(data);
+    cached.modified_date = modified_date;
 
-            println!("Cached file: {} in {} seconds", path.to_string_lossy(), (start.elapsed()).as_secs_f32());
-        }
-    });
+    // If the infrastructure around FILE_CACHE_CHECK_FILE_EXISTENCE
+    // changes, `the arc_unwrap_or_clone` might be useful.
+    let path_string = path_buf_to_string(arc_unwrap_or_clone(path.clone()));
+
+    #[cfg(feature = "convert-markdown")]
+    maybe_convert_markdown_htmlized(&path_string, &mut cached).await;
+
+    // try_insert_with_ttl doesn't panic, so it's safer.
+    // If due to an unfortunate event the file is cached twice, it's
+    // not a big deal.
+    _ = FILE_CACHE.try_insert_with_ttl(path_string, Arc::new(cached), 0, DEFAULT_CACHE_DURATION).await;
+
+    println!("Cached file: {} in {} seconds", path.to_string_lossy(), (start.elapsed()).as_secs_f32());
+    Ok(true)
 }
 
 /// Convert the file to HTML if it is a Markdown file.
@@ -197,9 +377,13 @@ async fn maybe_convert_markdown_htmlized(path: &str, cached: &mut ContentEncoded
     if path.ends_with(".md") {
         let htmlized = servente_generator::common_mark::convert_to_html(String::from_utf8_lossy(cached.uncompressed.as_slice()).as_ref());
 
-        let mut data = ContentEncodedVersions::create(htmlized.into());
+        let options = CACHE_OPTIONS.get_or_init(Default::default);
+        let mut data = ContentEncodedVersions::create_with_policy(htmlized.into(), options.compression_policy);
         data.media_type = Some(MediaType::HTML);
         data.modified_date = cached.modified_date;
+        if options.content_hash_etags {
+            data.compute_content_hash();
+        }
 
         cached.cache_details = Some(CachedFileDetails::Markdown {
              html_rendered: Arc::new(data)
@@ -228,23 +412,39 @@ pub fn remove_files_from_cache(paths: Vec<PathBuf>) {
 /// Starts the cache worker which makes sure that the cache is up to date.
 ///
 /// It has the following features:
-/// 1. Caches files on startup.
+/// 1. Caches files on startup, see [`warm_up`]. This function returns when
+///    the warm-up finished, or took longer than the
+///    [`CacheOptions::maximum_warm_up_duration`], after which
+///    [`is_warmed_up`] returns `true`.
 /// 2. Watches for file changes and updates the cache accordingly (behind a
 ///    feature flag).
-pub async fn start(path: &Path) {
+///
+/// The options only take effect the first time the cache is started.
+pub async fn start(path: &Path, options: CacheOptions) {
+    let maximum_warm_up_duration = options.maximum_warm_up_duration;
+    _ = CACHE_OPTIONS.set(options);
+
+    #[cfg(feature = "watch")]
+    start_watcher(path);
+
+    // The task keeps running in the background when the timeout elapses.
     let path_for_startup = path.to_owned();
-    tokio::task::spawn_blocking(move || {
-        if let Err(err) = cache_files_on_startup(&path_for_startup) {
-            #[cfg(debug_assertions)]
-            eprintln!("Failed to cache files on startup: {}", err);
+    let warm_up = tokio::task::spawn(async move {
+        warm_up(&path_for_startup).await
+    });
 
-            #[cfg(not(debug_assertions))]
-            { _ = err }
+    match tokio::time::timeout(maximum_warm_up_duration, warm_up).await {
+        Ok(Ok(Ok(report))) => {
+            println!("[Cache] Warmed up with {} files, skipped {}", report.cached_files, report.failed_files.len());
         }
-    });
+        Ok(Ok(Err(error))) => eprintln!("[Cache] Failed to warm up: {error}"),
+        Ok(Err(error)) => eprintln!("[Cache] Warm-up task failed: {error}"),
+        Err(_) => {
+            println!("[Cache] Warming up takes longer than {} seconds, continuing in the background", maximum_warm_up_duration.as_secs_f32());
+        }
+    }
 
-    #[cfg(feature = "watch")]
-    start_watcher(path);
+    WARM_UP_FINISHED.store(true, Ordering::Relaxed);
 }
 
 /// Starts the watcher which watches for file changes and updates the cache
@@ -282,3 +482,31 @@ fn start_watcher(path: &Path) {
         _ = watcher.watch(&path, RecursiveMode::Recursive);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn warm_up_skips_unreadable_files() {
+        let root = tempfile::Builder::new().prefix("warm-up").tempdir().unwrap();
+        std::fs::write(root.path().join("index.html"), "<!DOCTYPE html>").unwrap();
+        std::fs::write(root.path().join("style.css"), "body { color: red; }").unwrap();
+
+        // A file that was removed during the warm-up.
+        std::os::unix::fs::symlink(root.path().join("removed.html"), root.path().join("dangling.html")).unwrap();
+
+        let report = warm_up(root.path()).await.unwrap();
+        assert_eq!(report.cached_files, 2);
+        assert_eq!(report.failed_files.len(), 1);
+        assert_eq!(report.failed_files[0].0, root.path().join("dangling.html"));
+        assert_eq!(report.failed_files[0].1.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn warm_up_of_missing_directory_fails() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(warm_up(&root.path().join("missing")).await.is_err());
+    }
+}
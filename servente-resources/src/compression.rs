@@ -3,6 +3,7 @@
 
 use std::{
     fmt::Formatter,
+    sync::{Arc, RwLock},
     time::SystemTime,
 };
 
@@ -30,6 +31,30 @@ pub struct ContentEncodedVersions {
 
     /// The `gzip` encoded version of the resource.
     pub gzip: Option<Vec<u8>>,
+
+    /// The hash of the uncompressed version, see [`Self::compute_content_hash`].
+    pub content_hash: Option<u64>,
+
+    /// The encoded header sections of recent responses serving this
+    /// resource, so hot responses don't have to be encoded again. At most
+    /// [`MAX_PRECOMPUTED_HEADER_BLOCKS`] are kept, since the responses using
+    /// different content codings have different headers.
+    pub precomputed_header_blocks: RwLock<Vec<Arc<PrecomputedHeaderBlock>>>,
+}
+
+/// The maximum number of [`ContentEncodedVersions::precomputed_header_blocks`].
+pub const MAX_PRECOMPUTED_HEADER_BLOCKS: usize = 4;
+
+/// An encoded header section of a response, e.g. an HTTP/2 HPACK header
+/// block. The protocol implementation that computed it owns the format of
+/// both the key and the block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrecomputedHeaderBlock {
+    /// Identifies the header fields the block was computed from.
+    pub key: Vec<u8>,
+
+    /// The encoded header fields.
+    pub block: Vec<u8>,
 }
 
 impl core::fmt::Debug for ContentEncodedVersions {
@@ -38,10 +63,38 @@ impl core::fmt::Debug for ContentEncodedVersions {
             .field("uncompressed", &self.uncompressed.len())
             .field("brotli", &self.brotli.as_ref().map_or(0, |v| v.len()))
             .field("gzip", &self.gzip.as_ref().map_or(0, |v| v.len()))
+            .field("content_hash", &self.content_hash)
             .finish()
     }
 }
 
+/// Which compressed versions of a resource are precomputed and kept in memory,
+/// next to the uncompressed version.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// Precompute all supported content codings.
+    #[default]
+    All,
+
+    /// Don't precompute any compressed version.
+    None,
+
+    /// Only precompute the given content coding, which should be the one most
+    /// clients accept.
+    Only(ContentCoding),
+}
+
+impl CompressionPolicy {
+    /// Whether the given content coding should be precomputed.
+    pub fn includes(&self, coding: ContentCoding) -> bool {
+        match self {
+            Self::All => true,
+            Self::None => false,
+            Self::Only(only) => *only == coding,
+        }
+    }
+}
+
 /// Returns whether the given file should be compressed.
 /// We shouldn't compress images, since they are already compressed.
 fn should_compress_file(uncompressed: &[u8]) -> bool {
@@ -49,20 +102,86 @@ fn should_compress_file(uncompressed: &[u8]) -> bool {
 }
 
 impl ContentEncodedVersions {
+    /// Compute the hash of the contents, which is used as a strong entity
+    /// tag that, unlike the modification date, doesn't change when the file
+    /// is touched or restored without changing its contents.
+    pub fn compute_content_hash(&mut self) {
+        self.content_hash = Some(xxhash_rust::xxh3::xxh3_64(&self.uncompressed));
+    }
+
+    /// The strong entity tag based on the [`Self::content_hash`], if it was
+    /// computed.
+    ///
+    /// # References
+    /// * [RFC 9110 Section 8.8.3](https://www.rfc-editor.org/rfc/rfc9110.html#name-etag)
+    pub fn content_hash_etag(&self) -> Option<String> {
+        self.content_hash.map(|hash| format!("\"{hash:016x}\""))
+    }
+
     pub fn create(uncompressed: Vec<u8>) -> Self {
+        Self::create_with_policy(uncompressed, CompressionPolicy::All)
+    }
+
+    /// Create the versions, only precomputing the compressed versions that are
+    /// included by the given policy.
+    pub fn create_with_policy(uncompressed: Vec<u8>, policy: CompressionPolicy) -> Self {
         let mut result = ContentEncodedVersions {
             uncompressed,
             ..Default::default()
         };
 
         if should_compress_file(&result.uncompressed) {
-            result.brotli = ContentCoding::Brotli.encode(&result.uncompressed);
-            result.gzip = ContentCoding::Gzip.encode(&result.uncompressed);
+            if policy.includes(ContentCoding::Brotli) {
+                result.brotli = ContentCoding::Brotli.encode(&result.uncompressed);
+            }
+
+            if policy.includes(ContentCoding::Gzip) {
+                result.gzip = ContentCoding::Gzip.encode(&result.uncompressed);
+            }
         }
 
         result
     }
 
+    /// Finds the precomputed header block with the given `key`.
+    pub fn find_precomputed_header_block(&self, key: &[u8]) -> Option<Arc<PrecomputedHeaderBlock>> {
+        let blocks = self.precomputed_header_blocks.read().ok()?;
+        blocks.iter().find(|block| block.key == key).cloned()
+    }
+
+    /// Stores the precomputed header block, replacing the oldest one when
+    /// there are already [`MAX_PRECOMPUTED_HEADER_BLOCKS`].
+    pub fn store_precomputed_header_block(&self, block: PrecomputedHeaderBlock) {
+        let Ok(mut blocks) = self.precomputed_header_blocks.write() else {
+            return;
+        };
+
+        if blocks.iter().any(|existing| existing.key == block.key) {
+            return;
+        }
+
+        if blocks.len() >= MAX_PRECOMPUTED_HEADER_BLOCKS {
+            blocks.remove(0);
+        }
+        blocks.push(Arc::new(block));
+    }
+
+    /// The number of bytes used by the compressed versions.
+    pub fn compressed_size(&self) -> usize {
+        self.brotli.as_ref().map_or(0, |v| v.len()) + self.gzip.as_ref().map_or(0, |v| v.len())
+    }
+
+    /// The number of bytes used by all versions together.
+    pub fn total_size(&self) -> usize {
+        self.uncompressed.len() + self.compressed_size()
+    }
+
+    /// Drop the compressed versions, keeping only the uncompressed version.
+    pub fn discard_compressed_versions(&mut self) {
+        self.brotli = None;
+        self.gzip = None;
+    }
+
     pub fn determine_best_version_from_accept_encoding(&self, accept_encoding: &str) -> Option<ContentCoding> {
         if self.gzip.as_ref().map_or(usize::MAX, |v| v.len()) > self.uncompressed.len()
                 && self.brotli.as_ref().map_or(usize::MAX, |v| v.len()) > self.uncompressed.len() {
@@ -189,6 +308,15 @@ impl ContentEncodedVersions {
         None
     }
 
+    /// Get the version with the given coding, if it was created.
+    pub fn try_get_version(&self, coding: Option<ContentCoding>) -> Option<&Vec<u8>> {
+        match coding {
+            Some(ContentCoding::Brotli) => self.brotli.as_ref(),
+            Some(ContentCoding::Gzip) => self.gzip.as_ref(),
+            None => Some(&self.uncompressed),
+        }
+    }
+
     pub fn get_version(&self, coding: Option<ContentCoding>) -> &Vec<u8> {
         match coding {
             Some(ContentCoding::Brotli) => self.brotli.as_ref().unwrap(),
@@ -201,6 +329,7 @@ impl ContentEncodedVersions {
 #[cfg(test)]
 mod tests {
     use super::{
+        CompressionPolicy,
         ContentCoding,
         ContentEncodedVersions,
     };
@@ -364,4 +493,38 @@ mod tests {
         assert_eq!(versions.determine_best_version_from_accept_encoding("*;q=0.0"), None, "with *,q=0.0");
         assert_eq!(versions.determine_best_version_from_accept_encoding("*;q=0.0,gzip;q=1.0"), Some(ContentCoding::Gzip));
     }
+
+    #[test]
+    pub fn create_with_gzip_only_policy() {
+        let text = "Hello, compressed world! ".repeat(100).into_bytes();
+        let all = ContentEncodedVersions::create(text.clone());
+        let gzip_only = ContentEncodedVersions::create_with_policy(text, CompressionPolicy::Only(ContentCoding::Gzip));
+
+        assert!(gzip_only.brotli.is_none());
+        assert!(gzip_only.gzip.is_some());
+        assert!(gzip_only.total_size() < all.total_size());
+    }
+
+    #[test]
+    pub fn create_with_none_policy() {
+        let text = "Hello, compressed world! ".repeat(100).into_bytes();
+        let versions = ContentEncodedVersions::create_with_policy(text.clone(), CompressionPolicy::None);
+
+        assert_eq!(versions.compressed_size(), 0);
+        assert_eq!(versions.total_size(), text.len());
+    }
+
+    #[test]
+    pub fn content_hash_etag() {
+        let mut versions = ContentEncodedVersions::create(b"Hello, world!".to_vec());
+        assert_eq!(versions.content_hash_etag(), None);
+
+        versions.compute_content_hash();
+        let etag = versions.content_hash_etag().unwrap();
+        assert!(etag.starts_with('"') && etag.ends_with('"') && etag.len() == 18, "{etag}");
+
+        let mut other = ContentEncodedVersions::create(b"Hello, world?".to_vec());
+        other.compute_content_hash();
+        assert_ne!(other.content_hash_etag(), Some(etag));
+    }
 }
@@ -1,7 +1,7 @@
 // Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::io::Write;
+use std::io::{self, Read, Write};
 
 /// A list of supported content encodings.
 ///
@@ -38,23 +38,67 @@ pub enum ContentCoding {
 }
 
 impl ContentCoding {
-    /// Encodes the given data using the specified content encoding.
+    /// Encodes the given data using the specified content encoding, returning
+    /// `None` if the encoder failed.
     pub fn encode(&self, data: &Vec<u8>) -> Option<Vec<u8>> {
         let mut result = Vec::new();
         match self {
             ContentCoding::Brotli => {
                 let mut reader = brotli::CompressorReader::new(std::io::Cursor::new(&data), 4096, 11, 22);
-                std::io::copy(&mut reader, &mut result).unwrap();
+                std::io::copy(&mut reader, &mut result).ok()?;
                 Some(result)
             }
             ContentCoding::Gzip => {
                 let mut encoder = flate2::write::GzEncoder::new(result, flate2::Compression::default());
-                encoder.write_all(data).unwrap();
-                Some(encoder.finish().unwrap())
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()
             }
         }
     }
 
+    /// Decodes the given data, which was encoded using the specified content
+    /// encoding, returning `None` if the data is malformed.
+    pub fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        self.decode_up_to(data, usize::MAX)
+    }
+
+    /// Decodes the given data like [`Self::decode`], but stops after more
+    /// than `limit` bytes were decoded. The result is therefore longer than
+    /// the `limit` when the data decodes to more than that, without having to
+    /// decode all of it, which protects against decompression bombs.
+    pub fn decode_up_to(&self, data: &[u8], limit: usize) -> Option<Vec<u8>> {
+        let limit = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+        let mut result = Vec::new();
+        match self {
+            ContentCoding::Brotli => {
+                let reader = brotli::Decompressor::new(data, 4096);
+                reader.take(limit).read_to_end(&mut result).ok()?;
+            }
+            ContentCoding::Gzip => {
+                let reader = flate2::read::GzDecoder::new(data);
+                reader.take(limit).read_to_end(&mut result).ok()?;
+            }
+        }
+        Some(result)
+    }
+
+    /// Parses the HTTP identifier of the content encoding, ignoring the case.
+    /// The deprecated `x-gzip` alias is accepted as well.
+    ///
+    /// ## References
+    /// * [RFC 9110 Section 8.4.1.3](https://www.rfc-editor.org/rfc/rfc9110.html#section-8.4.1.3)
+    pub fn from_http_identifier(identifier: &str) -> Option<Self> {
+        if identifier.eq_ignore_ascii_case("br") {
+            return Some(ContentCoding::Brotli);
+        }
+
+        if identifier.eq_ignore_ascii_case("gzip") || identifier.eq_ignore_ascii_case("x-gzip") {
+            return Some(ContentCoding::Gzip);
+        }
+
+        None
+    }
+
     /// Returns the HTTP identifier for the content encoding, as specified in
     /// the IANA Registry (name field).
     ///
@@ -66,3 +110,79 @@ impl ContentCoding {
         }
     }
 }
+
+/// An encoder for compressing data that isn't available all at once, for
+/// example for files that are too large to keep in memory and are sent using
+/// the chunked transfer coding.
+pub enum StreamingEncoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+}
+
+impl StreamingEncoder {
+    /// The Brotli quality used for streaming. This is lower than the quality
+    /// used for cached resources, since compressing happens for every request.
+    const BROTLI_QUALITY: u32 = 5;
+
+    pub fn new(coding: ContentCoding) -> Self {
+        match coding {
+            ContentCoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, Self::BROTLI_QUALITY, 22))),
+            ContentCoding::Gzip => Self::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+        }
+    }
+
+    /// Compresses the given data, returning the compressed output that is
+    /// available so far. This might be empty, since the encoder can buffer
+    /// data internally.
+    pub fn encode(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// Finishes the stream, returning the remaining compressed output.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Brotli(encoder) => Ok(encoder.into_inner()),
+            Self::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_round_trip() {
+        let data = b"The quick brown fox jumps over the lazy dog".repeat(100);
+        for coding in [ContentCoding::Brotli, ContentCoding::Gzip] {
+            let encoded = coding.encode(&data).unwrap();
+            assert_eq!(coding.decode(&encoded).as_ref(), Some(&data), "{coding:?}");
+            assert_eq!(ContentCoding::from_http_identifier(coding.http_identifier()), Some(coding));
+        }
+    }
+
+    #[test]
+    fn test_decode_up_to_stops_after_limit() {
+        let data = vec![0; 1_000_000];
+        for coding in [ContentCoding::Brotli, ContentCoding::Gzip] {
+            let encoded = coding.encode(&data).unwrap();
+            assert_eq!(coding.decode_up_to(&encoded, 100).map(|decoded| decoded.len()), Some(101), "{coding:?}");
+            assert_eq!(coding.decode_up_to(&encoded, data.len()).as_ref(), Some(&data), "{coding:?}");
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert_eq!(ContentCoding::Gzip.decode(b"not gzip"), None);
+        assert_eq!(ContentCoding::Brotli.decode(b"\xFF\xFF\xFF\xFF"), None);
+    }
+}
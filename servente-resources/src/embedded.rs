@@ -0,0 +1,135 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Files that are embedded into the binary, so a single executable can serve
+//! a website without a `wwwroot` on the filesystem, e.g. in a container.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
+
+use crate::{
+    ContentEncodedVersions,
+    MediaType,
+};
+
+/// Embeds the files of a directory into the binary, and creates the
+/// [`EmbeddedFiles`] of them. The directory is relative to the `Cargo.toml`
+/// of the crate invoking the macro. The files have to be listed, since
+/// `include_bytes!` can't include a directory as a whole.
+///
+/// ```ignore
+/// let files = servente_resources::embed_files!("wwwroot", ["index.html", "css/style.css"]);
+/// ```
+#[macro_export]
+macro_rules! embed_files {
+    ($root:literal, [$($path:literal),* $(,)?]) => {
+        $crate::embedded::EmbeddedFiles::new(&[
+            $(($path, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $root, "/", $path)) as &'static [u8])),*
+        ])
+    };
+}
+
+/// A set of files that are embedded into the binary, by their path relative
+/// to the document root, e.g. `css/style.css`.
+pub struct EmbeddedFiles {
+    files: HashMap<&'static str, EmbeddedFile>,
+
+    /// The files can't change while the process is running, so the moment
+    /// they were loaded is used as the modification date of all of them.
+    modified_date: SystemTime,
+}
+
+struct EmbeddedFile {
+    contents: &'static [u8],
+
+    /// The versions are created on first use, since compressing all files
+    /// upfront would delay the startup of the server.
+    versions: OnceLock<Arc<ContentEncodedVersions>>,
+}
+
+impl EmbeddedFiles {
+    /// Creates the set from the paths and their contents. A leading slash of
+    /// a path is ignored.
+    pub fn new(files: &[(&'static str, &'static [u8])]) -> Self {
+        Self {
+            files: files.iter()
+                .map(|(path, contents)| (path.trim_start_matches('/'), EmbeddedFile {
+                    contents,
+                    versions: OnceLock::new(),
+                }))
+                .collect(),
+            modified_date: SystemTime::now(),
+        }
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// Get the file with its compressed versions.
+    pub fn get(&self, path: &str) -> Option<Arc<ContentEncodedVersions>> {
+        let file = self.files.get(path)?;
+        let versions = file.versions.get_or_init(|| {
+            let mut versions = ContentEncodedVersions::create(file.contents.to_vec());
+            versions.media_type = Some(MediaType::from_path(path).clone());
+            versions.modified_date = Some(self.modified_date);
+            Arc::new(versions)
+        });
+        Some(Arc::clone(versions))
+    }
+
+    /// Whether the path is a directory containing embedded files. The empty
+    /// path is the root directory.
+    pub fn is_directory(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return !self.files.is_empty();
+        }
+
+        self.files.keys().any(|file| {
+            file.strip_prefix(path).is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+impl std::fmt::Debug for EmbeddedFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddedFiles")
+            .field("files", &self.files.keys().collect::<Vec<_>>())
+            .field("modified_date", &self.modified_date)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_files() {
+        let files = crate::embed_files!("../resources", ["welcome.html"]);
+        let versions = files.get("welcome.html").unwrap();
+        assert_eq!(versions.uncompressed, crate::static_resources::WELCOME_HTML.as_bytes());
+        assert_eq!(versions.media_type, Some(MediaType::HTML));
+        assert!(versions.gzip.is_some());
+
+        // The versions are only created once.
+        assert!(Arc::ptr_eq(&versions, &files.get("welcome.html").unwrap()));
+        assert!(files.get("welcome.nl.html").is_none());
+    }
+
+    #[test]
+    fn test_is_directory() {
+        let files = EmbeddedFiles::new(&[("/index.html", b""), ("css/style.css", b"")]);
+        assert!(files.contains("index.html"));
+        assert!(files.is_directory(""));
+        assert!(files.is_directory("css"));
+        assert!(files.is_directory("css/"));
+        assert!(!files.is_directory("cs"));
+        assert!(!files.is_directory("css/style.css"));
+        assert!(!files.is_directory("index.html"));
+    }
+}
@@ -7,10 +7,13 @@
 pub mod cache;
 pub mod content_coding;
 pub mod compression;
+pub mod embedded;
 pub mod exclude;
 pub mod fs;
 mod magic;
 pub mod media_type;
+#[cfg(all(unix, feature = "mmap"))]
+pub mod mmap;
 pub mod static_resources;
 
 pub use cache::*;
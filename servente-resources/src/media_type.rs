@@ -18,6 +18,27 @@ impl MediaType {
             MediaType::Custom(s) => s,
         }
     }
+
+    /// Returns the type and subtype, without the parameters, e.g. `text/html`
+    /// for `text/html; charset=utf-8`.
+    #[must_use]
+    pub fn essence(&self) -> &str {
+        self.as_str().split(';').next().unwrap_or("").trim()
+    }
+
+    /// Returns whether resources of this media type benefit from compression,
+    /// i.e. they are textual and aren't already compressed.
+    #[must_use]
+    pub fn is_compressible(&self) -> bool {
+        let essence = self.essence();
+        if essence.starts_with("text/") {
+            return true;
+        }
+
+        matches!(essence, "application/javascript" | "application/json" | "application/xml" | "image/svg+xml")
+            || essence.ends_with("+json")
+            || essence.ends_with("+xml")
+    }
 }
 
 impl MediaType {
@@ -42,6 +63,7 @@ impl MediaType {
     //
     pub const JSON: MediaType = MediaType::Common("application/json; charset=utf-8");
     pub const PDF: MediaType = MediaType::Common("application/pdf");
+    pub const PROBLEM_JSON: MediaType = MediaType::Common("application/problem+json");
     pub const ZIP: MediaType = MediaType::Common("application/zip");
     pub const GZIP: MediaType = MediaType::Common("application/gzip");
     pub const BZIP2: MediaType = MediaType::Common("application/x-bzip2");
@@ -0,0 +1,96 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Memory-mapping of files, so they can be served without copying them into
+//! userspace buffers first.
+//!
+//! Accessing a mapping of a file that was truncated after it was mapped
+//! raises `SIGBUS`, so only map files that aren't expected to change, see
+//! [`crate::cache::is_file_stable`].
+
+use std::{
+    fmt::Debug,
+    fs::File,
+    io,
+    os::fd::AsRawFd,
+};
+
+/// A file that is mapped read-only into memory.
+pub struct MappedFile {
+    address: *mut libc::c_void,
+    length: usize,
+}
+
+// The mapping is read-only and private, so it can be shared freely.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the complete file into memory. Empty files can't be mapped.
+    pub fn map(file: &File) -> io::Result<Self> {
+        let length = file.metadata()?.len() as usize;
+        if length == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty files can't be mapped"));
+        }
+
+        // SAFETY: the arguments are valid, and the result is checked below.
+        let address = unsafe {
+            libc::mmap(std::ptr::null_mut(), length, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+
+        if address == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { address, length })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the mapping is valid for `length` bytes until it is dropped.
+        unsafe { std::slice::from_raw_parts(self.address as *const u8, self.length) }
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Debug for MappedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in `map` and isn't used anymore.
+        unsafe {
+            libc::munmap(self.address, self.length);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_map_file() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"Hello, mapped world!").unwrap();
+
+        let mapped = MappedFile::map(&file).unwrap();
+        assert_eq!(mapped.as_bytes(), b"Hello, mapped world!");
+    }
+
+    #[test]
+    fn test_map_empty_file() {
+        let file = tempfile::tempfile().unwrap();
+        assert_eq!(MappedFile::map(&file).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
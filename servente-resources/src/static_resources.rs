@@ -5,6 +5,15 @@
 //! application. These resources are embedded into the binary using the
 //! `include!` macros.
 
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+
+use crate::{
+    ContentEncodedVersions,
+    MediaType,
+};
+
 // The path to the `/resources` directory in the repository root.
 //
 // There isn't an environment variable like `CARGO_WORKSPACE_DIR` yet, so
@@ -20,3 +29,18 @@ pub const WELCOME_HTML: &str = include_str!(concat!(concat!(env!("CARGO_MANIFEST
 /// The HTML page that is shown when the user visits the root of the
 /// application, without having overridden the default welcome page.
 pub const WELCOME_HTML_NL: &str = include_str!(concat!(concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/"), "welcome.nl.html"));
+
+lazy_static! {
+    /// The [`WELCOME_HTML`] with its compressed versions, which are created on
+    /// first use, since the page never changes.
+    pub static ref WELCOME_HTML_VERSIONS: Arc<ContentEncodedVersions> = create_versions(WELCOME_HTML);
+
+    /// The [`WELCOME_HTML_NL`] with its compressed versions.
+    pub static ref WELCOME_HTML_NL_VERSIONS: Arc<ContentEncodedVersions> = create_versions(WELCOME_HTML_NL);
+}
+
+fn create_versions(html: &'static str) -> Arc<ContentEncodedVersions> {
+    let mut versions = ContentEncodedVersions::create(html.as_bytes().to_vec());
+    versions.media_type = Some(MediaType::HTML);
+    Arc::new(versions)
+}
@@ -0,0 +1,356 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The `permessage-deflate` extension, which compresses the payloads of
+//! messages using DEFLATE.
+//!
+//! # References
+//! * [RFC 7692](https://www.rfc-editor.org/rfc/rfc7692)
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use servente_http::{lists::parse_http_list, HeaderName, Request};
+
+use crate::frame::FrameError;
+
+/// The name of the extension in `Sec-WebSocket-Extensions`.
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+/// The trailer of a sync flush, which is removed from the compressed payload,
+/// and appended again before decompressing it.
+///
+/// # References
+/// * [RFC 7692 Section 7.2.1](https://www.rfc-editor.org/rfc/rfc7692.html#section-7.2.1)
+const SYNC_FLUSH_TRAILER: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// The amount by which the output buffers grow while (de)compressing.
+const BUFFER_INCREMENT: usize = 4 * 1024;
+
+/// The parameters the server accepted in the response to the handshake.
+///
+/// # References
+/// * [RFC 7692 Section 7.1](https://www.rfc-editor.org/rfc/rfc7692.html#section-7.1)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeflateParameters {
+    /// The server resets its compression context after every message.
+    pub server_no_context_takeover: bool,
+
+    /// The client resets its compression context after every message, so
+    /// the server resets its decompression context as well.
+    pub client_no_context_takeover: bool,
+
+    /// Whether the client asked for `server_max_window_bits`. Only `15` is
+    /// accepted, since the compressor always uses a 32 KiB window.
+    pub server_max_window_bits: bool,
+}
+
+impl DeflateParameters {
+    /// The value of `Sec-WebSocket-Extensions` in the response.
+    pub fn to_header_value(&self) -> String {
+        let mut value = String::from(EXTENSION_NAME);
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        if self.server_max_window_bits {
+            value.push_str("; server_max_window_bits=15");
+        }
+        value
+    }
+}
+
+/// Selects the first offer of `permessage-deflate` in the request that can
+/// be accepted. Returns `None` when the client didn't offer the extension,
+/// or none of its offers can be accepted, in which case the messages aren't
+/// compressed.
+///
+/// # References
+/// * [RFC 7692 Section 5](https://www.rfc-editor.org/rfc/rfc7692.html#section-5)
+pub fn negotiate(request: &Request) -> Option<DeflateParameters> {
+    request.headers.get_all(&HeaderName::SecWebSocketExtensions)
+        .find_map(|value| parse_http_list(&value.as_str_may_convert()).find_map(parse_offer))
+}
+
+/// Parses an offer, e.g. `permessage-deflate; client_max_window_bits`. Offers
+/// with unknown, duplicate or invalid parameters are declined.
+///
+/// # References
+/// * [RFC 7692 Section 7.1](https://www.rfc-editor.org/rfc/rfc7692.html#section-7.1)
+fn parse_offer(offer: &str) -> Option<DeflateParameters> {
+    let mut parts = offer.split(';').map(str::trim);
+    if !parts.next()?.eq_ignore_ascii_case(EXTENSION_NAME) {
+        return None;
+    }
+
+    let mut parameters = DeflateParameters::default();
+    let mut client_max_window_bits = false;
+    for part in parts {
+        let (name, value) = match part.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (part, None),
+        };
+
+        let seen = match (name.to_ascii_lowercase().as_str(), value) {
+            ("server_no_context_takeover", None) => std::mem::replace(&mut parameters.server_no_context_takeover, true),
+            ("client_no_context_takeover", None) => std::mem::replace(&mut parameters.client_no_context_takeover, true),
+            ("server_max_window_bits", Some(bits)) => {
+                if parse_window_bits(bits)? != 15 {
+                    return None;
+                }
+                std::mem::replace(&mut parameters.server_max_window_bits, true)
+            }
+            // Any window size can be decompressed, so this isn't included in
+            // the response, which means the client uses the default of 15.
+            ("client_max_window_bits", bits) => {
+                if let Some(bits) = bits {
+                    parse_window_bits(bits)?;
+                }
+                std::mem::replace(&mut client_max_window_bits, true)
+            }
+            _ => return None,
+        };
+
+        if seen {
+            return None;
+        }
+    }
+
+    Some(parameters)
+}
+
+fn parse_window_bits(value: &str) -> Option<u8> {
+    // Leading zeros aren't allowed.
+    if value.starts_with('0') {
+        return None;
+    }
+    value.parse().ok().filter(|bits| (8..=15).contains(bits))
+}
+
+/// The compression contexts of a connection that negotiated
+/// `permessage-deflate`.
+pub struct PerMessageDeflate {
+    parameters: DeflateParameters,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl PerMessageDeflate {
+    pub fn new(parameters: DeflateParameters) -> Self {
+        Self {
+            parameters,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    /// Compresses the payload of an outgoing message.
+    ///
+    /// # References
+    /// * [RFC 7692 Section 7.2.1](https://www.rfc-editor.org/rfc/rfc7692.html#section-7.2.1)
+    pub fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(payload.len() / 2 + 16);
+        let start = self.compress.total_in();
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(BUFFER_INCREMENT);
+            }
+
+            let consumed = (self.compress.total_in() - start) as usize;
+            // Only a lack of output space can make this fail, and there is.
+            _ = self.compress.compress_vec(&payload[consumed..], &mut output, FlushCompress::Sync);
+
+            // The flush is complete when not all output space was needed.
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break;
+            }
+        }
+
+        if output.ends_with(&SYNC_FLUSH_TRAILER) {
+            output.truncate(output.len() - SYNC_FLUSH_TRAILER.len());
+        }
+
+        if self.parameters.server_no_context_takeover {
+            self.compress.reset();
+        }
+
+        output
+    }
+
+    /// Decompresses the payload of an incoming message, which may not exceed
+    /// `max_size` bytes when decompressed.
+    ///
+    /// # References
+    /// * [RFC 7692 Section 7.2.2](https://www.rfc-editor.org/rfc/rfc7692.html#section-7.2.2)
+    pub fn decompress(&mut self, payload: &[u8], max_size: usize) -> Result<Vec<u8>, FrameError> {
+        let input = [payload, &SYNC_FLUSH_TRAILER].concat();
+        let mut output = Vec::new();
+        let start = self.decompress.total_in();
+        loop {
+            if output.len() > max_size {
+                return Err(FrameError::TooLarge);
+            }
+
+            if output.len() == output.capacity() {
+                output.reserve_exact(BUFFER_INCREMENT.min(max_size + 1 - output.len()));
+            }
+
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let produced = output.len();
+            let status = self.decompress.decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|_| FrameError::InvalidPayload)?;
+
+            let now_consumed = (self.decompress.total_in() - start) as usize;
+            if now_consumed == input.len() && output.len() < output.capacity() {
+                break;
+            }
+
+            // A final block ends the stream, so the rest can't be decoded.
+            if status == Status::StreamEnd || (now_consumed == consumed && output.len() == produced) {
+                return Err(FrameError::InvalidPayload);
+            }
+        }
+
+        if output.len() > max_size {
+            return Err(FrameError::TooLarge);
+        }
+
+        if self.parameters.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use servente_http::{HeaderMap, HeaderValue, HttpVersion, Method, RequestTarget};
+
+    use super::*;
+
+    fn create_request(extensions: &'static str) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.append_or_override(HeaderName::SecWebSocketExtensions, HeaderValue::from(extensions));
+        Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/chat").unwrap(),
+            version: HttpVersion::Http11,
+            headers,
+            body: None,
+            raw_body: None,
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(&create_request("permessage-deflate")), Some(DeflateParameters::default()));
+        assert_eq!(negotiate(&create_request("x-webkit-deflate-frame")), None);
+        assert_eq!(negotiate(&create_request("permessage-deflate; client_max_window_bits; server_no_context_takeover")), Some(DeflateParameters {
+            server_no_context_takeover: true,
+            ..Default::default()
+        }));
+        assert_eq!(negotiate(&create_request("permessage-deflate; client_no_context_takeover; server_max_window_bits=\"15\"")), Some(DeflateParameters {
+            client_no_context_takeover: true,
+            server_max_window_bits: true,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_next_offer() {
+        let request = create_request("permessage-deflate; server_max_window_bits=10, permessage-deflate; client_no_context_takeover");
+        assert_eq!(negotiate(&request), Some(DeflateParameters {
+            client_no_context_takeover: true,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_negotiate_declines_invalid_offers() {
+        for offer in [
+            "permessage-deflate; unknown",
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; client_max_window_bits=16",
+            "permessage-deflate; client_max_window_bits=09",
+        ] {
+            assert_eq!(negotiate(&create_request(offer)), None, "{offer}");
+        }
+    }
+
+    #[test]
+    fn test_to_header_value() {
+        assert_eq!(DeflateParameters::default().to_header_value(), "permessage-deflate");
+        assert_eq!(DeflateParameters {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+            server_max_window_bits: true,
+        }.to_header_value(), "permessage-deflate; server_no_context_takeover; client_no_context_takeover; server_max_window_bits=15");
+    }
+
+    #[test]
+    fn test_decompress_example() {
+        // The example of RFC 7692 Section 7.2.3.1
+        let mut deflate = PerMessageDeflate::new(DeflateParameters::default());
+        assert_eq!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], 1024).unwrap(), b"Hello");
+
+        // The same message again, using the context of the previous one, as
+        // in RFC 7692 Section 7.2.3.2
+        assert_eq!(deflate.decompress(&[0xf2, 0x00, 0x11, 0x00, 0x00], 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        for parameters in [
+            DeflateParameters::default(),
+            DeflateParameters { server_no_context_takeover: true, client_no_context_takeover: true, ..Default::default() },
+        ] {
+            let mut server = PerMessageDeflate::new(parameters);
+            // The client's side of the server's messages.
+            let mut client = PerMessageDeflate::new(DeflateParameters {
+                client_no_context_takeover: parameters.server_no_context_takeover,
+                ..Default::default()
+            });
+
+            let message = "Hello, world! ".repeat(1000);
+            for _ in 0..3 {
+                let compressed = server.compress(message.as_bytes());
+                assert!(compressed.len() < message.len() / 10);
+                assert!(!compressed.ends_with(&SYNC_FLUSH_TRAILER));
+                assert_eq!(client.decompress(&compressed, message.len()).unwrap(), message.as_bytes());
+            }
+
+            let compressed = server.compress(b"");
+            assert_eq!(client.decompress(&compressed, 0).unwrap(), b"");
+        }
+    }
+
+    #[test]
+    fn test_server_no_context_takeover_resets_the_context() {
+        let mut server = PerMessageDeflate::new(DeflateParameters { server_no_context_takeover: true, ..Default::default() });
+        let first = server.compress(b"Hello");
+        let second = server.compress(b"Hello");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_decompress_limits_the_size() {
+        let mut server = PerMessageDeflate::new(DeflateParameters::default());
+        let compressed = server.compress(&[0; 100_000]);
+
+        let mut deflate = PerMessageDeflate::new(DeflateParameters::default());
+        assert!(matches!(deflate.decompress(&compressed, 99_999), Err(FrameError::TooLarge)));
+
+        let mut deflate = PerMessageDeflate::new(DeflateParameters::default());
+        assert_eq!(deflate.decompress(&compressed, 100_000).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn test_decompress_invalid_data() {
+        let mut deflate = PerMessageDeflate::new(DeflateParameters::default());
+        assert!(matches!(deflate.decompress(&[0xFF, 0xFF, 0xFF], 1024), Err(FrameError::InvalidPayload)));
+    }
+}
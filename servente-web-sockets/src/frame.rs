@@ -0,0 +1,364 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The framing of WebSocket messages.
+//!
+//! # References
+//! * [RFC 6455 Section 5](https://www.rfc-editor.org/rfc/rfc6455.html#section-5)
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The status codes of close frames.
+///
+/// # References
+/// * [RFC 6455 Section 7.4.1](https://www.rfc-editor.org/rfc/rfc6455.html#section-7.4.1)
+pub mod close_code {
+    pub const NORMAL_CLOSURE: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_PAYLOAD_DATA: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+
+    /// Whether the code may be sent in a close frame. The codes `1005`,
+    /// `1006` and `1015` are reserved for reporting the absence of a code, an
+    /// abnormal closure and a TLS failure respectively, so they never appear
+    /// on the wire.
+    pub fn is_valid(code: u16) -> bool {
+        matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+/// The RSV1 bit, in the three least significant bits of [`FrameHeader::rsv`].
+pub const RSV1: u8 = 0b100;
+
+/// The maximum payload length of control frames.
+pub const MAX_CONTROL_FRAME_PAYLOAD_LENGTH: u64 = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Self::Continuation),
+            0x1 => Some(Self::Text),
+            0x2 => Some(Self::Binary),
+            0x8 => Some(Self::Close),
+            0x9 => Some(Self::Ping),
+            0xA => Some(Self::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Self::Continuation => 0x0,
+            Self::Text => 0x1,
+            Self::Binary => 0x2,
+            Self::Close => 0x8,
+            Self::Ping => 0x9,
+            Self::Pong => 0xA,
+        }
+    }
+
+    /// Control frames can be sent in between the frames of a fragmented
+    /// message, but can't be fragmented themselves.
+    pub fn is_control(self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Whether this is the final frame of the message.
+    pub fin: bool,
+
+    /// The RSV1, RSV2 and RSV3 bits, as the three least significant bits.
+    /// These must be zero unless an extension defines their meaning.
+    pub rsv: u8,
+
+    pub opcode: Opcode,
+
+    /// The masking key, which is present in all frames sent by the client.
+    pub mask: Option<[u8; 4]>,
+
+    pub payload_length: u64,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+
+    /// The peer violated the protocol, e.g. by setting reserved bits or by
+    /// sending an unmasked frame.
+    Protocol(&'static str),
+
+    /// A text message that isn't valid UTF-8.
+    InvalidPayload,
+
+    /// A frame or message that exceeds the configured maximum size.
+    TooLarge,
+}
+
+impl FrameError {
+    /// The code of the close frame that is sent because of this error.
+    pub fn close_code(&self) -> u16 {
+        match self {
+            Self::Io(..) => close_code::INTERNAL_ERROR,
+            Self::Protocol(..) => close_code::PROTOCOL_ERROR,
+            Self::InvalidPayload => close_code::INVALID_PAYLOAD_DATA,
+            Self::TooLarge => close_code::MESSAGE_TOO_BIG,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Reads the header of a frame, which is followed by the `payload_length`
+/// bytes of the payload.
+///
+/// # References
+/// * [RFC 6455 Section 5.2](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.2)
+pub async fn read_frame_header<R>(reader: &mut R) -> Result<FrameHeader, FrameError>
+        where R: AsyncRead + Unpin {
+    let mut start = [0; 2];
+    reader.read_exact(&mut start).await?;
+
+    let fin = start[0] & 0x80 != 0;
+    let rsv = (start[0] >> 4) & 0b111;
+    let Some(opcode) = Opcode::from_u8(start[0] & 0x0F) else {
+        return Err(FrameError::Protocol("unknown opcode"));
+    };
+
+    let masked = start[1] & 0x80 != 0;
+    let payload_length = match start[1] & 0x7F {
+        126 => {
+            let length = reader.read_u16().await? as u64;
+            if length < 126 {
+                return Err(FrameError::Protocol("the payload length isn't minimally encoded"));
+            }
+            length
+        }
+        127 => {
+            let length = reader.read_u64().await?;
+            if length >> 63 != 0 {
+                return Err(FrameError::Protocol("the most significant bit of the payload length is set"));
+            }
+            if length <= u16::MAX as u64 {
+                return Err(FrameError::Protocol("the payload length isn't minimally encoded"));
+            }
+            length
+        }
+        length => length as u64,
+    };
+
+    // # References
+    // * [RFC 6455 Section 5.5](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.5)
+    if opcode.is_control() {
+        if !fin {
+            return Err(FrameError::Protocol("fragmented control frame"));
+        }
+        if payload_length > MAX_CONTROL_FRAME_PAYLOAD_LENGTH {
+            return Err(FrameError::Protocol("the payload of the control frame is too long"));
+        }
+    }
+
+    let mask = if masked {
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).await?;
+        Some(mask)
+    } else {
+        None
+    };
+
+    Ok(FrameHeader { fin, rsv, opcode, mask, payload_length })
+}
+
+/// Masks or unmasks the payload, which is the same operation.
+///
+/// # References
+/// * [RFC 6455 Section 5.3](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.3)
+pub fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// Serializes an unmasked frame, since frames sent by the server must not be
+/// masked.
+pub fn serialize_frame(fin: bool, rsv: u8, opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(10 + payload.len());
+    frame.push(((fin as u8) << 7) | ((rsv & 0b111) << 4) | opcode.as_u8());
+
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Serializes a close frame. Without a `code`, the frame has no payload,
+/// which is also how the client's close frame without a code is echoed.
+pub fn serialize_close_frame(code: Option<u16>, reason: &str) -> Vec<u8> {
+    let mut payload = Vec::new();
+    if let Some(code) = code {
+        payload.extend_from_slice(&code.to_be_bytes());
+
+        // The code takes two bytes of the payload.
+        let mut length = reason.len().min(MAX_CONTROL_FRAME_PAYLOAD_LENGTH as usize - 2);
+        while !reason.is_char_boundary(length) {
+            length -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..length]);
+    }
+    serialize_frame(true, 0, Opcode::Close, &payload)
+}
+
+/// Parses the payload of a close frame into its code and reason.
+///
+/// # References
+/// * [RFC 6455 Section 5.5.1](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.5.1)
+pub fn parse_close_payload(payload: &[u8]) -> Result<(Option<u16>, &str), FrameError> {
+    match payload {
+        [] => Ok((None, "")),
+        [_] => Err(FrameError::Protocol("the close frame has a truncated code")),
+        [first, second, reason @ ..] => {
+            let code = u16::from_be_bytes([*first, *second]);
+            if !close_code::is_valid(code) {
+                return Err(FrameError::Protocol("the close frame has an invalid code"));
+            }
+            let reason = std::str::from_utf8(reason).map_err(|_| FrameError::InvalidPayload)?;
+            Ok((Some(code), reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(bytes: &[u8]) -> Result<FrameHeader, FrameError> {
+        read_frame_header(&mut &bytes[..]).await
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_header_short_length() {
+        let header = parse(&[0x81, 0x85, 1, 2, 3, 4]).await.unwrap();
+        assert_eq!(header, FrameHeader {
+            fin: true,
+            rsv: 0,
+            opcode: Opcode::Text,
+            mask: Some([1, 2, 3, 4]),
+            payload_length: 5,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_header_extended_lengths() {
+        let header = parse(&[0x02, 0xFE, 0x01, 0x00, 1, 2, 3, 4]).await.unwrap();
+        assert!(!header.fin);
+        assert_eq!(header.opcode, Opcode::Binary);
+        assert_eq!(header.payload_length, 256);
+
+        let header = parse(&[0x82, 0xFF, 0, 0, 0, 0, 0, 1, 0, 0, 1, 2, 3, 4]).await.unwrap();
+        assert_eq!(header.payload_length, 65536);
+        assert_eq!(header.mask, Some([1, 2, 3, 4]));
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_header_unmasked() {
+        let header = parse(&[0x89, 0x00]).await.unwrap();
+        assert_eq!(header.opcode, Opcode::Ping);
+        assert_eq!(header.mask, None);
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_header_protocol_errors() {
+        for bytes in [
+            // Reserved opcode
+            &[0x83, 0x80, 1, 2, 3, 4][..],
+            // Fragmented ping
+            &[0x09, 0x80, 1, 2, 3, 4],
+            // Overlong close
+            &[0x88, 0xFE, 0x00, 0x7E],
+            // Non-minimal 16-bit length
+            &[0x82, 0xFE, 0x00, 0x05],
+            // Non-minimal 64-bit length
+            &[0x82, 0xFF, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF],
+            // Most significant bit of the 64-bit length
+            &[0x82, 0xFF, 0x80, 0, 0, 0, 0, 0, 0, 0],
+        ] {
+            let result = parse(bytes).await;
+            assert!(matches!(result, Err(FrameError::Protocol(..))), "{bytes:x?}: {result:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_header_truncated() {
+        let result = parse(&[0x81, 0xFE, 0x01]).await;
+        assert!(matches!(result, Err(FrameError::Io(..))), "{result:?}");
+    }
+
+    #[test]
+    fn test_apply_mask() {
+        // The example of RFC 6455 Section 5.7
+        let mut payload = [0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        apply_mask(&mut payload, [0x37, 0xfa, 0x21, 0x3d]);
+        assert_eq!(&payload, b"Hello");
+    }
+
+    #[test]
+    fn test_serialize_frame() {
+        assert_eq!(serialize_frame(true, 0, Opcode::Text, b"Hello"), [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+        let frame = serialize_frame(true, 0, Opcode::Binary, &[0; 256]);
+        assert_eq!(frame[..4], [0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(frame.len(), 4 + 256);
+
+        let frame = serialize_frame(false, RSV1, Opcode::Binary, &[0; 65536]);
+        assert_eq!(frame[..10], [0x42, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame.len(), 10 + 65536);
+    }
+
+    #[test]
+    fn test_serialize_close_frame() {
+        assert_eq!(serialize_close_frame(None, ""), [0x88, 0x00]);
+        assert_eq!(serialize_close_frame(Some(close_code::PROTOCOL_ERROR), "x"), [0x88, 0x03, 0x03, 0xEA, b'x']);
+
+        let frame = serialize_close_frame(Some(close_code::NORMAL_CLOSURE), &"é".repeat(100));
+        assert!(frame.len() - 2 <= MAX_CONTROL_FRAME_PAYLOAD_LENGTH as usize);
+        assert!(std::str::from_utf8(&frame[4..]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_close_payload() {
+        assert_eq!(parse_close_payload(&[]).unwrap(), (None, ""));
+        assert_eq!(parse_close_payload(&[0x03, 0xE8, b'b', b'y', b'e']).unwrap(), (Some(1000), "bye"));
+        assert!(matches!(parse_close_payload(&[0x03]), Err(FrameError::Protocol(..))));
+        assert!(matches!(parse_close_payload(&[0x03, 0xED]), Err(FrameError::Protocol(..))), "1005 is reserved");
+        assert!(matches!(parse_close_payload(&[0x03, 0xE8, 0xFF]), Err(FrameError::InvalidPayload)));
+    }
+}
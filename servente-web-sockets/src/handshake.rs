@@ -0,0 +1,217 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The opening handshake, which upgrades an HTTP/1.1 connection to a
+//! WebSocket.
+//!
+//! # References
+//! * [RFC 6455 Section 4.2](https://www.rfc-editor.org/rfc/rfc6455.html#section-4.2)
+
+use base64::Engine;
+use servente_http::{
+    lists::parse_http_list,
+    HeaderName,
+    HeaderValue,
+    HttpVersion,
+    Method,
+    Request,
+    Response,
+    StatusCode,
+};
+
+use crate::{
+    deflate::{self, DeflateParameters},
+    WebSocketEndpoint,
+};
+
+/// The GUID that is appended to the `Sec-WebSocket-Key` for computing the
+/// `Sec-WebSocket-Accept`.
+pub const WEB_SOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol, in `Sec-WebSocket-Version`.
+pub const WEB_SOCKET_VERSION: &str = "13";
+
+/// Whether the request asks to upgrade the connection to a WebSocket. It is
+/// still validated by [`accept_handshake`].
+pub fn is_upgrade_request(request: &Request) -> bool {
+    header_contains_token(request, &HeaderName::Upgrade, "websocket")
+}
+
+/// Computes the `Sec-WebSocket-Accept` for the `Sec-WebSocket-Key`.
+///
+/// # References
+/// * [RFC 6455 Section 4.2.2](https://www.rfc-editor.org/rfc/rfc6455.html#section-4.2.2)
+pub fn create_accept_key(key: &str) -> String {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(key.as_bytes());
+    context.update(WEB_SOCKET_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(context.finish())
+}
+
+/// A handshake that was accepted by [`accept_handshake`].
+#[derive(Debug)]
+pub struct AcceptedHandshake {
+    /// The `101 Switching Protocols` response.
+    pub response: Response,
+
+    /// The parameters of `permessage-deflate`, if it was negotiated.
+    pub deflate: Option<DeflateParameters>,
+}
+
+/// Validates the handshake request, and returns the `101 Switching Protocols`
+/// response that accepts it for the `endpoint`. Otherwise, the error response
+/// is returned, after which the connection is still an HTTP/1.1 connection.
+///
+/// # References
+/// * [RFC 6455 Section 4.2.1](https://www.rfc-editor.org/rfc/rfc6455.html#section-4.2.1)
+pub fn accept_handshake(request: &Request, endpoint: &WebSocketEndpoint) -> Result<AcceptedHandshake, Box<Response>> {
+    if request.method != Method::Get || request.version != HttpVersion::Http11 {
+        return Err(Box::new(Response::with_status_and_string_body(StatusCode::BadRequest,
+            "The WebSocket handshake requires a GET request using HTTP/1.1")));
+    }
+
+    if !header_contains_token(request, &HeaderName::Connection, "upgrade") {
+        return Err(Box::new(Response::with_status_and_string_body(StatusCode::BadRequest,
+            "The WebSocket handshake requires the \"upgrade\" connection option")));
+    }
+
+    let version = request.headers.get(&HeaderName::SecWebSocketVersion).map(HeaderValue::as_str_may_convert);
+    if version.as_deref().map(str::trim) != Some(WEB_SOCKET_VERSION) {
+        let mut response = Response::with_status_and_string_body(StatusCode::UpgradeRequired,
+            "Unsupported WebSocket version");
+        response.headers.append_or_override(HeaderName::SecWebSocketVersion, HeaderValue::from(WEB_SOCKET_VERSION));
+        return Err(Box::new(response));
+    }
+
+    let Some(key) = request.headers.get(&HeaderName::SecWebSocketKey).map(HeaderValue::as_str_may_convert) else {
+        return Err(Box::new(Response::with_status_and_string_body(StatusCode::BadRequest, "Missing Sec-WebSocket-Key")));
+    };
+    let key = key.trim();
+    let is_nonce = base64::engine::general_purpose::STANDARD.decode(key)
+        .is_ok_and(|nonce| nonce.len() == 16);
+    if !is_nonce {
+        return Err(Box::new(Response::with_status_and_string_body(StatusCode::BadRequest, "Invalid Sec-WebSocket-Key")));
+    }
+
+    let mut response = Response::with_status(StatusCode::SwitchingProtocols);
+    response.headers.append_or_override(HeaderName::Upgrade, HeaderValue::from("websocket"));
+    response.headers.append_or_override(HeaderName::Connection, HeaderValue::from("Upgrade"));
+    response.headers.append_or_override(HeaderName::SecWebSocketAccept, HeaderValue::from(create_accept_key(key)));
+
+    let deflate = if endpoint.permessage_deflate() {
+        deflate::negotiate(request)
+    } else {
+        None
+    };
+    if let Some(parameters) = &deflate {
+        response.headers.append_or_override(HeaderName::SecWebSocketExtensions, HeaderValue::from(parameters.to_header_value()));
+    }
+
+    Ok(AcceptedHandshake { response, deflate })
+}
+
+fn header_contains_token(request: &Request, header_name: &HeaderName, token: &str) -> bool {
+    request.headers.get_all(header_name)
+        .any(|value| {
+            parse_http_list(&value.as_str_may_convert())
+                .any(|element| element.eq_ignore_ascii_case(token))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use servente_http::{HeaderMap, RequestTarget};
+
+    use super::*;
+
+    fn create_handshake_request(headers: &[(HeaderName, &'static str)]) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            target: RequestTarget::parse("/chat").unwrap(),
+            version: HttpVersion::Http11,
+            headers: HeaderMap::new(),
+            body: None,
+            raw_body: None,
+        };
+        for (name, value) in headers {
+            request.headers.append_or_override(name.clone(), HeaderValue::from(*value));
+        }
+        request
+    }
+
+    fn create_endpoint() -> WebSocketEndpoint {
+        WebSocketEndpoint::new(|_| unreachable!())
+    }
+
+    fn create_valid_handshake_request() -> Request {
+        create_handshake_request(&[
+            (HeaderName::Upgrade, "websocket"),
+            (HeaderName::Connection, "keep-alive, Upgrade"),
+            (HeaderName::SecWebSocketVersion, "13"),
+            (HeaderName::SecWebSocketKey, "dGhlIHNhbXBsZSBub25jZQ=="),
+        ])
+    }
+
+    #[test]
+    fn test_create_accept_key() {
+        // The example of RFC 6455 Section 1.3
+        assert_eq!(create_accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_is_upgrade_request() {
+        assert!(is_upgrade_request(&create_valid_handshake_request()));
+        assert!(is_upgrade_request(&create_handshake_request(&[(HeaderName::Upgrade, "foo, WebSocket")])));
+        assert!(!is_upgrade_request(&create_handshake_request(&[(HeaderName::Upgrade, "h2c")])));
+        assert!(!is_upgrade_request(&create_handshake_request(&[])));
+    }
+
+    #[test]
+    fn test_accept_handshake() {
+        let handshake = accept_handshake(&create_valid_handshake_request(), &create_endpoint()).unwrap();
+        let response = handshake.response;
+        assert_eq!(response.status, StatusCode::SwitchingProtocols);
+        assert_eq!(response.headers.get(&HeaderName::SecWebSocketAccept).unwrap().as_str_may_convert(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(response.headers.get(&HeaderName::Upgrade).unwrap().as_str_may_convert(), "websocket");
+        assert!(!response.headers.contains(&HeaderName::SecWebSocketExtensions));
+        assert_eq!(handshake.deflate, None);
+    }
+
+    #[test]
+    fn test_accept_handshake_negotiates_permessage_deflate() {
+        let mut request = create_valid_handshake_request();
+        request.headers.append_or_override(HeaderName::SecWebSocketExtensions, HeaderValue::from("permessage-deflate; client_max_window_bits"));
+
+        let handshake = accept_handshake(&request, &create_endpoint()).unwrap();
+        assert_eq!(handshake.deflate, None, "the extension is opt-in");
+        assert!(!handshake.response.headers.contains(&HeaderName::SecWebSocketExtensions));
+
+        let handshake = accept_handshake(&request, &create_endpoint().with_permessage_deflate()).unwrap();
+        assert_eq!(handshake.deflate, Some(DeflateParameters::default()));
+        assert_eq!(handshake.response.headers.get(&HeaderName::SecWebSocketExtensions).unwrap().as_str_may_convert(), "permessage-deflate");
+
+        let handshake = accept_handshake(&create_valid_handshake_request(), &create_endpoint().with_permessage_deflate()).unwrap();
+        assert_eq!(handshake.deflate, None, "the client didn't offer the extension");
+    }
+
+    #[test]
+    fn test_accept_handshake_rejects_invalid_requests() {
+        let mut request = create_valid_handshake_request();
+        request.method = Method::Post;
+        assert_eq!(accept_handshake(&request, &create_endpoint()).unwrap_err().status, StatusCode::BadRequest);
+
+        let mut request = create_valid_handshake_request();
+        request.headers.remove(&HeaderName::Connection);
+        assert_eq!(accept_handshake(&request, &create_endpoint()).unwrap_err().status, StatusCode::BadRequest);
+
+        let mut request = create_valid_handshake_request();
+        request.headers.append_or_override(HeaderName::SecWebSocketKey, HeaderValue::from("c2hvcnQ="));
+        assert_eq!(accept_handshake(&request, &create_endpoint()).unwrap_err().status, StatusCode::BadRequest);
+
+        let mut request = create_valid_handshake_request();
+        request.headers.append_or_override(HeaderName::SecWebSocketVersion, HeaderValue::from("8"));
+        let response = accept_handshake(&request, &create_endpoint()).unwrap_err();
+        assert_eq!(response.status, StatusCode::UpgradeRequired);
+        assert_eq!(response.headers.get(&HeaderName::SecWebSocketVersion).unwrap().as_str_may_convert(), "13");
+    }
+}
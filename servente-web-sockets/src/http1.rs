@@ -0,0 +1,392 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! WebSockets over HTTP/1.1 connections, which are upgraded using the
+//! [`crate::handshake`].
+//!
+//! # References
+//! * [RFC 6455 Section 6](https://www.rfc-editor.org/rfc/rfc6455.html#section-6)
+
+use std::io;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    deflate::{DeflateParameters, PerMessageDeflate},
+    frame::{
+        apply_mask,
+        parse_close_payload,
+        read_frame_header,
+        serialize_close_frame,
+        serialize_frame,
+        FrameError,
+        Opcode,
+        RSV1,
+    },
+    WebSocketConnection,
+    WebSocketEndpoint,
+    WebSocketFrameHandler,
+};
+
+/// The [`WebSocketConnection`] of an upgraded HTTP/1.1 connection, which
+/// writes unmasked frames to the client.
+pub struct Http1WebSocketConnection<'a, W> {
+    writer: &'a mut W,
+
+    /// Whether the close frame was sent, after which no other frames may be
+    /// sent.
+    close_sent: bool,
+
+    /// The compression contexts, when `permessage-deflate` was negotiated.
+    deflate: Option<PerMessageDeflate>,
+}
+
+impl<'a, W> Http1WebSocketConnection<'a, W>
+        where W: AsyncWrite + Unpin + Send {
+    pub fn new(writer: &'a mut W, deflate: Option<DeflateParameters>) -> Self {
+        Self {
+            writer,
+            close_sent: false,
+            deflate: deflate.map(PerMessageDeflate::new),
+        }
+    }
+
+    async fn send_frame(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "the WebSocket is closing"));
+        }
+
+        self.writer.write_all(&frame).await?;
+        self.writer.flush().await
+    }
+
+    async fn send_message(&mut self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        // Control frames are never compressed.
+        let frame = match &mut self.deflate {
+            Some(deflate) if !opcode.is_control() => serialize_frame(true, RSV1, opcode, &deflate.compress(payload)),
+            _ => serialize_frame(true, 0, opcode, payload),
+        };
+        self.send_frame(frame).await
+    }
+
+    async fn send_close(&mut self, code: Option<u16>, reason: &str) -> io::Result<()> {
+        self.send_frame(serialize_close_frame(code, reason)).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'a, W> WebSocketConnection for Http1WebSocketConnection<'a, W>
+        where W: AsyncWrite + Unpin + Send {
+    async fn send_message_text(&mut self, text: &str) -> io::Result<()> {
+        self.send_message(Opcode::Text, text.as_bytes()).await
+    }
+
+    async fn send_message_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        self.send_message(Opcode::Binary, data).await
+    }
+
+    async fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        self.send_close(Some(code), reason).await
+    }
+}
+
+/// Exchanges the messages of an upgraded connection until it is closed. When
+/// the client violates the protocol or exceeds the limits of the `endpoint`,
+/// the connection is closed with the corresponding close code. The caller
+/// closes the underlying connection afterwards.
+///
+/// When `permessage-deflate` was negotiated in the handshake, the messages in
+/// both directions are compressed using its `deflate` parameters.
+pub async fn handle_connection<R, W>(reader: &mut R, writer: &mut W, handler: &mut dyn WebSocketFrameHandler,
+        endpoint: &WebSocketEndpoint, deflate: Option<DeflateParameters>) -> io::Result<()>
+        where R: AsyncRead + Unpin + Send,
+              W: AsyncWrite + Unpin + Send {
+    let mut connection = Http1WebSocketConnection::new(writer, deflate);
+    match read_messages(reader, &mut connection, handler, endpoint).await {
+        Ok(()) => Ok(()),
+        Err(FrameError::Io(error)) => Err(error),
+        Err(error) => {
+            if !connection.close_sent {
+                connection.send_close(Some(error.close_code()), "").await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn read_messages<R, W>(reader: &mut R, connection: &mut Http1WebSocketConnection<'_, W>,
+        handler: &mut dyn WebSocketFrameHandler, endpoint: &WebSocketEndpoint) -> Result<(), FrameError>
+        where R: AsyncRead + Unpin + Send,
+              W: AsyncWrite + Unpin + Send {
+    // The opcode, whether it is compressed, and the payload of the frames of
+    // the fragmented message that is being received.
+    let mut message: Option<(Opcode, bool, Vec<u8>)> = None;
+
+    loop {
+        let header = read_frame_header(reader).await?;
+
+        // RSV1 marks a compressed message, so it is only allowed on the first
+        // frame of a message.
+        //
+        // # References
+        // * [RFC 7692 Section 6.1](https://www.rfc-editor.org/rfc/rfc7692.html#section-6.1)
+        let allowed_rsv = match header.opcode {
+            Opcode::Text | Opcode::Binary if connection.deflate.is_some() => RSV1,
+            _ => 0,
+        };
+        if header.rsv & !allowed_rsv != 0 {
+            return Err(FrameError::Protocol("reserved bits are set without a negotiated extension"));
+        }
+
+        // # References
+        // * [RFC 6455 Section 5.1](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.1)
+        let Some(mask) = header.mask else {
+            return Err(FrameError::Protocol("unmasked client frame"));
+        };
+
+        if header.payload_length > endpoint.max_frame_size() as u64 {
+            return Err(FrameError::TooLarge);
+        }
+
+        let mut payload = vec![0; header.payload_length as usize];
+        reader.read_exact(&mut payload).await?;
+        apply_mask(&mut payload, mask);
+
+        match header.opcode {
+            Opcode::Ping => {
+                handler.on_ping(&payload).await;
+                if !connection.close_sent {
+                    connection.send_message(Opcode::Pong, &payload).await?;
+                }
+                continue;
+            }
+            Opcode::Pong => {
+                handler.on_pong(&payload).await;
+                continue;
+            }
+            Opcode::Close => {
+                let (code, reason) = parse_close_payload(&payload)?;
+                handler.on_close(code, reason).await;
+
+                // # References
+                // * [RFC 6455 Section 5.5.1](https://www.rfc-editor.org/rfc/rfc6455.html#section-5.5.1)
+                if !connection.close_sent {
+                    connection.send_close(code, "").await?;
+                }
+                return Ok(());
+            }
+            Opcode::Text | Opcode::Binary => {
+                if message.is_some() {
+                    return Err(FrameError::Protocol("a new message started before the previous one was finished"));
+                }
+                if payload.len() > endpoint.max_message_size() {
+                    return Err(FrameError::TooLarge);
+                }
+                message = Some((header.opcode, header.rsv & RSV1 != 0, payload));
+            }
+            Opcode::Continuation => {
+                let Some((_, _, data)) = &mut message else {
+                    return Err(FrameError::Protocol("a continuation frame without a message"));
+                };
+                if data.len() + payload.len() > endpoint.max_message_size() {
+                    return Err(FrameError::TooLarge);
+                }
+                data.extend_from_slice(&payload);
+            }
+        }
+
+        if !header.fin {
+            continue;
+        }
+
+        let (opcode, compressed, data) = message.take().expect("the message was started by this or a previous frame");
+
+        // The messages received after the close frame was sent are discarded.
+        if connection.close_sent {
+            continue;
+        }
+
+        let data = match &mut connection.deflate {
+            Some(deflate) if compressed => deflate.decompress(&data, endpoint.max_message_size())?,
+            _ => data,
+        };
+
+        if opcode == Opcode::Text {
+            let text = String::from_utf8(data).map_err(|_| FrameError::InvalidPayload)?;
+            handler.on_text(connection, text).await?;
+        } else {
+            handler.on_binary(connection, data).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame::{close_code, RSV1};
+
+    use super::*;
+
+    /// Echoes the messages, and closes the connection on the text `bye`.
+    struct EchoHandler;
+
+    #[async_trait]
+    impl WebSocketFrameHandler for EchoHandler {
+        async fn on_text(&mut self, connection: &mut dyn WebSocketConnection, text: String) -> io::Result<()> {
+            if text == "bye" {
+                return connection.close(close_code::NORMAL_CLOSURE, "bye").await;
+            }
+            connection.send_message_text(&text).await
+        }
+
+        async fn on_binary(&mut self, connection: &mut dyn WebSocketConnection, data: Vec<u8>) -> io::Result<()> {
+            connection.send_message_binary(&data).await
+        }
+    }
+
+    fn client_frame(fin: bool, rsv: u8, opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = serialize_frame(fin, rsv, opcode, payload);
+        let payload_start = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        apply_mask(&mut frame[payload_start..], mask);
+        frame.splice(payload_start..payload_start, mask);
+        frame
+    }
+
+    async fn exchange(input: &[u8], endpoint: &WebSocketEndpoint) -> io::Result<Vec<u8>> {
+        exchange_with_deflate(input, endpoint, None).await
+    }
+
+    async fn exchange_with_deflate(input: &[u8], endpoint: &WebSocketEndpoint, deflate: Option<DeflateParameters>) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        handle_connection(&mut &input[..], &mut output, &mut EchoHandler, endpoint, deflate).await?;
+        Ok(output)
+    }
+
+    fn create_endpoint() -> WebSocketEndpoint {
+        WebSocketEndpoint::new(|_| Box::new(EchoHandler))
+    }
+
+    #[tokio::test]
+    async fn test_echo_and_close() {
+        let input = [
+            client_frame(true, 0, Opcode::Text, b"Hello"),
+            client_frame(true, 0, Opcode::Binary, &[1, 2, 3]),
+            client_frame(true, 0, Opcode::Close, &[0x03, 0xE8]),
+        ].concat();
+
+        let output = exchange(&input, &create_endpoint()).await.unwrap();
+        assert_eq!(output, [
+            serialize_frame(true, 0, Opcode::Text, b"Hello"),
+            serialize_frame(true, 0, Opcode::Binary, &[1, 2, 3]),
+            serialize_close_frame(Some(close_code::NORMAL_CLOSURE), ""),
+        ].concat());
+    }
+
+    #[tokio::test]
+    async fn test_continuation_frames_are_reassembled() {
+        let input = [
+            client_frame(false, 0, Opcode::Text, b"Hel"),
+            // Control frames can be interleaved with the fragments.
+            client_frame(true, 0, Opcode::Ping, b"ping"),
+            client_frame(false, 0, Opcode::Continuation, b"lo, "),
+            client_frame(true, 0, Opcode::Continuation, b"world"),
+            client_frame(true, 0, Opcode::Close, &[]),
+        ].concat();
+
+        let output = exchange(&input, &create_endpoint()).await.unwrap();
+        assert_eq!(output, [
+            serialize_frame(true, 0, Opcode::Pong, b"ping"),
+            serialize_frame(true, 0, Opcode::Text, b"Hello, world"),
+            serialize_close_frame(None, ""),
+        ].concat());
+    }
+
+    #[tokio::test]
+    async fn test_server_initiated_close_discards_later_messages() {
+        let input = [
+            client_frame(true, 0, Opcode::Text, b"bye"),
+            client_frame(true, 0, Opcode::Text, b"ignored"),
+            client_frame(true, 0, Opcode::Close, &[0x03, 0xE8]),
+        ].concat();
+
+        let output = exchange(&input, &create_endpoint()).await.unwrap();
+        assert_eq!(output, serialize_close_frame(Some(close_code::NORMAL_CLOSURE), "bye"));
+    }
+
+    #[tokio::test]
+    async fn test_violations_close_with_their_codes() {
+        let endpoint = create_endpoint()
+            .with_max_frame_size(16)
+            .with_max_message_size(24);
+        let unmasked = serialize_frame(true, 0, Opcode::Text, b"Hello");
+
+        for (input, code) in [
+            (unmasked, close_code::PROTOCOL_ERROR),
+            (client_frame(true, RSV1, Opcode::Text, b"Hello"), close_code::PROTOCOL_ERROR),
+            (client_frame(true, 0, Opcode::Continuation, b"Hello"), close_code::PROTOCOL_ERROR),
+            ([client_frame(false, 0, Opcode::Text, b"Hel"), client_frame(true, 0, Opcode::Text, b"lo")].concat(), close_code::PROTOCOL_ERROR),
+            (client_frame(true, 0, Opcode::Close, &[0x03, 0xED]), close_code::PROTOCOL_ERROR),
+            (client_frame(true, 0, Opcode::Text, &[0xC3, 0x28]), close_code::INVALID_PAYLOAD_DATA),
+            (client_frame(true, 0, Opcode::Binary, &[0; 17]), close_code::MESSAGE_TOO_BIG),
+            ([client_frame(false, 0, Opcode::Binary, &[0; 16]), client_frame(true, 0, Opcode::Continuation, &[0; 16])].concat(), close_code::MESSAGE_TOO_BIG),
+        ] {
+            let output = exchange(&input, &endpoint).await.unwrap();
+            assert_eq!(output, serialize_close_frame(Some(code), ""), "{input:x?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed_without_close_frame() {
+        let input = client_frame(true, 0, Opcode::Text, b"Hello");
+        let result = exchange(&input, &create_endpoint()).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn test_permessage_deflate() {
+        let parameters = DeflateParameters::default();
+        let mut client = PerMessageDeflate::new(parameters);
+        let hello = client.compress(b"Hello");
+        let input = [
+            // RFC 7692 Section 7.2.3.1, sent twice using context takeover
+            client_frame(true, RSV1, Opcode::Text, &hello),
+            client_frame(true, RSV1, Opcode::Text, &client.compress(b"Hello")),
+            // Messages may still be sent uncompressed.
+            client_frame(true, 0, Opcode::Binary, &[1, 2, 3]),
+            client_frame(true, 0, Opcode::Close, &[]),
+        ].concat();
+
+        let output = exchange_with_deflate(&input, &create_endpoint(), Some(parameters)).await.unwrap();
+
+        let mut server = PerMessageDeflate::new(parameters);
+        assert_eq!(output, [
+            serialize_frame(true, RSV1, Opcode::Text, &server.compress(b"Hello")),
+            serialize_frame(true, RSV1, Opcode::Text, &server.compress(b"Hello")),
+            serialize_frame(true, RSV1, Opcode::Binary, &server.compress(&[1, 2, 3])),
+            serialize_close_frame(None, ""),
+        ].concat());
+        assert_eq!(hello, [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_permessage_deflate_violations() {
+        let endpoint = create_endpoint().with_max_message_size(1024);
+        let mut client = PerMessageDeflate::new(DeflateParameters::default());
+        let compressed = client.compress(b"Hello");
+        let bomb = PerMessageDeflate::new(DeflateParameters::default()).compress(&[0; 2048]);
+
+        for (input, code) in [
+            ([client_frame(false, RSV1, Opcode::Text, &compressed[..2]), client_frame(true, RSV1, Opcode::Continuation, &compressed[2..])].concat(), close_code::PROTOCOL_ERROR),
+            (client_frame(true, RSV1, Opcode::Ping, b""), close_code::PROTOCOL_ERROR),
+            (client_frame(true, RSV1, Opcode::Binary, &bomb), close_code::MESSAGE_TOO_BIG),
+            (client_frame(true, RSV1, Opcode::Binary, &[0xFF, 0xFF]), close_code::INVALID_PAYLOAD_DATA),
+        ] {
+            let output = exchange_with_deflate(&input, &endpoint, Some(DeflateParameters::default())).await.unwrap();
+            assert_eq!(output, serialize_close_frame(Some(code), ""), "{input:x?}");
+        }
+    }
+}
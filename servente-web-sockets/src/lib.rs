@@ -0,0 +1,160 @@
+// Copyright (C) 2023 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! The `servente-web-sockets` crate implements the **WebSocket** protocol for
+//! the Servente webserver: the opening handshake, the frame codec, and the
+//! exchange of messages over upgraded HTTP/1.1 connections.
+//!
+//! # References
+//! * [RFC 6455](https://www.rfc-editor.org/rfc/rfc6455)
+
+pub mod deflate;
+pub mod frame;
+pub mod handshake;
+pub mod http1;
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use servente_http::Request;
+
+/// The default value of [`WebSocketEndpoint::with_max_frame_size`].
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// The default value of [`WebSocketEndpoint::with_max_message_size`].
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// The side of the connection the [`WebSocketFrameHandler`] uses to send
+/// messages to the client.
+#[async_trait]
+pub trait WebSocketConnection: Send {
+    /// Sends a text message to the client.
+    async fn send_message_text(&mut self, text: &str) -> io::Result<()>;
+
+    /// Sends a binary message to the client.
+    async fn send_message_binary(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Starts the closing handshake. Messages can't be sent afterwards, and
+    /// the messages the client sends before it replies with its own close
+    /// frame are discarded.
+    async fn close(&mut self, code: u16, reason: &str) -> io::Result<()>;
+}
+
+/// The application side of a WebSocket, which is invoked for the messages and
+/// control frames the client sends.
+#[async_trait]
+pub trait WebSocketFrameHandler: Send {
+    /// Invoked when a complete text message was received.
+    async fn on_text(&mut self, connection: &mut dyn WebSocketConnection, text: String) -> io::Result<()>;
+
+    /// Invoked when a complete binary message was received.
+    async fn on_binary(&mut self, connection: &mut dyn WebSocketConnection, data: Vec<u8>) -> io::Result<()>;
+
+    /// Invoked when the client sent a ping. It is answered with a pong
+    /// regardless of this handler.
+    async fn on_ping(&mut self, _payload: &[u8]) {}
+
+    /// Invoked when the client sent a pong, e.g. unsolicited as a heartbeat.
+    async fn on_pong(&mut self, _payload: &[u8]) {}
+
+    /// Invoked when the client sent a close frame. The `code` is `None` when
+    /// the client didn't specify one.
+    async fn on_close(&mut self, _code: Option<u16>, _reason: &str) {}
+}
+
+/// Creates the [`WebSocketFrameHandler`] for an accepted handshake request.
+pub type WebSocketHandlerFactory = dyn Fn(&Request) -> Box<dyn WebSocketFrameHandler> + Send + Sync;
+
+/// A path that accepts WebSocket connections, and the limits of these
+/// connections.
+#[derive(Clone)]
+pub struct WebSocketEndpoint {
+    factory: Arc<WebSocketHandlerFactory>,
+    max_frame_size: usize,
+    max_message_size: usize,
+    permessage_deflate: bool,
+}
+
+impl WebSocketEndpoint {
+    pub fn new<F>(factory: F) -> Self
+            where F: Fn(&Request) -> Box<dyn WebSocketFrameHandler> + Send + Sync + 'static {
+        Self {
+            factory: Arc::new(factory),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            permessage_deflate: false,
+        }
+    }
+
+    /// The maximum payload length of a single frame. Larger frames close the
+    /// connection with [`frame::close_code::MESSAGE_TOO_BIG`].
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// The maximum length of a message, i.e. of all its frames together.
+    /// Larger messages close the connection with
+    /// [`frame::close_code::MESSAGE_TOO_BIG`].
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Accepts the `permessage-deflate` extension when the client offers it,
+    /// see [`deflate`]. The messages of clients that don't offer it aren't
+    /// compressed.
+    pub fn with_permessage_deflate(mut self) -> Self {
+        self.permessage_deflate = true;
+        self
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    pub fn permessage_deflate(&self) -> bool {
+        self.permessage_deflate
+    }
+
+    /// Creates the handler for the connection of the given handshake request.
+    pub fn create_handler(&self, request: &Request) -> Box<dyn WebSocketFrameHandler> {
+        (self.factory)(request)
+    }
+}
+
+/// The paths that accept WebSocket connections. Requests for other paths are
+/// handled normally, even when they ask for an upgrade.
+#[derive(Clone, Default)]
+pub struct WebSocketRegistry {
+    endpoints: HashMap<String, WebSocketEndpoint>,
+}
+
+impl WebSocketRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the endpoint for the given path, e.g. `/chat`. This path is
+    /// matched against the percent-decoded and normalized path of the
+    /// request.
+    pub fn register(&mut self, path: impl Into<String>, endpoint: WebSocketEndpoint) {
+        self.endpoints.insert(path.into(), endpoint);
+    }
+
+    pub fn find(&self, path: &str) -> Option<&WebSocketEndpoint> {
+        self.endpoints.get(path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+}
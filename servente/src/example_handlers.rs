@@ -4,16 +4,31 @@
 use crate::handler::HandlerController;
 
 use servente_http::{
+    HeaderName,
     Request,
     Response, StatusCode,
 };
+use servente_resources::cache;
 
 fn ping_test(request: &Request) -> Result<Response, anyhow::Error> {
     println!("[TEST] Ping: {:#?}", request);
     Ok(Response::with_status_and_string_body(StatusCode::Ok, ""))
 }
 
+/// Reports whether the server is ready to receive traffic, which is after the
+/// cache was warmed up.
+fn readiness(_: &Request) -> Result<Response, anyhow::Error> {
+    if cache::is_warmed_up() {
+        return Ok(Response::with_status_and_string_body(StatusCode::Ok, "Ready"));
+    }
+
+    let mut response = Response::with_status_and_string_body(StatusCode::ServiceUnavailable, "Warming up");
+    response.headers.append_or_override(HeaderName::RetryAfter, "1".into());
+    Ok(response)
+}
+
 pub fn register(controller: &mut HandlerController) {
     controller.register_get("/test-ping".into(), &ping_test);
     controller.register_post("/test-ping".into(), &ping_test);
+    controller.register_get("/readyz".into(), &readiness);
 }
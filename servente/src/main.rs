@@ -12,10 +12,17 @@ use servente_http_handling::{handler, ServenteConfig, ServenteSettings};
 use servente_resources::cache;
 use tokio::task;
 
-use std::{io, time::{Instant, Duration}, env::current_dir};
+use std::{io, time::{Instant, Duration}};
 
 mod example_handlers;
 
+/// The UDP port HTTP/3 is served on, if it is enabled.
+#[cfg(feature = "http3")]
+const HTTP3_PORT: Option<u16> = Some(8080);
+
+#[cfg(not(feature = "http3"))]
+const HTTP3_PORT: Option<u16> = None;
+
 #[cfg(not(feature = "io_uring"))]
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -30,43 +37,34 @@ fn main() -> io::Result<()> {
 pub(crate) async fn begin() -> io::Result<()> {
     let start = Instant::now();
 
-    let wwwroot_path = current_dir().unwrap().join("wwwroot");
-
-    let mut handler_controller = handler::HandlerController::new();
-    example_handlers::register(&mut handler_controller);
-
-    let middleware = Vec::new();
-
-    #[cfg(feature = "cgi")]
-    let mut middleware = middleware;
-
-    #[cfg(feature = "cgi")]
-    setup_cgi(&mut middleware);
-
-    let config = ServenteConfig::new().build(ServenteSettings {
-        handler_controller,
-        read_headers_timeout: Duration::from_secs(45),
-        read_body_timeout: Duration::from_secs(60),
-        middleware,
-    });
+    let config = create_config();
+    let wwwroot_path = config.settings.document_root.clone().expect("the document root is resolved by create_config");
+    let bind_address = config.bind_address.clone();
+    let cache_options = cache::CacheOptions {
+        content_hash_etags: config.settings.content_hash_etags,
+        ..Default::default()
+    };
 
     #[cfg(feature = "http3")]
     let config_v3 = config.clone();
 
+    #[cfg(unix)]
+    let config = config.install_sighup_reload(|| Ok::<_, std::convert::Infallible>(create_config()))?;
+
     println!("Loaded after {} ms", start.elapsed().as_millis());
 
     let join_handle = task::spawn(async move {
-        servente_http1::start("127.0.0.1:8080", config).await
+        servente_http1::start(&bind_address, config).await
     });
 
     #[cfg(feature = "http3")]
     let join_handle_v3 = task::spawn(async move {
-        servente_http3::start(config_v3.tls_config).await
+        servente_http3::start(config_v3.tls_config, config_v3.settings.http3_port.unwrap_or(8080)).await
     });
 
     let wwwroot_path_cacher = wwwroot_path.clone();
     let join_handle_cache = task::spawn(async move {
-        cache::start(&wwwroot_path_cacher).await
+        cache::start(&wwwroot_path_cacher, cache_options).await
     });
 
     println!("[servente] Ready.");
@@ -86,9 +84,81 @@ pub(crate) async fn begin() -> io::Result<()> {
     Ok(())
 }
 
+/// Creates the configuration, which also loads the certificates. This is
+/// called again when the configuration is reloaded.
+fn create_config() -> ServenteConfig {
+    let mut handler_controller = handler::HandlerController::new();
+    example_handlers::register(&mut handler_controller);
+
+    let document_root = servente_http_handling::config::resolve_document_root(None)
+        .expect("failed to resolve the document root");
+
+    let middleware = Vec::new();
+
+    #[cfg(feature = "cgi")]
+    let mut middleware = middleware;
+
+    #[cfg(feature = "cgi")]
+    setup_cgi(&mut middleware, &document_root);
+
+    ServenteConfig::new().build(ServenteSettings {
+        handler_controller,
+        read_headers_timeout: Duration::from_secs(45),
+        read_body_timeout: Duration::from_secs(60),
+        middleware,
+        canonical_host: Default::default(),
+        excess_body_data: Default::default(),
+        unknown_expectations: Default::default(),
+        line_endings: Default::default(),
+        log_tls_parameters: Default::default(),
+        dynamic_table_budget: Default::default(),
+        header_order: Default::default(),
+        body_transforms: Default::default(),
+        max_parts: servente_http_handling::config::DEFAULT_MAX_PARTS,
+        security_headers: Default::default(),
+        default_headers: servente_http_handling::config::default_headers(),
+        hsts: None,
+        request_body_spill_threshold: Some(servente_http_handling::config::DEFAULT_REQUEST_BODY_SPILL_THRESHOLD),
+        http2_precomputed_header_blocks: false,
+        client_hints: Default::default(),
+        document_root: Some(document_root),
+        document_source: Default::default(),
+        request_loggers: Default::default(),
+        redacted_headers: Default::default(),
+        max_chunk_size: servente_http_handling::config::DEFAULT_MAX_CHUNK_SIZE,
+        max_request_body_size: servente_http_handling::config::DEFAULT_MAX_REQUEST_BODY_SIZE,
+        tls_max_early_data_size: 0,
+        file_backend: Default::default(),
+        content_hash_etags: Default::default(),
+        http3_port: HTTP3_PORT,
+        http2_ping_interval: servente_http_handling::config::DEFAULT_HTTP2_PING_INTERVAL,
+        http2_max_unacknowledged_pings: servente_http_handling::config::DEFAULT_HTTP2_MAX_UNACKNOWLEDGED_PINGS,
+        h2c_prior_knowledge: false,
+        client_connection_limit: None,
+        rewrite_rules: Default::default(),
+        maintenance_mode: Default::default(),
+        chunked_transfer_threshold: servente_http_handling::config::DEFAULT_CHUNKED_TRANSFER_THRESHOLD,
+        symlink_policy: Default::default(),
+        redirect_directories: false,
+        asset_fingerprints: Default::default(),
+        shutdown_grace_period: servente_http_handling::config::DEFAULT_SHUTDOWN_GRACE_PERIOD,
+        rate_limiter: None,
+        request_validators: vec![std::sync::Arc::new(servente_http_handling::SensitivePathValidator)],
+        max_keep_alive_requests: servente_http_handling::config::DEFAULT_MAX_KEEP_ALIVE_REQUESTS,
+        keep_alive_idle_timeout: servente_http_handling::config::DEFAULT_KEEP_ALIVE_IDLE_TIMEOUT,
+        web_sockets: Default::default(),
+        index_files: vec![String::from("index.html")],
+        stats: Default::default(),
+    })
+}
+
 #[cfg(feature = "cgi")]
-fn setup_cgi(middleware: &mut Vec<std::sync::Arc<dyn servente_http_handling::Middleware>>) {
+fn setup_cgi(middleware: &mut Vec<std::sync::Arc<dyn servente_http_handling::Middleware>>, document_root: &std::path::Path) {
     use std::sync::Arc;
 
-    middleware.push(Arc::new(servente_cgi::CgiMiddleware::new()));
+    let scripts = servente_cgi::CgiScripts::new()
+        .with_extensions(&["cgi", "pl"])
+        .with_interpreter("py", "python3")
+        .with_directory("/cgi-bin/");
+    middleware.push(Arc::new(servente_cgi::CgiMiddleware::new(document_root.to_path_buf(), scripts)));
 }